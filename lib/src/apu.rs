@@ -0,0 +1,754 @@
+//! Audio processing unit (APU)
+//!
+//! Emulates the four sound channels (two square waves, a programmable wave and
+//! noise) and mixes them into interleaved stereo samples at `SAMPLE_RATE`.
+use crate::memory::{MemoryRead, MemoryWrite};
+
+/// Rate at which the APU produces samples, in Hz
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// APU clock, in Hz. This is not affected by CGB double-speed mode.
+const CLOCK_RATE: u32 = 4194304;
+
+/// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u32 = CLOCK_RATE / 512;
+
+/// Maximum number of buffered samples (~1 second of stereo audio) if nobody
+/// is consuming them
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize * 2;
+
+/// Square wave duty cycles (12.5%, 25%, 50%, 75%)
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Noise channel divisors, indexed by NR43 bits 0-2
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Bits that always read back as 1 for each register in 0xFF10 - 0xFF26
+const READ_MASKS: [u8; 23] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 - NR44
+    0x00, 0x00, 0x70,             // NR50 - NR52
+];
+
+/// Length counter, shared by all channels
+#[derive(Default)]
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+struct Length {
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    /// Returns `false` once the counter runs out
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+
+        true
+    }
+
+    fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+}
+
+/// Volume envelope for the square and noise channels
+#[derive(Default)]
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+struct Envelope {
+    /// NRx2
+    reg: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn dac_enabled(&self) -> bool {
+        self.reg & 0xF8 != 0
+    }
+
+    fn period(&self) -> u8 {
+        self.reg & 0x7
+    }
+
+    fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            if self.reg & 1 << 3 != 0 {
+                self.volume = (self.volume + 1).min(15);
+            } else {
+                self.volume = self.volume.saturating_sub(1);
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.reg >> 4;
+        self.timer = self.period();
+    }
+}
+
+/// Frequency sweep for channel 1
+#[derive(Default)]
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+struct Sweep {
+    /// NR10
+    reg: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn period(&self) -> u8 {
+        (self.reg >> 4) & 0x7
+    }
+
+    fn shift(&self) -> u8 {
+        self.reg & 0x7
+    }
+
+    /// Compute the next frequency, which may overflow past 2047
+    fn next(&self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if self.reg & 1 << 3 != 0 {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// Square wave channel (channels 1 and 2)
+#[derive(Default)]
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+struct Square {
+    enabled: bool,
+    duty: u8,
+    position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    fn new(sweep: bool) -> Self {
+        Self {
+            sweep: if sweep { Some(Sweep::default()) } else { None },
+            ..Default::default()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+
+        let high = DUTY_PATTERNS[self.duty as usize] & 1 << self.position != 0;
+        Some(if self.enabled && high { self.envelope.volume } else { 0 })
+    }
+
+    fn clock_sweep(&mut self) {
+        let sweep = match &mut self.sweep {
+            Some(sweep) => sweep,
+            None => return,
+        };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.timer = if sweep.period() == 0 { 8 } else { sweep.period() };
+
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+
+        let frequency = sweep.next();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if sweep.shift() != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+
+            // The new frequency is checked for overflow again, but not used
+            if sweep.next() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(64);
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.timer = if sweep.period() == 0 { 8 } else { sweep.period() };
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+
+            if sweep.shift() != 0 && sweep.next() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+}
+
+/// Programmable wave channel (channel 3)
+#[derive(Default)]
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    /// NR32 bits 5-6
+    volume: u8,
+    position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0xF };
+
+        let sample = match self.volume {
+            0 => 0,
+            code => sample >> (code - 1),
+        };
+
+        Some(if self.enabled { sample } else { 0 })
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(256);
+        self.timer = self.period();
+        self.position = 0;
+    }
+}
+
+/// Noise channel (channel 4)
+#[derive(Default)]
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+struct Noise {
+    enabled: bool,
+    /// NR43
+    reg: u8,
+    lfsr: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[(self.reg & 0x7) as usize] << (self.reg >> 4)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | bit << 14;
+
+            // 7-bit mode
+            if self.reg & 1 << 3 != 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | bit << 6;
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+
+        let high = self.lfsr & 1 == 0;
+        Some(if self.enabled && high { self.envelope.volume } else { 0 })
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(64);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+}
+
+/// Audio processing unit
+///
+/// Registers are mapped to 0xFF10 - 0xFF26, and wave RAM to 0xFF30 - 0xFF3F.
+#[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
+pub struct Apu {
+    /// Raw register values, used for reads
+    regs: [u8; 23],
+
+    /// NR52 bit 7
+    enabled: bool,
+
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+
+    /// Cycles until the next frame sequencer step
+    sequencer_timer: u32,
+    sequencer_step: u8,
+
+    /// Fractional cycle count towards the next sample, scaled by `SAMPLE_RATE`
+    sample_timer: u32,
+
+    /// Current DC offset of each output, removed by a high-pass filter
+    capacitor: (f32, f32),
+
+    /// Interleaved stereo samples that have not been consumed yet
+    #[cfg_attr(feature = "save", serde(skip))]
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub const BASE_ADDR: u16 = 0xFF10;
+    pub const LAST_ADDR: u16 = 0xFF26;
+    pub const WAVE_RAM_BASE_ADDR: u16 = 0xFF30;
+    pub const WAVE_RAM_LAST_ADDR: u16 = 0xFF3F;
+
+    pub fn new() -> Self {
+        Self {
+            regs: [0; 23],
+            enabled: false,
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::default(),
+            noise: Noise::default(),
+            sequencer_timer: FRAME_SEQUENCER_PERIOD,
+            sequencer_step: 0,
+            sample_timer: 0,
+            capacitor: (0.0, 0.0),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Run the APU for the given number of CPU cycles, counted at single
+    /// speed. The CPU already halves them in double-speed mode, so the APU
+    /// keeps its rate.
+    pub fn step(&mut self, cycles: u16) {
+        let mut cycles = cycles as u32;
+
+        while cycles > 0 {
+            // Run up to the next sample or frame sequencer step, whichever is first
+            let until_sample = (CLOCK_RATE - self.sample_timer).div_ceil(SAMPLE_RATE);
+            let n = cycles.min(until_sample).min(self.sequencer_timer).max(1);
+            cycles -= n;
+
+            if self.enabled {
+                self.square1.tick(n);
+                self.square2.tick(n);
+                self.wave.tick(n);
+                self.noise.tick(n);
+            }
+
+            self.sequencer_timer -= n.min(self.sequencer_timer);
+            if self.sequencer_timer == 0 {
+                self.sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                self.clock_sequencer();
+            }
+
+            self.sample_timer += n * SAMPLE_RATE;
+            if self.sample_timer >= CLOCK_RATE {
+                self.sample_timer -= CLOCK_RATE;
+                self.push_sample();
+            }
+        }
+    }
+
+    /// Remove all samples produced so far.
+    pub fn drain_samples(&mut self) -> std::vec::Drain<'_, f32> {
+        self.samples.drain(..)
+    }
+
+    fn clock_sequencer(&mut self) {
+        let step = self.sequencer_step;
+        self.sequencer_step = (step + 1) % 8;
+
+        if !self.enabled {
+            return;
+        }
+
+        if step & 1 == 0 {
+            if !self.square1.length.clock() {
+                self.square1.enabled = false;
+            }
+            if !self.square2.length.clock() {
+                self.square2.enabled = false;
+            }
+            if !self.wave.length.clock() {
+                self.wave.enabled = false;
+            }
+            if !self.noise.length.clock() {
+                self.noise.enabled = false;
+            }
+        }
+
+        if step == 2 || step == 6 {
+            self.square1.clock_sweep();
+        }
+
+        if step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+    }
+
+    fn push_sample(&mut self) {
+        if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+            // Nobody is listening, so drop the oldest half
+            self.samples.drain(..MAX_BUFFERED_SAMPLES / 2);
+        }
+
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        // NR51: bits 4-7 route channels to the left, bits 0-3 to the right
+        let panning = self.regs[0x15];
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, output) in outputs.iter().enumerate() {
+            // Each DAC maps 0-15 to a voltage between -1 and 1
+            if let Some(output) = output {
+                let voltage = *output as f32 / 7.5 - 1.0;
+                if panning & 1 << (i + 4) != 0 {
+                    left += voltage;
+                }
+                if panning & 1 << i != 0 {
+                    right += voltage;
+                }
+            }
+        }
+
+        // NR50: master volume for each side, from 1 to 8
+        let volume = self.regs[0x14];
+        let left = left / 4.0 * (((volume >> 4) & 0x7) + 1) as f32 / 8.0;
+        let right = right / 4.0 * ((volume & 0x7) + 1) as f32 / 8.0;
+
+        let left = self.high_pass(left, true);
+        let right = self.high_pass(right, false);
+
+        self.samples.push(left);
+        self.samples.push(right);
+    }
+
+    /// Remove DC offset, like the capacitors on the real hardware.
+    fn high_pass(&mut self, input: f32, left: bool) -> f32 {
+        // Charge factor of the capacitor per sample
+        const CHARGE: f32 = 0.996;
+
+        let capacitor = if left { &mut self.capacitor.0 } else { &mut self.capacitor.1 };
+        let output = input - *capacitor;
+        *capacitor = input - output * CHARGE;
+        output
+    }
+
+    /// NR52 power off clears all registers
+    fn power_off(&mut self) {
+        let ram = self.wave.ram;
+
+        self.regs = [0; 23];
+        self.square1 = Square::new(true);
+        self.square2 = Square::new(false);
+        self.wave = Wave { ram, ..Default::default() };
+        self.noise = Noise::default();
+    }
+
+    fn channel_status(&self) -> u8 {
+        self.square1.enabled as u8
+            | (self.square2.enabled as u8) << 1
+            | (self.wave.enabled as u8) << 2
+            | (self.noise.enabled as u8) << 3
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryRead<u16, u8> for Apu {
+    #[inline]
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => (self.enabled as u8) << 7 | READ_MASKS[0x16] | self.channel_status(),
+            Self::BASE_ADDR..=Self::LAST_ADDR => {
+                let idx = (addr - Self::BASE_ADDR) as usize;
+                self.regs[idx] | READ_MASKS[idx]
+            }
+            Self::WAVE_RAM_BASE_ADDR..=Self::WAVE_RAM_LAST_ADDR => {
+                let idx = (addr - Self::WAVE_RAM_BASE_ADDR) as usize;
+                self.wave.ram[idx]
+            }
+            _ => unreachable!("Unexpected read from: {}", addr),
+        }
+    }
+}
+
+impl MemoryWrite<u16, u8> for Apu {
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        if let Self::WAVE_RAM_BASE_ADDR..=Self::WAVE_RAM_LAST_ADDR = addr {
+            let idx = (addr - Self::WAVE_RAM_BASE_ADDR) as usize;
+            self.wave.ram[idx] = value;
+            return;
+        }
+
+        if addr == 0xFF26 {
+            let enabled = value & 1 << 7 != 0;
+            if self.enabled && !enabled {
+                self.power_off();
+            } else if !self.enabled && enabled {
+                self.sequencer_step = 0;
+            }
+            self.enabled = enabled;
+            return;
+        }
+
+        // All other registers are read-only while powered off
+        if !self.enabled {
+            return;
+        }
+
+        let idx = (addr - Self::BASE_ADDR) as usize;
+        self.regs[idx] = value;
+
+        match addr {
+            // Channel 1
+            0xFF10 => {
+                if let Some(sweep) = &mut self.square1.sweep {
+                    sweep.reg = value;
+                }
+            }
+            0xFF11 => {
+                self.square1.duty = value >> 6;
+                self.square1.length.counter = 64 - (value & 0x3F) as u16;
+            }
+            0xFF12 => {
+                self.square1.envelope.reg = value;
+                if !self.square1.envelope.dac_enabled() {
+                    self.square1.enabled = false;
+                }
+            }
+            0xFF13 => {
+                self.square1.frequency = (self.square1.frequency & 0x700) | value as u16;
+            }
+            0xFF14 => {
+                self.square1.frequency = (self.square1.frequency & 0xFF) | ((value & 0x7) as u16) << 8;
+                self.square1.length.enabled = value & 1 << 6 != 0;
+                if value & 1 << 7 != 0 {
+                    self.square1.trigger();
+                }
+            }
+
+            // Channel 2
+            0xFF16 => {
+                self.square2.duty = value >> 6;
+                self.square2.length.counter = 64 - (value & 0x3F) as u16;
+            }
+            0xFF17 => {
+                self.square2.envelope.reg = value;
+                if !self.square2.envelope.dac_enabled() {
+                    self.square2.enabled = false;
+                }
+            }
+            0xFF18 => {
+                self.square2.frequency = (self.square2.frequency & 0x700) | value as u16;
+            }
+            0xFF19 => {
+                self.square2.frequency = (self.square2.frequency & 0xFF) | ((value & 0x7) as u16) << 8;
+                self.square2.length.enabled = value & 1 << 6 != 0;
+                if value & 1 << 7 != 0 {
+                    self.square2.trigger();
+                }
+            }
+
+            // Channel 3
+            0xFF1A => {
+                self.wave.dac_enabled = value & 1 << 7 != 0;
+                if !self.wave.dac_enabled {
+                    self.wave.enabled = false;
+                }
+            }
+            0xFF1B => {
+                self.wave.length.counter = 256 - value as u16;
+            }
+            0xFF1C => {
+                self.wave.volume = (value >> 5) & 0x3;
+            }
+            0xFF1D => {
+                self.wave.frequency = (self.wave.frequency & 0x700) | value as u16;
+            }
+            0xFF1E => {
+                self.wave.frequency = (self.wave.frequency & 0xFF) | ((value & 0x7) as u16) << 8;
+                self.wave.length.enabled = value & 1 << 6 != 0;
+                if value & 1 << 7 != 0 {
+                    self.wave.trigger();
+                }
+            }
+
+            // Channel 4
+            0xFF20 => {
+                self.noise.length.counter = 64 - (value & 0x3F) as u16;
+            }
+            0xFF21 => {
+                self.noise.envelope.reg = value;
+                if !self.noise.envelope.dac_enabled() {
+                    self.noise.enabled = false;
+                }
+            }
+            0xFF22 => {
+                self.noise.reg = value;
+            }
+            0xFF23 => {
+                self.noise.length.enabled = value & 1 << 6 != 0;
+                if value & 1 << 7 != 0 {
+                    self.noise.trigger();
+                }
+            }
+
+            // NR50, NR51 and unused registers are only stored
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Cycles per frame sequencer length clock (256 Hz)
+    const LENGTH_PERIOD: u16 = (FRAME_SEQUENCER_PERIOD * 2) as u16;
+
+    fn run(apu: &mut Apu, mut cycles: u32) {
+        while cycles > 0 {
+            let n = cycles.min(u16::MAX as u32);
+            apu.step(n as u16);
+            cycles -= n;
+        }
+    }
+
+    fn powered_on() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xFF);
+        apu
+    }
+
+    #[test]
+    fn produces_samples_at_sample_rate() {
+        let mut apu = powered_on();
+        run(&mut apu, CLOCK_RATE);
+
+        let samples = apu.drain_samples().count();
+        assert_eq!(samples, SAMPLE_RATE as usize * 2);
+        assert_eq!(apu.drain_samples().count(), 0);
+    }
+
+    #[test]
+    fn square_channel_plays_until_length_expires() {
+        let mut apu = powered_on();
+
+        // 50% duty, length of 2, max volume, ~1 kHz
+        apu.write(0xFF16, 0b1000_0000 | (64 - 2));
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF18, 0x83);
+        apu.write(0xFF19, 0b1100_0111);
+        assert_eq!(apu.read(0xFF26) & 0x0F, 0b0010);
+
+        run(&mut apu, CLOCK_RATE / 100);
+        let samples: Vec<f32> = apu.drain_samples().collect();
+        let peak = samples.iter().cloned().fold(0.0f32, |a, b| a.max(b.abs()));
+        assert!(peak > 0.1, "peak = {}", peak);
+
+        // Two length clocks later, the channel is done
+        run(&mut apu, LENGTH_PERIOD as u32 * 2);
+        assert_eq!(apu.read(0xFF26) & 0x0F, 0);
+    }
+
+    #[test]
+    fn registers_read_back_with_unused_bits_set() {
+        let mut apu = powered_on();
+        apu.write(0xFF11, 0b1000_0001);
+        assert_eq!(apu.read(0xFF11), 0b1011_1111);
+        assert_eq!(apu.read(0xFF15), 0xFF);
+
+        // Powering off clears everything but wave RAM
+        apu.write(0xFF30, 0x12);
+        apu.write(0xFF26, 0);
+        assert_eq!(apu.read(0xFF11), 0x3F);
+        assert_eq!(apu.read(0xFF26), 0x70);
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        // Writes are ignored while off
+        apu.write(0xFF24, 0x77);
+        assert_eq!(apu.read(0xFF24), 0);
+    }
+}
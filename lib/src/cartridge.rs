@@ -295,6 +295,52 @@ impl Rom {
         self.data = data;
     }
 
+    /// Take the data out of this ROM, leaving it empty
+    ///
+    /// This is used by the save state feature.
+    #[cfg(feature = "save")]
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
+    /// Raw cartridge header (0x100-0x14F)
+    pub(crate) fn header(&self) -> &[u8] {
+        &self.data[0x100..0x150]
+    }
+
+    /// Patch `addr` in every bank that can be mapped there, where the byte
+    /// is `compare` (or any byte, without one). Returns the offsets patched
+    /// along with the bytes they held.
+    pub(crate) fn patch(&mut self, addr: u16, value: u8, compare: Option<u8>) -> Vec<(usize, u8)> {
+        let offsets: Vec<usize> = match addr {
+            0x0000..=0x3FFF => vec![addr as usize],
+            0x4000..=Self::LAST_ADDR => (1..self.num_banks as usize)
+                .map(|bank| bank * Self::BANK_SIZE + (addr as usize - 0x4000))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut patched = Vec::new();
+        for offset in offsets {
+            let Some(byte) = self.data.get_mut(offset) else { continue };
+            if compare.is_none_or(|compare| *byte == compare) {
+                patched.push((offset, *byte));
+                *byte = value;
+            }
+        }
+
+        patched
+    }
+
+    /// Put back the bytes returned by [`patch`](Self::patch).
+    pub(crate) fn unpatch(&mut self, patched: &[(usize, u8)]) {
+        for &(offset, byte) in patched {
+            if let Some(data) = self.data.get_mut(offset) {
+                *data = byte;
+            }
+        }
+    }
+
     pub fn update_bank_0(&mut self, bank: u16) {
         assert!(bank < self.num_banks);
         self.active_bank_0 = bank;
@@ -351,19 +397,64 @@ impl MemoryWrite<u16, u8> for Rom {
     }
 }
 
+#[derive(Clone)]
 pub struct BootRom {
-    data: &'static [u8; 256],
+    data: Box<[u8]>,
 }
 
 impl BootRom {
     pub const BASE_ADDR: u16 = 0x0000;
     pub const LAST_ADDR: u16 = 0x00FF;
 
+    /// CGB boot ROMs are also mapped here, leaving the cartridge header visible
+    pub const CGB_BASE_ADDR: u16 = 0x0200;
+    pub const CGB_LAST_ADDR: u16 = 0x08FF;
+
+    pub const DMG_SIZE: usize = 0x100;
+    pub const CGB_SIZE: usize = 0x900;
+
+    /// Built-in DMG boot ROM
     pub fn new() -> Self {
         Self {
-            data: include_bytes!("dmg_boot.bin"),
+            data: Box::new(*include_bytes!("dmg_boot.bin")),
+        }
+    }
+
+    /// Load a DMG or CGB boot ROM image.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        match data.len() {
+            Self::DMG_SIZE | Self::CGB_SIZE => Ok(Self {
+                data: data.into_boxed_slice(),
+            }),
+            len => Err(Error::InvalidValue(format!(
+                "Invalid boot ROM size: {} bytes (expected {} for DMG or {} for CGB)",
+                len,
+                Self::DMG_SIZE,
+                Self::CGB_SIZE
+            ))),
         }
     }
+
+    /// Returns `true` if this is a CGB boot ROM
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == Self::CGB_SIZE
+    }
+
+    /// Returns `true` if this boot ROM is mapped at `addr`
+    #[inline]
+    pub fn contains(&self, addr: u16) -> bool {
+        match addr {
+            Self::BASE_ADDR..=Self::LAST_ADDR => true,
+            Self::CGB_BASE_ADDR..=Self::CGB_LAST_ADDR => self.is_cgb(),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for BootRom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "BootRom({} bytes)", self.data.len())
+    }
 }
 
 impl MemoryRead<u16, u8> for BootRom {
@@ -437,13 +528,15 @@ impl Controller {
 
     /// Create a controller from a `Cartridge`
     pub fn from_cartridge(cartridge: Cartridge) -> Result<Self> {
+        cartridge.check()?;
+
         // Extract ROM and RAM info from cartridge header
         let cartridge_type = cartridge.cartridge_type()?;
         let rom_size = cartridge.rom_size()?;
         let ram_size = cartridge.ram_size()?;
         let rom = Rom::from_bytes(&cartridge.data, rom_size);
         let boot_rom = if cartridge.boot_rom {
-            BootRom::new().into()
+            cartridge.boot_rom_image.unwrap_or_else(BootRom::new).into()
         } else {
             None
         };
@@ -494,11 +587,30 @@ impl Controller {
         self.rom.load(data);
     }
 
+    /// Take the ROM data out of this controller
+    ///
+    /// This is used by the save state feature.
+    #[cfg(feature = "save")]
+    pub(crate) fn take_rom(&mut self) -> Vec<u8> {
+        self.rom.take()
+    }
+
     /// Reset this controller
     ///
-    /// ROM remains unchanged, while the RAM is reset
+    /// ROM and RAM remain unchanged, as pulling the power does not erase
+    /// battery-backed RAM, while banking goes back to its power-on state
     pub(crate) fn reset(&mut self) {
-        self.ram = Ram::new(self.ram_size);
+        self.rom.active_bank_0 = 0;
+        self.rom.active_bank_1 = 1;
+
+        if let Some(ram) = &mut self.ram {
+            ram.active_bank = 0;
+        }
+
+        self.rtc_active = false;
+        self.banking_mode = false;
+        self.ram_enable = false;
+        self.ram_rom_bank = 0;
     }
 }
 
@@ -711,6 +823,43 @@ pub enum CartridgeType {
 }
 
 impl CartridgeType {
+    /// Every type there is, in header order
+    pub const ALL: [CartridgeType; 29] = {
+        use CartridgeType::*;
+        [
+            Rom, Mbc1, Mbc1Ram, Mbc1RamBattery, Mbc2, Mbc2Battery, RomRam, RomRamBattery, Mmm01, Mmm01Ram,
+            Mmm01RamBattery, Mbc3TimerBattery, Mbc3TimerRamBattery, Mbc3, Mbc3Ram, Mbc3RamBattery, Mbc4, Mbc4Ram,
+            Mbc4RamBattery, Mbc5, Mbc5Ram, Mbc5RamBattery, Mbc5Rumble, Mbc5RumbleRam, Mbc5RumbleRamBattery,
+            PocketCamera, BandaiTama5, HuC3, HuC1RamBattery,
+        ]
+    };
+
+    /// Memory bank controllers the emulator implements, as named by [`CartridgeType::mbc`]
+    pub const SUPPORTED_MBCS: [&'static str; 5] = ["None", "MBC1", "MBC2", "MBC3", "MBC5"];
+
+    /// The memory bank controller on the cartridge, or "None"
+    pub fn mbc(&self) -> &'static str {
+        use CartridgeType::*;
+        match self {
+            Rom | RomRam | RomRamBattery => "None",
+            Mbc1 | Mbc1Ram | Mbc1RamBattery => "MBC1",
+            Mbc2 | Mbc2Battery => "MBC2",
+            Mmm01 | Mmm01Ram | Mmm01RamBattery => "MMM01",
+            Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3 | Mbc3Ram | Mbc3RamBattery => "MBC3",
+            Mbc4 | Mbc4Ram | Mbc4RamBattery => "MBC4",
+            Mbc5 | Mbc5Ram | Mbc5RamBattery | Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery => "MBC5",
+            PocketCamera => "Pocket Camera",
+            BandaiTama5 => "Bandai TAMA5",
+            HuC3 => "HuC3",
+            HuC1RamBattery => "HuC1",
+        }
+    }
+
+    /// Returns `true` if the emulator can run games with this type
+    pub fn is_supported(&self) -> bool {
+        Self::SUPPORTED_MBCS.contains(&self.mbc())
+    }
+
     pub fn is_none(&self) -> bool {
         use CartridgeType::*;
         match self {
@@ -831,14 +980,87 @@ pub struct Cartridge {
     /// If `true`, boot ROM is executed on boot/reset,
     /// prior to loading the game
     pub(crate) boot_rom: bool,
+
+    /// Boot ROM to run instead of the built-in DMG one
+    pub(crate) boot_rom_image: Option<BootRom>,
+
+    /// Hardware to emulate instead of the one the header asks for: `true`
+    /// for CGB, `false` for DMG
+    pub(crate) force_cgb: Option<bool>,
 }
 
 impl Cartridge {
+    /// End of the header, past the global checksum
+    const HEADER_END: usize = 0x150;
+
+    /// The logo every licensed cartridge has, which the boot ROM checks
+    pub const NINTENDO_LOGO: [u8; 48] = [
+        0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+        0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+        0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+    ];
+
     pub fn from_bytes(data: Vec<u8>, boot_rom: bool) -> Self {
         Self {
             data,
             boot_rom,
+            boot_rom_image: None,
+            force_cgb: None,
+        }
+    }
+
+    /// Run the given boot ROM on boot/reset, prior to loading the game.
+    pub fn with_boot_rom(mut self, boot_rom: BootRom) -> Self {
+        self.boot_rom = true;
+        self.boot_rom_image = Some(boot_rom);
+        self
+    }
+
+    /// Run on CGB (`true`) or DMG (`false`) hardware, whatever the header
+    /// says. [`Cartridge::check`] refuses to run CGB-only games on a DMG.
+    pub fn with_cgb(mut self, cgb: bool) -> Self {
+        self.force_cgb = Some(cgb);
+        self
+    }
+
+    /// Pad the ROM with 0xFF, or cut it short, to the size its header says,
+    /// e.g. for overdumps and homebrew that get it wrong. Without a header
+    /// to go by, it is left alone.
+    pub fn with_header_size(mut self) -> Self {
+        if let (true, Ok(size)) = (self.data.len() >= Self::HEADER_END, self.rom_size()) {
+            self.data.resize(usize::from(size), 0xFF);
         }
+        self
+    }
+
+    /// Checks that this cartridge can be loaded: that it has a header the
+    /// emulator understands, and as much ROM as the header says.
+    ///
+    /// Unlike [`Cartridge::validate`], the title and codes are not looked at.
+    pub fn check(&self) -> Result<()> {
+        if self.data.len() < Self::HEADER_END {
+            return Err(CartridgeError::Header(self.data.len()).into());
+        }
+
+        match self.cartridge_type() {
+            Ok(cartridge_type) if !cartridge_type.is_supported() => {
+                return Err(CartridgeError::Unsupported(cartridge_type).into());
+            }
+            Ok(_) => (),
+            Err(_) => return Err(CartridgeError::UnknownType(self.cartridge_type_byte()).into()),
+        }
+        self.ram_size()?;
+
+        if self.cgb_only() && !self.cgb() {
+            return Err(CartridgeError::CgbOnly.into());
+        }
+
+        let size = usize::from(self.rom_size()?);
+        if size != self.data.len() {
+            return Err(CartridgeError::Size(self.data.len(), size).into());
+        }
+
+        Ok(())
     }
 
     /// Tries to figure out if this is a valid cartridge.
@@ -875,6 +1097,11 @@ impl Cartridge {
         &self.data[0x104..=0x133]
     }
 
+    /// Returns `true` if the header has the Nintendo logo
+    pub fn verify_logo(&self) -> bool {
+        self.logo() == Self::NINTENDO_LOGO
+    }
+
     /// Game title (uppercase ASCII)
     pub fn title(&self) -> Result<&str> {
         let raw = &self.data[0x134..0x143];
@@ -889,7 +1116,13 @@ impl Cartridge {
     /// CGB flag
     /// `false`: supports old functions
     /// `true`: CGB only
+    ///
+    /// Or the hardware picked with [`Cartridge::with_cgb`].
     pub fn cgb(&self) -> bool {
+        if let Some(cgb) = self.force_cgb {
+            return cgb;
+        }
+
         let cgb = self.data[0x143];
         match cgb {
             0x80 | 0xC0 => true,
@@ -897,6 +1130,11 @@ impl Cartridge {
         }
     }
 
+    /// Returns `true` if the game does not run on a DMG at all
+    pub fn cgb_only(&self) -> bool {
+        self.data[0x143] == 0xC0
+    }
+
     pub fn licensee_code(&self) -> Result<&str> {
         let raw = &self.data[0x144..=0x145];
         let code: &str = std::str::from_utf8(raw)?;
@@ -922,7 +1160,13 @@ impl Cartridge {
 
     /// Cartridge type
     pub fn cartridge_type(&self) -> Result<CartridgeType> {
-        CartridgeType::try_from(self.data[0x147])
+        CartridgeType::try_from(self.cartridge_type_byte())
+    }
+
+    /// Cartridge type, as is, for types that [`Cartridge::cartridge_type`]
+    /// does not know
+    pub fn cartridge_type_byte(&self) -> u8 {
+        self.data[0x147]
     }
 
     /// ROM size
@@ -1001,5 +1245,63 @@ mod test {
         assert_eq!(cartridge.cgb(), true);
         assert_eq!(cartridge.licensee_code().unwrap(), "Nintendo R&D 1");
         assert!(cartridge.verify_header_checksum());
+        assert!(cartridge.verify_logo());
+    }
+
+    #[test]
+    fn broken_cartridges_are_rejected() {
+        let tetris = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/tetris_world.gb")).unwrap();
+        assert!(Cartridge::from_bytes(tetris.clone(), false).check().is_ok());
+
+        let error = |data: Vec<u8>| Controller::from_cartridge(Cartridge::from_bytes(data, false)).err().unwrap().to_string();
+        assert_eq!(error(vec![0; 0x100]), "Cartridge error: Too short for a header (256 bytes)");
+        assert_eq!(error(tetris[..0x4000].to_vec()), "Cartridge error: ROM is 16384 bytes, but its header says 32768");
+        assert!(Cartridge::from_bytes(tetris[..0x4000].to_vec(), false).with_header_size().check().is_ok());
+        assert!(Cartridge::from_bytes([&tetris[..], &[0; 100]].concat(), false).with_header_size().check().is_ok());
+
+        let mut camera = tetris.clone();
+        camera[0x147] = 0xFC;
+        assert_eq!(error(camera), "Cartridge error: Cartridge type 0xFC (PocketCamera) uses the Pocket Camera memory \
+                                   bank controller, which is not supported. Supported memory bank controllers: None, MBC1, MBC2, MBC3, MBC5");
+
+        let mut unknown = tetris.clone();
+        unknown[0x147] = 0xEE;
+        assert!(error(unknown).starts_with("Cartridge error: Unknown cartridge type 0xEE. Supported"));
+
+        let mut cgb_only = tetris.clone();
+        cgb_only[0x143] = 0xC0;
+        assert!(Cartridge::from_bytes(cgb_only.clone(), false).with_cgb(true).check().is_ok());
+        assert_eq!(Controller::from_cartridge(Cartridge::from_bytes(cgb_only, false).with_cgb(false))
+                       .err().unwrap().to_string(),
+                   "Cartridge error: This game only runs on a Game Boy Color");
+
+        let mut bad_size = tetris;
+        bad_size[0x148] = 0xFF;
+        assert!(error(bad_size).starts_with("Invalid value: "));
+    }
+
+    #[test]
+    fn every_cartridge_type_is_listed() {
+        let bytes: Vec<u8> = (0..=0xFF).filter(|&byte| CartridgeType::try_from(byte).is_ok()).collect();
+        assert_eq!(CartridgeType::ALL.iter().map(|&t| t as u8).collect::<Vec<_>>(), bytes);
+
+        assert!(CartridgeType::Mbc3TimerRamBattery.is_supported());
+        assert!(!CartridgeType::HuC1RamBattery.is_supported());
+    }
+
+    #[test]
+    fn boot_rom_sizes() {
+        let dmg = BootRom::from_bytes(vec![0; BootRom::DMG_SIZE]).unwrap();
+        assert!(!dmg.is_cgb());
+        assert!(dmg.contains(0x00FF));
+        assert!(!dmg.contains(0x0200));
+
+        // The cartridge header stays visible while a CGB boot ROM is mapped
+        let cgb = BootRom::from_bytes(vec![0; BootRom::CGB_SIZE]).unwrap();
+        assert!(cgb.is_cgb());
+        assert!(!cgb.contains(0x0100));
+        assert!(cgb.contains(0x08FF));
+
+        assert!(BootRom::from_bytes(vec![0; 512]).is_err());
     }
 }
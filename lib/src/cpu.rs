@@ -157,8 +157,13 @@ impl Cpu {
     ///
     /// This involves resetting memory, the ROM controller, and the PPU
     pub fn reset(&mut self) {
-        self.registers = RegisterFile::new(self.cgb);
         self.memory.reset();
+        // The boot ROM sets up registers itself
+        self.registers = if self.memory.has_boot_rom() {
+            RegisterFile::empty()
+        } else {
+            RegisterFile::new(self.cgb)
+        };
         self.dma = DmaController::new(self.cgb);
         self.ime = false;
         self.halted = false;
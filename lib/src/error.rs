@@ -1,3 +1,5 @@
+use crate::cartridge::CartridgeType;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -8,6 +10,16 @@ pub enum CartridgeError {
     Type,
     RomSize,
     RamSize,
+    /// Too short to hold a header
+    Header(usize),
+    /// Bytes in the ROM, and bytes the header says it has
+    Size(usize, usize),
+    /// A type byte that no cartridge has
+    UnknownType(u8),
+    /// A memory bank controller the emulator does not implement
+    Unsupported(CartridgeType),
+    /// A CGB-only game, forced to run on a DMG
+    CgbOnly,
 }
 
 impl std::fmt::Display for CartridgeError {
@@ -19,10 +31,26 @@ impl std::fmt::Display for CartridgeError {
             Self::Type => write!(f, "Invalid type"),
             Self::RomSize => write!(f, "Invalid ROM size"),
             Self::RamSize => write!(f, "Invalid RAM size"),
+            Self::Header(len) => write!(f, "Too short for a header ({} bytes)", len),
+            Self::Size(len, size) => write!(f, "ROM is {} bytes, but its header says {}", len, size),
+            Self::UnknownType(byte) => write!(f, "Unknown cartridge type {:#04X}. {}", byte, Supported),
+            Self::Unsupported(cartridge_type) => write!(f,
+                "Cartridge type {:#04X} ({:?}) uses the {} memory bank controller, which is not supported. {}",
+                *cartridge_type as u8, cartridge_type, cartridge_type.mbc(), Supported),
+            Self::CgbOnly => write!(f, "This game only runs on a Game Boy Color"),
         }
     }
 }
 
+/// The memory bank controllers that can be used instead, for errors
+struct Supported;
+
+impl std::fmt::Display for Supported {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Supported memory bank controllers: {}", CartridgeType::SUPPORTED_MBCS.join(", "))
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     IoError(String),
@@ -30,6 +58,7 @@ pub enum Error {
     InvalidValue(String),
     BincodeError(String),
     CartridgeError(CartridgeError),
+    StateMismatch(String),
 }
 
 impl std::error::Error for Error {}
@@ -42,6 +71,7 @@ impl std::fmt::Display for Error {
             Self::InvalidValue(msg) => write!(f, "Invalid value: {}", msg),
             Self::BincodeError(msg) => write!(f, "Bincode error: {}", msg),
             Self::CartridgeError(err) => write!(f, "Cartridge error: {}", err),
+            Self::StateMismatch(msg) => write!(f, "Save state mismatch: {}", msg),
         }
     }
 }
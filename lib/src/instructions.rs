@@ -965,6 +965,66 @@ impl Instruction {
     }
 }
 
+/// Opcodes that do not decode to any instruction (see `Instruction::decode`)
+const INVALID_OPCODES: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+/// A single disassembled instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Disassembly {
+    pub addr: u16,
+
+    /// Raw instruction bytes, including any operands
+    pub bytes: Vec<u8>,
+
+    /// Instruction mnemonic and operands
+    pub text: String,
+}
+
+/// Disassemble up to `count` instructions from `data`, which is mapped at
+/// `addr`.
+///
+/// Invalid opcodes and instructions cut off by the end of `data` are shown as
+/// raw `db` bytes instead.
+pub fn disassemble(data: &[u8], addr: u16, count: usize) -> Vec<Disassembly> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+
+    while result.len() < count && offset < data.len() {
+        let inst_addr = addr.wrapping_add(offset as u16);
+
+        let mut raw = [0u8; 3];
+        for (i, b) in data[offset..].iter().take(3).enumerate() {
+            raw[i] = *b;
+        }
+
+        let decoded = if INVALID_OPCODES.contains(&raw[0]) {
+            None
+        } else {
+            let (inst, size, _) = Instruction::decode(raw);
+            Some((inst, size as usize)).filter(|(_, size)| offset + size <= data.len())
+        };
+
+        let (text, size) = match decoded {
+            Some((inst @ Instruction::Jr { offset: jump, .. }, size)) => {
+                // Also show where relative jumps end up
+                let target = inst_addr.wrapping_add(size as u16).wrapping_add(jump as u16);
+                (format!("{} ; {:#06X}", inst, target), size)
+            }
+            Some((inst, size)) => (inst.to_string(), size),
+            None => (format!("db {:#04X}", raw[0]), 1),
+        };
+
+        result.push(Disassembly {
+            addr: inst_addr,
+            bytes: data[offset..offset + size].to_vec(),
+            text,
+        });
+        offset += size;
+    }
+
+    result
+}
+
 /// Prettier display for all GBC instructions
 impl std::fmt::Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -1135,4 +1195,21 @@ mod test {
             assert_eq!(expected_cycles, &cycles);
         }
     }
+
+    #[test]
+    fn disassemble_bytes() {
+        // ld BC, 0x1234; jr -2; cb bit 7, H; invalid; cut-off call
+        let data = [0x01, 0x34, 0x12, 0x18, 0xFE, 0xCB, 0x7C, 0xD3, 0xCD, 0x00];
+        let result = disassemble(&data, 0x150, 10);
+
+        let text: Vec<&str> = result.iter().map(|d| d.text.as_str()).collect();
+        assert_eq!(text, ["ld BC, 0x1234", "jr -2 ; 0x0153", "bit H, 7", "db 0xD3", "db 0xCD", "nop"]);
+
+        assert_eq!(result[0].addr, 0x150);
+        assert_eq!(result[0].bytes, [0x01, 0x34, 0x12]);
+        assert_eq!(result[2].addr, 0x155);
+        assert_eq!(result[2].bytes, [0xCB, 0x7C]);
+
+        assert_eq!(disassemble(&data, 0, 2).len(), 2);
+    }
 }
@@ -1,4 +1,4 @@
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum JoypadInput {
     Up,
     Down,
@@ -35,7 +35,7 @@ impl JoypadInput {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum JoypadEvent {
     Up(JoypadInput),
     Down(JoypadInput),
@@ -1,3 +1,4 @@
+pub mod apu;
 pub mod cartridge;
 mod cpu;
 mod dma;
@@ -17,8 +18,11 @@ pub use cpu::Cpu;
 use cpu::Interrupt;
 use cartridge::{Cartridge, Controller};
 pub use error::{Error, Result};
+pub use instructions::{disassemble, Disassembly};
 use joypad::JoypadEvent;
-use ppu::FrameBuffer;
+use memory::{MemoryRead, MemoryWrite};
+use ppu::{FrameBuffer, Ppu};
+use registers::{Reg16, RegisterFile, RegisterOps};
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct GameboyState<'a> {
@@ -26,21 +30,125 @@ pub struct GameboyState<'a> {
     pub rtc: Option<Vec<u8>>,
 }
 
+/// Identifies the cartridge that a save state was created from.
+#[cfg(feature = "save")]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct CartridgeId {
+    title: [u8; 16],
+    header_checksum: u8,
+    global_checksum: u16,
+}
+
+#[cfg(feature = "save")]
+impl CartridgeId {
+    fn from_header(header: &[u8]) -> Self {
+        let mut title = [0u8; 16];
+        title.copy_from_slice(&header[0x34..0x44]);
+
+        Self {
+            title,
+            header_checksum: header[0x4D],
+            global_checksum: (header[0x4E] as u16) << 8 | header[0x4F] as u16,
+        }
+    }
+}
+
+#[cfg(feature = "save")]
+impl std::fmt::Display for CartridgeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // The last byte doubles as the CGB flag on newer cartridges
+        let title: String = self.title.iter()
+            .take_while(|&&b| b.is_ascii_graphic() || b == b' ')
+            .map(|&b| b as char)
+            .collect();
+        write!(f, "{} ({:#06X})", title, self.global_checksum)
+    }
+}
+
+/// A copy of the CPU registers and LCD status, taken between instructions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuState {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub zero: bool,
+    pub subtract: bool,
+    pub half_carry: bool,
+    pub carry: bool,
+    pub halted: bool,
+    /// Current scanline (LY)
+    pub ly: u8,
+    /// LCD status register (STAT)
+    pub stat: u8,
+}
+
+impl std::fmt::Display for CpuState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+
+        write!(f, "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} [{}{}{}{}]{} LY={} STAT={:02X} (mode {})",
+               self.af, self.bc, self.de, self.hl, self.sp, self.pc,
+               flag(self.zero, 'Z'), flag(self.subtract, 'N'), flag(self.half_carry, 'H'), flag(self.carry, 'C'),
+               if self.halted { " halted" } else { "" },
+               self.ly, self.stat, self.stat & 0b11)
+    }
+}
+
+/// The CPU's clock, as a percentage of the normal one. The rest of the
+/// system runs at its normal pace either way.
+#[derive(Clone, Copy, Debug)]
+struct CpuClock {
+    percent: u32,
+    /// Scaled cycles short of a whole one, in hundredths of a percent
+    remainder: u32,
+}
+
+impl Default for CpuClock {
+    fn default() -> Self {
+        Self { percent: 100, remainder: 0 }
+    }
+}
+
+impl CpuClock {
+    /// How far the rest of the system gets while the CPU takes `cycles`.
+    fn scale(&mut self, cycles: u16) -> u16 {
+        if self.percent == 100 {
+            return cycles;
+        }
+
+        let total = self.remainder + u32::from(cycles) * 100;
+        self.remainder = total % self.percent;
+        (total / self.percent) as u16
+    }
+}
+
 #[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
 /// Gameboy
 pub struct Gameboy {
     cpu: Cpu,
 
+    /// Whether the cartridge clock runs on emulated time
+    #[cfg_attr(feature = "save", serde(skip))]
+    emulated_clock: bool,
+
+    /// How fast the CPU runs next to the rest of the system
+    #[cfg_attr(feature = "save", serde(skip))]
+    cpu_clock: CpuClock,
+
     #[cfg(feature = "debug")]
     #[cfg_attr(feature = "save", serde(skip))]
     debugger: debug::Debugger,
 }
 
 impl Gameboy {
-    const FRAME_FREQUENCY: f64 = 59.7; // Hz
+    /// A frame is 154 lines of 456 dots, at 4 MiHz: about 59.73 Hz
+    pub const FRAME_FREQUENCY: f64 = 4194304.0 / (154.0 * 456.0); // Hz
 
-    /// Frame duration, in ns
-    pub const FRAME_DURATION: u64 = ((1f64 / Self::FRAME_FREQUENCY) * 1e9) as u64;
+    /// Frame duration, in ns, to the nearest
+    pub const FRAME_DURATION: u64 = (1e9 / Self::FRAME_FREQUENCY + 0.5) as u64;
 
     /// Initialize the emulator from a `Cartridge`.
     pub fn init(cartridge: Cartridge, trace: bool) -> Result<Self> {
@@ -49,12 +157,16 @@ impl Gameboy {
         #[cfg(feature = "debug")]
         let gameboy = Self {
             cpu,
+            emulated_clock: false,
+            cpu_clock: CpuClock::default(),
             debugger: debug::Debugger::new(),
         };
 
         #[cfg(not(feature = "debug"))]
         let gameboy = Self {
             cpu,
+            emulated_clock: false,
+            cpu_clock: CpuClock::default(),
         };
 
         Ok(gameboy)
@@ -79,6 +191,9 @@ impl Gameboy {
 
         let mut interrupts = Vec::new();
 
+        // The rest of the system keeps its own pace, whatever the CPU's
+        let cycles = self.cpu_clock.scale(cycles_taken);
+
         // Update the memory bus
         //
         // Internally, this executes a step for each of:
@@ -87,7 +202,7 @@ impl Gameboy {
         // 2. Timer
         // 3. Serial
         // 4. RTC (if present)
-        self.cpu.memory.step(cycles_taken, speed, &mut interrupts);
+        self.cpu.memory.step(cycles, speed, &mut interrupts);
 
         // Trigger any pending interrupts
         for interrupt in interrupts {
@@ -116,6 +231,151 @@ impl Gameboy {
         self.cpu.memory.ppu_mut().frame_buffer().unwrap()
     }
 
+    /// Get the frame buffer without running the Gameboy.
+    ///
+    /// Right after [`frame`](Self::frame), or after restoring a state saved
+    /// then, this holds the whole last frame.
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        self.cpu.memory.ppu().current_frame()
+    }
+
+    /// Run the Gameboy until either a frame is ready, or the CPU is about to
+    /// execute an instruction at one of the `breakpoints`.
+    ///
+    /// At least one instruction is always executed, so that this can be
+    /// called again to continue past a breakpoint.
+    ///
+    /// Returns `true` if a frame is ready, which can then be fetched with
+    /// [`frame`](Self::frame).
+    pub fn run_until_break(&mut self, breakpoints: &[u16]) -> bool {
+        while !self.cpu.memory.ppu().is_frame_ready() {
+            self.step();
+
+            if !self.cpu.halted && breakpoints.contains(&self.cpu.registers.PC) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Run the Gameboy for at least `cycles` CPU cycles (456 are a line, at
+    /// normal speed), or until a frame is ready, to go through a frame a bit
+    /// at a time.
+    ///
+    /// Returns `true` if a frame is ready, which can then be fetched with
+    /// [`frame`](Self::frame). Until then, [`frame_buffer`](Self::frame_buffer)
+    /// has the lines drawn so far, over the last frame.
+    pub fn step_cycles(&mut self, cycles: u32) -> bool {
+        let mut taken = 0;
+        while taken < cycles && !self.cpu.memory.ppu().is_frame_ready() {
+            taken += self.step();
+        }
+
+        self.cpu.memory.ppu().is_frame_ready()
+    }
+
+    /// Whether a CGB game switched the CPU to double speed, as bit 7 of KEY1
+    /// says. Frames take as long either way, as the PPU keeps its pace.
+    pub fn double_speed(&self) -> bool {
+        self.cpu.speed
+    }
+
+    /// CPU registers
+    pub fn registers(&self) -> &RegisterFile {
+        &self.cpu.registers
+    }
+
+    /// Snapshot of the CPU registers and LCD status.
+    pub fn cpu_state(&self) -> CpuState {
+        let registers = &self.cpu.registers;
+
+        CpuState {
+            af: registers.read(Reg16::AF),
+            bc: registers.read(Reg16::BC),
+            de: registers.read(Reg16::DE),
+            hl: registers.read(Reg16::HL),
+            sp: registers.SP,
+            pc: registers.PC,
+            zero: registers.zero(),
+            subtract: registers.subtract(),
+            half_carry: registers.half_carry(),
+            carry: registers.carry(),
+            halted: self.cpu.halted,
+            ly: self.read_memory(0xFF44),
+            stat: self.read_memory(0xFF41),
+        }
+    }
+
+    /// The PPU, e.g. to look at tiles and palettes in VRAM.
+    pub fn ppu(&self) -> &Ppu {
+        self.cpu.memory.ppu()
+    }
+
+    /// Read a byte of memory, as seen by the CPU.
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.cpu.memory.read(addr)
+    }
+
+    /// Write a byte of memory, as the CPU would.
+    pub fn write_memory(&mut self, addr: u16, value: u8) {
+        self.cpu.memory.write(addr, value)
+    }
+
+    /// Patch the cartridge ROM at `addr`, as a Game Genie does.
+    ///
+    /// Every bank that can be mapped at `addr` is patched, where it holds
+    /// `compare` (or whatever it holds, without one). Addresses outside the
+    /// ROM are left alone. Returns the bytes that were replaced, which
+    /// [`unpatch_rom`] puts back.
+    ///
+    /// [`unpatch_rom`]: Self::unpatch_rom
+    pub fn patch_rom(&mut self, addr: u16, value: u8, compare: Option<u8>) -> Vec<(usize, u8)> {
+        self.controller().rom.patch(addr, value, compare)
+    }
+
+    /// Undo a [`patch_rom`](Self::patch_rom).
+    pub fn unpatch_rom(&mut self, patched: &[(usize, u8)]) {
+        self.controller().rom.unpatch(patched)
+    }
+
+    /// Connect or disconnect a link cable.
+    ///
+    /// While connected, serial transfers wait for the other side. The
+    /// frontend moves bytes between the two using [`link_outgoing`],
+    /// [`link_complete`] and [`link_receive`]. Disconnecting finishes any
+    /// transfer this side started, as if nothing was plugged in.
+    ///
+    /// [`link_outgoing`]: Self::link_outgoing
+    /// [`link_complete`]: Self::link_complete
+    /// [`link_receive`]: Self::link_receive
+    pub fn set_link_connected(&mut self, connected: bool) {
+        self.cpu.memory.io_mut().set_link_connected(connected);
+    }
+
+    /// The byte to send to the other side, when this side starts a transfer
+    /// on its internal clock. Returned once per transfer.
+    pub fn link_outgoing(&mut self) -> Option<u8> {
+        self.cpu.memory.io_mut().link_outgoing()
+    }
+
+    /// Finish a transfer started by this side with the byte the other side
+    /// sent back.
+    pub fn link_complete(&mut self, byte: u8) {
+        if self.cpu.memory.io_mut().link_finish(byte) {
+            self.cpu.trigger_interrupt(Interrupt::Serial);
+        }
+    }
+
+    /// Handle a transfer started by the other side. Returns the byte to send
+    /// back, or `None` if this side is not ready yet, in which case this
+    /// should be retried later.
+    pub fn link_receive(&mut self, byte: u8) -> Option<u8> {
+        let reply = self.cpu.memory.io_mut().link_receive(byte)?;
+        self.cpu.trigger_interrupt(Interrupt::Serial);
+        Some(reply)
+    }
+
     pub fn update_joypad(&mut self, joypad_events: Option<&[JoypadEvent]>) {
         if let Some(events) = joypad_events {
             for event in events {
@@ -151,7 +411,92 @@ impl Gameboy {
         Ok(data)
     }
 
-    /// Reset the emulator
+    /// Save a snapshot of this Gameboy that can be restored with `load_state`.
+    ///
+    /// Unlike `save`, the snapshot records which cartridge is inserted, so
+    /// that it cannot be restored on top of a different ROM.
+    #[cfg(feature = "save")]
+    pub fn save_state(&self) -> Result<Vec<u8>> {
+        let id = CartridgeId::from_header(self.cpu.memory.controller().rom.header());
+
+        let mut data = Vec::new();
+        bincode::serialize_into(&mut data, &(id, self))?;
+        Ok(data)
+    }
+
+    /// Restore a snapshot created by `save_state`.
+    ///
+    /// Returns an error, leaving this Gameboy untouched, if the snapshot was
+    /// taken with a different cartridge inserted.
+    #[cfg(feature = "save")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let (id, mut gameboy): (CartridgeId, Self) = bincode::deserialize_from(data)?;
+
+        let current = CartridgeId::from_header(self.cpu.memory.controller().rom.header());
+        if id != current {
+            return Err(Error::StateMismatch(format!("state is for {}, but {} is inserted", id, current)));
+        }
+
+        // The ROM is not part of the state, so carry it over, along with the
+        // boot ROM for resets
+        let rom = self.cpu.memory.controller_mut().take_rom();
+        gameboy.cpu.memory.controller_mut().load_rom(rom);
+        gameboy.cpu.memory.controller_mut().boot_rom = self.cpu.memory.controller_mut().boot_rom.take();
+
+        self.cpu = gameboy.cpu;
+        self.set_emulated_clock(self.emulated_clock);
+
+        Ok(())
+    }
+
+    /// Hash the full state of the emulator, i.e. everything in a save state:
+    /// CPU registers, memory, and PPU, APU and cartridge state.
+    ///
+    /// The hash is stable across runs and builds, so that it can be stored to
+    /// check later runs against.
+    #[cfg(feature = "save")]
+    pub fn state_hash(&self) -> u64 {
+        /// 64-bit FNV-1a
+        struct Fnv(u64);
+
+        impl std::io::Write for Fnv {
+            fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+                for &b in data {
+                    self.0 = (self.0 ^ b as u64).wrapping_mul(0x100_0000_01B3);
+                }
+                Ok(data.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut hasher = Fnv(0xCBF2_9CE4_8422_2325);
+        bincode::serialize_into(&mut hasher, self).unwrap();
+        hasher.0
+    }
+
+    /// Over- or underclock the CPU, to `percent` of its normal speed (at
+    /// least 1), without speeding up or slowing down anything else. Games
+    /// that count on their timing are bound to break.
+    pub fn set_cpu_clock(&mut self, percent: u32) {
+        self.cpu_clock = CpuClock { percent: percent.max(1), remainder: 0 };
+    }
+
+    /// Run the cartridge clock (MBC3 RTC) on emulated time instead of the
+    /// system clock, so that runs from the same state play out identically.
+    pub fn set_emulated_clock(&mut self, enabled: bool) {
+        self.emulated_clock = enabled;
+        if let Some(rtc) = &mut self.cpu.memory.controller_mut().rtc {
+            rtc.set_emulated_clock(enabled);
+        }
+    }
+
+    /// Reset the emulator, as if the power was cycled
+    ///
+    /// The boot ROM runs again, if there is one. Cartridge RAM and the clock
+    /// are left alone, as they are battery-backed.
     pub fn reset(&mut self) {
         // Reset the CPU
         self.cpu.reset();
@@ -241,6 +586,14 @@ impl Gameboy {
         Ok(())
     }
 
+    /// Remove all audio samples produced so far.
+    ///
+    /// Samples are interleaved stereo (left, right) at `apu::SAMPLE_RATE`. If
+    /// they are not consumed, only the most recent second or so is kept.
+    pub fn drain_audio_samples(&mut self) -> std::vec::Drain<'_, f32> {
+        self.cpu.memory.io_mut().apu().drain_samples()
+    }
+
     /// Returns a String containing the serial output of this Gameboy _so far_.
     ///
     /// In other words, this output is cumulative and contains every character
@@ -248,4 +601,251 @@ impl Gameboy {
     pub fn serial_output(&self) -> String {
         self.cpu.memory.io().serial_buffer().into_iter().collect()
     }
+
+    /// Remove the bytes the game sent over serial since the last call, e.g.
+    /// to print them as they come. Like audio samples, only the most recent
+    /// few thousand are kept if they are not taken.
+    pub fn drain_serial_output(&mut self) -> std::vec::Drain<'_, u8> {
+        self.cpu.memory.io_mut().drain_serial()
+    }
+}
+
+#[cfg(all(test, feature = "save"))]
+mod test {
+    use std::path::Path;
+
+    use crate::cartridge::{BootRom, Rom};
+
+    use super::*;
+
+    fn sample_cartridge(name: &str) -> Cartridge {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("samples")
+            .join(name);
+        let data = std::fs::read(path).unwrap();
+        Cartridge::from_bytes(data, false)
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+        for _ in 0..10 {
+            gameboy.frame(None);
+        }
+
+        let state = gameboy.save_state().unwrap();
+        let pc = gameboy.cpu().registers.PC;
+
+        for _ in 0..10 {
+            gameboy.frame(None);
+        }
+        assert_ne!(gameboy.cpu().registers.PC, pc);
+
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.cpu().registers.PC, pc);
+
+        // The ROM is still there
+        gameboy.frame(None);
+    }
+
+    #[test]
+    fn overclocked_cpus_run_more_per_frame() {
+        let mut clock = CpuClock { percent: 300, remainder: 0 };
+        let scaled: u16 = (0..3).map(|_| clock.scale(4)).sum();
+        assert_eq!(scaled, 4);
+
+        let cycles_per_frame = |percent| {
+            let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+            gameboy.set_cpu_clock(percent);
+            for _ in 0..10 {
+                gameboy.frame(None);
+            }
+            let mut cycles = 0;
+            while !gameboy.ppu().is_frame_ready() {
+                cycles += gameboy.step();
+            }
+            cycles
+        };
+        let normal = cycles_per_frame(100);
+        assert!((70200..=70224).contains(&normal), "{} cycles", normal);
+        assert!((2 * normal - 8..=2 * normal + 8).contains(&cycles_per_frame(200)));
+        assert!((normal / 2 - 8..=normal / 2 + 8).contains(&cycles_per_frame(50)));
+    }
+
+    #[test]
+    fn double_speed_keeps_frames_and_sound_in_time() {
+        // Switches to double speed as it boots
+        let mut gameboy = Gameboy::init(sample_cartridge("smb_deluxe.gbc"), false).unwrap();
+        for _ in 0..60 {
+            gameboy.frame(None);
+        }
+        assert!(gameboy.double_speed());
+        gameboy.drain_audio_samples();
+
+        let mut cycles = 0;
+        while !gameboy.ppu().is_frame_ready() {
+            cycles += gameboy.step();
+        }
+        gameboy.frame(None);
+        assert!((70200..=70240).contains(&cycles), "{} cycles", cycles);
+
+        // Stereo samples for a frame's time
+        let samples = gameboy.drain_audio_samples().count() as f64;
+        let expected = 2.0 * apu::SAMPLE_RATE as f64 / Gameboy::FRAME_FREQUENCY;
+        assert!((samples - expected).abs() < 8.0, "{} samples", samples);
+    }
+
+    #[test]
+    fn step_part_of_a_frame() {
+        let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+        for _ in 0..10 {
+            gameboy.frame(None);
+        }
+
+        // Frames are ready at the start of VBlank, 10 lines before the next
+        // one starts drawing
+        assert!(!gameboy.step_cycles(456 * 20));
+        let ly = gameboy.read_memory(0xFF44);
+        assert!((9..=11).contains(&ly), "LY {}", ly);
+
+        let mut steps = 0;
+        while !gameboy.step_cycles(456 * 10) {
+            steps += 1;
+        }
+        assert!((12..=14).contains(&steps), "{} steps", steps);
+        assert_eq!(gameboy.read_memory(0xFF44), 144);
+        gameboy.frame(None);
+        assert!(!gameboy.step_cycles(4));
+    }
+
+    #[test]
+    fn state_hash_follows_the_state() {
+        let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+        gameboy.frame(None);
+        let state = gameboy.save_state().unwrap();
+        let hash = gameboy.state_hash();
+        assert_eq!(gameboy.state_hash(), hash);
+
+        gameboy.frame(None);
+        assert_ne!(gameboy.state_hash(), hash);
+
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.state_hash(), hash);
+    }
+
+    #[test]
+    fn tiles_and_palettes_are_readable() {
+        let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+        for _ in 0..120 {
+            gameboy.frame(None);
+        }
+
+        let ppu = gameboy.ppu();
+        assert_eq!((ppu.vram_banks(), ppu.palettes(false), ppu.palettes(true)), (1, 1, 2));
+
+        // The copyright screen is up, so some tiles are drawn in
+        let tiles: Vec<_> = (0..ppu::TILES_PER_BANK).map(|tile| ppu.tile(0, tile)).collect();
+        assert!(tiles.iter().any(|tile| tile.iter().flatten().any(|&color| color != 0)));
+        assert!(tiles.iter().flatten().flatten().all(|&color| color < 4));
+
+        // Tetris sets BGP to the usual lightest to darkest
+        assert_eq!(ppu.palette(false, 0), ppu::DMG_PALETTE);
+
+        // The screen is not scrolled, so the map starts with what is on it
+        assert_eq!((ppu.scroll(), ppu.bg_map()), ((0, 0), 0));
+        let frame = gameboy.frame_buffer();
+        assert!((0..16).all(|x| ppu.map_pixel(0, x * 8, 72) == frame.read(x as usize * 8, 72)));
+
+        // Tile numbers are all on bank 0
+        assert_eq!(ppu.map_tiles(0).len(), 32 * 32);
+        assert!(ppu.map_tiles(0).iter().any(|&tile| tile != ppu.map_tiles(0)[0]));
+        assert_eq!(ppu.map_attributes(0), None);
+
+        let registers = ppu.registers();
+        assert_eq!((registers.scx, registers.scy, registers.bgp), (0, 0, 0xE4));
+        assert_eq!(registers.lcdc & 0x80, 0x80);
+
+        // Nothing is moving yet, and OAM has been cleared
+        assert_eq!(ppu.sprite_height(), 8);
+        assert!(ppu.oam_entries().iter().all(|sprite| sprite.y == 0 && !sprite.flip_x));
+    }
+
+    #[test]
+    fn frames_go_by_with_the_lcd_off() {
+        let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+
+        // Tetris turns the LCD off for a few frames while it sets up VRAM
+        let off = (0..20).filter(|_| {
+            gameboy.frame(None);
+            !gameboy.ppu().lcd_enabled()
+        }).count();
+        assert!(off > 1);
+        assert!(gameboy.ppu().lcd_enabled());
+    }
+
+    #[test]
+    fn cpu_state_matches_registers() {
+        let mut gameboy = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+        gameboy.frame(None);
+
+        let state = gameboy.cpu_state();
+        assert_eq!(state.pc, gameboy.cpu().registers.PC);
+        assert_eq!(state.sp, gameboy.cpu().registers.SP);
+        assert_eq!(state.zero, state.af & 0x80 != 0);
+        assert!(state.to_string().starts_with(&format!("AF={:04X} ", state.af)));
+    }
+
+    #[test]
+    fn reset_runs_the_boot_rom_and_keeps_cartridge_ram() {
+        let cartridge = sample_cartridge("pokemon_gold.gbc").with_boot_rom(BootRom::new());
+        let mut gameboy = Gameboy::init(cartridge, false).unwrap();
+        let boot = gameboy.cpu.memory.read(0x0000u16);
+
+        // Write to cartridge RAM, and unmap the boot ROM
+        gameboy.cpu.memory.write(0x0000u16, 0x0Au8);
+        gameboy.cpu.memory.write(0xA000u16, 0x42u8);
+        gameboy.cpu.memory.write(0xFF50u16, 0x01u8);
+        assert_ne!(gameboy.cpu.memory.read(0x0000u16), boot);
+
+        gameboy.reset();
+        assert_eq!(gameboy.cpu().registers.PC, 0x0000);
+        assert_eq!(gameboy.cpu.memory.read(0x0000u16), boot);
+
+        gameboy.cpu.memory.write(0x0000u16, 0x0Au8);
+        let ram: u8 = gameboy.cpu.memory.read(0xA000u16);
+        assert_eq!(ram, 0x42);
+    }
+
+    #[test]
+    fn rom_patches_only_replace_the_compared_byte() {
+        let mut gameboy = Gameboy::init(sample_cartridge("pokemon_gold.gbc"), false).unwrap();
+        let byte = gameboy.read_memory(0x4000);
+
+        assert!(gameboy.patch_rom(0x4000, byte.wrapping_add(1), Some(byte.wrapping_sub(1))).is_empty());
+        assert!(gameboy.patch_rom(0x8000, 0x00, None).is_empty());
+
+        let patched = gameboy.patch_rom(0x4000, byte.wrapping_add(1), Some(byte));
+        assert!(!patched.is_empty());
+        assert!(patched.iter().all(|&(offset, old)| offset % Rom::BANK_SIZE == 0 && old == byte));
+        assert_eq!(gameboy.read_memory(0x4000), byte.wrapping_add(1));
+
+        gameboy.unpatch_rom(&patched);
+        assert_eq!(gameboy.read_memory(0x4000), byte);
+    }
+
+    #[test]
+    fn save_state_from_another_rom_is_rejected() {
+        let tetris = Gameboy::init(sample_cartridge("tetris_world.gb"), false).unwrap();
+        let state = tetris.save_state().unwrap();
+
+        let mut pokemon = Gameboy::init(sample_cartridge("pokemon_gold.gbc"), false).unwrap();
+        let pc = pokemon.cpu().registers.PC;
+
+        match pokemon.load_state(&state) {
+            Err(Error::StateMismatch(_)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(pokemon.cpu().registers.PC, pc);
+    }
 }
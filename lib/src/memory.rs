@@ -1,3 +1,4 @@
+use crate::apu::Apu;
 use crate::cartridge::{BootRom, Cartridge, Controller, Ram as CartridgeRam, Rom};
 use crate::cpu::Interrupt;
 use crate::error::Result;
@@ -120,17 +121,29 @@ pub struct Io {
     serial: [u8; 2],
     serial_buffer: Vec<char>,
 
+    /// Bytes sent over serial since the frontend last took them
+    #[cfg_attr(feature = "save", serde(skip))]
+    serial_pending: Vec<u8>,
+
+    /// Whether a link cable is connected. Transfers then wait for the other
+    /// side, instead of finishing right away.
+    #[cfg_attr(feature = "save", serde(skip))]
+    link_connected: bool,
+
+    /// Whether the byte for the current transfer was handed to the link
+    #[cfg_attr(feature = "save", serde(skip))]
+    link_sent: bool,
+
     /// Timer: 0xFF04 - 0xFF07
     timer: Timer,
 
     /// Interrupt flags (IF) 0xFF0F
     pub int_flags: u8,
 
-    /// Range: 0xFF10 - 0xFF26
-    sound: [u8; 23],
-
-    /// Range: 0xFF30 - 0xFF3F
-    waveform_ram: [u8; 16],
+    /// Sound registers and wave RAM
+    ///
+    /// Range: 0xFF10 - 0xFF26, 0xFF30 - 0xFF3F
+    apu: Apu,
 
     // KEY1: 0xFF4D
     pub prep_speed_switch: u8,
@@ -153,16 +166,22 @@ impl Io {
 
     pub const SC_ADDR: u16 = 0xFF02;
     pub const SC_REQUEST_MASK: u8 = 1 << 7;
+    pub const SC_CLOCK_MASK: u8 = 1 << 0;
+
+    /// Serial bytes kept for the frontend, if it never takes them
+    const MAX_PENDING_SERIAL: usize = 4096;
 
     pub fn new() -> Self {
         Self {
             joypad: Joypad::new(),
             serial: [0; 2],
             serial_buffer: Vec::new(),
+            serial_pending: Vec::new(),
+            link_connected: false,
+            link_sent: false,
             timer: Timer::new(),
             int_flags: 0,
-            sound: [0; 23],
-            waveform_ram: [0; 16],
+            apu: Apu::new(),
             prep_speed_switch: 0,
             disable_boot_rom: 0,
             hdma: [0; 5],
@@ -192,11 +211,16 @@ impl Io {
         &mut self.timer
     }
 
+    pub fn apu(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
     /// Determine if a serial interrupt needs to be triggered.
     pub fn serial_interrupt(&mut self) -> bool {
         let sc = self.serial[1];
 
-        if sc & Self::SC_REQUEST_MASK != 0 {
+        // Linked transfers finish when the other side responds
+        if sc & Self::SC_REQUEST_MASK != 0 && !self.link_connected {
             self.serial[1] = sc & !Io::SC_REQUEST_MASK;
             true
         } else {
@@ -204,6 +228,52 @@ impl Io {
         }
     }
 
+    /// Connect or disconnect the link cable.
+    pub fn set_link_connected(&mut self, connected: bool) {
+        self.link_connected = connected;
+        self.link_sent = false;
+    }
+
+    /// Returns the byte to send for a transfer clocked by this side, once per
+    /// transfer.
+    pub fn link_outgoing(&mut self) -> Option<u8> {
+        let sc = self.serial[1];
+        let requested = sc & Self::SC_REQUEST_MASK != 0 && sc & Self::SC_CLOCK_MASK != 0;
+
+        if self.link_connected && requested && !self.link_sent {
+            self.link_sent = true;
+            Some(self.serial[0])
+        } else {
+            None
+        }
+    }
+
+    /// Finish the transfer in progress with the byte shifted in from the
+    /// other side. Returns `false` if there was no transfer.
+    pub fn link_finish(&mut self, byte: u8) -> bool {
+        if self.serial[1] & Self::SC_REQUEST_MASK == 0 {
+            return false;
+        }
+
+        self.serial[0] = byte;
+        self.serial[1] &= !Self::SC_REQUEST_MASK;
+        self.link_sent = false;
+        true
+    }
+
+    /// Shift in a byte clocked by the other side, returning the byte shifted
+    /// out. Returns `None` if this side is not waiting for a transfer.
+    pub fn link_receive(&mut self, byte: u8) -> Option<u8> {
+        let sc = self.serial[1];
+        if sc & Self::SC_REQUEST_MASK == 0 || sc & Self::SC_CLOCK_MASK != 0 {
+            return None;
+        }
+
+        let out = self.serial[0];
+        self.link_finish(byte);
+        Some(out)
+    }
+
     /// Write to the HDMA start register without triggering HDMA start.
     #[inline]
     pub fn hdma_reg_write(&mut self, value: u8) {
@@ -217,6 +287,11 @@ impl Io {
     pub fn serial_buffer(&self) -> &[char] {
         &self.serial_buffer
     }
+
+    /// Remove the bytes sent over serial since the last call.
+    pub fn drain_serial(&mut self) -> std::vec::Drain<'_, u8> {
+        self.serial_pending.drain(..)
+    }
 }
 
 impl MemoryRead<u16, u8> for Io {
@@ -236,14 +311,7 @@ impl MemoryRead<u16, u8> for Io {
                 self.timer.read(addr)
             }
             0xFF0F => self.int_flags,
-            0xFF10..=0xFF26 => {
-                let idx = (addr - 0xFF10) as usize;
-                self.sound[idx]
-            }
-            0xFF30..=0xFF3F => {
-                let idx = (addr - 0xFF30) as usize;
-                self.waveform_ram[idx]
-            }
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.read(addr),
             0xFF4D => self.prep_speed_switch,
             0xFF50 => self.disable_boot_rom,
             0xFF51..=0xFF55 => {
@@ -274,9 +342,16 @@ impl MemoryWrite<u16, u8> for Io {
             0xFF02 => {
                 // Serial control
                 self.serial[1] = value;
+                self.link_sent = false;
                 if value == 0x81 {
                     let c = self.serial[0] as char;
                     self.serial_buffer.push(c);
+
+                    if self.serial_pending.len() >= Self::MAX_PENDING_SERIAL {
+                        // Nobody is reading, so drop the oldest half
+                        self.serial_pending.drain(..Self::MAX_PENDING_SERIAL / 2);
+                    }
+                    self.serial_pending.push(self.serial[0]);
                 }
             }
             0xFF04..=0xFF07 => {
@@ -285,14 +360,7 @@ impl MemoryWrite<u16, u8> for Io {
             0xFF0F => {
                 self.int_flags = value;
             }
-            0xFF10..=0xFF26 => {
-                let idx = (addr - 0xFF10) as usize;
-                self.sound[idx] = value;
-            }
-            0xFF30..=0xFF3F => {
-                let idx = (addr - 0xFF30) as usize;
-                self.waveform_ram[idx] = value;
-            }
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.write(addr, value),
             0xFF4D => {
                 self.prep_speed_switch = value;
             }
@@ -423,6 +491,9 @@ impl MemoryBus {
             interrupts.push(Interrupt::Timer);
         }
 
+        // Generate audio samples
+        self.io.apu().step(cycles);
+
         // Check if a serial interrupt needs to be triggered
         //
         // TODO: This does not happen every cycle, right?
@@ -437,10 +508,18 @@ impl MemoryBus {
         }
     }
 
+    /// Whether a boot ROM runs on reset
+    pub(crate) fn has_boot_rom(&self) -> bool {
+        self.controller.boot_rom.is_some()
+    }
+
     /// Reset the memory bus
+    ///
+    /// The boot ROM, if any, is mapped in again
     pub fn reset(&mut self) {
         let cgb = self.cgb;
-        let boot_rom = self.boot_rom;
+        let boot_rom = self.has_boot_rom();
+        self.boot_rom = boot_rom;
 
         self.controller.reset();
 
@@ -527,7 +606,8 @@ impl MemoryRead<u16, u8> for MemoryBus {
     /// This will be converted into a read from the relevant memory section.
     fn read(&self, addr: u16) -> u8 {
         match addr {
-            BootRom::BASE_ADDR..=BootRom::LAST_ADDR if self.controller.boot_rom.is_some() => {
+            BootRom::BASE_ADDR..=BootRom::CGB_LAST_ADDR
+                if self.io.disable_boot_rom == 0 && matches!(&self.controller.boot_rom, Some(b) if b.contains(addr)) => {
                 // If the boot ROM is active, read from it instead of cartridge ROM
                 self.controller.boot_rom.as_ref().unwrap().read(addr)
             }
@@ -594,9 +674,8 @@ impl MemoryWrite<u16, u8> for MemoryBus {
                 // Prohibited memory area -- no effect on writes
             }
             0xFF50 => {
-                // Disable boot ROM
+                // Disable boot ROM. It is kept around to run again on reset.
                 if self.io.disable_boot_rom == 0 && value & 0x1 != 0 {
-                    self.controller.boot_rom.take();
                     self.io.disable_boot_rom = 1;
                 }
             }
@@ -638,4 +717,53 @@ mod test {
         let value: u8 = ram.read(Ram::BASE_ADDR + 0x1234u16);
         assert_eq!(value, 0x66);
     }
+
+    #[test]
+    fn linked_serial_transfers() {
+        let mut io = Io::new();
+
+        // Without a link, transfers finish right away
+        io.write(0xFF01, 0x42);
+        io.write(Io::SC_ADDR, 0x81);
+        assert!(io.serial_interrupt());
+        assert_eq!(io.link_outgoing(), None);
+
+        // With one, the byte is handed out once and the transfer waits
+        io.set_link_connected(true);
+        io.write(Io::SC_ADDR, 0x81);
+        assert_eq!(io.link_outgoing(), Some(0x42));
+        assert_eq!(io.link_outgoing(), None);
+        assert!(!io.serial_interrupt());
+        assert!(io.link_finish(0x99));
+        assert_eq!((io.read(0xFF01), io.read(Io::SC_ADDR)), (0x99, 0x01));
+
+        // Transfers clocked by the other side
+        assert_eq!(io.link_receive(0x11), None);
+        io.write(Io::SC_ADDR, 0x80);
+        assert_eq!(io.link_outgoing(), None);
+        assert_eq!(io.link_receive(0x11), Some(0x99));
+        assert_eq!((io.read(0xFF01), io.read(Io::SC_ADDR)), (0x11, 0x00));
+    }
+
+    #[test]
+    fn serial_output_is_drained() {
+        let mut io = Io::new();
+        for &byte in b"Hi" {
+            io.write(0xFF01, byte);
+            io.write(Io::SC_ADDR, 0x81);
+        }
+
+        assert_eq!(io.drain_serial().collect::<Vec<_>>(), b"Hi");
+        assert_eq!(io.drain_serial().count(), 0);
+        assert_eq!(io.serial_buffer(), ['H', 'i']);
+
+        // Only the latest bytes are kept until someone reads them
+        for i in 0..Io::MAX_PENDING_SERIAL + 1 {
+            io.write(0xFF01, i as u8);
+            io.write(Io::SC_ADDR, 0x81);
+        }
+        let pending: Vec<_> = io.drain_serial().collect();
+        assert_eq!(pending.len(), Io::MAX_PENDING_SERIAL / 2 + 1);
+        assert_eq!(pending.last(), Some(&(Io::MAX_PENDING_SERIAL as u8)));
+    }
 }
@@ -51,7 +51,16 @@ use crate::memory::{MemoryRead, MemoryWrite};
 pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 
-#[derive(Clone, Copy, Debug)]
+/// Number of 8x8 tiles each VRAM bank can hold, from 0x8000 to 0x97FF
+pub const TILES_PER_BANK: usize = 384;
+
+/// Width and height of a BG map, in pixels
+pub const BG_MAP_SIZE: usize = 256;
+
+/// Number of sprites in OAM
+pub const OAM_SPRITES: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
 pub struct GameboyRgb {
     pub red: u8,
@@ -78,8 +87,10 @@ impl GameboyRgb {
     }
 }
 
-// Basic DMG/monochrome color palette
-static DMG_PALETTE: [GameboyRgb; 4] = [
+/// Basic DMG/monochrome color palette, from lightest to darkest.
+///
+/// Monochrome games are rendered using only these four colors.
+pub static DMG_PALETTE: [GameboyRgb; 4] = [
     // White
     GameboyRgb {
         red: 0xE0, green: 0xF8, blue: 0xD0,
@@ -340,6 +351,40 @@ struct Sprite {
     pub attr: u8,
 }
 
+/// A sprite in OAM, with its attributes picked apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OamEntry {
+    /// Position of the bottom right corner on the screen, plus 1: sprites
+    /// at (8, 16) are in the top left corner, and at 0 they are hidden
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    /// Palette and VRAM bank the tile is drawn with
+    pub palette: u8,
+    pub bank: u8,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Whether BG colors 1-3 are drawn over the sprite
+    pub behind_bg: bool,
+}
+
+/// A copy of the LCD registers, as the CPU would read them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PpuRegisters {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub wy: u8,
+    pub wx: u8,
+    /// DMG palettes
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+}
+
 #[cfg_attr(feature = "save", derive(serde::Serialize), derive(serde::Deserialize))]
 pub struct Ppu {
     /// Video RAM (0x8000 - 0x9FFF)
@@ -544,6 +589,9 @@ impl Ppu {
         if self.lcdc.lcd_display_enable() && stat_mode_change {
             // Render data to the frame
             self.render();
+        } else if stat_mode_change && mode == StatMode::Vblank {
+            // Nothing is drawn with the LCD off, but frames still go by
+            self.frame_buffer.ready = true;
         }
     }
 
@@ -1015,6 +1063,11 @@ impl Ppu {
         let upper_bit = (upper & 1 << pixel_pos) >> pixel_pos;
         let color_index = upper_bit << 1 | lower_bit;
 
+        (self.palette_color(color_index, tile_palette_num, sprite), color_index)
+    }
+
+    /// Returns the color at `color_index` in a BG/window or sprite palette.
+    fn palette_color(&self, color_index: u8, tile_palette_num: u8, sprite: bool) -> GameboyRgb {
         // (8)
         let mut pixel_data;
 
@@ -1062,7 +1115,7 @@ impl Ppu {
             pixel_data = DMG_PALETTE[palette_index as usize];
         }
 
-        (pixel_data, color_index)
+        pixel_data
     }
 
     /// Write a single byte of data to palette RAM.
@@ -1141,6 +1194,123 @@ impl Ppu {
         &mut self.vram
     }
 
+    /// Number of VRAM banks: two on the CGB, one on the DMG.
+    pub fn vram_banks(&self) -> u8 {
+        if self.cgb { 2 } else { 1 }
+    }
+
+    /// Color indexes (0-3) of the pixels in tile `tile` of VRAM bank `bank`,
+    /// from the top left, a row at a time.
+    ///
+    /// Tiles are numbered from 0x8000, up to `TILES_PER_BANK`.
+    pub fn tile(&self, bank: u8, tile: usize) -> [[u8; 8]; 8] {
+        let addr = Vram::BASE_ADDR + (tile * 16) as u16;
+        let data = self.vram.get_bank_slice(bank, addr, 16);
+
+        let mut pixels = [[0; 8]; 8];
+        for (y, row) in pixels.iter_mut().enumerate() {
+            let (lower, upper) = (data[y * 2], data[y * 2 + 1]);
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let bit = 7 - x;
+                *pixel = (upper >> bit & 1) << 1 | lower >> bit & 1;
+            }
+        }
+
+        pixels
+    }
+
+    /// Number of BG or sprite palettes: eight of each on the CGB, and BGP,
+    /// OBP0 and OBP1 on the DMG.
+    pub fn palettes(&self, sprite: bool) -> u8 {
+        match (self.cgb, sprite) {
+            (true, _) => 8,
+            (false, false) => 1,
+            (false, true) => 2,
+        }
+    }
+
+    /// The four colors of BG or sprite palette `num`, as the PPU would draw
+    /// them.
+    pub fn palette(&self, sprite: bool, num: u8) -> [GameboyRgb; 4] {
+        [0, 1, 2, 3].map(|color_index| self.palette_color(color_index, num, sprite))
+    }
+
+    /// Whether the LCD is on. While it is off nothing is drawn, and the frame
+    /// buffer keeps the last frame that was.
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc.lcd_display_enable()
+    }
+
+    /// Scroll registers (SCX, SCY): the top left corner of the screen on the
+    /// BG map.
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    /// Which BG map the BG is drawn from: 0 at 0x9800, or 1 at 0x9C00.
+    pub fn bg_map(&self) -> u8 {
+        (self.lcdc.bg_tile_map() == 0x9C00) as u8
+    }
+
+    /// Color of the pixel at (`x`, `y`) on BG map `map` (0 or 1), as the BG
+    /// would be drawn with it.
+    pub fn map_pixel(&self, map: u8, x: u8, y: u8) -> GameboyRgb {
+        self.fetch_bg_pixel_data(x, y, 0x9800 + map as u16 * 0x400).0
+    }
+
+    /// Tile numbers on BG map `map` (0 or 1), 32 by 32 from the top left, a
+    /// row at a time.
+    pub fn map_tiles(&self, map: u8) -> &[u8] {
+        self.vram.get_bank_slice(0, 0x9800 + map as u16 * 0x400, 0x400)
+    }
+
+    /// Attributes of the tiles on BG map `map`, in the same order, or `None`
+    /// on the DMG, which has none.
+    pub fn map_attributes(&self, map: u8) -> Option<&[u8]> {
+        self.cgb.then(|| self.vram.get_bank_slice(1, 0x9800 + map as u16 * 0x400, 0x400))
+    }
+
+    pub fn registers(&self) -> PpuRegisters {
+        PpuRegisters {
+            lcdc: self.lcdc.raw,
+            stat: self.stat.raw,
+            scy: self.scy,
+            scx: self.scx,
+            ly: self.ly,
+            lyc: self.lyc,
+            wy: self.wy,
+            wx: self.wx,
+            bgp: self.bgp,
+            obp0: self.obp0,
+            obp1: self.obp1,
+        }
+    }
+
+    /// Height of every sprite: 8, or 16 for pairs of tiles.
+    pub fn sprite_height(&self) -> u8 {
+        if self.lcdc.sprite_size() { 16 } else { 8 }
+    }
+
+    /// Every sprite in OAM, in order, visible or not.
+    pub fn oam_entries(&self) -> [OamEntry; OAM_SPRITES] {
+        std::array::from_fn(|i| {
+            let (y, x, tile, attr) = (self.oam[i * 4], self.oam[i * 4 + 1], self.oam[i * 4 + 2], self.oam[i * 4 + 3]);
+            // As in `fetch_sprite_pixel_data`
+            let (palette, bank) = if self.cgb { (attr & 0x07, (attr & 1 << 3) >> 3) } else { ((attr & 1 << 4) >> 4, 0) };
+
+            OamEntry {
+                x,
+                y,
+                tile,
+                palette,
+                bank,
+                flip_x: attr & 1 << 5 != 0,
+                flip_y: attr & 1 << 6 != 0,
+                behind_bg: attr & 1 << 7 != 0,
+            }
+        })
+    }
+
     /// Get a reference to the frame buffer, if it's ready.
     ///
     /// The frame will be ready only during VBLANK.
@@ -1156,6 +1326,11 @@ impl Ppu {
     pub fn is_frame_ready(&self) -> bool {
         self.frame_buffer.ready
     }
+
+    /// Get a reference to the frame buffer as it is, ready or not.
+    pub fn current_frame(&self) -> &FrameBuffer {
+        &self.frame_buffer
+    }
 }
 
 impl MemoryRead<u16, u8> for Ppu {
@@ -28,7 +28,7 @@ impl RtcTime {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct RtcState {
     /// Current time
     current: RtcTime,
@@ -50,6 +50,11 @@ struct RtcState {
 
     /// Selected register
     selected: u8,
+
+    /// If `true`, time is measured in emulated cycles rather than taken from
+    /// the system clock
+    #[serde(skip)]
+    emulated_clock: bool,
 }
 
 impl RtcState {
@@ -62,6 +67,17 @@ impl RtcState {
             tick_cycle: 0,
             cycle: 0,
             selected: 0,
+            emulated_clock: false,
+        }
+    }
+
+    /// The current time, according to the clock in use.
+    fn now(&self, cycle_time: u64) -> DateTime<Utc> {
+        if self.emulated_clock {
+            let elapsed = (self.cycle - self.tick_cycle) * cycle_time;
+            self.timestamp + chrono::Duration::nanoseconds(elapsed as i64)
+        } else {
+            Utc::now()
         }
     }
 
@@ -69,22 +85,23 @@ impl RtcState {
         let cycle_time = Cpu::cycle_time(speed) as u64;
 
         if self.current.halt {
-            // Clock is halted
-            self.timestamp = Utc::now();
+            // Clock is halted, so the time that passes is not counted later
+            self.timestamp = self.now(cycle_time);
+            self.tick_cycle = self.cycle;
             return;
         }
 
         self.cycle += cycles as u64;
 
         if (self.cycle - self.tick_cycle) / cycle_time >= Rtc::TICK_INTERVAL {
-            self.tick();
+            self.tick(cycle_time);
         }
     }
 
-    fn tick(&mut self) {
+    fn tick(&mut self, cycle_time: u64) {
         // Adjust for skew by getting the actual number of seconds
         // since the last tick
-        let now = Utc::now();
+        let now = self.now(cycle_time);
         let seconds = (now - self.timestamp).num_seconds();
         self.current.seconds += seconds as u8;
 
@@ -195,6 +212,13 @@ impl RtcState {
         }
     }
 
+    /// Count time from the system clock's now on, e.g. before going back to
+    /// it from emulated time, which may be ahead of or behind it.
+    fn rebase(&mut self) {
+        self.timestamp = Utc::now();
+        self.tick_cycle = self.cycle;
+    }
+
     /// Advance the RTC to the current timestamp.
     ///
     /// This needs to be done right after loading an RTC state from a file.
@@ -210,8 +234,9 @@ impl RtcState {
 
         // Figure out the number of seconds, minutes, hours, and days that have
         // occurred since the last RTC timestamp
-        let seconds = delta.num_seconds();
-        assert!(seconds >= 0, "RTC timestamp is more recent than current UTC time");
+        // A timestamp from the future, e.g. after the system clock was set
+        // back, leaves the time as it was
+        let seconds = delta.num_seconds().max(0);
         let minutes = seconds / 60;
         let hours = minutes / 60;
         let days = hours / 24;
@@ -257,9 +282,18 @@ impl Rtc {
     }
 
     /// Dump the state of the RTC
+    ///
+    /// The timestamp is always by the system clock, as that is what it is
+    /// advanced by when loaded.
     pub fn dump(&self) -> Vec<u8> {
         let mut data = Vec::new();
-        bincode::serialize_into(&mut data, &self).unwrap();
+        if self.state.emulated_clock {
+            let mut state = self.state.clone();
+            state.rebase();
+            bincode::serialize_into(&mut data, &Self { state }).unwrap();
+        } else {
+            bincode::serialize_into(&mut data, &self).unwrap();
+        }
         data
     }
 
@@ -286,4 +320,71 @@ impl Rtc {
     pub fn advance(&mut self) {
         self.state.advance()
     }
+
+    /// Measure time in emulated cycles instead of using the system clock.
+    pub fn set_emulated_clock(&mut self, enabled: bool) {
+        if self.state.emulated_clock && !enabled {
+            self.state.rebase();
+        }
+        self.state.emulated_clock = enabled;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Cycles in an emulated hour, at single speed
+    fn hour() -> u64 {
+        3600 * 1_000_000_000 / Cpu::cycle_time(false) as u64
+    }
+
+    fn run(rtc: &mut Rtc, cycles: u64) {
+        for _ in 0..cycles / u16::MAX as u64 {
+            rtc.step(u16::MAX, false);
+        }
+    }
+
+    #[test]
+    fn halted_clock_stands_still() {
+        let mut rtc = Rtc::new();
+        rtc.set_emulated_clock(true);
+        rtc.select(0x0C);
+        rtc.write(1 << 6);
+
+        run(&mut rtc, hour());
+        let timestamp = rtc.state.timestamp;
+        run(&mut rtc, hour());
+        assert_eq!(rtc.state.timestamp, timestamp);
+        assert!(rtc.state.timestamp <= Utc::now());
+    }
+
+    #[test]
+    fn emulated_time_is_saved_by_the_system_clock() {
+        let mut rtc = Rtc::new();
+        rtc.set_emulated_clock(true);
+        // As when fast-forwarding, ahead of the system clock
+        run(&mut rtc, hour());
+        assert!(rtc.state.timestamp > Utc::now());
+
+        let mut loaded = Rtc::from_bytes(&rtc.dump()).unwrap();
+        assert!(loaded.state.timestamp <= Utc::now());
+        loaded.advance();
+        assert_eq!(loaded.state.current.hours, rtc.state.current.hours);
+        assert_eq!(loaded.state.current.minutes, rtc.state.current.minutes);
+
+        // And counted from now when going back to it
+        rtc.set_emulated_clock(false);
+        assert!(rtc.state.timestamp <= Utc::now());
+    }
+
+    #[test]
+    fn timestamps_from_the_future_are_not_counted() {
+        let mut rtc = Rtc::new();
+        rtc.state.current.minutes = 5;
+        rtc.state.timestamp = Utc::now() + chrono::Duration::hours(1);
+        rtc.advance();
+        assert_eq!(rtc.state.current.minutes, 5);
+        assert_eq!(rtc.state.current.hours, 0);
+    }
 }
@@ -0,0 +1,280 @@
+//! Audio output
+//!
+//! Samples produced by the APU are queued into a buffer that is shared with
+//! the output stream, which drains it from its own thread. The buffer takes
+//! care of resampling from `apu::SAMPLE_RATE` to the host's rate.
+//!
+//! Output is only available with the `audio` feature. Without it, the output
+//! is never created and samples are simply dropped.
+#[cfg(feature = "audio")]
+pub use self::cpal_output::AudioOutput;
+
+#[cfg(not(feature = "audio"))]
+pub use self::null_output::AudioOutput;
+
+#[cfg(feature = "audio")]
+mod cpal_output {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::Duration;
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use gbc::apu::SAMPLE_RATE;
+
+    /// Amount of audio to buffer before playback (re)starts, in frames
+    const PREBUFFER: usize = SAMPLE_RATE as usize / 20;
+
+    /// Oldest frames are dropped past this point to keep latency down
+    const MAX_BUFFERED: usize = SAMPLE_RATE as usize / 5;
+
+    /// How quickly the last sample fades to silence on an underrun
+    const FADE: f32 = 0.995;
+
+    /// Amount of audio kept buffered when audio drives timing, in frames
+    const SYNC_LATENCY: usize = SAMPLE_RATE as usize / 10;
+
+    /// Give up waiting on the output stream after this long, e.g. if the device
+    /// went away
+    const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Stereo frames waiting to be played.
+    struct SampleBuffer {
+        frames: VecDeque<(f32, f32)>,
+
+        /// Number of source frames consumed per output frame
+        step: f64,
+
+        /// Position between the first two frames
+        position: f64,
+
+        /// Last frame played, faded out on underruns to avoid clicks
+        last: (f32, f32),
+
+        /// `false` while filling up after an underrun
+        playing: bool,
+    }
+
+    impl SampleBuffer {
+        fn new() -> Self {
+            Self {
+                frames: VecDeque::with_capacity(MAX_BUFFERED),
+                step: 1.0,
+                position: 0.0,
+                last: (0.0, 0.0),
+                playing: false,
+            }
+        }
+
+        fn push(&mut self, samples: &[f32]) {
+            for frame in samples.chunks_exact(2) {
+                self.frames.push_back((frame[0], frame[1]));
+            }
+
+            if self.frames.len() > MAX_BUFFERED {
+                let excess = self.frames.len() - MAX_BUFFERED;
+                self.frames.drain(..excess);
+            }
+
+            if self.frames.len() >= PREBUFFER {
+                self.playing = true;
+            }
+        }
+
+        /// Produce the next output frame, interpolating between source frames.
+        fn next(&mut self) -> (f32, f32) {
+            while self.position >= 1.0 && self.frames.len() > 2 {
+                self.frames.pop_front();
+                self.position -= 1.0;
+            }
+
+            if !self.playing || self.frames.len() < 2 || self.position >= 1.0 {
+                // Underrun: wait for the buffer to fill up again
+                self.playing = false;
+                self.last = (self.last.0 * FADE, self.last.1 * FADE);
+                return self.last;
+            }
+
+            let (a, b) = (self.frames[0], self.frames[1]);
+            let t = self.position as f32;
+            self.position += self.step;
+
+            self.last = (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+            self.last
+        }
+    }
+
+    /// Buffer shared with the output stream, which signals the condition
+    /// variable whenever it consumes frames
+    type Shared = Arc<(Mutex<SampleBuffer>, Condvar)>;
+
+    /// Plays audio on the default output device.
+    pub struct AudioOutput {
+        shared: Shared,
+        host_rate: u32,
+        muted: bool,
+        _stream: cpal::Stream,
+    }
+
+    impl AudioOutput {
+        pub fn new(muted: bool) -> Result<Self, String> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or("No output device")?;
+            let config = device.default_output_config().map_err(|e| e.to_string())?;
+            let format = config.sample_format();
+            let config: cpal::StreamConfig = config.into();
+
+            let shared = Arc::new((Mutex::new(SampleBuffer::new()), Condvar::new()));
+
+            let stream = match format {
+                cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, shared.clone()),
+                cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, shared.clone()),
+                cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, shared.clone()),
+                other => return Err(format!("Unsupported sample format: {}", other)),
+            }?;
+            stream.play().map_err(|e| e.to_string())?;
+
+            log::info!("Audio output at {} Hz, {} channel(s)", config.sample_rate.0, config.channels);
+
+            Ok(Self {
+                shared,
+                host_rate: config.sample_rate.0,
+                muted,
+                _stream: stream,
+            })
+        }
+
+        /// Queue samples drained from the `Gameboy`.
+        ///
+        /// `speed` is the current emulation speed, which scales playback to keep
+        /// up with the emulator. When running uncapped (`None`), the samples are
+        /// dropped.
+        pub fn push(&mut self, samples: &[f32], speed: Option<f64>) {
+            let speed = match speed {
+                Some(speed) if !self.muted => speed,
+                _ => return,
+            };
+
+            let mut buffer = self.shared.0.lock().unwrap();
+            buffer.step = SAMPLE_RATE as f64 * speed / self.host_rate as f64;
+            buffer.push(samples);
+        }
+
+        /// Block until the output stream has played enough of the buffer that
+        /// it is time to run the next frame.
+        ///
+        /// Returns `false` without waiting if nothing is being played (e.g.
+        /// while muted), in which case the caller needs to pace itself.
+        pub fn wait(&self) -> bool {
+            if self.muted {
+                return false;
+            }
+
+            let (buffer, consumed) = &*self.shared;
+            let buffer = buffer.lock().unwrap();
+            let result = consumed.wait_timeout_while(buffer, SYNC_TIMEOUT, |buffer| {
+                buffer.frames.len() > SYNC_LATENCY
+            });
+
+            !result.unwrap().1.timed_out()
+        }
+
+        /// Returns whether the sound is muted now.
+        pub fn toggle_mute(&mut self) -> bool {
+            self.muted = !self.muted;
+            self.muted
+        }
+    }
+
+    fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, shared: Shared)
+                       -> Result<cpal::Stream, String>
+        where T: cpal::SizedSample + cpal::FromSample<f32>
+    {
+        let channels = config.channels as usize;
+
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let (buffer, consumed) = &*shared;
+                let mut buffer = buffer.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let (left, right) = buffer.next();
+                    for (i, sample) in frame.iter_mut().enumerate() {
+                        let value = match (channels, i) {
+                            (1, _) => (left + right) / 2.0,
+                            (_, 0) => left,
+                            (_, 1) => right,
+                            _ => 0.0,
+                        };
+                        *sample = T::from_sample(value);
+                    }
+                }
+                consumed.notify_all();
+            },
+            |e| log::error!("Audio stream error: {}", e),
+            None,
+        );
+
+        stream.map_err(|e| e.to_string())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn resamples_between_frames() {
+            let mut buffer = SampleBuffer::new();
+            buffer.step = 0.5;
+
+            let ramp: Vec<f32> = (0..PREBUFFER).flat_map(|i| vec![i as f32, -(i as f32)]).collect();
+            buffer.push(&ramp);
+
+            assert_eq!(buffer.next(), (0.0, 0.0));
+            assert_eq!(buffer.next(), (0.5, -0.5));
+            assert_eq!(buffer.next(), (1.0, -1.0));
+            assert_eq!(buffer.next(), (1.5, -1.5));
+        }
+
+        #[test]
+        fn underruns_fade_out_and_wait_for_prebuffer() {
+            let mut buffer = SampleBuffer::new();
+            buffer.push(&vec![1.0; PREBUFFER * 2]);
+
+            for _ in 0..PREBUFFER {
+                buffer.next();
+            }
+
+            // Out of samples
+            let (left, _) = buffer.next();
+            assert!(left < 1.0 && left > 0.9);
+            assert!(!buffer.playing);
+
+            // A little more data is not enough to restart playback
+            buffer.push(&[1.0; 4]);
+            assert!(buffer.next().0 < left);
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+mod null_output {
+    /// Stand-in for builds without audio support.
+    pub struct AudioOutput;
+
+    impl AudioOutput {
+        pub fn new(_muted: bool) -> Result<Self, String> {
+            Err("built without the `audio` feature".to_string())
+        }
+
+        pub fn push(&mut self, _samples: &[f32], _speed: Option<f64>) {}
+
+        pub fn wait(&self) -> bool {
+            false
+        }
+
+        pub fn toggle_mute(&mut self) -> bool {
+            true
+        }
+    }
+}
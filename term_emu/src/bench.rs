@@ -0,0 +1,161 @@
+//! Headless benchmarking, for the `bench` subcommand
+//!
+//! Frames are run back to back with no rendering or sleeping, so that only
+//! the emulator core is measured, e.g. under `perf record` or `cargo
+//! flamegraph`. There is no input, or with a seed, buttons are pressed at
+//! random, the same way every time. The cartridge clock runs on emulated
+//! time, so every run of a ROM plays out the same, and the last frame's
+//! checksum shows whether a change to the core changed what it does.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use flate2::Crc;
+use gbc::joypad::{JoypadEvent, JoypadInput};
+use gbc::Gameboy;
+
+/// Frames each random press is held for, and then let go for
+const PRESS_FRAMES: u32 = 6;
+
+const INPUTS: [JoypadInput; 8] = [
+    JoypadInput::Up, JoypadInput::Down, JoypadInput::Left, JoypadInput::Right,
+    JoypadInput::A, JoypadInput::B, JoypadInput::Start, JoypadInput::Select,
+];
+
+/// Presses one button at a time, picked by a xorshift generator.
+struct RandomInput {
+    state: u64,
+    held: Option<JoypadInput>,
+}
+
+impl RandomInput {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at 0
+        Self { state: seed ^ 0x9E37_79B9_7F4A_7C15, held: None }
+    }
+
+    /// The events for frame number `frame`.
+    fn events(&mut self, frame: u32) -> Vec<JoypadEvent> {
+        if !frame.is_multiple_of(PRESS_FRAMES) {
+            return Vec::new();
+        }
+        if let Some(input) = self.held.take() {
+            return vec![JoypadEvent::Up(input)];
+        }
+
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        let input = INPUTS[(self.state % INPUTS.len() as u64) as usize];
+        self.held = Some(input);
+        vec![JoypadEvent::Down(input)]
+    }
+}
+
+/// Timings for a benchmark run.
+pub struct BenchResult {
+    pub frames: u32,
+    pub elapsed: Duration,
+
+    /// Slowest single frame
+    pub worst: Duration,
+
+    /// CRC32 of the last frame's pixels
+    pub checksum: u32,
+}
+
+impl BenchResult {
+    /// Emulated frames per second
+    pub fn fps(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Average time per frame
+    pub fn average(&self) -> Duration {
+        self.elapsed / self.frames.max(1)
+    }
+}
+
+/// Run `frames` frames as fast as possible, pressing buttons at random with
+/// `seed`, if any.
+pub fn run(gameboy: &mut Gameboy, frames: u32, seed: Option<u64>) -> BenchResult {
+    gameboy.set_emulated_clock(true);
+    let mut input = seed.map(RandomInput::new);
+    let mut worst = Duration::ZERO;
+
+    let start = Instant::now();
+    for frame in 0..frames {
+        let frame_start = Instant::now();
+        let events = input.as_mut().map(|input| input.events(frame));
+        gameboy.frame(events.as_deref());
+        // Not played anywhere, and would only pile up
+        gameboy.drain_audio_samples();
+        worst = worst.max(frame_start.elapsed());
+    }
+    let elapsed = start.elapsed();
+
+    let mut crc = Crc::new();
+    for pixel in &gameboy.frame_buffer().data {
+        crc.update(&[pixel.red, pixel.green, pixel.blue]);
+    }
+
+    BenchResult {
+        frames,
+        elapsed,
+        worst,
+        checksum: crc.sum(),
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let realtime = Duration::from_nanos(Gameboy::FRAME_DURATION);
+
+        writeln!(f, "Frames:        {}", self.frames)?;
+        writeln!(f, "Elapsed:       {:.3?}", self.elapsed)?;
+        writeln!(f, "FPS:           {:.1} ({:.1}x realtime)", self.fps(),
+                 realtime.as_secs_f64() * self.fps())?;
+        writeln!(f, "Average frame: {:.3?}", self.average())?;
+        writeln!(f, "Worst frame:   {:.3?}", self.worst)?;
+        write!(f, "Last frame:    CRC32 {:08X}", self.checksum)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    #[test]
+    fn runs_the_requested_number_of_frames() {
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap();
+
+        let result = run(&mut gameboy, 10, None);
+        assert_eq!(result.frames, 10);
+        assert!(result.worst <= result.elapsed);
+        assert!(result.average() <= result.worst);
+        assert!(result.fps() > 0.0);
+    }
+
+    #[test]
+    fn seeds_press_the_same_buttons_every_time() {
+        let presses = |seed| {
+            let mut input = RandomInput::new(seed);
+            (0..600).flat_map(|frame| input.events(frame)).collect::<Vec<_>>()
+        };
+
+        let events = presses(1);
+        assert_eq!(events, presses(1));
+        assert_ne!(events, presses(2));
+        assert_eq!(events.len(), 600 / PRESS_FRAMES as usize);
+        // One at a time, each let go of before the next
+        for pair in events.chunks(2) {
+            match pair {
+                [JoypadEvent::Down(down), JoypadEvent::Up(up)] => assert_eq!(down, up),
+                _ => panic!("{:?}", pair),
+            }
+        }
+        // All sorts, not the same one over and over
+        assert!(INPUTS.iter().all(|input| events.contains(&JoypadEvent::Down(*input))));
+    }
+}
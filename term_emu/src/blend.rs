@@ -0,0 +1,117 @@
+//! Blending of flickering pixels, for `run --blend`
+//!
+//! Games fake transparency and fit in more sprites by showing them every
+//! other frame, which the real LCD smeared into a steady, see-through image.
+//! On a terminal, it flickers instead. With blending, a pixel that goes back
+//! to the color it had two frames ago is shown as the average of its last two
+//! colors, and anything else as it is. Pixels that scroll past change to new
+//! colors rather than back and forth, so moving scenes do not leave a ghost
+//! behind.
+//!
+//! Like the tone, this only changes what the terminal shows, and screenshots
+//! and recordings keep the frames the game drew.
+use gbc::ppu::{FrameBuffer, GameboyRgb};
+
+pub struct Blend {
+    /// The last two frames pushed, the latest first
+    last: FrameBuffer,
+    before: FrameBuffer,
+    /// Frames pushed so far, up to 2
+    frames: usize,
+
+    /// The latest frame, blended with the one before where it flickers
+    blended: FrameBuffer,
+}
+
+impl Default for Blend {
+    fn default() -> Self {
+        Self {
+            last: FrameBuffer::new(),
+            before: FrameBuffer::new(),
+            frames: 0,
+            blended: FrameBuffer::new(),
+        }
+    }
+}
+
+fn average(a: GameboyRgb, b: GameboyRgb) -> GameboyRgb {
+    let mean = |a: u8, b: u8| (u16::from(a) + u16::from(b)).div_ceil(2) as u8;
+    GameboyRgb { red: mean(a.red, b.red), green: mean(a.green, b.green), blue: mean(a.blue, b.blue) }
+}
+
+impl Blend {
+    /// Blend in a new frame. Only frames the game drew count, not the same
+    /// one shown again while paused.
+    pub fn push(&mut self, frame_buffer: &FrameBuffer) {
+        for (i, out) in self.blended.data.iter_mut().enumerate() {
+            let (now, last, before) = (frame_buffer.data[i], self.last.data[i], self.before.data[i]);
+            *out = if self.frames == 2 && now != last && now == before { average(now, last) } else { now };
+        }
+
+        std::mem::swap(&mut self.last, &mut self.before);
+        self.last.data.copy_from_slice(&frame_buffer.data);
+        self.frames = (self.frames + 1).min(2);
+    }
+
+    /// The latest frame, as it is to be shown.
+    pub fn frame(&self) -> &FrameBuffer {
+        &self.blended
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::ppu::{LCD_HEIGHT, LCD_WIDTH};
+
+    use super::*;
+
+    const BLACK: GameboyRgb = GameboyRgb { red: 0, green: 0, blue: 0 };
+    const WHITE: GameboyRgb = GameboyRgb { red: 0xFF, green: 0xFF, blue: 0xFF };
+    const GRAY: GameboyRgb = GameboyRgb { red: 0x80, green: 0x80, blue: 0x80 };
+
+    #[test]
+    fn flicker_is_blended() {
+        let mut blend = Blend::default();
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.data.fill(WHITE);
+
+        // A sprite on every other frame
+        for i in 0..6 {
+            frame_buffer.write(10, 10, if i % 2 == 0 { BLACK } else { WHITE });
+            blend.push(&frame_buffer);
+
+            let pixel = blend.frame().read(10, 10);
+            match i {
+                // Nothing to tell flicker apart from a change by yet
+                0 | 1 => assert_eq!(pixel, frame_buffer.read(10, 10)),
+                _ => assert_eq!(pixel, GRAY),
+            }
+            assert_eq!(blend.frame().read(0, 0), WHITE);
+        }
+
+        // Until it stays put
+        frame_buffer.write(10, 10, BLACK);
+        blend.push(&frame_buffer);
+        blend.push(&frame_buffer);
+        assert_eq!(blend.frame().read(10, 10), BLACK);
+    }
+
+    #[test]
+    fn scrolling_leaves_no_ghost() {
+        let mut blend = Blend::default();
+        let mut frame_buffer = FrameBuffer::new();
+
+        // Stripes, scrolling a pixel a frame, and then faster
+        for (frame, scroll) in (0..40).chain((40..120).step_by(4)).enumerate() {
+            for y in 0..LCD_HEIGHT {
+                for x in 0..LCD_WIDTH {
+                    let stripe = (x + scroll) / 8 % 3;
+                    frame_buffer.write(x, y, [WHITE, GRAY, BLACK][stripe]);
+                }
+            }
+
+            blend.push(&frame_buffer);
+            assert!(blend.frame().data == frame_buffer.data, "ghosting on frame {}", frame);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Screen capture
+//!
+//! Frames are captured at the Gameboy's native resolution, independent of how
+//! they are rendered in the terminal. Encoding is done on separate threads so
+//! that captures never stall the emulator.
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use gbc::ppu::{FrameBuffer, LCD_HEIGHT, LCD_WIDTH};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageError, ImageResult, RgbaImage};
+
+use crate::persist;
+
+/// Copy a frame out as raw 8-bit RGB pixels, in row-major order.
+pub fn frame_pixels(frame_buffer: &FrameBuffer) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(LCD_WIDTH * LCD_HEIGHT * 3);
+
+    for y in 0..LCD_HEIGHT {
+        for x in 0..LCD_WIDTH {
+            let rgb = frame_buffer.read(x, y);
+            pixels.extend_from_slice(&[rgb.red, rgb.green, rgb.blue]);
+        }
+    }
+
+    pixels
+}
+
+/// Save a frame as a PNG right away, creating the folder it goes in if
+/// needed.
+pub fn save_png(frame_buffer: &FrameBuffer, path: &Path) -> ImageResult<()> {
+    persist::create_parent(path).map_err(ImageError::IoError)?;
+    image::save_buffer(path, &frame_pixels(frame_buffer), LCD_WIDTH as u32, LCD_HEIGHT as u32, image::ColorType::Rgb8)
+}
+
+/// Save a frame as a PNG in the background.
+///
+/// Encoding happens on a separate thread so that the emulator does not stall,
+/// and the result is logged once it is done.
+pub fn save_screenshot(frame_buffer: &FrameBuffer, path: PathBuf) {
+    let pixels = frame_pixels(frame_buffer);
+
+    thread::spawn(move || {
+        let result = persist::create_parent(&path).map_err(ImageError::IoError).and_then(|_| {
+            image::save_buffer(&path, &pixels, LCD_WIDTH as u32, LCD_HEIGHT as u32, image::ColorType::Rgb8)
+        });
+        match result {
+            Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+            Err(e) => log::error!("Failed to save screenshot to {}: {}", path.display(), e),
+        }
+    });
+}
+
+/// Records frames into an animated GIF.
+///
+/// Frames are streamed to an encoder thread, which writes the file out once
+/// the recording is stopped.
+pub struct GifRecorder {
+    tx: Sender<Vec<u8>>,
+    encoder: JoinHandle<()>,
+
+    /// Number of frames to drop after each recorded frame
+    skip: u32,
+
+    /// Frames dropped since the last recorded frame
+    skipped: u32,
+
+    /// Frames left until the maximum duration is reached
+    remaining: usize,
+}
+
+impl GifRecorder {
+    /// Start recording to `path`.
+    ///
+    /// `frame_duration` is the time between frames passed to `push`. Only one
+    /// out of every `skip + 1` frames is recorded, and recording ends once
+    /// `max_duration` worth of frames has been recorded.
+    pub fn start(path: PathBuf, frame_duration: Duration, skip: u32, max_duration: Duration) -> Self {
+        let delay = frame_duration * (skip + 1);
+        let remaining = (max_duration.as_nanos() / delay.as_nanos().max(1)) as usize;
+
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let encoder = thread::spawn(move || {
+            let result = (|| -> ImageResult<usize> {
+                persist::create_parent(&path)?;
+                let mut encoder = GifEncoder::new(BufWriter::new(File::create(&path)?));
+                encoder.set_repeat(Repeat::Infinite)?;
+
+                let mut frames = 0;
+                for pixels in rx {
+                    let frame = RgbaImage::from_raw(LCD_WIDTH as u32, LCD_HEIGHT as u32, pixels).unwrap();
+                    encoder.encode_frame(Frame::from_parts(frame, 0, 0, Delay::from_saturating_duration(delay)))?;
+                    frames += 1;
+                }
+
+                Ok(frames)
+            })();
+
+            match result {
+                Ok(frames) => log::info!("Saved {} frame recording to {}", frames, path.display()),
+                Err(e) => log::error!("Failed to save recording to {}: {}", path.display(), e),
+            }
+        });
+
+        log::info!("Recording started");
+
+        Self {
+            tx,
+            encoder,
+            skip,
+            skipped: skip,
+            remaining,
+        }
+    }
+
+    /// Record a frame, subject to frame skipping.
+    ///
+    /// Returns `false` once the maximum duration has been reached, at which
+    /// point the recording should be stopped.
+    pub fn push(&mut self, frame_buffer: &FrameBuffer) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+
+        if self.skipped < self.skip {
+            self.skipped += 1;
+            return true;
+        }
+        self.skipped = 0;
+
+        let mut pixels = Vec::with_capacity(LCD_WIDTH * LCD_HEIGHT * 4);
+        for rgb in frame_pixels(frame_buffer).chunks(3) {
+            pixels.extend_from_slice(rgb);
+            pixels.push(0xFF);
+        }
+
+        // The encoder only goes away if it failed, which it reports itself
+        let _ = self.tx.send(pixels);
+
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            log::warn!("Maximum recording duration reached");
+            return false;
+        }
+
+        true
+    }
+
+    /// Stop recording.
+    ///
+    /// The file is finished in the background; join the returned handle to
+    /// wait for it to be written.
+    pub fn stop(self) -> JoinHandle<()> {
+        drop(self.tx);
+        self.encoder
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::ppu::GameboyRgb;
+
+    use super::*;
+
+    #[test]
+    fn frame_pixels_are_row_major_rgb() {
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.write(1, 0, GameboyRgb { red: 1, green: 2, blue: 3 });
+        frame_buffer.write(0, 1, GameboyRgb { red: 4, green: 5, blue: 6 });
+
+        let pixels = frame_pixels(&frame_buffer);
+        assert_eq!(pixels.len(), LCD_WIDTH * LCD_HEIGHT * 3);
+        assert_eq!(&pixels[3..6], &[1, 2, 3]);
+        assert_eq!(&pixels[LCD_WIDTH * 3..LCD_WIDTH * 3 + 3], &[4, 5, 6]);
+    }
+
+    #[test]
+    fn recording_skips_frames_and_stops_at_max_duration() {
+        use image::AnimationDecoder;
+        use image::codecs::gif::GifDecoder;
+
+        let path = std::env::temp_dir().join(format!("gbcemu-recording-{}.gif", std::process::id()));
+        let frame_buffer = FrameBuffer::new();
+        let frame_duration = Duration::from_millis(50);
+
+        // 1 frame out of 2, so 100ms per recorded frame, up to 3 frames
+        let mut recorder = GifRecorder::start(path.clone(), frame_duration, 1, Duration::from_millis(300));
+        let mut pushed = 0;
+        while recorder.push(&frame_buffer) {
+            pushed += 1;
+            assert!(pushed < 100);
+        }
+        assert_eq!(pushed, 4);
+        recorder.stop().join().unwrap();
+
+        let decoder = GifDecoder::new(File::open(&path).unwrap()).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].delay(), Delay::from_numer_denom_ms(100, 1));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+//! Cheat codes, for `run --cheat`
+//!
+//! Game Genie codes patch the ROM when the game is loaded, where it holds the
+//! byte the code expects. GameShark codes write a byte of RAM before every
+//! frame. Either kind can be turned off and on again while playing.
+use std::fmt;
+
+use gbc::Gameboy;
+
+/// Where GameShark codes can write
+const RAM_START: u16 = 0xA000;
+const RAM_END: u16 = 0xDFFF;
+
+/// Switchable work RAM, and the register that picks the bank mapped there
+const BANKED_RAM_START: u16 = 0xD000;
+const RAM_BANK_SELECT: u16 = 0xFF70;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Code {
+    GameGenie { addr: u16, value: u8, compare: Option<u8> },
+    /// `bank` picks the work RAM bank for addresses in D000-DFFF on a CGB
+    GameShark { bank: Option<u8>, addr: u16, value: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    /// As given, in upper case
+    text: String,
+    code: Code,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Parse a Game Genie (`ABC-DEF` or `ABC-DEF-GHI`) or GameShark (`01VVLLHH`)
+/// code.
+pub fn parse_cheat(code: &str) -> Result<Cheat, String> {
+    let text = code.trim().to_uppercase();
+    let digits = text.chars()
+        .filter(|&c| c != '-')
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("Cheat codes are made of hex digits, got: {}", code))?;
+
+    let code = match (digits.len(), text.contains('-')) {
+        (6 | 9, _) => game_genie(&digits)?,
+        (8, false) => game_shark(&digits)?,
+        _ => return Err(format!(
+            "Expected a Game Genie (ABC-DEF or ABC-DEF-GHI) or GameShark (01VVLLHH) code, got: {}", code)),
+    };
+
+    Ok(Cheat { text, code })
+}
+
+fn byte(high: u8, low: u8) -> u8 {
+    (high << 4) | low
+}
+
+fn game_genie(d: &[u8]) -> Result<Code, String> {
+    let value = byte(d[0], d[1]);
+    let addr = (u16::from(d[5] ^ 0xF) << 12) | (u16::from(d[2]) << 8) | (u16::from(d[3]) << 4) | u16::from(d[4]);
+    if addr > 0x7FFF {
+        return Err(format!("Game Genie codes patch the ROM, at 0000-7FFF, not {:04X}", addr));
+    }
+
+    // The 8th digit is not used
+    let compare = (d.len() == 9).then(|| byte(d[6], d[8]).rotate_right(2) ^ 0xBA);
+
+    Ok(Code::GameGenie { addr, value, compare })
+}
+
+fn game_shark(d: &[u8]) -> Result<Code, String> {
+    let kind = byte(d[0], d[1]);
+    let value = byte(d[2], d[3]);
+    let addr = u16::from_le_bytes([byte(d[4], d[5]), byte(d[6], d[7])]);
+
+    let bank = match kind {
+        0x01 => None,
+        0x80..=0x87 | 0x90..=0x97 => Some(kind & 0x07),
+        _ => return Err(format!("Unsupported GameShark code type: {:02X}", kind)),
+    };
+    if !(RAM_START..=RAM_END).contains(&addr) {
+        return Err(format!("GameShark codes write to RAM, at {:04X}-{:04X}, not {:04X}", RAM_START, RAM_END, addr));
+    }
+
+    Ok(Code::GameShark { bank, addr, value })
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    enabled: bool,
+
+    /// Bytes replaced by Game Genie codes, to put back when disabled
+    patched: Vec<(usize, u8)>,
+}
+
+impl Cheats {
+    pub fn new(cheats: Vec<Cheat>) -> Self {
+        Self { cheats, ..Self::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Patch the ROM with the Game Genie codes, and start writing the
+    /// GameShark ones.
+    pub fn enable(&mut self, gameboy: &mut Gameboy) {
+        if self.enabled {
+            return;
+        }
+
+        for cheat in &self.cheats {
+            if let Code::GameGenie { addr, value, compare } = cheat.code {
+                let patched = gameboy.patch_rom(addr, value, compare);
+                if patched.is_empty() {
+                    log::warn!("Cheat {} does not match the ROM, check that it is for this game", cheat);
+                }
+                self.patched.extend(patched);
+            }
+        }
+        self.enabled = true;
+    }
+
+    /// Put the ROM back as it was, and stop writing to RAM.
+    pub fn disable(&mut self, gameboy: &mut Gameboy) {
+        // Last patched first, for codes that patch the same byte
+        self.patched.reverse();
+        gameboy.unpatch_rom(&self.patched);
+        self.patched.clear();
+        self.enabled = false;
+    }
+
+    /// Turn the cheats off if they are on, or on if they are off, and say which.
+    pub fn toggle(&mut self, gameboy: &mut Gameboy) -> bool {
+        if self.enabled {
+            self.disable(gameboy);
+        } else {
+            self.enable(gameboy);
+        }
+
+        self.enabled
+    }
+
+    /// Apply the GameShark codes, before each frame.
+    pub fn frame(&self, gameboy: &mut Gameboy) {
+        if !self.enabled {
+            return;
+        }
+
+        for cheat in &self.cheats {
+            match cheat.code {
+                Code::GameShark { bank: Some(bank), addr, value } if addr >= BANKED_RAM_START && gameboy.cpu().cgb => {
+                    let previous = gameboy.read_memory(RAM_BANK_SELECT);
+                    gameboy.write_memory(RAM_BANK_SELECT, bank);
+                    gameboy.write_memory(addr, value);
+                    gameboy.write_memory(RAM_BANK_SELECT, previous);
+                }
+                Code::GameShark { addr, value, .. } => gameboy.write_memory(addr, value),
+                Code::GameGenie { .. } => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn game_genie_codes() {
+        let cheat = parse_cheat("3e2-34e-e0a").unwrap();
+        assert_eq!(cheat.to_string(), "3E2-34E-E0A");
+        assert_eq!(cheat.code, Code::GameGenie { addr: 0x1234, value: 0x3E, compare: Some(0x00) });
+
+        let cheat = parse_cheat("00A17B").unwrap();
+        assert_eq!(cheat.code, Code::GameGenie { addr: 0x4A17, value: 0x00, compare: None });
+
+        // The 6th digit is inverted, so a 0 puts the address past the ROM
+        assert!(parse_cheat("3E2-340").unwrap_err().contains("F234"));
+    }
+
+    #[test]
+    fn game_shark_codes() {
+        let cheat = parse_cheat("010FA1C0").unwrap();
+        assert_eq!(cheat.code, Code::GameShark { bank: None, addr: 0xC0A1, value: 0x0F });
+
+        let cheat = parse_cheat("9263D0D1").unwrap();
+        assert_eq!(cheat.code, Code::GameShark { bank: Some(2), addr: 0xD1D0, value: 0x63 });
+
+        assert!(parse_cheat("020FA1C0").unwrap_err().contains("type: 02"));
+        assert!(parse_cheat("010F00FF").unwrap_err().contains("FF00"));
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        assert!(parse_cheat("").is_err());
+        assert!(parse_cheat("3E2-34G").is_err());
+        assert!(parse_cheat("3E2-34E-E0").is_err());
+        assert!(parse_cheat("010F-A1C0").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Terminal color support
+//!
+//! Frames are built with 24-bit colors. Terminals without truecolor support
+//! get each color quantized to the nearest entry of the xterm 256-color
+//! palette instead, right before it is emitted. Grayscale rendering works the
+//! same way, and combines with either mode.
+use std::str::FromStr;
+
+use crossterm::style::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Pick based on the environment
+    Auto,
+    TrueColor,
+    Ansi256,
+}
+
+impl ColorMode {
+    /// Resolve `Auto` to what the terminal supports.
+    ///
+    /// Terminals advertise truecolor support through `COLORTERM`. Windows
+    /// Terminal does not set it, but always supports it.
+    pub fn resolve(self) -> Self {
+        match self {
+            Self::Auto => {
+                let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+                if colorterm == "truecolor" || colorterm == "24bit" || std::env::var_os("WT_SESSION").is_some() {
+                    Self::TrueColor
+                } else {
+                    Self::Ansi256
+                }
+            }
+            mode => mode,
+        }
+    }
+
+    /// Convert a color to one the terminal can show.
+    pub fn convert(self, color: Color) -> Color {
+        match (self, color) {
+            (Self::Ansi256, Color::Rgb { r, g, b }) => Color::AnsiValue(nearest_ansi256(r, g, b)),
+            _ => color,
+        }
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "truecolor" | "24bit" => Ok(Self::TrueColor),
+            "256" => Ok(Self::Ansi256),
+            _ => Err(format!("Unknown color mode: {} (expected truecolor, 256 or auto)", s)),
+        }
+    }
+}
+
+/// Perceived brightness of a color (ITU-R BT.601).
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114 + 500) / 1000) as u8
+}
+
+/// Convert a color to the gray of the same luminance.
+pub fn grayscale(color: Color) -> Color {
+    match color {
+        Color::Rgb { r, g, b } => {
+            let luma = luma(r, g, b);
+            Color::Rgb { r: luma, g: luma, b: luma }
+        }
+        color => color,
+    }
+}
+
+/// Channel levels of the 6x6x6 color cube at indices 16-231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn nearest_cube_level(v: u8) -> usize {
+    // Midpoints between neighbouring levels
+    match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        115..=154 => 2,
+        155..=194 => 3,
+        195..=234 => 4,
+        _ => 5,
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let dr = r1 as i32 - r2 as i32;
+    let dg = g1 as i32 - g2 as i32;
+    let db = b1 as i32 - b2 as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+/// Index of the closest color in the xterm 256-color palette.
+///
+/// Only the color cube and the grayscale ramp (16-255) are considered, as the
+/// first 16 colors are usually themed by the terminal.
+pub fn nearest_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    let (ri, gi, bi) = (nearest_cube_level(r), nearest_cube_level(g), nearest_cube_level(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+
+    // The ramp at 232-255 goes from 8 to 238 in steps of 10
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let level = 8 + 10 * step;
+    let gray = (level, level, level);
+
+    if distance((r, g, b), gray) < distance((r, g, b), cube) {
+        232 + step
+    } else {
+        cube_index as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// RGB value of a palette entry at 16-255
+    fn palette_rgb(index: u8) -> (u8, u8, u8) {
+        match index {
+            16..=231 => {
+                let i = index as usize - 16;
+                (CUBE_LEVELS[i / 36], CUBE_LEVELS[i / 6 % 6], CUBE_LEVELS[i % 6])
+            }
+            _ => {
+                let level = 8 + 10 * (index - 232);
+                (level, level, level)
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_colors() {
+        assert_eq!(nearest_ansi256(0, 0, 0), 16);
+        assert_eq!(nearest_ansi256(255, 255, 255), 231);
+        assert_eq!(nearest_ansi256(255, 0, 0), 196);
+        assert_eq!(nearest_ansi256(0x80, 0x80, 0x80), 244);
+        // DMG green shades
+        assert_eq!(nearest_ansi256(0x9B, 0xBC, 0x0F), 142);
+        // Dark and unsaturated enough that the grayscale ramp is closer
+        assert_eq!(nearest_ansi256(0x0F, 0x38, 0x0F), 234);
+
+        // Matches a brute force search over the palette
+        for &(r, g, b) in &[(12, 200, 99), (250, 10, 128), (77, 77, 80), (1, 2, 3), (224, 248, 208)] {
+            let best = (16..=255u8).min_by_key(|&i| distance((r, g, b), palette_rgb(i))).unwrap();
+            assert_eq!(distance((r, g, b), palette_rgb(nearest_ansi256(r, g, b))),
+                       distance((r, g, b), palette_rgb(best)), "{:?}", (r, g, b));
+        }
+    }
+
+    #[test]
+    fn convert_colors() {
+        let rgb = Color::Rgb { r: 255, g: 0, b: 0 };
+        assert_eq!(ColorMode::TrueColor.convert(rgb), rgb);
+        assert_eq!(ColorMode::Ansi256.convert(rgb), Color::AnsiValue(196));
+        assert_eq!(ColorMode::Ansi256.convert(Color::Reset), Color::Reset);
+
+        assert_eq!("256".parse(), Ok(ColorMode::Ansi256));
+        assert_eq!("TrueColor".parse(), Ok(ColorMode::TrueColor));
+        assert!("16".parse::<ColorMode>().is_err());
+    }
+
+    #[test]
+    fn grayscale_colors() {
+        assert_eq!(grayscale(Color::Rgb { r: 255, g: 255, b: 255 }), Color::Rgb { r: 255, g: 255, b: 255 });
+        assert_eq!(grayscale(Color::Rgb { r: 0, g: 0, b: 0 }), Color::Rgb { r: 0, g: 0, b: 0 });
+        // Green is brighter than red, which is brighter than blue
+        assert_eq!(grayscale(Color::Rgb { r: 0, g: 255, b: 0 }), Color::Rgb { r: 150, g: 150, b: 150 });
+        assert_eq!(grayscale(Color::Rgb { r: 255, g: 0, b: 0 }), Color::Rgb { r: 76, g: 76, b: 76 });
+        assert_eq!(grayscale(Color::Rgb { r: 0, g: 0, b: 255 }), Color::Rgb { r: 29, g: 29, b: 29 });
+        assert_eq!(grayscale(Color::Reset), Color::Reset);
+    }
+}
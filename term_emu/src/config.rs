@@ -0,0 +1,543 @@
+//! Persistent preferences, loaded from `config.toml`
+//!
+//! The file lives in `$XDG_CONFIG_HOME/gbc-terminal` (`~/.config/gbc-terminal`
+//! by default), or `%APPDATA%\gbc-terminal` on Windows. A commented-out
+//! default is written there on first run. Command line flags take precedence
+//! over anything set in the file.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crossterm::event::KeyCode;
+use gbc::joypad::JoypadInput;
+use gbc::ppu::GameboyRgb;
+use toml::Value;
+
+use crate::color::ColorMode;
+use crate::gamepad::Button;
+use crate::input::{self, DpadConflict, KeyCombo};
+use crate::pace::{self, Pacing};
+use crate::palette::{self, Palette};
+use crate::render::Backend;
+use crate::tone;
+
+const DEFAULT: &str = r##"# gbc-terminal configuration. Command line flags take precedence.
+
+# Emulation speed multiplier
+#speed = 1.0
+
+# Frames to skip drawing after each one that is drawn, from 0 to 9
+#frameskip = 0
+
+# Draw at most this many frames a second, without slowing down the game
+#max_render_fps = 20
+
+# How to wait between frames: spin (least jitter, keeps a core busy), sleep
+# (least CPU), or hybrid (sleep, then spin through the last spin_threshold
+# microseconds)
+#pacing = "hybrid"
+#spin_threshold = 1000
+
+# Show each frame all at once (true), never (false), or if supported (unset)
+#sync_output = true
+
+# Set to false to start muted
+#audio = true
+
+# How to draw: half-block, sextant, ascii, or sixel or kitty (need terminal support)
+#renderer = "half-block"
+
+# Render 2x3 pixels per cell using Unicode sextants (needs font support)
+#fullres = false
+
+# Render in plain ASCII characters without color
+#ascii = false
+
+# Draw each cell this many terminal columns wide, from 1 to 4
+#scale_width = 1
+
+# Stretch the image so pixels come out square instead, for cells this many
+# times as tall as they are wide (measure your font if 2.0 looks off)
+#correct_aspect = false
+#cell_aspect = 2.0
+
+# Colors to send to the terminal: truecolor, 256, or auto to detect
+#color_mode = "auto"
+
+# Render in shades of gray only
+#grayscale = false
+
+# Adjust the image for the terminal's colors, from 0.1 to 4.0 (1.0 leaves it)
+#brightness = 1.0
+#contrast = 1.0
+#gamma = 1.0
+
+# Colors for monochrome games: green, pocket, grey, or 4 hex colors from
+# lightest to darkest
+#palette = "pocket"
+#palette = ["#e0f8d0", "#88c070", "#346856", "#081820"]
+
+# Make color games look more like they did on the CGB's screen
+#color_correct = false
+
+# Keep showing the last frame while a game has the LCD off, instead of going
+# blank like the real screen, e.g. if a game flickers as it turns it on and off
+#keep_last_frame = false
+
+# Blend pixels that flicker every other frame, like the real screen smeared
+# them, for sprites that games make see-through that way
+#blend = false
+
+# Outline high-contrast edges, to make sprites easier to pick out with low
+# vision, in a color of your choice
+#accessibility_outline = false
+#outline_color = "#ffff00"
+
+# Ring the terminal bell when a key saves, loads, takes a screenshot and the like
+#bell = false
+
+# Where saves, save states, screenshots and recordings go, in a folder per game
+# (default: ~/.local/share/gbc-terminal, or %APPDATA%\gbc-terminal\saves)
+#save_dir = "~/games/saves"
+
+# Joypad inputs that auto-fire while held, and how many times per second
+#turbo = ["A", "B"]
+#turbo_rate = 10
+
+# When opposite directions are held at once: last (the one pressed last wins),
+# cancel (neither), or allow (both, which some games don't expect)
+#dpad_conflict = "last"
+
+# How long an arrow key is held in the menu or ROM picker before it repeats,
+# in milliseconds, and how many times a second it does then
+#menu_repeat_delay = 400
+#menu_repeat_rate = 12
+
+# Key to quit with, pressed twice to confirm, with ctrl-, alt- or shift- in
+# front for modifiers (Ctrl-C always quits right away)
+#quit_key = "ctrl-q"
+
+# Key bindings, on top of the defaults: KEY = "INPUT"
+[bindings]
+#left = "Left"
+#x = "A"
+
+# Gamepad buttons, with the `gamepad` feature, on top of the defaults:
+# BUTTON = "INPUT", where BUTTON is south, east, north, west, lb, rb, select,
+# start, up, down, left or right
+[gamepad]
+#west = "B"
+"##;
+
+/// CPU cycles in a frame, at normal speed: 154 lines of 456
+const CYCLES_PER_FRAME: u32 = 154 * 456;
+
+/// Most frames `--frameskip` can skip between the ones that are drawn
+pub const MAX_FRAMESKIP: u32 = 9;
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub bindings: Vec<(KeyCode, JoypadInput)>,
+    pub gamepad: Vec<(Button, JoypadInput)>,
+    pub turbo: Vec<JoypadInput>,
+    pub turbo_rate: Option<f64>,
+    pub dpad_conflict: Option<DpadConflict>,
+    pub quit_key: Option<KeyCombo>,
+    pub menu_repeat_delay: Option<Duration>,
+    pub menu_repeat_rate: Option<f64>,
+    pub palette: Option<Palette>,
+    pub color_correct: Option<bool>,
+    pub keep_last_frame: Option<bool>,
+    pub blend: Option<bool>,
+    pub accessibility_outline: Option<bool>,
+    pub outline_color: Option<GameboyRgb>,
+    pub bell: Option<bool>,
+    pub speed: Option<f64>,
+    pub frameskip: Option<u32>,
+    pub max_render_fps: Option<f64>,
+    pub pacing: Option<Pacing>,
+    pub spin_threshold: Option<Duration>,
+    pub sync_output: Option<bool>,
+    pub audio: Option<bool>,
+    pub renderer: Option<Backend>,
+    pub fullres: Option<bool>,
+    pub ascii: Option<bool>,
+    pub scale_width: Option<usize>,
+    pub correct_aspect: Option<bool>,
+    pub cell_aspect: Option<f64>,
+    pub color_mode: Option<ColorMode>,
+    pub grayscale: Option<bool>,
+    pub brightness: Option<f64>,
+    pub contrast: Option<f64>,
+    pub gamma: Option<f64>,
+    pub save_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Path to the config file, if there is a place for it.
+    pub fn path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+
+        dir.map(|dir| dir.join("gbc-terminal").join("config.toml"))
+    }
+
+    /// Load the config file, creating a default one if it does not exist yet.
+    ///
+    /// Problems with the file are logged, and never stop the emulator from
+    /// starting.
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let created = path.parent().map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| std::fs::write(&path, DEFAULT));
+                match created {
+                    Ok(()) => log::info!("Created a default config at {}", path.display()),
+                    Err(e) => log::warn!("Failed to create {}: {}", path.display(), e),
+                }
+                return Self::default();
+            }
+            Err(e) => {
+                log::error!("Failed to read {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+
+        let (config, errors) = Self::parse(&text);
+        for e in errors {
+            log::error!("{}: {}", path.display(), e);
+        }
+        config
+    }
+
+    /// Parse a config file.
+    ///
+    /// Invalid settings are skipped, and returned as errors naming the
+    /// offending key.
+    pub fn parse(text: &str) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+
+        let table = match text.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                errors.push(e.to_string());
+                return (config, errors);
+            }
+        };
+
+        for (key, value) in &table {
+            let result = match key.as_str() {
+                "speed" => number(value).and_then(|speed| parse_speed(&speed.to_string()))
+                    .map(|speed| config.speed = Some(speed)),
+                "frameskip" => value.as_integer().ok_or_else(|| format!("expected a number, got {}", value))
+                    .and_then(|frameskip| parse_frameskip(&frameskip.to_string()))
+                    .map(|frameskip| config.frameskip = Some(frameskip)),
+                "max_render_fps" => number(value).and_then(|fps| parse_max_render_fps(&fps.to_string()))
+                    .map(|fps| config.max_render_fps = Some(fps)),
+                "pacing" => string(value).and_then(str::parse).map(|pacing| config.pacing = Some(pacing)),
+                "spin_threshold" => value.as_integer().ok_or_else(|| format!("expected a number, got {}", value))
+                    .and_then(|micros| pace::parse_spin_threshold(&micros.to_string()))
+                    .map(|threshold| config.spin_threshold = Some(threshold)),
+                "sync_output" => boolean(value).map(|sync| config.sync_output = Some(sync)),
+                "audio" => boolean(value).map(|audio| config.audio = Some(audio)),
+                "renderer" => string(value).and_then(str::parse).map(|backend| config.renderer = Some(backend)),
+                "fullres" => boolean(value).map(|fullres| config.fullres = Some(fullres)),
+                "ascii" => boolean(value).map(|ascii| config.ascii = Some(ascii)),
+                "scale_width" => value.as_integer().ok_or_else(|| format!("expected a number, got {}", value))
+                    .and_then(|scale| parse_scale_width(&scale.to_string()))
+                    .map(|scale| config.scale_width = Some(scale)),
+                "correct_aspect" => boolean(value).map(|correct| config.correct_aspect = Some(correct)),
+                "cell_aspect" => number(value).and_then(|aspect| parse_cell_aspect(&aspect.to_string()))
+                    .map(|aspect| config.cell_aspect = Some(aspect)),
+                "color_mode" => string(value).and_then(str::parse).map(|mode| config.color_mode = Some(mode)),
+                "grayscale" => boolean(value).map(|grayscale| config.grayscale = Some(grayscale)),
+                "brightness" => number(value).and_then(|n| tone::parse_factor(&n.to_string()))
+                    .map(|brightness| config.brightness = Some(brightness)),
+                "contrast" => number(value).and_then(|n| tone::parse_factor(&n.to_string()))
+                    .map(|contrast| config.contrast = Some(contrast)),
+                "gamma" => number(value).and_then(|n| tone::parse_factor(&n.to_string()))
+                    .map(|gamma| config.gamma = Some(gamma)),
+                "save_dir" => string(value).map(|dir| config.save_dir = Some(expand_home(dir))),
+                "color_correct" => boolean(value).map(|correct| config.color_correct = Some(correct)),
+                "keep_last_frame" => boolean(value).map(|keep| config.keep_last_frame = Some(keep)),
+                "blend" => boolean(value).map(|blend| config.blend = Some(blend)),
+                "accessibility_outline" => boolean(value).map(|outline| config.accessibility_outline = Some(outline)),
+                "outline_color" => string(value).and_then(palette::parse_hex).map(|color| config.outline_color = Some(color)),
+                "bell" => boolean(value).map(|bell| config.bell = Some(bell)),
+                "palette" => palette(value).map(|palette| config.palette = Some(palette)),
+                "turbo" => turbo(value).map(|turbo| config.turbo = turbo),
+                "turbo_rate" => number(value).and_then(|rate| input::parse_turbo_rate(&rate.to_string()))
+                    .map(|rate| config.turbo_rate = Some(rate)),
+                "dpad_conflict" => string(value).and_then(str::parse).map(|how| config.dpad_conflict = Some(how)),
+                "quit_key" => string(value).and_then(str::parse).map(|key| config.quit_key = Some(key)),
+                "menu_repeat_delay" => number(value).and_then(|delay| input::parse_repeat_delay(&delay.to_string()))
+                    .map(|delay| config.menu_repeat_delay = Some(delay)),
+                "menu_repeat_rate" => number(value).and_then(|rate| input::parse_repeat_rate(&rate.to_string()))
+                    .map(|rate| config.menu_repeat_rate = Some(rate)),
+                "bindings" => match value.as_table() {
+                    Some(bindings) => {
+                        for (key, input) in bindings {
+                            match binding(key, input) {
+                                Ok(binding) => config.bindings.push(binding),
+                                Err(e) => errors.push(format!("bindings.{}: {}", key, e)),
+                            }
+                        }
+                        Ok(())
+                    }
+                    None => Err("expected a table of KEY = \"INPUT\"".to_string()),
+                },
+                "gamepad" => match value.as_table() {
+                    Some(bindings) => {
+                        for (button, input) in bindings {
+                            match gamepad_binding(button, input) {
+                                Ok(binding) => config.gamepad.push(binding),
+                                Err(e) => errors.push(format!("gamepad.{}: {}", button, e)),
+                            }
+                        }
+                        Ok(())
+                    }
+                    None => Err("expected a table of BUTTON = \"INPUT\"".to_string()),
+                },
+                _ => Err("unknown setting".to_string()),
+            };
+
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", key, e));
+            }
+        }
+
+        (config, errors)
+    }
+}
+
+/// Parse how many terminal columns each cell is drawn across.
+pub fn parse_scale_width(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(scale @ 1..=4) => Ok(scale),
+        _ => Err(format!("Width scale must be 1, 2, 3 or 4, got {}", s)),
+    }
+}
+
+/// Parse a position on the terminal, as `col,row` counting from 0 at the top
+/// left.
+pub fn parse_position(s: &str) -> Result<(u16, u16), String> {
+    let position = s.split_once(',').and_then(|(col, row)| Some((col.trim().parse().ok()?, row.trim().parse().ok()?)));
+    position.ok_or_else(|| format!("Expected a position as col,row, got: {}", s))
+}
+
+/// Parse the height of a terminal cell over its width.
+pub fn parse_cell_aspect(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(aspect) if (1.0..=4.0).contains(&aspect) => Ok(aspect),
+        _ => Err(format!("Expected a cell height over width from 1 to 4, got: {}", s)),
+    }
+}
+
+pub fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(format!("Expected a positive number, got: {}", s)),
+    }
+}
+
+pub fn parse_frame_count(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(frames) if frames >= 1 => Ok(frames),
+        _ => Err(format!("Expected a number of frames, at least 1, got: {}", s)),
+    }
+}
+
+/// Parse `--cycle-step`, from a cycle to a whole frame.
+pub fn parse_cycle_step(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(cycles @ 1..=CYCLES_PER_FRAME) => Ok(cycles),
+        _ => Err(format!("Expected a number of cycles from 1 to {}, got: {}", CYCLES_PER_FRAME, s)),
+    }
+}
+
+/// Parse `--cpu-clock`, a multiplier of the CPU's speed.
+pub fn parse_cpu_clock(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(clock) if (0.25..=8.0).contains(&clock) => Ok(clock),
+        _ => Err(format!("Expected a CPU clock multiplier from 0.25 to 8, got: {}", s)),
+    }
+}
+
+pub fn parse_frameskip(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(frameskip) if frameskip <= MAX_FRAMESKIP => Ok(frameskip),
+        _ => Err(format!("Expected a number of frames from 0 to {}, got: {}", MAX_FRAMESKIP, s)),
+    }
+}
+
+pub fn parse_max_render_fps(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps >= 1.0 => Ok(fps),
+        _ => Err(format!("Expected a number of frames per second, at least 1, got: {}", s)),
+    }
+}
+
+/// Paths in the file can start with `~`, for the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+fn number(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Float(f) => Ok(*f),
+        Value::Integer(i) => Ok(*i as f64),
+        _ => Err(format!("expected a number, got {}", value)),
+    }
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    value.as_bool().ok_or_else(|| format!("expected true or false, got {}", value))
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value.as_str().ok_or_else(|| format!("expected a string, got {}", value))
+}
+
+fn palette(value: &Value) -> Result<Palette, String> {
+    match value {
+        Value::String(name) => name.parse(),
+        Value::Array(colors) => {
+            let colors = colors.iter()
+                .map(|color| color.as_str().ok_or_else(|| format!("expected a hex color, got {}", color)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Palette::from_hex(&colors)
+        }
+        _ => Err(format!("expected a palette name or 4 hex colors, got {}", value)),
+    }
+}
+
+fn turbo(value: &Value) -> Result<Vec<JoypadInput>, String> {
+    let inputs = value.as_array().ok_or_else(|| format!("expected a list of joypad inputs, got {}", value))?;
+    inputs.iter()
+        .map(|input| string(input).and_then(input::parse_joypad_input))
+        .collect()
+}
+
+fn binding(key: &str, input: &Value) -> Result<(KeyCode, JoypadInput), String> {
+    let input = input.as_str().ok_or_else(|| format!("expected a joypad input, got {}", input))?;
+    Ok((input::parse_keycode(key)?, input::parse_joypad_input(input)?))
+}
+
+fn gamepad_binding(button: &str, input: &Value) -> Result<(Button, JoypadInput), String> {
+    let input = input.as_str().ok_or_else(|| format!("expected a joypad input, got {}", input))?;
+    Ok((button.parse()?, input::parse_joypad_input(input)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let (config, errors) = Config::parse(DEFAULT);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(config.bindings.is_empty() && config.speed.is_none());
+
+        let (config, errors) = Config::parse(r##"
+            speed = 2
+            frameskip = 1
+            sync_output = false
+            max_render_fps = 20
+            pacing = "spin"
+            spin_threshold = 500
+            audio = false
+            ascii = true
+            renderer = "sixel"
+            scale_width = 2
+            cell_aspect = 2.25
+            color_mode = "256"
+            gamma = 1.5
+            color_correct = true
+            keep_last_frame = true
+            blend = true
+            accessibility_outline = true
+            outline_color = "#ff00ff"
+            bell = true
+            save_dir = "/tmp/saves"
+            turbo = ["a", "B"]
+            dpad_conflict = "cancel"
+            quit_key = "alt-x"
+            menu_repeat_delay = 250
+            menu_repeat_rate = 20
+            palette = ["#ffffff", "#aaaaaa", "#555555", "#000000"]
+            [bindings]
+            x = "A"
+            left = "Left"
+            [gamepad]
+            west = "B"
+        "##);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.speed, Some(2.0));
+        assert_eq!(config.frameskip, Some(1));
+        assert_eq!(config.sync_output, Some(false));
+        assert_eq!(config.max_render_fps, Some(20.0));
+        assert_eq!(config.pacing, Some(Pacing::Spin));
+        assert_eq!(config.spin_threshold, Some(Duration::from_micros(500)));
+        assert_eq!(config.audio, Some(false));
+        assert_eq!(config.ascii, Some(true));
+        assert_eq!(config.renderer, Some(Backend::Sixel));
+        assert_eq!(config.scale_width, Some(2));
+        assert_eq!(config.cell_aspect, Some(2.25));
+        assert_eq!(config.color_mode, Some(ColorMode::Ansi256));
+        assert_eq!(config.gamma, Some(1.5));
+        assert_eq!(config.color_correct, Some(true));
+        assert_eq!(config.keep_last_frame, Some(true));
+        assert_eq!(config.blend, Some(true));
+        assert_eq!(config.accessibility_outline, Some(true));
+        assert_eq!(config.outline_color, Some(GameboyRgb { red: 0xFF, green: 0, blue: 0xFF }));
+        assert_eq!(config.bell, Some(true));
+        assert_eq!(config.save_dir, Some(PathBuf::from("/tmp/saves")));
+        assert_eq!(config.palette, "grey".parse().ok());
+        assert_eq!(config.bindings.len(), 2);
+        assert_eq!(config.gamepad, [(Button::West, JoypadInput::B)]);
+        assert_eq!(config.turbo, [JoypadInput::A, JoypadInput::B]);
+        assert_eq!(config.dpad_conflict, Some(DpadConflict::Cancel));
+        assert_eq!(config.menu_repeat_delay, Some(Duration::from_millis(250)));
+        assert_eq!(config.menu_repeat_rate, Some(20.0));
+        assert_eq!(config.quit_key, "alt-x".parse().ok());
+
+        // Bad settings are reported by key, and the rest still apply
+        let (config, errors) = Config::parse(r#"
+            speed = -1
+            fullres = "yes"
+            palette = "sepia"
+            colour = 1
+            audio = true
+            turbo_rate = 100
+            [bindings]
+            x = "Turbo"
+            [gamepad]
+            trigger = "A"
+        "#);
+        assert_eq!(config.audio, Some(true));
+        assert!(config.speed.is_none() && config.fullres.is_none() && config.palette.is_none());
+        let keys: Vec<_> = errors.iter().map(|e| e.split(':').next().unwrap()).collect();
+        assert_eq!(keys, ["bindings.x", "colour", "fullres", "gamepad.trigger", "palette", "speed", "turbo_rate"]);
+
+        // Syntax errors leave everything at the defaults
+        let (_, errors) = Config::parse("speed = ");
+        assert_eq!(errors.len(), 1);
+    }
+}
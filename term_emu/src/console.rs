@@ -0,0 +1,255 @@
+//! Interactive debug console
+//!
+//! With `--debug`, emulation drops into a line-based prompt whenever a PC
+//! breakpoint is hit or the break key is pressed. The terminal is taken out
+//! of raw mode and the alternate screen while the prompt is up, and restored
+//! once emulation continues.
+use std::io::{self, BufRead, Write};
+
+use gbc::Gameboy;
+
+use crate::disasm::parse_addr;
+use crate::input::InputLock;
+use crate::tty;
+
+const HELP: &str = "\
+Commands (addresses and values in hex):
+  r ADDR [LEN]   read memory
+  w ADDR VALUE   write memory
+  b ADDR         set a breakpoint on PC
+  d ADDR         delete a breakpoint
+  bl             list breakpoints
+  regs           show registers
+  l [COUNT]      disassemble at PC
+  s [COUNT]      step instructions
+  c              continue";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Read { addr: u16, len: u16 },
+    Write { addr: u16, value: u8 },
+    Break(u16),
+    Delete(u16),
+    Breakpoints,
+    Registers,
+    List(usize),
+    Step(usize),
+    Continue,
+    Help,
+}
+
+fn parse_count(arg: Option<&str>, default: usize) -> Result<usize, String> {
+    arg.map_or(Ok(default), |n| n.parse().map_err(|_| format!("Invalid count: {}", n)))
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    let addr = |i: usize| args.get(i).ok_or("Missing address".to_string()).and_then(|a| parse_addr(a));
+
+    match args.first().copied() {
+        Some("r") | Some("read") => {
+            let len = match args.get(2) {
+                Some(len) => parse_addr(len)?,
+                None => 1,
+            };
+            Ok(Command::Read { addr: addr(1)?, len })
+        }
+        Some("w") | Some("write") => {
+            let value = args.get(2).ok_or("Missing value")?;
+            let value = u8::from_str_radix(value.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("Invalid value: {}", value))?;
+            Ok(Command::Write { addr: addr(1)?, value })
+        }
+        Some("b") | Some("break") => Ok(Command::Break(addr(1)?)),
+        Some("d") | Some("delete") => Ok(Command::Delete(addr(1)?)),
+        Some("bl") => Ok(Command::Breakpoints),
+        Some("regs") => Ok(Command::Registers),
+        Some("l") | Some("list") => Ok(Command::List(parse_count(args.get(1).copied(), 5)?)),
+        Some("s") | Some("step") => Ok(Command::Step(parse_count(args.get(1).copied(), 1)?)),
+        Some("c") | Some("continue") => Ok(Command::Continue),
+        Some("h") | Some("help") | None => Ok(Command::Help),
+        Some(other) => Err(format!("Unknown command: {} (try `help`)", other)),
+    }
+}
+
+pub struct Console {
+    pub breakpoints: Vec<u16>,
+    input: InputLock,
+
+    /// Set whenever the prompt was shown, as the game needs a full redraw
+    pub shown: bool,
+}
+
+impl Console {
+    pub fn new(input: InputLock) -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            input,
+            shown: false,
+        }
+    }
+
+    /// Run until the next frame is ready, dropping into the prompt on every
+    /// breakpoint hit along the way.
+    pub fn run_until_frame(&mut self, gameboy: &mut Gameboy) {
+        while !gameboy.run_until_break(&self.breakpoints) {
+            let reason = format!("Breakpoint hit at {:#06X}", gameboy.registers().PC);
+            self.prompt(gameboy, &reason);
+        }
+    }
+
+    /// Run for `cycles` cycles, or until the next frame is ready, as
+    /// `Gameboy::step_cycles` does, dropping into the prompt on every
+    /// breakpoint hit along the way.
+    ///
+    /// Returns `true` if a frame is ready.
+    pub fn run_cycles(&mut self, gameboy: &mut Gameboy, cycles: u32) -> bool {
+        let mut taken = 0;
+        while taken < cycles && !gameboy.ppu().is_frame_ready() {
+            taken += gameboy.step();
+
+            let pc = gameboy.registers().PC;
+            if !gameboy.cpu().halted && self.breakpoints.contains(&pc) {
+                self.prompt(gameboy, &format!("Breakpoint hit at {:#06X}", pc));
+            }
+        }
+
+        gameboy.ppu().is_frame_ready()
+    }
+
+    /// Show the prompt until the user continues.
+    pub fn prompt(&mut self, gameboy: &mut Gameboy, reason: &str) {
+        let input = self.input.clone();
+        let _paused = input.pause();
+        let mut stdout = io::stdout();
+
+        // Hand the terminal back for line-buffered input
+        tty::restore();
+
+        println!("{}", reason);
+        let _ = self.execute(gameboy, Command::List(1), &mut stdout);
+
+        let mut stdin = io::stdin().lock();
+        loop {
+            print!("(gbc) ");
+            let _ = stdout.flush();
+
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+
+            let result = parse_command(&line).and_then(|command| {
+                self.execute(gameboy, command, &mut stdout).map_err(|e| e.to_string())
+            });
+            match result {
+                Ok(true) => break,
+                Ok(false) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        if let Err(e) = tty::enter() {
+            log::error!("Failed to set up the terminal: {}", e);
+        }
+        self.shown = true;
+    }
+
+    /// Execute a command, writing any output to `out`.
+    ///
+    /// Returns `true` once emulation should continue.
+    pub fn execute<W: Write>(&mut self, gameboy: &mut Gameboy, command: Command, out: &mut W) -> io::Result<bool> {
+        match command {
+            Command::Read { addr, len } => {
+                for row in (0..len).step_by(16) {
+                    let start = addr.wrapping_add(row);
+                    write!(out, "{:04X}:", start)?;
+                    for i in 0..16.min(len - row) {
+                        write!(out, " {:02X}", gameboy.read_memory(start.wrapping_add(i)))?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+            Command::Write { addr, value } => {
+                gameboy.write_memory(addr, value);
+            }
+            Command::Break(addr) => {
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+            }
+            Command::Delete(addr) => {
+                self.breakpoints.retain(|&b| b != addr);
+            }
+            Command::Breakpoints => {
+                for addr in &self.breakpoints {
+                    writeln!(out, "{:#06X}", addr)?;
+                }
+            }
+            Command::Registers => {
+                writeln!(out, "{}", gameboy.registers())?;
+            }
+            Command::List(count) => {
+                let pc = gameboy.registers().PC;
+                // Instructions are at most 3 bytes long
+                let bytes: Vec<u8> = (0..count as u16 * 3).map(|i| gameboy.read_memory(pc.wrapping_add(i))).collect();
+                for line in gbc::disassemble(&bytes, pc, count) {
+                    writeln!(out, "{}", crate::disasm::format_line(&line))?;
+                }
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    gameboy.step();
+                }
+                self.execute(gameboy, Command::List(1), out)?;
+            }
+            Command::Continue => return Ok(true),
+            Command::Help => writeln!(out, "{}", HELP)?,
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_command("r c000 20"), Ok(Command::Read { addr: 0xC000, len: 0x20 }));
+        assert_eq!(parse_command("w 0xC000 0x42"), Ok(Command::Write { addr: 0xC000, value: 0x42 }));
+        assert_eq!(parse_command("b $150"), Ok(Command::Break(0x150)));
+        assert_eq!(parse_command("s"), Ok(Command::Step(1)));
+        assert_eq!(parse_command("l 10"), Ok(Command::List(10)));
+        assert_eq!(parse_command(""), Ok(Command::Help));
+        assert!(parse_command("r").is_err());
+        assert!(parse_command("w c000 zz").is_err());
+        assert!(parse_command("jump").is_err());
+    }
+
+    #[test]
+    fn memory_and_breakpoints() {
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap();
+        let mut console = Console::new(InputLock::default());
+        let mut out = Vec::new();
+
+        console.execute(&mut gameboy, Command::Write { addr: 0xC000, value: 0x42 }, &mut out).unwrap();
+        console.execute(&mut gameboy, Command::Read { addr: 0xC000, len: 2 }, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "C000: 42 00\n");
+
+        // The ROM is all NOPs, so PC walks straight into the breakpoint
+        let pc = gameboy.registers().PC;
+        console.execute(&mut gameboy, Command::Break(pc + 2), &mut Vec::new()).unwrap();
+        assert!(!gameboy.run_until_break(&console.breakpoints));
+        assert_eq!(gameboy.registers().PC, pc + 2);
+
+        // Continuing runs past it
+        console.execute(&mut gameboy, Command::Delete(pc + 2), &mut Vec::new()).unwrap();
+        assert!(gameboy.run_until_break(&console.breakpoints));
+        assert!(console.execute(&mut gameboy, Command::Continue, &mut Vec::new()).unwrap());
+    }
+}
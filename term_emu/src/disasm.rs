@@ -0,0 +1,67 @@
+//! Static disassembly of cartridge ROM, for the `disasm` subcommand
+use gbc::Disassembly;
+
+const BANK_SIZE: usize = 0x4000;
+
+/// Parse a 16-bit address, in hex with an optional `0x` or `$` prefix.
+pub fn parse_addr(s: &str) -> Result<u16, String> {
+    let hex = s.trim_start_matches("0x").trim_start_matches("0X").trim_start_matches('$');
+    u16::from_str_radix(hex, 16).map_err(|_| format!("Invalid address: {} (expected hex, e.g. 0x150)", s))
+}
+
+/// Disassemble `count` instructions starting at `addr`, as seen by the CPU
+/// with ROM bank `bank` mapped at 0x4000-0x7FFF.
+///
+/// Disassembly stops at the end of the bank that `addr` is in.
+pub fn disassemble_rom(rom: &[u8], addr: u16, bank: usize, count: usize) -> Result<Vec<Disassembly>, String> {
+    let (start, end) = match addr as usize {
+        addr @ 0x0000..=0x3FFF => (addr, BANK_SIZE),
+        addr @ 0x4000..=0x7FFF => {
+            let base = bank * BANK_SIZE;
+            (base + addr - BANK_SIZE, base + BANK_SIZE)
+        }
+        _ => return Err(format!("{:#06X} is outside of cartridge ROM (0x0000-0x7FFF)", addr)),
+    };
+
+    if start >= rom.len() {
+        return Err(format!("{:#06X} in bank {} is past the end of the ROM", addr, bank));
+    }
+
+    Ok(gbc::disassemble(&rom[start..end.min(rom.len())], addr, count))
+}
+
+/// Format a line as `address: raw bytes  mnemonic`.
+pub fn format_line(line: &Disassembly) -> String {
+    let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:04X}: {:<8}  {}", line.addr, bytes.join(" "), line.text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disassemble_banked_rom() {
+        let mut rom = vec![0u8; 4 * BANK_SIZE];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[2 * BANK_SIZE] = 0x3E; // ld A, 0x42 at the start of bank 2
+        rom[2 * BANK_SIZE + 1] = 0x42;
+
+        let lines = disassemble_rom(&rom, 0x100, 1, 2).unwrap();
+        assert_eq!(format_line(&lines[0]), "0100: 00        nop");
+        assert_eq!(format_line(&lines[1]), "0101: C3 50 01  jp 0x0150");
+
+        let lines = disassemble_rom(&rom, 0x4000, 2, 1).unwrap();
+        assert_eq!(lines[0].text, "ld A, 0x42");
+
+        // Stops at the end of the bank
+        assert_eq!(disassemble_rom(&rom, 0x7FFF, 1, 10).unwrap().len(), 1);
+
+        assert!(disassemble_rom(&rom, 0x4000, 4, 1).is_err());
+        assert!(disassemble_rom(&rom, 0x8000, 1, 1).is_err());
+
+        assert_eq!(parse_addr("0x150"), Ok(0x150));
+        assert_eq!(parse_addr("$4000"), Ok(0x4000));
+        assert!(parse_addr("xyz").is_err());
+    }
+}
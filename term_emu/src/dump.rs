@@ -0,0 +1,155 @@
+//! Headless frame dumps, for `run --dump-frames` and `--until-frame`
+//!
+//! Without a terminal to draw to, frames are written out as numbered PNGs
+//! instead, e.g. to make a video or to compare runs. There is no live input,
+//! but a movie can be played back. `--until-frame` only keeps the last frame,
+//! which can be checked against a golden image.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gbc::Gameboy;
+use gbc::ppu::{FrameBuffer, LCD_HEIGHT, LCD_WIDTH};
+
+use crate::capture;
+use crate::movie::Player;
+use crate::palette::Screen;
+use crate::serial::SerialOut;
+
+/// Frames to dump when neither `--dump-count` nor a movie says otherwise
+pub const DEFAULT_FRAMES: u32 = 600;
+
+/// Run `frames` frames, writing each one to `dir` as `000000.png`,
+/// `000001.png` and so on, and passing on serial output as it comes. The
+/// directory is created if needed.
+pub fn run(gameboy: &mut Gameboy, dir: &Path, frames: u32, screen: Screen, player: Option<Player>,
+           serial: Option<&mut SerialOut>) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    run_frames(gameboy, frames, screen, player, serial, |i, frame_buffer| {
+        let path = dir.join(format!("{:06}.png", i));
+        capture::save_png(frame_buffer, &path).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))
+    })
+}
+
+/// Run `frames` frames, like [`run`], and return the last one as it would be
+/// shown, for `--until-frame`.
+pub fn run_until(gameboy: &mut Gameboy, frames: u32, screen: Screen, player: Option<Player>,
+                 serial: Option<&mut SerialOut>) -> io::Result<FrameBuffer> {
+    let mut last = FrameBuffer::new();
+    run_frames(gameboy, frames, screen, player, serial, |i, frame_buffer| {
+        if i + 1 == frames {
+            last.data.copy_from_slice(&frame_buffer.data);
+        }
+        Ok(())
+    })?;
+
+    Ok(last)
+}
+
+/// Run `frames` frames, playing back the movie and passing on serial output,
+/// and hand each one to `each` as it would be shown.
+fn run_frames(gameboy: &mut Gameboy, frames: u32, screen: Screen, mut player: Option<Player>,
+              mut serial: Option<&mut SerialOut>, mut each: impl FnMut(u32, &FrameBuffer) -> io::Result<()>)
+              -> io::Result<()> {
+    let mut joypad_events = Vec::new();
+    let mut current = FrameBuffer::new();
+
+    for i in 0..frames {
+        if let Some(movie) = &mut player {
+            if !movie.frame(gameboy, &mut joypad_events) {
+                player = None;
+                joypad_events.clear();
+            }
+        }
+
+        gameboy.frame(Some(&joypad_events));
+        screen.show(gameboy, &mut current);
+        each(i, &current)?;
+
+        if let Some(serial) = serial.as_deref_mut() {
+            serial.frame(gameboy)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How far a frame is from a golden image
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// Largest difference in any channel of any pixel
+    pub max_delta: u8,
+    /// Pixels that differ at all
+    pub pixels: usize,
+}
+
+/// Compare `frame_buffer` with the PNG at `golden`, which has to be a whole
+/// frame.
+pub fn compare(frame_buffer: &FrameBuffer, golden: &Path) -> Result<Difference, String> {
+    let image = image::open(golden).map_err(|e| format!("Failed to read {}: {}", golden.display(), e))?.to_rgb8();
+    if image.dimensions() != (LCD_WIDTH as u32, LCD_HEIGHT as u32) {
+        return Err(format!("{} is {}x{}, not {}x{}", golden.display(), image.width(), image.height(),
+                           LCD_WIDTH, LCD_HEIGHT));
+    }
+
+    let pixels = capture::frame_pixels(frame_buffer);
+    let deltas: Vec<u8> = pixels.chunks(3).zip(image.as_raw().chunks(3))
+        .map(|(ours, theirs)| ours.iter().zip(theirs).map(|(a, b)| a.abs_diff(*b)).max().unwrap())
+        .collect();
+
+    Ok(Difference {
+        max_delta: deltas.iter().copied().max().unwrap_or(0),
+        pixels: deltas.iter().filter(|&&delta| delta > 0).count(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::cartridge::Cartridge;
+    use gbc::ppu::GameboyRgb;
+
+    use super::*;
+
+    #[test]
+    fn writes_one_png_per_frame() {
+        let dir = std::env::temp_dir().join(format!("gbcemu-dump-{}", std::process::id()));
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap();
+
+        run(&mut gameboy, &dir, 3, Screen::default(), None, None).unwrap();
+
+        let mut names: Vec<_> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["000000.png", "000001.png", "000002.png"]);
+
+        let image = image::open(dir.join("000000.png")).unwrap();
+        assert_eq!((image.width(), image.height()), (LCD_WIDTH as u32, LCD_HEIGHT as u32));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn frames_are_compared_with_golden_images() {
+        let path = std::env::temp_dir().join(format!("gbcemu-golden-{}.png", std::process::id()));
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap();
+
+        let mut frame = run_until(&mut gameboy, 3, Screen::default(), None, None).unwrap();
+        capture::save_png(&frame, &path).unwrap();
+        assert_eq!(compare(&frame, &path), Ok(Difference { max_delta: 0, pixels: 0 }));
+
+        // Away from what is there, whichever way there is room
+        let nudge = |value: u8, by: u8| if value >= by { value - by } else { value + by };
+        let pixel = frame.read(10, 10);
+        frame.write(10, 10, GameboyRgb { red: nudge(pixel.red, 0x30), ..pixel });
+        let pixel = frame.read(11, 10);
+        frame.write(11, 10, GameboyRgb { blue: nudge(pixel.blue, 0x01), ..pixel });
+        assert_eq!(compare(&frame, &path), Ok(Difference { max_delta: 0x30, pixels: 2 }));
+
+        image::save_buffer(&path, &[0; 3 * 4], 2, 2, image::ColorType::Rgb8).unwrap();
+        assert!(compare(&frame, &path).unwrap_err().ends_with("is 2x2, not 160x144"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
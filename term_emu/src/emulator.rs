@@ -0,0 +1,175 @@
+//! The emulator without the terminal around it
+//!
+//! An `Emulator` runs a game a frame at a time with the joypad input it is
+//! given, maps the frames through the palette (or corrects their colors),
+//! and renders them with one of the backends to any `Write`. Reading keys,
+//! timing and everything else the `run` command does on top is left to the
+//! caller.
+use std::io::{self, Write};
+
+use gbc::Gameboy;
+use gbc::cartridge::Cartridge;
+use gbc::joypad::JoypadEvent;
+use gbc::ppu::FrameBuffer;
+
+use crate::palette::{Palette, Screen};
+use crate::render::{Backend, RenderOptions, Renderer};
+
+/// How an `Emulator` shows its frames
+#[derive(Clone, Copy, Debug)]
+pub struct EmulatorOptions {
+    pub backend: Backend,
+    pub render: RenderOptions,
+    /// For monochrome games, ignored for color ones
+    pub palette: Option<Palette>,
+    /// Make color games look as they did on the CGB's LCD
+    pub color_correct: bool,
+    /// Keep the last frame while the LCD is off, instead of a blank screen
+    pub keep_last_frame: bool,
+}
+
+impl Default for EmulatorOptions {
+    fn default() -> Self {
+        Self {
+            backend: Backend::HalfBlock,
+            render: RenderOptions::default(),
+            palette: None,
+            color_correct: false,
+            keep_last_frame: false,
+        }
+    }
+}
+
+pub struct Emulator {
+    gameboy: Gameboy,
+    screen: Screen,
+    renderer: Box<dyn Renderer>,
+
+    /// The last frame, after applying the palette or color correction
+    frame: FrameBuffer,
+    /// Rendered output, kept around between frames
+    out: Vec<u8>,
+}
+
+impl Emulator {
+    /// Start the game in `cartridge`, rendered for an 80x24 terminal until
+    /// [`resize`](Self::resize) says otherwise.
+    pub fn new(cartridge: Cartridge, options: EmulatorOptions) -> gbc::Result<Self> {
+        let screen = Screen::new(&cartridge, options.palette, options.color_correct, options.keep_last_frame);
+        let mut renderer = options.backend.renderer(options.render);
+        renderer.resize(80, 24);
+
+        Ok(Self {
+            gameboy: Gameboy::init(cartridge, false)?,
+            screen,
+            renderer,
+            frame: FrameBuffer::new(),
+            out: Vec::new(),
+        })
+    }
+
+    /// Run a single frame, with `joypad_events` applied at the start of the
+    /// next one.
+    ///
+    /// Returns the frame, after applying the palette or color correction.
+    pub fn step_frame(&mut self, joypad_events: &[JoypadEvent]) -> &FrameBuffer {
+        self.gameboy.frame(Some(joypad_events));
+        self.screen.show(&self.gameboy, &mut self.frame);
+
+        &self.frame
+    }
+
+    /// The last frame from [`step_frame`](Self::step_frame).
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    /// Write the escape sequences that bring the terminal up to date with the
+    /// last frame to `out`. Only what changed since the last call is drawn.
+    pub fn render_to(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.out.clear();
+        self.renderer.render(&self.frame, &mut self.out)?;
+        out.write_all(&self.out)
+    }
+
+    /// Update the terminal size, in cells, to center the image in.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.renderer.resize(cols, rows);
+    }
+
+    /// Draw everything on the next [`render_to`](Self::render_to), after
+    /// something else drew over the terminal.
+    pub fn invalidate(&mut self) {
+        self.renderer.invalidate();
+    }
+
+    /// The Gameboy underneath, e.g. for save states or sound.
+    pub fn gameboy(&self) -> &Gameboy {
+        &self.gameboy
+    }
+
+    pub fn gameboy_mut(&mut self) -> &mut Gameboy {
+        &mut self.gameboy
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use gbc::joypad::JoypadInput;
+
+    use super::*;
+
+    fn tetris() -> Cartridge {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/tetris_world.gb");
+        Cartridge::from_bytes(std::fs::read(path).unwrap(), false)
+    }
+
+    #[test]
+    fn frames_are_mapped_and_rendered() {
+        let palette = Palette::NAMED[0].1;
+        let options = EmulatorOptions { palette: Some(palette), ..EmulatorOptions::default() };
+        let mut emulator = Emulator::new(tetris(), options).unwrap();
+
+        for _ in 0..60 {
+            emulator.step_frame(&[JoypadEvent::Down(JoypadInput::Start), JoypadEvent::Up(JoypadInput::Start)]);
+        }
+        let frame = emulator.frame_buffer();
+        assert!(frame.data.iter().all(|pixel| palette.shades.contains(pixel)));
+
+        let mut out = Vec::new();
+        emulator.render_to(&mut out).unwrap();
+        assert!(!out.is_empty());
+
+        // Nothing changed, so there is nothing to draw
+        out.clear();
+        emulator.render_to(&mut out).unwrap();
+        emulator.invalidate();
+        let mut redrawn = Vec::new();
+        emulator.render_to(&mut redrawn).unwrap();
+        assert!(out.len() < redrawn.len());
+    }
+
+    #[test]
+    fn the_screen_goes_blank_with_the_lcd_off() {
+        let palette = Palette::NAMED[0].1;
+        let options = EmulatorOptions { palette: Some(palette), ..EmulatorOptions::default() };
+        let mut blank = Emulator::new(tetris(), options).unwrap();
+        let options = EmulatorOptions { keep_last_frame: true, ..options };
+        let mut kept = Emulator::new(tetris(), options).unwrap();
+
+        // Once set up, Tetris next turns the LCD off going from the copyright
+        // screen to the title
+        for frame in 0.. {
+            blank.step_frame(&[]);
+            kept.step_frame(&[]);
+            if frame > 20 && !blank.gameboy().ppu().lcd_enabled() {
+                break;
+            }
+        }
+
+        assert!(blank.frame_buffer().data.iter().all(|&pixel| pixel == palette.shades[0]));
+        assert!(kept.frame_buffer().data.iter().any(|&pixel| pixel != palette.shades[0]));
+    }
+}
@@ -0,0 +1,241 @@
+//! Gamepad input
+//!
+//! Controllers are read alongside the keyboard, and their buttons are turned
+//! into the same joypad events. Buttons are named by where they sit on the pad
+//! (`south` is A on an Xbox pad and B on a Nintendo one), and mapped to the
+//! Game Boy's inputs as the Game Boy lays them out: `east` is A, and `south` is
+//! B. The left stick works as the D-pad too. Controllers can come and go while
+//! a game runs.
+//!
+//! Gamepads are only read with the `gamepad` feature. Without it, there are
+//! none, and the keyboard is all there is.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use gbc::joypad::JoypadInput;
+
+#[cfg(feature = "gamepad")]
+pub use self::gilrs_input::Gamepads;
+
+#[cfg(not(feature = "gamepad"))]
+pub use self::null_input::Gamepads;
+
+/// Gamepad buttons that can be bound to joypad inputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    South,
+    East,
+    North,
+    West,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "south" => Ok(Self::South),
+            "east" => Ok(Self::East),
+            "north" => Ok(Self::North),
+            "west" => Ok(Self::West),
+            "lb" => Ok(Self::LeftShoulder),
+            "rb" => Ok(Self::RightShoulder),
+            "select" => Ok(Self::Select),
+            "start" => Ok(Self::Start),
+            "up" => Ok(Self::DPadUp),
+            "down" => Ok(Self::DPadDown),
+            "left" => Ok(Self::DPadLeft),
+            "right" => Ok(Self::DPadRight),
+            _ => Err(format!("Unknown gamepad button: {} (expected south, east, north, west, lb, rb, select, \
+                              start, up, down, left or right)", s)),
+        }
+    }
+}
+
+/// Maps gamepad buttons to joypad inputs, like `KeyBindings` for keys.
+#[derive(Clone)]
+pub struct GamepadBindings {
+    map: HashMap<Button, JoypadInput>,
+}
+
+impl GamepadBindings {
+    pub fn lookup(&self, button: Button) -> Option<JoypadInput> {
+        self.map.get(&button).copied()
+    }
+
+    pub fn bind(&mut self, button: Button, input: JoypadInput) {
+        self.map.insert(button, input);
+    }
+}
+
+impl Default for GamepadBindings {
+    fn default() -> Self {
+        let map = [
+            (Button::East, JoypadInput::A),
+            (Button::South, JoypadInput::B),
+            (Button::Start, JoypadInput::Start),
+            (Button::Select, JoypadInput::Select),
+            (Button::DPadUp, JoypadInput::Up),
+            (Button::DPadDown, JoypadInput::Down),
+            (Button::DPadLeft, JoypadInput::Left),
+            (Button::DPadRight, JoypadInput::Right),
+        ];
+
+        Self {
+            map: map.iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+mod gilrs_input {
+    use std::collections::HashMap;
+
+    use gbc::joypad::{JoypadEvent, JoypadInput};
+    use gilrs::{Axis, EventType, GamepadId, Gilrs};
+
+    use super::{Button, GamepadBindings};
+
+    /// How far the stick has to be pushed to press a direction
+    const STICK_THRESHOLD: f32 = 0.5;
+
+    fn button(button: gilrs::Button) -> Option<Button> {
+        Some(match button {
+            gilrs::Button::South => Button::South,
+            gilrs::Button::East => Button::East,
+            gilrs::Button::North => Button::North,
+            gilrs::Button::West => Button::West,
+            gilrs::Button::LeftTrigger => Button::LeftShoulder,
+            gilrs::Button::RightTrigger => Button::RightShoulder,
+            gilrs::Button::Select => Button::Select,
+            gilrs::Button::Start => Button::Start,
+            gilrs::Button::DPadUp => Button::DPadUp,
+            gilrs::Button::DPadDown => Button::DPadDown,
+            gilrs::Button::DPadLeft => Button::DPadLeft,
+            gilrs::Button::DPadRight => Button::DPadRight,
+            _ => return None,
+        })
+    }
+
+    pub struct Gamepads {
+        gilrs: Gilrs,
+        bindings: GamepadBindings,
+
+        /// Inputs each gamepad holds, to let go of if it is unplugged
+        held: HashMap<GamepadId, Vec<JoypadInput>>,
+    }
+
+    impl Gamepads {
+        pub fn new(bindings: GamepadBindings) -> Result<Self, String> {
+            let gilrs = Gilrs::new().map_err(|e| e.to_string())?;
+            for (_, gamepad) in gilrs.gamepads() {
+                log::info!("Gamepad: {}", gamepad.name());
+            }
+
+            Ok(Self {
+                gilrs,
+                bindings,
+                held: HashMap::new(),
+            })
+        }
+
+        fn press(&mut self, id: GamepadId, input: JoypadInput, down: bool, events: &mut Vec<JoypadEvent>) {
+            let held = self.held.entry(id).or_default();
+            let was_down = held.contains(&input);
+            if down && !was_down {
+                held.push(input);
+                events.push(JoypadEvent::Down(input));
+            } else if !down && was_down {
+                held.retain(|&held| held != input);
+                events.push(JoypadEvent::Up(input));
+            }
+        }
+
+        /// Push the joypad events from gamepads since the last poll to
+        /// `events`, returning a message if one was plugged in or out.
+        pub fn poll(&mut self, events: &mut Vec<JoypadEvent>) -> Option<String> {
+            let mut message = None;
+
+            while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+                match event {
+                    EventType::ButtonPressed(pressed, _) | EventType::ButtonRepeated(pressed, _) => {
+                        if let Some(input) = button(pressed).and_then(|button| self.bindings.lookup(button)) {
+                            self.press(id, input, true, events);
+                        }
+                    }
+                    EventType::ButtonReleased(released, _) => {
+                        if let Some(input) = button(released).and_then(|button| self.bindings.lookup(button)) {
+                            self.press(id, input, false, events);
+                        }
+                    }
+                    EventType::AxisChanged(axis @ (Axis::LeftStickX | Axis::LeftStickY), value, _) => {
+                        let (negative, positive) = match axis {
+                            Axis::LeftStickX => (JoypadInput::Left, JoypadInput::Right),
+                            // Up is positive
+                            _ => (JoypadInput::Down, JoypadInput::Up),
+                        };
+                        self.press(id, negative, value < -STICK_THRESHOLD, events);
+                        self.press(id, positive, value > STICK_THRESHOLD, events);
+                    }
+                    EventType::Connected => {
+                        message = Some(format!("Gamepad connected: {}", self.gilrs.gamepad(id).name()));
+                    }
+                    EventType::Disconnected => {
+                        for input in self.held.remove(&id).unwrap_or_default() {
+                            events.push(JoypadEvent::Up(input));
+                        }
+                        message = Some("Gamepad disconnected".to_string());
+                    }
+                    _ => (),
+                }
+            }
+
+            message
+        }
+    }
+}
+
+#[cfg(not(feature = "gamepad"))]
+mod null_input {
+    use gbc::joypad::JoypadEvent;
+
+    use super::GamepadBindings;
+
+    /// Stand-in for builds without gamepad support.
+    pub struct Gamepads;
+
+    impl Gamepads {
+        pub fn new(_bindings: GamepadBindings) -> Result<Self, String> {
+            Err("built without the `gamepad` feature".to_string())
+        }
+
+        pub fn poll(&mut self, _events: &mut Vec<JoypadEvent>) -> Option<String> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buttons_are_bound_the_game_boy_way() {
+        let mut bindings = GamepadBindings::default();
+        assert_eq!(bindings.lookup(Button::East), Some(JoypadInput::A));
+        assert_eq!(bindings.lookup(Button::South), Some(JoypadInput::B));
+        assert_eq!(bindings.lookup(Button::North), None);
+
+        bindings.bind("North".parse().unwrap(), JoypadInput::A);
+        assert_eq!(bindings.lookup(Button::North), Some(JoypadInput::A));
+        assert!("trigger".parse::<Button>().is_err());
+    }
+}
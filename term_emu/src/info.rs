@@ -0,0 +1,150 @@
+//! Cartridge header inspection, for the `info` subcommand
+use std::fmt;
+
+use gbc::cartridge::{Cartridge, CartridgeType};
+
+/// Cartridge headers end at this offset
+const HEADER_END: usize = 0x150;
+
+/// Metadata read from a cartridge header.
+pub struct CartridgeInfo {
+    pub title: String,
+    pub cgb: bool,
+
+    /// `None` if the header holds an unknown value
+    pub cartridge_type: Option<String>,
+    pub cartridge_type_byte: u8,
+    pub mbc: Option<&'static str>,
+    /// Whether the emulator implements the memory bank controller
+    pub mbc_supported: bool,
+    pub rom_size: Option<usize>,
+    pub ram_size: Option<usize>,
+
+    pub header_checksum: u8,
+    pub header_checksum_ok: bool,
+    pub global_checksum: u16,
+}
+
+impl CartridgeInfo {
+    /// Read the header of a ROM image.
+    pub fn from_rom(data: Vec<u8>) -> Result<Self, String> {
+        if data.len() < HEADER_END {
+            return Err(format!("Not a ROM: only {} bytes, too short for a cartridge header", data.len()));
+        }
+
+        let cartridge = Cartridge::from_bytes(data, false);
+        let cartridge_type = cartridge.cartridge_type().ok();
+
+        Ok(Self {
+            title: cartridge.title().unwrap_or_default().trim_end_matches('\0').to_string(),
+            cgb: cartridge.cgb(),
+            cartridge_type: cartridge_type.map(|t| format!("{:?}", t)),
+            cartridge_type_byte: cartridge.cartridge_type_byte(),
+            mbc: cartridge_type.map(|t| t.mbc()),
+            mbc_supported: cartridge_type.is_some_and(|t| t.is_supported()),
+            rom_size: cartridge.rom_size().ok().map(usize::from),
+            ram_size: cartridge.ram_size().ok().map(usize::from),
+            header_checksum: cartridge.header_checksum(),
+            header_checksum_ok: cartridge.verify_header_checksum(),
+            global_checksum: cartridge.global_checksum(),
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "title": self.title,
+            "cgb": self.cgb,
+            "cartridge_type": self.cartridge_type,
+            "cartridge_type_byte": self.cartridge_type_byte,
+            "mbc": self.mbc,
+            "mbc_supported": self.mbc_supported,
+            "rom_size": self.rom_size,
+            "ram_size": self.ram_size,
+            "header_checksum": self.header_checksum,
+            "header_checksum_ok": self.header_checksum_ok,
+            "global_checksum": self.global_checksum,
+        })
+    }
+}
+
+/// Every memory bank controller, the cartridge type bytes that use it, and
+/// whether the emulator supports it, for `info --list-mbc`.
+pub fn mbc_list() -> String {
+    let mut mbcs: Vec<(&str, Vec<u8>)> = Vec::new();
+    for cartridge_type in CartridgeType::ALL {
+        match mbcs.iter_mut().find(|(mbc, _)| *mbc == cartridge_type.mbc()) {
+            Some((_, bytes)) => bytes.push(cartridge_type as u8),
+            None => mbcs.push((cartridge_type.mbc(), vec![cartridge_type as u8])),
+        }
+    }
+
+    mbcs.iter()
+        .map(|(mbc, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+            let supported = if CartridgeType::SUPPORTED_MBCS.contains(mbc) { "supported" } else { "not supported" };
+            format!("{:<14} {:<30} {}", mbc, bytes.join(" "), supported)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Formats sizes in KiB, or "unknown".
+fn size(size: Option<usize>) -> String {
+    match size {
+        Some(size) => format!("{} KiB", size / 1024),
+        None => "unknown".to_string(),
+    }
+}
+
+impl fmt::Display for CartridgeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Title:           {}", self.title)?;
+        writeln!(f, "CGB:             {}", if self.cgb { "yes" } else { "no" })?;
+        writeln!(f, "Cartridge type:  {} ({:#04X})", self.cartridge_type.as_deref().unwrap_or("unknown"),
+                 self.cartridge_type_byte)?;
+        writeln!(f, "MBC:             {} ({})", self.mbc.unwrap_or("unknown"),
+                 if self.mbc_supported { "supported" } else { "not supported" })?;
+        writeln!(f, "ROM size:        {}", size(self.rom_size))?;
+        writeln!(f, "RAM size:        {}", size(self.ram_size))?;
+        writeln!(f, "Header checksum: {:#04X} ({})", self.header_checksum,
+                 if self.header_checksum_ok { "ok" } else { "mismatch" })?;
+        write!(f, "Global checksum: {:#06X}", self.global_checksum)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_header() {
+        let mut data = vec![0u8; 32 * 1024];
+        data[0x134..0x138].copy_from_slice(b"TEST");
+        data[0x143] = 0x80;
+        data[0x147] = 0x13; // MBC3 + RAM + battery
+        data[0x149] = 0x03;
+        data[0x14D] = 0xFF;
+
+        let info = CartridgeInfo::from_rom(data).unwrap();
+        assert_eq!(info.title, "TEST");
+        assert!(info.cgb);
+        assert_eq!(info.cartridge_type.as_deref(), Some("Mbc3RamBattery"));
+        assert_eq!(info.rom_size, Some(32 * 1024));
+        assert_eq!(info.ram_size, Some(32 * 1024));
+        assert!(!info.header_checksum_ok);
+        assert_eq!(info.mbc, Some("MBC3"));
+        assert!(info.mbc_supported);
+        assert_eq!(info.to_json()["cartridge_type"], "Mbc3RamBattery");
+        assert!(info.to_string().contains("MBC:             MBC3 (supported)"));
+
+        assert!(CartridgeInfo::from_rom(vec![0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn list_mbcs() {
+        let list = mbc_list();
+        assert!(list.lines().any(|line| line.starts_with("MBC1 ") && line.contains("0x01 0x02 0x03 ")
+                                        && line.ends_with(" supported")));
+        assert!(list.lines().any(|line| line.starts_with("HuC1 ") && line.ends_with("not supported")));
+    }
+}
@@ -0,0 +1,1006 @@
+//! Terminal input handling
+//!
+//! Keyboard input is read on a dedicated thread using crossterm's event API and
+//! forwarded to the main loop over a channel. The main loop then feeds each key
+//! event into a `KeyTracker`, which converts them into joypad events.
+//!
+//! Terminals that implement the kitty keyboard protocol report real key releases.
+//! Everywhere else, we only ever see key presses (plus the terminal's auto-repeat),
+//! so a key is considered released once no press has been seen for a short while.
+//!
+//! Menus and the ROM picker repeat held arrow keys at their own pace, set with
+//! `--menu-repeat-delay` and `--menu-repeat-rate`, rather than the terminal's,
+//! so that holding one scrolls at the same speed everywhere.
+//!
+//! A real D-pad cannot be pressed both ways at once, and some games misbehave
+//! if it is, so opposite directions held together are resolved as set with
+//! `--dpad-conflict`.
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use gbc::Gameboy;
+use gbc::joypad::{JoypadEvent, JoypadInput};
+
+/// How long a key is considered held after its last repeat when the terminal
+/// does not report releases.
+///
+/// This needs to be longer than the terminal's auto-repeat interval so that a
+/// held key does not flicker between up and down.
+const FALLBACK_RELEASE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The same, but after the press itself. The first repeat only comes after
+/// the terminal's repeat delay, usually 250 to 660ms, which this needs to be
+/// longer than so that a held key is not let go of before it.
+const FIRST_REPEAT_TIMEOUT: Duration = Duration::from_millis(750);
+
+/// Maps terminal keys to joypad inputs.
+///
+/// Each key maps to at most one input, but an input can have any number of keys
+/// bound to it (including none).
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    map: HashMap<KeyCode, JoypadInput>,
+}
+
+impl KeyBindings {
+    /// Returns the joypad input bound to this key, if any.
+    pub fn lookup(&self, keycode: KeyCode) -> Option<JoypadInput> {
+        self.map.get(&normalize(keycode)).copied()
+    }
+
+    /// Bind a key to an input, replacing any previous binding for that key.
+    pub fn bind(&mut self, keycode: KeyCode, input: JoypadInput) {
+        if let Some(prev) = self.map.insert(normalize(keycode), input) {
+            log::warn!("Key {:?} was bound to {:?}, rebinding to {:?}", keycode, prev, input);
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let map = [
+            (KeyCode::Char('n'), JoypadInput::B),
+            (KeyCode::Char('m'), JoypadInput::A),
+            (KeyCode::Char('j'), JoypadInput::Start),
+            (KeyCode::Char('k'), JoypadInput::Select),
+            (KeyCode::Char('w'), JoypadInput::Up),
+            (KeyCode::Char('s'), JoypadInput::Down),
+            (KeyCode::Char('a'), JoypadInput::Left),
+            (KeyCode::Char('d'), JoypadInput::Right),
+        ];
+
+        Self {
+            map: map.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Letters are matched case-insensitively so that bindings keep working with
+/// caps lock or shift held.
+fn normalize(keycode: KeyCode) -> KeyCode {
+    match keycode {
+        KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+        _ => keycode,
+    }
+}
+
+/// Parse a key name, e.g. `a`, `left`, `space` or `f5`.
+pub fn parse_keycode(name: &str) -> Result<KeyCode, String> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(c));
+    }
+
+    let keycode = match name.to_ascii_lowercase().as_str() {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "enter" | "return" => KeyCode::Enter,
+        "space" => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "esc" | "escape" => KeyCode::Esc,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "insert" => KeyCode::Insert,
+        "delete" => KeyCode::Delete,
+        other => {
+            match other.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("Unknown key: {}", name)),
+            }
+        }
+    };
+
+    Ok(keycode)
+}
+
+/// A key along with the modifiers held with it, e.g. `ctrl-q`, for hotkeys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+/// Quits, with a second press to confirm
+pub const DEFAULT_QUIT_KEY: KeyCombo = KeyCombo { code: KeyCode::Char('q'), modifiers: KeyModifiers::CONTROL };
+
+impl KeyCombo {
+    /// Whether `key` is this combination. Shift only counts for keys other
+    /// than letters and symbols, which tell it apart themselves.
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        let mut modifiers = key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if let KeyCode::Char(_) = key.code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        normalize(key.code) == normalize(self.code) && modifiers == self.modifiers
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    /// Parse modifiers joined to a key name with `-`, e.g. `ctrl-q`,
+    /// `alt-shift-f4` or `q`. `ctrl--` is Ctrl and the `-` key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::empty();
+        let mut rest = s;
+        while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier: {} (expected ctrl, alt or shift)", modifier)),
+            };
+            rest = key;
+        }
+
+        let code = parse_keycode(rest)?;
+        if let KeyCode::Char(_) = code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+
+        Ok(Self { code, modifiers })
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (modifier, name) in [(KeyModifiers::CONTROL, "Ctrl"), (KeyModifiers::ALT, "Alt"), (KeyModifiers::SHIFT, "Shift")] {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}-", name)?;
+            }
+        }
+
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Parse a joypad input name (case-insensitive), e.g. `A` or `left`.
+pub fn parse_joypad_input(name: &str) -> Result<JoypadInput, String> {
+    let input = match name.to_ascii_lowercase().as_str() {
+        "up" => JoypadInput::Up,
+        "down" => JoypadInput::Down,
+        "left" => JoypadInput::Left,
+        "right" => JoypadInput::Right,
+        "a" => JoypadInput::A,
+        "b" => JoypadInput::B,
+        "start" => JoypadInput::Start,
+        "select" => JoypadInput::Select,
+        _ => return Err(format!("Unknown joypad input: {}", name)),
+    };
+
+    Ok(input)
+}
+
+/// Parse a `KEY=INPUT` binding, e.g. `a=Left`.
+pub fn parse_binding(binding: &str) -> Result<(KeyCode, JoypadInput), String> {
+    // Split on the last `=` so that `==A` binds the `=` key
+    let idx = binding.rfind('=')
+        .ok_or_else(|| format!("Expected KEY=INPUT, got: {}", binding))?;
+    let (key, input) = (&binding[..idx], &binding[idx + 1..]);
+
+    Ok((parse_keycode(key)?, parse_joypad_input(input)?))
+}
+
+/// Asks the terminal to report key releases. Terminals that do not implement
+/// the kitty keyboard protocol ignore this and we fall back to timeouts.
+#[cfg(unix)]
+pub fn keyboard_enhancement() -> PushKeyboardEnhancementFlags {
+    PushKeyboardEnhancementFlags(
+        KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+            | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+            | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+    )
+}
+
+/// How long the event thread waits for input before checking whether it
+/// should pause or stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Lets the main thread take over terminal input from the event thread, e.g.
+/// to read a line from a prompt.
+#[derive(Clone, Default)]
+pub struct InputLock {
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    reading: Arc<Mutex<()>>,
+}
+
+/// Keeps the event thread paused until dropped.
+pub struct InputPaused<'a> {
+    paused: &'a AtomicBool,
+    _reading: MutexGuard<'a, ()>,
+}
+
+impl InputLock {
+    /// Pause the event thread, waiting until it is no longer reading.
+    pub fn pause(&self) -> InputPaused<'_> {
+        self.paused.store(true, Ordering::SeqCst);
+        InputPaused {
+            paused: &self.paused,
+            _reading: self.reading.lock().unwrap(),
+        }
+    }
+
+    /// Stop the event thread for good, waiting until it is no longer reading,
+    /// so that later input is left for whatever reads the terminal next.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        drop(self.reading.lock().unwrap());
+    }
+}
+
+impl Drop for InputPaused<'_> {
+    fn drop(&mut self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Spawns a thread that forwards all terminal events to the returned channel.
+///
+/// The thread stops reading while `lock` is paused, and exits once it is
+/// stopped or the receiver is dropped. It also exits if the terminal cannot
+/// be read from, e.g. stdin was closed, which disconnects the channel.
+pub fn spawn_event_channel(lock: InputLock) -> Receiver<Event> {
+    let (tx, rx) = mpsc::channel::<Event>();
+    thread::spawn(move || loop {
+        if lock.paused.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        let _reading = lock.reading.lock().unwrap();
+        if lock.stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let event = match event::poll(POLL_INTERVAL) {
+            Ok(true) => event::read(),
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        match event {
+            Ok(event) => {
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                log::error!("Stopped reading input: {}", e);
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Turbo presses per second when not set
+pub const DEFAULT_TURBO_RATE: f64 = 10.0;
+
+/// Parse a turbo rate, in presses per second.
+pub fn parse_turbo_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|_| format!("Invalid turbo rate: {}", s))?;
+    // Presses and releases need to last at least a frame each
+    if (1.0..=30.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("Turbo rate must be between 1 and 30, got {}", s))
+    }
+}
+
+/// Take the releases of inputs pressed earlier in `events` out of them, along
+/// with whatever follows for those inputs, to go with the next frame instead.
+///
+/// A frame's events are all applied at once, so a tap shorter than a frame
+/// would be let go before the game could see it. Held over, it lasts a frame.
+pub fn hold_over_taps(events: &mut Vec<JoypadEvent>) -> Vec<JoypadEvent> {
+    let mut pressed = Vec::new();
+    let mut held_over = Vec::new();
+    let mut later = Vec::new();
+
+    events.retain(|&event| {
+        let (JoypadEvent::Down(input) | JoypadEvent::Up(input)) = event;
+        if held_over.contains(&input) {
+            later.push(event);
+            return false;
+        }
+
+        match event {
+            JoypadEvent::Down(_) => pressed.push(input),
+            JoypadEvent::Up(_) if pressed.contains(&input) => {
+                held_over.push(input);
+                later.push(event);
+                return false;
+            }
+            JoypadEvent::Up(_) => (),
+        }
+        true
+    });
+
+    later
+}
+
+/// What to do when opposite directions are held at once, e.g. Left and Right
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DpadConflict {
+    /// The one pressed last wins, and the other comes back once it is let go
+    #[default]
+    Last,
+    /// Neither is pressed until one is let go
+    Cancel,
+    /// Both are pressed, as on no real Game Boy
+    Allow,
+}
+
+impl FromStr for DpadConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "last" => Ok(Self::Last),
+            "cancel" => Ok(Self::Cancel),
+            "allow" => Ok(Self::Allow),
+            _ => Err(format!("Unknown D-pad conflict handling: {} (expected last, cancel or allow)", s)),
+        }
+    }
+}
+
+/// The direction opposite `input`, or `None` for buttons
+fn opposite(input: JoypadInput) -> Option<JoypadInput> {
+    match input {
+        JoypadInput::Up => Some(JoypadInput::Down),
+        JoypadInput::Down => Some(JoypadInput::Up),
+        JoypadInput::Left => Some(JoypadInput::Right),
+        JoypadInput::Right => Some(JoypadInput::Left),
+        _ => None,
+    }
+}
+
+/// Converts terminal key events into joypad events.
+pub struct KeyTracker {
+    bindings: KeyBindings,
+
+    /// Set once the terminal reports a key release
+    release_events: bool,
+
+    /// Keys that are currently held, along with when they count as let go of
+    /// without a release
+    held: HashMap<KeyCode, Instant>,
+
+    /// Inputs that are pressed and released repeatedly while held
+    turbo: Vec<JoypadInput>,
+
+    /// Frames that each turbo press and release lasts
+    turbo_period: u32,
+
+    /// Held turbo inputs, with the frames since they last toggled and whether
+    /// they are currently down
+    turbo_state: HashMap<JoypadInput, (u32, bool)>,
+
+    dpad_conflict: DpadConflict,
+
+    /// Directions held, in the order they were pressed, whether or not the
+    /// game sees them
+    directions: Vec<JoypadInput>,
+}
+
+impl KeyTracker {
+    pub fn new(bindings: KeyBindings) -> Self {
+        Self {
+            bindings,
+            release_events: false,
+            held: HashMap::new(),
+            turbo: Vec::new(),
+            turbo_period: 1,
+            turbo_state: HashMap::new(),
+            dpad_conflict: DpadConflict::default(),
+            directions: Vec::new(),
+        }
+    }
+
+    pub fn set_dpad_conflict(&mut self, dpad_conflict: DpadConflict) {
+        self.dpad_conflict = dpad_conflict;
+    }
+
+    /// Whether the game sees `direction` as pressed.
+    fn direction_down(&self, direction: JoypadInput, opposite: JoypadInput) -> bool {
+        let position = |input| self.directions.iter().position(|&held| held == input);
+        match (position(direction), position(opposite), self.dpad_conflict) {
+            (None, ..) => false,
+            (Some(_), None, _) | (Some(_), Some(_), DpadConflict::Allow) => true,
+            (Some(direction), Some(opposite), DpadConflict::Last) => direction > opposite,
+            (Some(_), Some(_), DpadConflict::Cancel) => false,
+        }
+    }
+
+    /// Push `event` to `events`, resolving opposite directions held at once.
+    fn push(&mut self, event: JoypadEvent, events: &mut Vec<JoypadEvent>) {
+        let (input, down) = match event {
+            JoypadEvent::Down(input) => (input, true),
+            JoypadEvent::Up(input) => (input, false),
+        };
+        let Some(other) = opposite(input) else {
+            events.push(event);
+            return;
+        };
+
+        let before = [self.direction_down(input, other), self.direction_down(other, input)];
+        self.directions.retain(|&held| held != input);
+        if down {
+            self.directions.push(input);
+        }
+        let after = [self.direction_down(input, other), self.direction_down(other, input)];
+
+        // Releases go first, so that both are never down together
+        for (i, &direction) in [input, other].iter().enumerate() {
+            if before[i] && !after[i] {
+                events.push(JoypadEvent::Up(direction));
+            }
+        }
+        for (i, &direction) in [input, other].iter().enumerate() {
+            if !before[i] && after[i] {
+                events.push(JoypadEvent::Down(direction));
+            }
+        }
+    }
+
+    /// Make `inputs` auto-fire at `rate` presses per second while held.
+    pub fn set_turbo(&mut self, inputs: Vec<JoypadInput>, rate: f64) {
+        let fps = 1e9 / Gameboy::FRAME_DURATION as f64;
+        self.turbo = inputs;
+        self.turbo_period = ((fps / rate / 2.0).round() as u32).max(1);
+    }
+
+    /// Whether any held key is bound to `input`. Several keys can be, and
+    /// the input is only let go of once all of them are.
+    fn input_held(&self, input: JoypadInput) -> bool {
+        self.held.keys().any(|&code| self.bindings.lookup(code) == Some(input))
+    }
+
+    /// Whether `key` is bound to a joypad input, and so not free for a hotkey.
+    pub fn is_bound(&self, key: KeyCode) -> bool {
+        self.bindings.lookup(key).is_some()
+    }
+
+    /// Handle a single key event, pushing any resulting joypad events to `events`.
+    ///
+    /// All keys are tracked, even if they are not bound to an input, so that
+    /// `is_held` can be used for emulator hotkeys.
+    pub fn handle(&mut self, key: KeyEvent, now: Instant, events: &mut Vec<JoypadEvent>) {
+        let code = normalize(key.code);
+        let input = self.bindings.lookup(code);
+
+        match key.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                let already = input.is_some_and(|input| self.input_held(input));
+                let timeout = if self.held.contains_key(&code) { FALLBACK_RELEASE_TIMEOUT } else { FIRST_REPEAT_TIMEOUT };
+                if self.held.insert(code, now + timeout).is_none() && !already {
+                    if let Some(input) = input {
+                        self.push(JoypadEvent::Down(input), events);
+                        if self.turbo.contains(&input) {
+                            self.turbo_state.entry(input).or_insert((0, true));
+                        }
+                    }
+                }
+            }
+            KeyEventKind::Release => {
+                self.release_events = true;
+                if self.held.remove(&code).is_some() {
+                    if let Some(input) = input.filter(|&input| !self.input_held(input)) {
+                        self.push(JoypadEvent::Up(input), events);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Advance turbo inputs by a frame, pushing the presses and releases that
+    /// are due.
+    ///
+    /// Inputs stop toggling as soon as all of their keys are released, so
+    /// the release from `handle` or `release_stale` is the last event.
+    pub fn turbo_frame(&mut self, events: &mut Vec<JoypadEvent>) {
+        let bindings = &self.bindings;
+        let held: Vec<JoypadInput> = self.held.keys().filter_map(|code| bindings.lookup(*code)).collect();
+        self.turbo_state.retain(|input, _| held.contains(input));
+
+        let mut toggled = Vec::new();
+        for (input, (frames, down)) in &mut self.turbo_state {
+            *frames += 1;
+            if *frames >= self.turbo_period {
+                *frames = 0;
+                *down = !*down;
+                toggled.push(if *down { JoypadEvent::Down(*input) } else { JoypadEvent::Up(*input) });
+            }
+        }
+        for event in toggled {
+            self.push(event, events);
+        }
+    }
+
+    /// Returns `true` if the key is currently held down.
+    pub fn is_held(&self, keycode: KeyCode) -> bool {
+        self.held.contains_key(&normalize(keycode))
+    }
+
+    /// Release any held keys that have timed out.
+    ///
+    /// This is a no-op on terminals that report key releases.
+    pub fn release_stale(&mut self, now: Instant, events: &mut Vec<JoypadEvent>) {
+        if self.release_events {
+            return;
+        }
+
+        let bindings = &self.bindings;
+        let mut released = Vec::new();
+        self.held.retain(|code, until| {
+            if now < *until {
+                return true;
+            }
+
+            if let Some(input) = bindings.lookup(*code).filter(|input| !released.contains(input)) {
+                released.push(input);
+            }
+
+            false
+        });
+        for input in released {
+            if !self.input_held(input) {
+                self.push(JoypadEvent::Up(input), events);
+            }
+        }
+    }
+
+    /// Release every held key, e.g. when the keyboard goes to another game.
+    pub fn release_all(&mut self, events: &mut Vec<JoypadEvent>) {
+        let bindings = &self.bindings;
+        let mut released = Vec::new();
+        for input in self.held.drain().filter_map(|(code, _)| bindings.lookup(code)) {
+            if !released.contains(&input) {
+                released.push(input);
+            }
+        }
+        for input in released {
+            self.push(JoypadEvent::Up(input), events);
+        }
+    }
+}
+
+/// How long a menu key is held before it repeats, and how many times a second
+/// it does then, when not set
+pub const DEFAULT_REPEAT_DELAY: Duration = Duration::from_millis(400);
+pub const DEFAULT_REPEAT_RATE: f64 = 12.0;
+
+/// Keys that repeat while held in menus. The rest only act when pressed.
+const REPEATED_KEYS: [KeyCode; 6] = [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right, KeyCode::PageUp, KeyCode::PageDown];
+
+/// Parse the delay before held menu keys repeat, in milliseconds.
+pub fn parse_repeat_delay(s: &str) -> Result<Duration, String> {
+    match s.parse::<u64>() {
+        Ok(millis) if (50..=2000).contains(&millis) => Ok(Duration::from_millis(millis)),
+        _ => Err(format!("Expected a delay from 50 to 2000 milliseconds, got: {}", s)),
+    }
+}
+
+/// Parse how many times a second held menu keys repeat.
+pub fn parse_repeat_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (1.0..=60.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("Expected a repeat rate from 1 to 60, got: {}", s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct HeldKey {
+    key: KeyEvent,
+    /// The last time the terminal reported it, for terminals that do not
+    /// report releases
+    seen: Instant,
+    /// When it repeats next
+    next: Instant,
+}
+
+/// Repeats held navigation keys in menus, ignoring the terminal's own
+/// auto-repeat.
+///
+/// Where the terminal does not report releases, a key is held for as long as
+/// the terminal keeps repeating it, as with `KeyTracker`. Its first repeat
+/// comes after the terminal's own delay, so there holding a key takes that
+/// long to count as holding it.
+#[derive(Clone, Debug)]
+pub struct KeyRepeat {
+    delay: Duration,
+    interval: Duration,
+
+    /// Set once the terminal reports a key release
+    release_events: bool,
+
+    held: Option<HeldKey>,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPEAT_DELAY, DEFAULT_REPEAT_RATE)
+    }
+}
+
+impl KeyRepeat {
+    /// Repeat held keys after `delay`, `rate` times a second.
+    pub fn new(delay: Duration, rate: f64) -> Self {
+        Self {
+            delay,
+            interval: Duration::from_secs_f64(1.0 / rate),
+            release_events: false,
+            held: None,
+        }
+    }
+
+    /// Forget the terminal stopped reporting a key a while ago.
+    fn expire(&mut self, now: Instant) {
+        if !self.release_events && self.held.is_some_and(|held| now >= held.seen + FALLBACK_RELEASE_TIMEOUT) {
+            self.held = None;
+        }
+    }
+
+    /// Handle a key event, returning it if it is a press to act on. Repeats
+    /// from the terminal are not, and neither are releases.
+    pub fn handle(&mut self, key: KeyEvent, now: Instant) -> Option<KeyEvent> {
+        self.expire(now);
+
+        match key.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                if let Some(held) = self.held.as_mut().filter(|held| held.key.code == key.code) {
+                    held.seen = now;
+                    return None;
+                }
+                if key.kind == KeyEventKind::Repeat {
+                    // Held since before the menu was opened
+                    return None;
+                }
+
+                self.held = REPEATED_KEYS.contains(&key.code).then_some(HeldKey { key, seen: now, next: now + self.delay });
+                Some(key)
+            }
+            KeyEventKind::Release => {
+                self.release_events = true;
+                if self.held.is_some_and(|held| held.key.code == key.code) {
+                    self.held = None;
+                }
+                None
+            }
+        }
+    }
+
+    /// The held key, if it is time for it to repeat.
+    pub fn poll(&mut self, now: Instant) -> Option<KeyEvent> {
+        self.expire(now);
+
+        let held = self.held.as_mut().filter(|held| now >= held.next)?;
+        // At a steady pace, without catching up after a stall
+        held.next = (held.next + self.interval).max(now);
+        Some(held.key)
+    }
+
+    /// How long until the held key repeats or is let go of, at most `limit`.
+    pub fn timeout(&self, now: Instant, limit: Duration) -> Duration {
+        let due = self.held.map(|held| match self.release_events {
+            true => held.next,
+            false => held.next.min(held.seen + FALLBACK_RELEASE_TIMEOUT),
+        });
+        due.map_or(limit, |due| due.saturating_duration_since(now).min(limit))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    #[test]
+    fn parse_bindings() {
+        let (key, input) = parse_binding("a=Left").unwrap();
+        assert_eq!(key, KeyCode::Char('a'));
+        assert!(matches!(input, JoypadInput::Left));
+
+        let (key, input) = parse_binding("left=left").unwrap();
+        assert_eq!(key, KeyCode::Left);
+        assert!(matches!(input, JoypadInput::Left));
+
+        let (key, input) = parse_binding("==start").unwrap();
+        assert_eq!(key, KeyCode::Char('='));
+        assert!(matches!(input, JoypadInput::Start));
+
+        assert_eq!(parse_binding("f5=A").unwrap().0, KeyCode::F(5));
+        assert!(parse_binding("a").is_err());
+        assert!(parse_binding("a=Jump").is_err());
+        assert!(parse_binding("f13=A").is_err());
+    }
+
+    #[test]
+    fn parse_key_combos() {
+        let combo: KeyCombo = "Ctrl-q".parse().unwrap();
+        assert_eq!(combo, DEFAULT_QUIT_KEY);
+        assert_eq!(combo.to_string(), "Ctrl-Q");
+        assert!(combo.matches(&KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL)));
+        assert!(!combo.matches(&KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE)));
+        assert!(!combo.matches(&KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL | KeyModifiers::ALT)));
+
+        // Shift is in the letter already
+        let combo: KeyCombo = "q".parse().unwrap();
+        assert!(combo.matches(&KeyEvent::new(KeyCode::Char('Q'), KeyModifiers::SHIFT)));
+
+        let combo: KeyCombo = "alt-shift-f4".parse().unwrap();
+        assert_eq!(combo.to_string(), "Alt-Shift-F4");
+        assert!(combo.matches(&KeyEvent::new(KeyCode::F(4), KeyModifiers::ALT | KeyModifiers::SHIFT)));
+        assert!(!combo.matches(&KeyEvent::new(KeyCode::F(4), KeyModifiers::ALT)));
+
+        assert_eq!("ctrl--".parse::<KeyCombo>().unwrap().code, KeyCode::Char('-'));
+        assert!("meta-q".parse::<KeyCombo>().is_err());
+        assert!("ctrl-".parse::<KeyCombo>().is_err());
+    }
+
+    #[test]
+    fn quick_taps_last_a_frame() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/tetris_world.gb");
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(std::fs::read(path).unwrap(), false), false).unwrap();
+        // Pressed buttons read as 0, with the buttons selected
+        let a_down = |gameboy: &mut Gameboy| {
+            gameboy.write_memory(0xFF00, 0x10);
+            gameboy.read_memory(0xFF00) & 1 == 0
+        };
+
+        let mut events = vec![JoypadEvent::Down(JoypadInput::A), JoypadEvent::Up(JoypadInput::A),
+                              JoypadEvent::Down(JoypadInput::B), JoypadEvent::Down(JoypadInput::A)];
+        let later = hold_over_taps(&mut events);
+        assert_eq!(events, [JoypadEvent::Down(JoypadInput::A), JoypadEvent::Down(JoypadInput::B)]);
+        assert_eq!(later, [JoypadEvent::Up(JoypadInput::A), JoypadEvent::Down(JoypadInput::A)]);
+
+        let mut events = vec![JoypadEvent::Down(JoypadInput::A), JoypadEvent::Up(JoypadInput::A)];
+        let mut later = hold_over_taps(&mut events);
+        gameboy.frame(Some(&events));
+        assert!(a_down(&mut gameboy));
+
+        assert!(hold_over_taps(&mut later).is_empty());
+        gameboy.frame(Some(&later));
+        assert!(!a_down(&mut gameboy));
+    }
+
+    #[test]
+    fn rebinding_a_key_replaces_the_old_input() {
+        let mut bindings = KeyBindings::default();
+        bindings.bind(KeyCode::Char('a'), JoypadInput::A);
+
+        assert!(matches!(bindings.lookup(KeyCode::Char('a')), Some(JoypadInput::A)));
+        assert!(matches!(bindings.lookup(KeyCode::Char('A')), Some(JoypadInput::A)));
+        assert!(bindings.lookup(KeyCode::Left).is_none());
+    }
+
+    #[test]
+    fn unbound_keys_are_tracked_without_joypad_events() {
+        let mut tracker = KeyTracker::new(KeyBindings::default());
+        let mut events = Vec::new();
+        let now = Instant::now();
+
+        tracker.handle(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE), now, &mut events);
+        assert!(tracker.is_held(KeyCode::Tab));
+        assert!(events.is_empty());
+
+        // Released once the fallback timeout expires
+        tracker.release_stale(now + FIRST_REPEAT_TIMEOUT, &mut events);
+        assert!(!tracker.is_held(KeyCode::Tab));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn held_keys_last_until_the_terminal_repeats_them() {
+        let press = KeyEvent::new(KeyCode::Char('m'), KeyModifiers::NONE);
+        let ms = Duration::from_millis;
+        let mut tracker = KeyTracker::new(KeyBindings::default());
+        let mut events = Vec::new();
+        let now = Instant::now();
+
+        // The first repeat comes after the terminal's delay, and the rest
+        // every 33ms
+        tracker.handle(press, now, &mut events);
+        tracker.release_stale(now + ms(299), &mut events);
+        for t in (300..=600).step_by(33) {
+            tracker.handle(press, now + ms(t), &mut events);
+            tracker.release_stale(now + ms(t + 20), &mut events);
+        }
+        assert_eq!(events, [JoypadEvent::Down(JoypadInput::A)]);
+
+        // Let go of soon after they stop
+        tracker.release_stale(now + ms(597) + FALLBACK_RELEASE_TIMEOUT, &mut events);
+        assert_eq!(events, [JoypadEvent::Down(JoypadInput::A), JoypadEvent::Up(JoypadInput::A)]);
+    }
+
+    #[test]
+    fn turbo_inputs_toggle_until_released() {
+        let mut tracker = KeyTracker::new(KeyBindings::default());
+        tracker.set_turbo(vec![JoypadInput::A], 20.0);
+        assert_eq!(tracker.turbo_period, 1);
+        let mut events = Vec::new();
+        let now = Instant::now();
+
+        // M is A, N is B
+        tracker.handle(KeyEvent::new(KeyCode::Char('m'), KeyModifiers::NONE), now, &mut events);
+        tracker.handle(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE), now, &mut events);
+        for _ in 0..3 {
+            tracker.turbo_frame(&mut events);
+        }
+        assert_eq!(events, [
+            JoypadEvent::Down(JoypadInput::A),
+            JoypadEvent::Down(JoypadInput::B),
+            JoypadEvent::Up(JoypadInput::A),
+            JoypadEvent::Down(JoypadInput::A),
+            JoypadEvent::Up(JoypadInput::A),
+        ]);
+
+        // Nothing more once released
+        events.clear();
+        tracker.release_stale(now + FIRST_REPEAT_TIMEOUT, &mut events);
+        tracker.turbo_frame(&mut events);
+        assert_eq!(events.len(), 2);
+        assert!(!events.contains(&JoypadEvent::Down(JoypadInput::A)));
+    }
+
+    #[test]
+    fn menu_keys_repeat_at_their_own_pace() {
+        let key = |code, kind| KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind);
+        let ms = Duration::from_millis;
+        let mut repeat = KeyRepeat::new(ms(300), 10.0);
+        let now = Instant::now();
+
+        // On a terminal that reports releases, its repeats are dropped, and
+        // held keys repeat after the delay, ten times a second
+        repeat.handle(key(KeyCode::Enter, KeyEventKind::Release), now);
+        assert!(repeat.handle(key(KeyCode::Down, KeyEventKind::Press), now).is_some());
+        assert!(repeat.handle(key(KeyCode::Down, KeyEventKind::Repeat), now + ms(20)).is_none());
+        assert_eq!(repeat.timeout(now + ms(100), ms(1000)), ms(200));
+        assert!(repeat.poll(now + ms(299)).is_none());
+        assert_eq!(repeat.poll(now + ms(300)).map(|key| key.code), Some(KeyCode::Down));
+        assert!(repeat.poll(now + ms(350)).is_none());
+        assert!(repeat.poll(now + ms(400)).is_some());
+
+        // Until it is let go of
+        assert!(repeat.handle(key(KeyCode::Down, KeyEventKind::Release), now + ms(450)).is_none());
+        assert!(repeat.poll(now + ms(500)).is_none());
+        assert_eq!(repeat.timeout(now + ms(500), ms(1000)), ms(1000));
+
+        // Enter acts once
+        assert!(repeat.handle(key(KeyCode::Enter, KeyEventKind::Press), now + ms(500)).is_some());
+        assert!(repeat.poll(now + ms(2000)).is_none());
+    }
+
+    #[test]
+    fn menu_keys_are_let_go_of_without_releases() {
+        let press = KeyEvent::new(KeyCode::Up, KeyModifiers::NONE);
+        let ms = Duration::from_millis;
+        let mut repeat = KeyRepeat::new(ms(100), 20.0);
+        let now = Instant::now();
+
+        // Presses that keep coming are the terminal's repeats
+        assert!(repeat.handle(press, now).is_some());
+        for t in (30..=150).step_by(30) {
+            assert!(repeat.handle(press, now + ms(t)).is_none());
+        }
+        assert!(repeat.poll(now + ms(150)).is_some());
+
+        // And once they stop, the key is up
+        assert!(repeat.poll(now + ms(150) + FALLBACK_RELEASE_TIMEOUT).is_none());
+        assert!(repeat.handle(press, now + ms(400)).is_some());
+    }
+
+    #[test]
+    fn opposite_directions_are_resolved() {
+        let press = |tracker: &mut KeyTracker, code, kind| {
+            let mut events = Vec::new();
+            tracker.handle(KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind), Instant::now(), &mut events);
+            events
+        };
+        // A is Left, D is Right
+        let (down, up) = (KeyEventKind::Press, KeyEventKind::Release);
+        use JoypadEvent::{Down, Up};
+        use JoypadInput::{Left, Right};
+
+        let mut last = KeyTracker::new(KeyBindings::default());
+        assert_eq!(press(&mut last, KeyCode::Char('a'), down), [Down(Left)]);
+        assert_eq!(press(&mut last, KeyCode::Char('d'), down), [Up(Left), Down(Right)]);
+        assert_eq!(press(&mut last, KeyCode::Char('d'), up), [Up(Right), Down(Left)]);
+        assert_eq!(press(&mut last, KeyCode::Char('a'), up), [Up(Left)]);
+
+        let mut cancel = KeyTracker::new(KeyBindings::default());
+        cancel.set_dpad_conflict(DpadConflict::Cancel);
+        assert_eq!(press(&mut cancel, KeyCode::Char('a'), down), [Down(Left)]);
+        assert_eq!(press(&mut cancel, KeyCode::Char('d'), down), [Up(Left)]);
+        assert_eq!(press(&mut cancel, KeyCode::Char('a'), up), [Down(Right)]);
+        assert_eq!(press(&mut cancel, KeyCode::Char('d'), up), [Up(Right)]);
+
+        let mut allow = KeyTracker::new(KeyBindings::default());
+        allow.set_dpad_conflict(DpadConflict::Allow);
+        assert_eq!(press(&mut allow, KeyCode::Char('a'), down), [Down(Left)]);
+        assert_eq!(press(&mut allow, KeyCode::Char('d'), down), [Down(Right)]);
+        // Other directions are not in the way
+        assert_eq!(press(&mut last, KeyCode::Char('w'), down), [Down(JoypadInput::Up)]);
+    }
+
+    #[test]
+    fn inputs_bound_to_several_keys_are_let_go_of_with_the_last() {
+        let press = |tracker: &mut KeyTracker, code, kind| {
+            let mut events = Vec::new();
+            tracker.handle(KeyEvent::new_with_kind(code, KeyModifiers::NONE, kind), Instant::now(), &mut events);
+            events
+        };
+        let (down, up) = (KeyEventKind::Press, KeyEventKind::Release);
+        use JoypadEvent::{Down, Up};
+        use JoypadInput::{Left, Right, A};
+
+        // On top of M for A and A for Left
+        let mut bindings = KeyBindings::default();
+        bindings.bind(KeyCode::Char('x'), A);
+        bindings.bind(KeyCode::Left, Left);
+        let mut tracker = KeyTracker::new(bindings);
+
+        assert_eq!(press(&mut tracker, KeyCode::Char('m'), down), [Down(A)]);
+        assert_eq!(press(&mut tracker, KeyCode::Char('x'), down), []);
+        assert_eq!(press(&mut tracker, KeyCode::Char('m'), up), []);
+        assert_eq!(press(&mut tracker, KeyCode::Char('x'), up), [Up(A)]);
+
+        assert_eq!(press(&mut tracker, KeyCode::Char('a'), down), [Down(Left)]);
+        assert_eq!(press(&mut tracker, KeyCode::Left, down), []);
+        assert_eq!(press(&mut tracker, KeyCode::Char('a'), up), []);
+        // Still held, so still in the way of Right
+        assert_eq!(press(&mut tracker, KeyCode::Char('d'), down), [Up(Left), Down(Right)]);
+        assert_eq!(press(&mut tracker, KeyCode::Char('d'), up), [Up(Right), Down(Left)]);
+        assert_eq!(press(&mut tracker, KeyCode::Left, up), [Up(Left)]);
+
+        // Without releases, both time out together
+        let mut tracker = KeyTracker::new(tracker.bindings);
+        let mut events = Vec::new();
+        let now = Instant::now();
+        tracker.handle(KeyEvent::new(KeyCode::Char('m'), KeyModifiers::NONE), now, &mut events);
+        tracker.handle(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE), now, &mut events);
+        tracker.release_stale(now + FIRST_REPEAT_TIMEOUT, &mut events);
+        assert_eq!(events, [Down(A), Up(A)]);
+    }
+}
@@ -0,0 +1,223 @@
+//! Kitty graphics protocol output
+//!
+//! Terminals that implement the protocol (Kitty, WezTerm, Ghostty) get the
+//! frame as an image at full resolution, which the terminal scales to fill
+//! the window.
+//!
+//! Each frame replaces the image before it, under the same image and placement
+//! ids, so nothing accumulates on the terminal side. Frames are sent whole, as
+//! not every terminal supports editing part of an image, but compressed, which
+//! brings a typical Gameboy frame down to a few KB. Frames that did not change
+//! are not sent at all.
+use std::io::{self, Write};
+
+use crossterm::{cursor, queue, style, terminal};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use gbc::ppu::{FrameBuffer, GameboyRgb};
+
+use crate::render::{Crop, Renderer};
+use crate::tty;
+
+/// Id of the image, and of its placement, on the terminal
+const IMAGE_ID: u32 = 1;
+
+/// Largest payload allowed in a single escape sequence
+const CHUNK_SIZE: usize = 4096;
+
+/// Whether the terminal answers a graphics protocol query.
+#[cfg(unix)]
+pub fn supported() -> bool {
+    // Asks whether a 1x1 image could be loaded, without keeping it
+    match tty::query(b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\") {
+        Some(response) => String::from_utf8_lossy(&response).contains("_Gi=31;OK"),
+        None => false,
+    }
+}
+
+/// There is no way to ask, so take the user's word for it.
+#[cfg(not(unix))]
+pub fn supported() -> bool {
+    true
+}
+
+#[derive(Default)]
+pub struct KittyRenderer {
+    /// Top-left corner of the image on the terminal, and its size, in cells
+    origin: (u16, u16),
+    size: (u16, u16),
+
+    /// Where to keep the image instead of centering it, in cells
+    fixed: Option<(u16, u16)>,
+
+    /// The part of the frame to draw
+    crop: Crop,
+
+    /// The last frame sent, or `None` if the terminal needs to be cleared and
+    /// the image redrawn
+    drawn: Option<Box<[GameboyRgb]>>,
+}
+
+impl KittyRenderer {
+    /// Draw `crop` of each frame, at `at` without ever clearing the terminal
+    /// if given.
+    pub fn new(at: Option<(u16, u16)>, crop: Crop) -> Self {
+        Self { fixed: at, crop, ..Self::default() }
+    }
+}
+
+impl Renderer for KittyRenderer {
+    fn render(&mut self, frame_buffer: &FrameBuffer, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.drawn.as_deref() == Some(&*frame_buffer.data) {
+            return Ok(());
+        }
+
+        if self.drawn.is_none() && self.fixed.is_none() {
+            queue!(out, style::ResetColor, terminal::Clear(terminal::ClearType::All))?;
+        }
+
+        queue!(out, cursor::MoveTo(self.origin.0, self.origin.1))?;
+        let start = out.len();
+        encode(frame_buffer, self.crop, self.size, out)?;
+        log::debug!("Kitty graphics bytes: {}", out.len() - start);
+
+        self.drawn = Some(frame_buffer.data.clone());
+        Ok(())
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        let (cell_width, cell_height) = tty::cell_pixels(cols, rows);
+        // A fixed image gets the space to the right of and below its corner
+        let (left, top) = self.fixed.unwrap_or((0, 0));
+        let (cols, rows) = (cols.saturating_sub(left), rows.saturating_sub(top));
+
+        // The largest size that keeps the pixels square, leaving the last row
+        // free so that the terminal never scrolls
+        let (width, height) = (cols as f64 * cell_width as f64, rows.saturating_sub(1).max(1) as f64 * cell_height as f64);
+        let (image_width, image_height) = (self.crop.width as f64, self.crop.height as f64);
+        let scale = (width / image_width).min(height / image_height);
+        let image_cols = ((image_width * scale / cell_width as f64) as u16).max(1);
+        let image_rows = ((image_height * scale / cell_height as f64) as u16).max(1);
+
+        self.size = (image_cols, image_rows);
+        self.origin = self.fixed.unwrap_or((cols.saturating_sub(image_cols) / 2, rows.saturating_sub(image_rows) / 2));
+        self.invalidate();
+    }
+
+    fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+}
+
+/// Append `crop` of a frame as a compressed RGB image, shown over `cols` by
+/// `rows` cells at the cursor, split into as many escape sequences as needed.
+pub fn encode(frame_buffer: &FrameBuffer, crop: Crop, (cols, rows): (u16, u16), out: &mut Vec<u8>) -> io::Result<()> {
+    let mut compressed = ZlibEncoder::new(Vec::with_capacity(crop.width * crop.height), Compression::fast());
+    for rgb in crop.pixels(frame_buffer) {
+        compressed.write_all(&[rgb.red, rgb.green, rgb.blue])?;
+    }
+    let payload = base64(&compressed.finish()?);
+
+    let chunks = payload.chunks(CHUNK_SIZE);
+    let last = chunks.len() - 1;
+    for (i, chunk) in chunks.enumerate() {
+        out.extend_from_slice(b"\x1b_G");
+        if i == 0 {
+            // Quietly, and without moving the cursor
+            write!(out, "a=T,f=24,o=z,s={},v={},i={},p={},c={},r={},q=2,C=1,",
+                   crop.width, crop.height, IMAGE_ID, IMAGE_ID, cols, rows)?;
+        }
+        write!(out, "m={};", if i == last { 0 } else { 1 })?;
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(b"\x1b\\");
+    }
+
+    Ok(())
+}
+
+/// Standard base64, with padding
+fn base64(data: &[u8]) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[n >> (18 - 6 * i) & 0x3F]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+    use gbc::ppu::{LCD_HEIGHT, LCD_WIDTH};
+
+    use super::*;
+
+    #[test]
+    fn base64_encoding() {
+        assert_eq!(base64(b""), b"");
+        assert_eq!(base64(b"f"), b"Zg==");
+        assert_eq!(base64(b"fo"), b"Zm8=");
+        assert_eq!(base64(b"foo"), b"Zm9v");
+        assert_eq!(base64(b"foobar"), b"Zm9vYmFy");
+        assert_eq!(base64(&[0xFF, 0xEE]), b"/+4=");
+    }
+
+    #[test]
+    fn frames_are_sent_in_chunks() {
+        // Noise, so that the frame does not compress into a single chunk
+        let mut frame_buffer = FrameBuffer::new();
+        let mut seed = 1u32;
+        for rgb in frame_buffer.data.iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let [red, green, blue, _] = seed.to_le_bytes();
+            *rgb = GameboyRgb { red, green, blue };
+        }
+
+        let mut out = Vec::new();
+        encode(&frame_buffer, Crop::FULL, (80, 36), &mut out).unwrap();
+        let output = String::from_utf8(out).unwrap();
+
+        let sequences: Vec<&str> = output.split_terminator("\x1b\\").collect();
+        assert!(sequences.len() > 1);
+        assert!(sequences[0].starts_with("\x1b_Ga=T,f=24,o=z,s=160,v=144,i=1,p=1,c=80,r=36,q=2,C=1,m=1;"));
+        assert!(sequences[1..sequences.len() - 1].iter().all(|s| s.starts_with("\x1b_Gm=1;")));
+        assert!(sequences.last().unwrap().starts_with("\x1b_Gm=0;"));
+
+        // The payloads put back together are the frame
+        let payload: String = sequences.iter().map(|s| s.split_once(';').unwrap().1).collect();
+        assert!(sequences.iter().all(|s| s.split_once(';').unwrap().1.len() <= CHUNK_SIZE));
+        let compressed = decode_base64(&payload);
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&compressed[..]).read_to_end(&mut pixels).unwrap();
+        assert_eq!(pixels.len(), LCD_WIDTH * LCD_HEIGHT * 3);
+        assert_eq!(&pixels[..3], &[frame_buffer.data[0].red, frame_buffer.data[0].green, frame_buffer.data[0].blue]);
+    }
+
+    fn decode_base64(s: &str) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        let values: Vec<u32> = s.bytes().take_while(|&b| b != b'=')
+            .map(|b| ALPHABET.iter().position(|&a| a == b).unwrap() as u32)
+            .collect();
+        let mut out = Vec::new();
+        for chunk in values.chunks(4) {
+            let n = chunk.iter().enumerate().fold(0, |n, (i, v)| n | v << (18 - 6 * i));
+            out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+        }
+        out
+    }
+}
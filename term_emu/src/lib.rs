@@ -0,0 +1,52 @@
+//! A Gameboy (Color) emulator that draws in the terminal
+//!
+//! [`Emulator`] is the way to embed it: it runs a game a frame at a time with
+//! whatever joypad input it is given, and renders the frames as escape
+//! sequences. The `gbcemu` binary is built from the modules below, which are
+//! not part of the API.
+pub mod emulator;
+
+pub use emulator::{Emulator, EmulatorOptions};
+pub use color::ColorMode;
+pub use palette::Palette;
+pub use render::{Backend, Crop, RenderOptions};
+
+#[doc(hidden)] pub mod audio;
+#[doc(hidden)] pub mod bench;
+#[doc(hidden)] pub mod blend;
+#[doc(hidden)] pub mod capture;
+#[doc(hidden)] pub mod cheats;
+#[doc(hidden)] pub mod color;
+#[doc(hidden)] pub mod config;
+#[doc(hidden)] pub mod console;
+#[doc(hidden)] pub mod disasm;
+#[doc(hidden)] pub mod dump;
+#[doc(hidden)] pub mod gamepad;
+#[doc(hidden)] pub mod info;
+#[doc(hidden)] pub mod input;
+#[doc(hidden)] pub mod kitty;
+#[doc(hidden)] pub mod link;
+#[doc(hidden)] pub mod log_pane;
+#[doc(hidden)] pub mod menu;
+#[doc(hidden)] pub mod movie;
+#[doc(hidden)] pub mod mp4;
+#[doc(hidden)] pub mod outline;
+#[doc(hidden)] pub mod overlay;
+#[doc(hidden)] pub mod pace;
+#[doc(hidden)] pub mod palette;
+#[doc(hidden)] pub mod patch;
+#[doc(hidden)] pub mod persist;
+#[doc(hidden)] pub mod picker;
+#[doc(hidden)] pub mod profile;
+#[doc(hidden)] pub mod raw;
+#[doc(hidden)] pub mod remote;
+#[doc(hidden)] pub mod render;
+#[doc(hidden)] pub mod render_thread;
+#[doc(hidden)] pub mod rewind;
+#[doc(hidden)] pub mod rom;
+#[doc(hidden)] pub mod script;
+#[doc(hidden)] pub mod serial;
+#[doc(hidden)] pub mod sixel;
+#[doc(hidden)] pub mod tone;
+#[doc(hidden)] pub mod tty;
+#[doc(hidden)] pub mod vram;
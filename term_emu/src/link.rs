@@ -0,0 +1,279 @@
+//! Link cable emulation over TCP
+//!
+//! Two instances are linked by pointing them at the same address: the first
+//! one finds nothing there and listens, and the second one connects to it.
+//! Two games run side by side in one instance, with `--dual`, are linked by a
+//! [`LocalLink`] instead.
+//!
+//! Each serial transfer is a single message. The side that clocks the
+//! transfer sends `TRANSFER` with its byte, and the other side answers with
+//! `REPLY` and its own byte once its game is ready to receive. If both sides
+//! clock a transfer at the same time, each one takes the other's `TRANSFER`
+//! as the answer.
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use gbc::Gameboy;
+
+const TRANSFER: u8 = 0;
+const REPLY: u8 = 1;
+
+enum State {
+    Listening(TcpListener),
+    Connected(TcpStream),
+    Disconnected,
+}
+
+pub struct Link {
+    state: State,
+
+    /// Bytes read that do not make up a whole message yet
+    incoming: Vec<u8>,
+
+    /// Transfer from the other side that the game was not ready for yet
+    pending: Option<u8>,
+
+    /// Whether a transfer clocked by this side is waiting for an answer
+    awaiting_reply: bool,
+}
+
+impl Link {
+    /// Connect to the other side at `addr`, or listen there if it is not up
+    /// yet.
+    pub fn open(addr: &str, gameboy: &mut Gameboy) -> io::Result<Self> {
+        let state = match TcpStream::connect(addr) {
+            Ok(stream) => {
+                log::info!("Link connected to {}", addr);
+                State::Connected(Self::setup(stream, gameboy)?)
+            }
+            Err(_) => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                log::info!("Waiting for the other side of the link on {}", addr);
+                State::Listening(listener)
+            }
+        };
+
+        Ok(Self {
+            state,
+            incoming: Vec::new(),
+            pending: None,
+            awaiting_reply: false,
+        })
+    }
+
+    fn setup(stream: TcpStream, gameboy: &mut Gameboy) -> io::Result<TcpStream> {
+        // Every byte matters for latency, so don't batch them up
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        gameboy.set_link_connected(true);
+        Ok(stream)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, State::Connected(_))
+    }
+
+    /// Move bytes between the game and the other side. Never blocks.
+    ///
+    /// If the connection drops, the game carries on as if the cable was
+    /// unplugged.
+    pub fn update(&mut self, gameboy: &mut Gameboy) {
+        if let State::Listening(listener) = &self.state {
+            match listener.accept() {
+                Ok((stream, peer)) => match Self::setup(stream, gameboy) {
+                    Ok(stream) => {
+                        log::info!("Link connected to {}", peer);
+                        self.state = State::Connected(stream);
+                    }
+                    Err(e) => log::warn!("Failed to set up link with {}: {}", peer, e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                Err(e) => {
+                    log::warn!("Link disabled: {}", e);
+                    self.state = State::Disconnected;
+                }
+            }
+        }
+
+        if let State::Connected(stream) = &mut self.state {
+            if let Err(e) = exchange(stream, &mut self.incoming, &mut self.pending, &mut self.awaiting_reply, gameboy) {
+                log::warn!("Link disconnected: {}", e);
+                gameboy.set_link_connected(false);
+                self.state = State::Disconnected;
+            }
+        }
+    }
+}
+
+/// A cable between two games in the same process
+pub struct LocalLink {
+    /// Transfers clocked by each side, until the other one is ready for them
+    pending: [Option<u8>; 2],
+}
+
+impl LocalLink {
+    pub fn connect(a: &mut Gameboy, b: &mut Gameboy) -> Self {
+        a.set_link_connected(true);
+        b.set_link_connected(true);
+        Self { pending: [None, None] }
+    }
+
+    /// Move bytes between the two games, as the TCP link does.
+    pub fn update(&mut self, a: &mut Gameboy, b: &mut Gameboy) {
+        if let Some(byte) = a.link_outgoing() {
+            self.pending[0] = Some(byte);
+        }
+        if let Some(byte) = b.link_outgoing() {
+            self.pending[1] = Some(byte);
+        }
+
+        match self.pending {
+            // Both sides clocked a transfer, so each gets the other's byte
+            [Some(from_a), Some(from_b)] => {
+                a.link_complete(from_b);
+                b.link_complete(from_a);
+                self.pending = [None, None];
+            }
+            [Some(from_a), None] => {
+                if let Some(reply) = b.link_receive(from_a) {
+                    a.link_complete(reply);
+                    self.pending[0] = None;
+                }
+            }
+            [None, Some(from_b)] => {
+                if let Some(reply) = a.link_receive(from_b) {
+                    b.link_complete(reply);
+                    self.pending[1] = None;
+                }
+            }
+            [None, None] => (),
+        }
+    }
+}
+
+fn exchange(stream: &mut TcpStream, incoming: &mut Vec<u8>, pending: &mut Option<u8>, awaiting_reply: &mut bool,
+            gameboy: &mut Gameboy) -> io::Result<()> {
+    if let Some(byte) = gameboy.link_outgoing() {
+        // Messages are tiny, so the socket should never be too full to take one
+        stream.write_all(&[TRANSFER, byte])?;
+        *awaiting_reply = true;
+    }
+
+    let mut buf = [0u8; 64];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "closed by the other side")),
+            Ok(n) => incoming.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    let whole = incoming.len() / 2 * 2;
+    let messages: Vec<u8> = incoming.drain(..whole).collect();
+    for message in messages.chunks_exact(2) {
+        match message[0] {
+            TRANSFER => *pending = Some(message[1]),
+            REPLY => {
+                gameboy.link_complete(message[1]);
+                *awaiting_reply = false;
+            }
+            kind => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown message {}", kind))),
+        }
+    }
+
+    if let Some(byte) = *pending {
+        if *awaiting_reply {
+            // Both sides clocked a transfer, so each gets the other's byte
+            gameboy.link_complete(byte);
+            *awaiting_reply = false;
+            *pending = None;
+        } else if let Some(reply) = gameboy.link_receive(byte) {
+            stream.write_all(&[REPLY, reply])?;
+            *pending = None;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    fn gameboy() -> Gameboy {
+        Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap()
+    }
+
+    fn transfer_done(gameboy: &Gameboy) -> bool {
+        gameboy.read_memory(0xFF02) & 0x80 == 0
+    }
+
+    #[test]
+    fn linked_transfer_and_disconnect() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+
+        let (mut a, mut b) = (gameboy(), gameboy());
+        let mut link_a = Link::open(&addr, &mut a).unwrap();
+        let mut link_b = Link::open(&addr, &mut b).unwrap();
+        assert!(!link_a.is_connected() && link_b.is_connected());
+
+        // A clocks the transfer, B waits for it
+        a.write_memory(0xFF01, 0x42);
+        b.write_memory(0xFF01, 0x99);
+        b.write_memory(0xFF02, 0x80);
+        a.write_memory(0xFF02, 0x81);
+
+        for _ in 0..500 {
+            link_a.update(&mut a);
+            link_b.update(&mut b);
+            if transfer_done(&a) && transfer_done(&b) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(a.read_memory(0xFF01), 0x99);
+        assert_eq!(b.read_memory(0xFF01), 0x42);
+        // Serial interrupts were raised
+        assert_ne!(a.read_memory(0xFF0F) & 0x08, 0);
+        assert_ne!(b.read_memory(0xFF0F) & 0x08, 0);
+
+        // The other side going away does not leave A hanging
+        drop(link_b);
+        a.write_memory(0xFF02, 0x81);
+        for _ in 0..500 {
+            link_a.update(&mut a);
+            if !link_a.is_connected() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!link_a.is_connected());
+        a.step();
+        assert!(transfer_done(&a));
+    }
+
+    #[test]
+    fn local_transfer() {
+        let (mut a, mut b) = (gameboy(), gameboy());
+        let mut link = LocalLink::connect(&mut a, &mut b);
+
+        // B clocks the transfer before A is ready for it
+        b.write_memory(0xFF01, 0x99);
+        b.write_memory(0xFF02, 0x81);
+        link.update(&mut a, &mut b);
+        assert!(!transfer_done(&b));
+
+        a.write_memory(0xFF01, 0x42);
+        a.write_memory(0xFF02, 0x80);
+        link.update(&mut a, &mut b);
+        assert!(transfer_done(&a) && transfer_done(&b));
+        assert_eq!(a.read_memory(0xFF01), 0x99);
+        assert_eq!(b.read_memory(0xFF01), 0x42);
+    }
+}
@@ -0,0 +1,145 @@
+//! Log pane, shown under the game with L
+//!
+//! Everything logged is kept in memory too, up to the last `CAPACITY` lines,
+//! as well as going wherever the log goes. The emulator's own info messages
+//! are kept whatever `RUST_LOG` says, e.g. register dumps and notifications.
+//! The pane shows as many of the latest lines as fit, and can be scrolled
+//! back through them.
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crossterm::style::{Attribute, Color};
+use crossterm::{cursor, queue, style};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Lines kept to scroll back through
+const CAPACITY: usize = 1000;
+
+/// Rows the pane takes at the bottom of the terminal, including its heading
+pub const ROWS: u16 = 10;
+
+/// Heading of the pane
+const HEADING: &str = " Log (L to hide, Page Up/Page Down to scroll) ";
+
+static LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Counts the lines kept so far, to tell when there are new ones
+static KEPT: AtomicU64 = AtomicU64::new(0);
+
+/// Logs through an env_logger, keeping lines for the pane on the way.
+struct Logger {
+    inner: env_logger::Logger,
+}
+
+/// Whether a record is kept whether or not `RUST_LOG` lets it through.
+fn always_kept(metadata: &Metadata) -> bool {
+    let target = metadata.target();
+    let ours = ["gbcemu", "gbc"].iter().any(|name| target == *name || target.starts_with(&format!("{}::", name)));
+    ours && metadata.level() <= Level::Info
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || always_kept(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        } else if !always_kept(record.metadata()) {
+            return;
+        }
+
+        push(&format!("{:<5} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Log through `inner` from now on, keeping lines for the pane.
+pub fn init(inner: env_logger::Logger) {
+    let max_level = inner.filter().max(LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(Logger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Keep `text` for the pane, a line at a time.
+fn push(text: &str) {
+    let mut lines = LINES.lock().unwrap();
+    for line in text.lines() {
+        if lines.len() == CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+        KEPT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of lines kept so far, which changes whenever there is a new one.
+pub fn kept() -> u64 {
+    KEPT.load(Ordering::Relaxed)
+}
+
+/// How far back the pane can be scrolled, in lines.
+pub fn max_scroll() -> usize {
+    LINES.lock().unwrap().len().saturating_sub(usize::from(ROWS - 1))
+}
+
+/// Up to `count` lines, oldest first, ending `scroll` lines before the latest.
+fn tail(count: usize, scroll: usize) -> Vec<String> {
+    let lines = LINES.lock().unwrap();
+    let end = lines.len().saturating_sub(scroll);
+    lines.range(end.saturating_sub(count)..end).cloned().collect()
+}
+
+/// Draw the pane on the bottom `ROWS` rows of a terminal of `size`, scrolled
+/// back `scroll` lines.
+pub fn draw(out: &mut impl io::Write, (cols, rows): (u16, u16), scroll: usize) -> io::Result<()> {
+    let width = usize::from(cols);
+    let top = rows.saturating_sub(ROWS);
+    let fit = |line: &str| -> String { format!("{:<width$}", line.chars().take(width).collect::<String>(), width = width) };
+
+    queue!(out, style::SetBackgroundColor(Color::Black), style::SetForegroundColor(Color::White))?;
+    queue!(out, cursor::MoveTo(0, top), style::SetAttribute(Attribute::Reverse), style::Print(fit(HEADING)),
+           style::SetAttribute(Attribute::NoReverse))?;
+
+    // Latest at the bottom, with blank rows above while there are few
+    let count = usize::from(ROWS - 1);
+    let lines = tail(count, scroll);
+    for row in 0..count {
+        let line = (row + lines.len()).checked_sub(count).map_or("", |i| lines[i].as_str());
+        queue!(out, cursor::MoveTo(0, top + 1 + row as u16), style::Print(fit(line)))?;
+    }
+
+    queue!(out, style::ResetColor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latest_lines_are_drawn_at_the_bottom() {
+        // Tests share the lines, so these are told apart by their contents
+        for i in 0..20 {
+            push(&format!("pane test {}", i));
+        }
+        push("pane test last\nand more");
+
+        let lines = tail(3, 0);
+        assert_eq!(lines, ["pane test 19", "pane test last", "and more"]);
+        assert_eq!(tail(2, 2), ["pane test 18", "pane test 19"]);
+
+        let mut out = Vec::new();
+        draw(&mut out, (30, 40), 0).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("and more                      "));
+        assert!(!out.contains("and more                       "));
+        assert!(out.contains(" Log (L to hide, Page Up/Page "));
+    }
+}
@@ -1,241 +1,2029 @@
-use std::io::{Write, Read};
-use std::{io, u8};
-use std::path::PathBuf;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::time::{Instant, Duration};
 
 use gbc::Gameboy;
-use gbc::cartridge::Cartridge;
+use gbc::cartridge::{BootRom, Cartridge};
+use gbc::error::CartridgeError;
 use gbc::joypad::{JoypadEvent, JoypadInput};
-use gbc::ppu::{FrameBuffer, LCD_WIDTH, LCD_HEIGHT};
+use gbc::ppu::{FrameBuffer, GameboyRgb, Ppu};
 
-use std::sync::mpsc;
-use std::sync::mpsc::Receiver;
-use std::sync::mpsc::TryRecvError;
-use std::{thread};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
 use structopt::StructOpt;
 
-pub use crossterm::{
-    cursor,
-    style,
-    style::Color,
-    event::{self, Event, KeyCode, KeyEvent},
-    execute, queue,
-    terminal::{self, ClearType},
-    Command, Result,
-};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{terminal, Result};
 
-const FRAMES_PER_CYCLE: u32 = 2;
+use gbcemu::{audio, bench, blend, capture, cheats, color, config, console, disasm, dump, gamepad, info, input, kitty, link, log_pane,
+             menu, movie, mp4, outline, overlay, pace, palette, patch, persist, picker, profile, raw, remote, render, render_thread, rewind, rom, script,
+             serial, sixel, tone, tty, vram};
+use gamepad::{GamepadBindings, Gamepads};
+use input::{DpadConflict, KeyBindings, KeyCombo, KeyRepeat, KeyTracker};
+use link::{Link, LocalLink};
+use menu::{Action, Menu};
+use movie::{Movie, Player, Recorder, Session};
+use mp4::Mp4Recorder;
+use outline::Outline;
+use audio::AudioOutput;
+use blend::Blend;
+use capture::GifRecorder;
+use cheats::{Cheat, Cheats};
+use color::ColorMode;
+use config::Config;
+use console::Console;
+use overlay::Overlay;
+use pace::{Pacer, Pacing};
+use palette::{Palette, Screen};
+use persist::{FileKind, GameDir, SaveFiles};
+use picker::Picker;
+use profile::Profile;
+use raw::RawOut;
+use remote::InputSocket;
+use render::{Backend, Crop, RenderOptions, DEFAULT_CELL_ASPECT};
+use render_thread::RenderThread;
+use rewind::Rewind;
+use rom::Hardware;
+use serial::{SerialOut, SerialTarget};
+use tone::Tone;
+use vram::VramViewer;
 
+/// Hold to run as fast as possible, or press to keep doing so until pressed
+/// again.
+const FAST_FORWARD_KEY: KeyCode = KeyCode::Tab;
+const FAST_FORWARD_TOGGLE_KEY: KeyCode = KeyCode::BackTab;
+
+/// With `--dual`, moves the keyboard over to the other game
+const DUAL_FOCUS_KEY: KeyCode = KeyCode::Tab;
+/// Columns between the two games
+const DUAL_GAP: u16 = 2;
+
+/// How long after the quit key it can be pressed again to quit
+const QUIT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Hold to run at `SLOW_MOTION_SPEED`.
+const SLOW_MOTION_KEY: KeyCode = KeyCode::Backspace;
+const SLOW_MOTION_SPEED: f64 = 0.25;
+
+/// Press to pause or resume, and to advance a single frame while paused.
+const PAUSE_KEY: KeyCode = KeyCode::Char(' ');
+const STEP_KEY: KeyCode = KeyCode::Char('.');
+
+/// Press to open the debug console, with `--debug`.
+const BREAK_KEY: KeyCode = KeyCode::F(10);
+
+/// Press to open the menu, which pauses the game.
+const MENU_KEY: KeyCode = KeyCode::Esc;
+
+/// Press to reset the game, as if the power was cycled.
+const RESET_KEY: KeyCode = KeyCode::F(1);
+
+/// Press to turn `--cheat` codes off, and back on.
+const CHEATS_KEY: KeyCode = KeyCode::F(4);
+
+/// Press to show the tiles in VRAM instead of the game, then the BG map, the
+/// sprites, and the game again. Page Up and Page Down flip through the tiles
+/// and maps, and the arrow keys scroll the map.
+const VRAM_VIEW_KEY: KeyCode = KeyCode::F(7);
+
+/// Press to show the latest log lines under the game, and again to hide them.
+/// Page Up and Page Down scroll back through them.
+const LOG_PANE_KEY: KeyCode = KeyCode::Char('l');
+
+/// How long to wait for input at a time while nothing runs, e.g. paused or in
+/// the menu, before checking on everything else
+const IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How often the FPS in the window title is updated
+const TITLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Hold to rewind, at twice the normal speed.
+const REWIND_KEY: KeyCode = KeyCode::Char('r');
+/// Cycles between rewind states, and how many seconds' worth are kept.
+const REWIND_INTERVAL: u32 = 2;
+const REWIND_SECONDS: u32 = 15;
+
+// Only ever parsed once, so the size of `Run` does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 #[structopt(about = "A simple GBC terminal emulator written in Rust")]
 enum Args {
     #[structopt(about = "Run a ROM on the emulator")]
     Run {
+        #[structopt(parse(from_os_str), required_unless_one = &["rom-dir", "raw-info"], help = "Path to ROM file")]
+        rom_file: Option<PathBuf>,
+
+        #[structopt(long, help = "ROM to load from a zip archive with several in it")]
+        entry: Option<String>,
+
+        #[structopt(long, parse(from_os_str), value_name = "file", conflicts_with = "rom-dir",
+                    help = "Apply an IPS or BPS patch to the ROM, e.g. a fan translation")]
+        patch: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), value_name = "dir",
+                    conflicts_with_all = &["rom-file", "entry", "record", "playback", "dump-frames", "record-mp4", "script",
+                                          "until-frame"],
+                    help = "Pick the ROM to run from a list of those in a directory, coming back to it after each game")]
+        rom_dir: Option<PathBuf>,
+
+        #[structopt(long, parse(try_from_str = parse_boot_rom),
+                    help = "Run a DMG (256 bytes) or CGB (2304 bytes) boot ROM before the game")]
+        boot_rom: Option<BootRom>,
+
+        #[structopt(long, help = "Run the ROM even if its header looks wrong, e.g. for homebrew")]
+        force: bool,
+
+        #[structopt(long, default_value = "auto",
+                    help = "Hardware to run on: dmg for the original Game Boy, cgb for the Color, or auto for what the game asks for")]
+        mode: Hardware,
+
+        #[structopt(long = "bind", parse(try_from_str = input::parse_binding), number_of_values = 1,
+                    help = "Bind a key to a joypad input, e.g. `--bind a=Left` (repeatable)")]
+        bindings: Vec<(KeyCode, JoypadInput)>,
+
+        #[structopt(long, require_delimiter = true, parse(try_from_str = input::parse_joypad_input),
+                    help = "Joypad inputs that auto-fire while held, e.g. `--turbo a,b`")]
+        turbo: Vec<JoypadInput>,
+
+        #[structopt(long, parse(try_from_str = input::parse_turbo_rate),
+                    help = "Turbo presses per second, from 1 to 30 [default: 10]")]
+        turbo_rate: Option<f64>,
+
+        #[structopt(long, value_name = "how",
+                    help = "When opposite directions are held: last (the one pressed last wins), cancel (neither), or allow (both) [default: last]")]
+        dpad_conflict: Option<DpadConflict>,
+
+        #[structopt(long, value_name = "key",
+                    help = "Key to quit with, pressed twice, e.g. `ctrl-x` or `f11` (Ctrl-C always quits) [default: ctrl-q]")]
+        quit_key: Option<KeyCombo>,
+
+        #[structopt(long, parse(try_from_str = input::parse_repeat_delay), value_name = "ms",
+                    help = "How long an arrow key is held in the menu or ROM picker before it repeats [default: 400]")]
+        menu_repeat_delay: Option<Duration>,
+
+        #[structopt(long, parse(try_from_str = input::parse_repeat_rate), value_name = "n",
+                    help = "How many times a second a held arrow key repeats in the menu or ROM picker [default: 12]")]
+        menu_repeat_rate: Option<f64>,
+
+        #[structopt(long, conflicts_with_all = &["fullres", "ascii"],
+                    help = "How to draw: half-block, sextant, ascii, or sixel or kitty (need terminal support) [default: half-block]")]
+        renderer: Option<Backend>,
+
+        #[structopt(long, help = "Render 2x3 pixels per cell using Unicode sextants (needs font support)")]
+        fullres: bool,
+
+        #[structopt(long, conflicts_with = "fullres",
+                    help = "Render in plain ASCII characters without color, for terminals that show nothing else")]
+        ascii: bool,
+
+        #[structopt(long, parse(try_from_str = config::parse_scale_width),
+                    help = "Draw each cell N terminal columns wide, from 1 to 4, for a bigger image [default: 1]")]
+        scale_width: Option<usize>,
+
+        #[structopt(long, conflicts_with = "scale-width",
+                    help = "Stretch the image so that pixels are square, for cells --cell-aspect times as tall as wide")]
+        correct_aspect: bool,
+
+        #[structopt(long, parse(try_from_str = config::parse_position), value_name = "col,row", conflicts_with = "rom-dir",
+                    help = "Draw at this corner of the terminal (0,0 is the top left), leaving the rest of it alone")]
+        at: Option<(u16, u16)>,
+
+        #[structopt(long, value_name = "left,top,width,height",
+                    help = "Only draw this part of the 160x144 frame, e.g. 0,0,160,128 to leave out a status bar at the bottom")]
+        crop: Option<Crop>,
+
+        #[structopt(long, parse(try_from_str = config::parse_cell_aspect),
+                    help = "Height of a terminal cell over its width, from 1 to 4, for --correct-aspect [default: 2.0]")]
+        cell_aspect: Option<f64>,
+
+        #[structopt(long, help = "Colors to send to the terminal: truecolor, 256 or auto [default: auto]")]
+        color_mode: Option<ColorMode>,
+
+        #[structopt(long, help = "Render in shades of gray only")]
+        grayscale: bool,
+
+        #[structopt(long, parse(try_from_str = tone::parse_factor),
+                    help = "Scale the brightness of the image, from 0.1 to 4.0, nudged with - and = [default: 1.0]")]
+        brightness: Option<f64>,
+
+        #[structopt(long, parse(try_from_str = tone::parse_factor),
+                    help = "Scale the contrast of the image, from 0.1 to 4.0 [default: 1.0]")]
+        contrast: Option<f64>,
+
+        #[structopt(long, parse(try_from_str = tone::parse_factor),
+                    help = "Gamma for the image, from 0.1 to 4.0, above 1.0 to lighten dark scenes [default: 1.0]")]
+        gamma: Option<f64>,
+
+        #[structopt(long, help = "Colors for monochrome games: green, pocket or grey")]
+        palette: Option<Palette>,
+
+        #[structopt(long, parse(try_from_str = palette::parse_palette_rgb), conflicts_with = "palette",
+                    help = "Custom colors for monochrome games, lightest first, e.g. `#e0f8d0,#88c070,#346856,#081820`")]
+        palette_rgb: Option<Palette>,
+
+        #[structopt(long, parse(try_from_str = palette::parse_palette_file),
+                    conflicts_with_all = &["palette", "palette-rgb"],
+                    help = "Load custom colors for monochrome games from a JSON array of 4 hex colors")]
+        palette_file: Option<Palette>,
+
+        #[structopt(long, help = "Make color games look more like they did on the CGB's screen, less saturated")]
+        color_correct: bool,
+
+        #[structopt(long, help = "Keep showing the last frame while the game has the LCD off, instead of a blank screen")]
+        keep_last_frame: bool,
+
+        #[structopt(long, conflicts_with = "cycle-step",
+                    help = "Blend pixels that flicker every other frame, smoothing out see-through sprites like the real screen did")]
+        blend: bool,
+
+        #[structopt(long, help = "Outline high-contrast edges in a bright color, to make sprites easier to pick out")]
+        accessibility_outline: bool,
+
+        #[structopt(long, parse(try_from_str = palette::parse_hex), value_name = "#rrggbb",
+                    help = "Color of the outlines drawn by --accessibility-outline [default: #ffff00]")]
+        outline_color: Option<GameboyRgb>,
+
+        #[structopt(long, parse(try_from_str = config::parse_speed),
+                    help = "Emulation speed multiplier, e.g. 2.0 for double speed [default: 1.0]")]
+        speed: Option<f64>,
+
+        #[structopt(long, parse(try_from_str = config::parse_frameskip),
+                    help = "Frames to skip drawing after each one that is drawn, from 0 to 9, for slow terminals [default: 0]")]
+        frameskip: Option<u32>,
+
+        #[structopt(long, value_name = "cycles", parse(try_from_str = config::parse_cycle_step),
+                    conflicts_with_all = &["frameskip", "dual", "raw-out", "record-mp4", "dump-frames", "script", "until-frame"],
+                    help = "Run this many CPU cycles a frame's time instead of whole frames, drawing frames as they are drawn, e.g. 456 for a line at a time")]
+        cycle_step: Option<u32>,
+
+        #[structopt(long, value_name = "x", parse(try_from_str = config::parse_cpu_clock),
+                    conflicts_with_all = &["dual", "dump-frames", "script", "until-frame"],
+                    help = "Over- or underclock the CPU, from 0.25 to 8 times its speed, leaving the rest alone (breaks timing-sensitive games)")]
+        cpu_clock: Option<f64>,
+
+        #[structopt(long, parse(try_from_str = config::parse_max_render_fps),
+                    help = "Draw at most this many frames a second, without slowing down the game")]
+        max_render_fps: Option<f64>,
+
+        #[structopt(long,
+                    help = "Have the terminal show each frame all at once, to avoid tearing [default: if supported]")]
+        sync_output: bool,
+
+        #[structopt(long, conflicts_with = "sync-output",
+                    help = "Never use synchronized output, for terminals that print it instead")]
+        no_sync_output: bool,
+
+        #[structopt(long, help = "Start with audio muted")]
+        mute: bool,
+
+        #[structopt(long, help = "Pace emulation by audio playback instead of sleeping (needs audio)")]
+        audio_sync: bool,
+
+        #[structopt(long, value_name = "how",
+                    help = "How to wait between frames: spin (least jitter), sleep (least CPU), or hybrid (sleep, then spin through the end) [default: hybrid]")]
+        pacing: Option<Pacing>,
+
+        #[structopt(long, parse(try_from_str = pace::parse_spin_threshold), value_name = "µs",
+                    help = "How much of the wait hybrid pacing spins through, in microseconds [default: 1000]")]
+        spin_threshold: Option<Duration>,
+
+        #[structopt(long, default_value = "1", help = "Frames to drop after each frame recorded to a GIF")]
+        record_skip: u32,
+
+        #[structopt(long, default_value = "60", help = "Maximum length of a GIF recording, in seconds")]
+        record_max: u64,
+
+        #[structopt(long, help = "Enable the debug console, opened with F10 or on a breakpoint")]
+        debug: bool,
+
+        #[structopt(long, help = "Time every frame, and print a summary of the timings on exit")]
+        profile: bool,
+
+        #[structopt(long, parse(from_os_str), value_name = "path",
+                    help = "Append the log to a file instead of stderr, where it would be drawn over, at info level unless RUST_LOG is set")]
+        log_file: Option<PathBuf>,
+
+        #[structopt(long, value_name = "n", conflicts_with_all = &["playback", "dump-frames"],
+                    help = "Run this many frames as fast as possible before showing the game, e.g. to get past intros")]
+        skip_frames: Option<u32>,
+
+        #[structopt(long, conflicts_with_all = &["playback", "dump-frames"],
+                    help = "Start from the quicksave state in slot 1, as saved with F5, before skipping any frames")]
+        load_state: bool,
+
+        #[structopt(long, conflicts_with_all = &["load-state", "playback", "dump-frames"],
+                    help = "Save the state on quit, and pick up from it the next time the game is run")]
+        auto_resume: bool,
+
+        #[structopt(long, parse(from_os_str), value_name = "dir",
+                    help = "Keep saves, save states, screenshots and recordings here, in a folder per game")]
+        save_dir: Option<PathBuf>,
+
+        #[structopt(long, conflicts_with = "dump-frames",
+                    help = "Show the first frame and wait for a key before running, e.g. to get a recording ready")]
+        start_paused: bool,
+
+        #[structopt(long, help = "Ring the terminal bell when a key saves, loads, takes a screenshot and the like")]
+        bell: bool,
+
+        #[structopt(long = "cheat", value_name = "code", parse(try_from_str = cheats::parse_cheat), number_of_values = 1,
+                    conflicts_with_all = &["rom-dir", "dump-frames"],
+                    help = "Apply a Game Genie (ABC-DEF-GHI) or GameShark (01VVLLHH) code, toggled with F4 (repeatable)")]
+        cheats: Vec<Cheat>,
+
+        #[structopt(long, value_name = "host:port",
+                    help = "Link to another instance for two-player games, listening if it is not up yet")]
+        link: Option<String>,
+
+        #[structopt(long, parse(from_os_str), value_name = "path", conflicts_with_all = &["dual", "dump-frames", "script"],
+                    help = "Take joypad input from programs too, as `down <input>` and `up <input>` lines over a Unix socket")]
+        input_socket: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), value_name = "rom",
+                    conflicts_with_all = &["rom-dir", "link", "at", "crop", "record", "playback", "debug", "raw-out", "record-mp4"],
+                    help = "Run a second game next to the first, linked to it, e.g. to trade with itself (Tab switches the keys)")]
+        dual: Option<PathBuf>,
+
+        #[structopt(long, value_name = "stdout|file",
+                    help = "Print what the game sends over serial, e.g. test ROM results, on quitting, or append it to a file")]
+        serial_out: Option<SerialTarget>,
+
+        #[structopt(long, parse(from_os_str), value_name = "path", conflicts_with = "dump-frames",
+                    help = "Write every frame as raw RGB bytes to a file or named pipe, e.g. for ffmpeg")]
+        raw_out: Option<PathBuf>,
+
+        #[structopt(long, help = "Print the format --raw-out writes, with the matching ffmpeg options, and exit")]
+        raw_info: bool,
+
+        #[structopt(long, parse(from_os_str), value_name = "file.mp4", conflicts_with = "dump-frames",
+                    help = "Record video and sound to an MP4 file with ffmpeg, until the game is quit")]
+        record_mp4: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), value_name = "movie.gbm", conflicts_with = "link",
+                    help = "Record joypad input to a movie file, for exact playback")]
+        record: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), value_name = "movie.gbm", conflicts_with_all = &["link", "record"],
+                    help = "Play back a movie file, ignoring live input until it ends")]
+        playback: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), value_name = "dir", conflicts_with_all = &["link", "record", "debug"],
+                    help = "Run without a terminal, writing every frame to a PNG in the given directory")]
+        dump_frames: Option<PathBuf>,
+
+        #[structopt(long, requires = "dump-frames",
+                    help = "Number of frames to dump [default: the length of the movie, or 600]")]
+        dump_count: Option<u32>,
+
+        #[structopt(long, conflicts_with_all = &["link", "record", "playback", "debug", "dump-frames", "raw-out", "record-mp4"],
+                    help = "Run without a terminal, taking commands like `press a 5` on stdin and answering on stdout")]
+        script: bool,
+
+        #[structopt(long, value_name = "n", parse(try_from_str = config::parse_frame_count),
+                    conflicts_with_all = &["link", "record", "debug", "dump-frames", "raw-out", "record-mp4", "script"],
+                    help = "Run without a terminal up to frame n, save it as a PNG, and exit")]
+        until_frame: Option<u32>,
+
+        #[structopt(long, parse(from_os_str), value_name = "file.png", requires = "until-frame",
+                    help = "Where --until-frame saves the frame [default: frame-<n>.png]")]
+        frame_out: Option<PathBuf>,
+
+        #[structopt(long, parse(from_os_str), value_name = "golden.png", requires = "until-frame",
+                    help = "Exit with an error if the --until-frame frame differs from this image")]
+        compare: Option<PathBuf>,
+
+        #[structopt(long, requires = "compare",
+                    help = "Largest difference in any color channel --compare accepts, from 0 to 255 [default: 0]")]
+        tolerance: Option<u8>,
+    },
+
+    #[structopt(about = "Run a ROM headless, as fast as possible, and report the emulated FPS")]
+    Bench {
         #[structopt(parse(from_os_str), help = "Path to ROM file")]
         rom_file: PathBuf,
+
+        #[structopt(long, help = "ROM to load from a zip archive with several in it")]
+        entry: Option<String>,
+
+        #[structopt(long, default_value = "3600", help = "Number of frames to run")]
+        frames: u32,
+
+        #[structopt(long, value_name = "n",
+                    help = "Press buttons at random, the same way for the same seed, instead of not at all")]
+        seed: Option<u64>,
+
+        #[structopt(long, help = "Run the ROM even if its header looks wrong, e.g. for homebrew")]
+        force: bool,
+    },
+
+    #[structopt(about = "Disassemble instructions from a ROM")]
+    Disasm {
+        #[structopt(parse(from_os_str), help = "Path to ROM file")]
+        rom_file: PathBuf,
+
+        #[structopt(long, help = "ROM to load from a zip archive with several in it")]
+        entry: Option<String>,
+
+        #[structopt(long, default_value = "0x100", parse(try_from_str = disasm::parse_addr),
+                    help = "Address to start at, in hex")]
+        addr: u16,
+
+        #[structopt(long, default_value = "20", help = "Number of instructions to disassemble")]
+        count: usize,
+
+        #[structopt(long, default_value = "1", help = "ROM bank mapped at 0x4000-0x7FFF")]
+        bank: usize,
+    },
+
+    #[structopt(about = "Print the cartridge header of a ROM")]
+    Info {
+        #[structopt(parse(from_os_str), required_unless = "list-mbc", help = "Path to ROM file")]
+        rom_file: Option<PathBuf>,
+
+        #[structopt(long, help = "ROM to load from a zip archive with several in it")]
+        entry: Option<String>,
+
+        #[structopt(long, help = "Print as JSON")]
+        json: bool,
+
+        #[structopt(long, conflicts_with_all = &["rom-file", "json"],
+                    help = "List the memory bank controllers cartridges use, and which of them are supported")]
+        list_mbc: bool,
+    },
+}
+
+/// Options for running a ROM
+#[derive(Clone)]
+struct Options {
+    bindings: KeyBindings,
+    gamepad: GamepadBindings,
+    turbo: Vec<JoypadInput>,
+    turbo_rate: f64,
+    dpad_conflict: DpadConflict,
+    quit_key: KeyCombo,
+    menu_repeat: KeyRepeat,
+    backend: Backend,
+    scale_width: usize,
+    /// Cell height over width, to stretch the image by instead of `scale_width`
+    correct_aspect: Option<f64>,
+    /// Where to draw on the normal screen, instead of taking over the terminal
+    at: Option<(u16, u16)>,
+    /// The part of the frame to draw
+    crop: Crop,
+    color_mode: ColorMode,
+    grayscale: bool,
+    tone: Tone,
+    palette: Option<Palette>,
+    color_correct: bool,
+    keep_last_frame: bool,
+    /// Blend flickering pixels on their way to the terminal
+    blend: bool,
+    /// Color of `--accessibility-outline`, if on
+    outline: Option<GameboyRgb>,
+    speed: f64,
+    frameskip: u32,
+    /// Cycles to run at a time, instead of whole frames
+    cycle_step: Option<u32>,
+    /// CPU speed multiplier, for over- and underclocking
+    cpu_clock: Option<f64>,
+    max_render_fps: Option<f64>,
+    /// `None` to use it if the terminal supports it
+    sync_output: Option<bool>,
+    mute: bool,
+    audio_sync: bool,
+    pacing: Pacing,
+    /// How much of the wait `Pacing::Hybrid` spins through
+    spin_threshold: Duration,
+    record_skip: u32,
+    record_max: Duration,
+    debug: bool,
+    profile: bool,
+    skip_frames: u32,
+    load_state: bool,
+    auto_resume: bool,
+    save_dir: PathBuf,
+    start_paused: bool,
+    bell: bool,
+    cheats: Vec<Cheat>,
+    link: Option<String>,
+    input_socket: Option<PathBuf>,
+    serial_out: Option<SerialTarget>,
+    raw_out: Option<PathBuf>,
+    record_mp4: Option<PathBuf>,
+    record: Option<PathBuf>,
+    playback: Option<Movie>,
+}
+
+/// The renderer picked by `--renderer`, or else by `--ascii` or `--fullres`.
+fn pick_backend(renderer: Option<Backend>, ascii: bool, fullres: bool) -> Option<Backend> {
+    renderer.or(if ascii {
+        Some(Backend::Ascii)
+    } else if fullres {
+        Some(Backend::Sextant)
+    } else {
+        None
+    })
+}
+
+/// Load a boot ROM image, checking its size.
+fn parse_boot_rom(s: &str) -> std::result::Result<BootRom, String> {
+    let data = std::fs::read(s).map_err(|e| format!("Failed to read {}: {}", s, e))?;
+    BootRom::from_bytes(data).map_err(|e| e.to_string())
+}
+
+/// Real time that a pass through the main loop, running `frames` frames of
+/// which only the last one is drawn, should take at the given speed. Games run
+/// at their normal speed however many frames are skipped.
+fn cycle_duration(frames: u32, speed: f64) -> Duration {
+    Duration::from_nanos(Gameboy::FRAME_DURATION * frames as u64).div_f64(speed)
+}
+
+/// Runs `frames` Gameboy frames as fast as possible, without input, and
+/// throws away their sound.
+fn fast_forward(gameboy: &mut Gameboy, frames: u32, cheats: &Cheats) {
+    for _ in 0..frames {
+        cheats.frame(gameboy);
+        gameboy.frame(None);
+        gameboy.drain_audio_samples();
     }
 }
 
-fn char_to_joypad_input(keycode: Option<char>) -> Option<JoypadInput> {
-    match keycode.unwrap() {
-        'n' => Some(JoypadInput::B),
-        'm' => Some(JoypadInput::A),
-        'j' => Some(JoypadInput::Start),
-        'k' => Some(JoypadInput::Select),
-        'w' => Some(JoypadInput::Up),
-        's' => Some(JoypadInput::Down),
-        'a' => Some(JoypadInput::Left),
-        'd' => Some(JoypadInput::Right),
-        _ => None,
+/// Runs `frames` Gameboy frames.
+///
+/// With a debug console, breakpoints are checked after every instruction.
+/// With a link, serial bytes are exchanged after every frame. Turbo inputs
+/// are toggled, the input recorded or played back for a movie, and GameShark
+/// codes written, before every frame.
+#[allow(clippy::too_many_arguments)]
+fn handle_frame(gameboy: &mut Gameboy, joypad_events: &mut Vec<JoypadEvent>, frames: u32,
+                key_tracker: &mut KeyTracker, mut console: Option<&mut Console>, mut link: Option<&mut Link>,
+                movie: &mut Option<Session>, cheats: &Cheats) {
+    for _ in 0..frames {
+        key_tracker.turbo_frame(joypad_events);
+        let later = input::hold_over_taps(joypad_events);
+        movie_frame(movie, gameboy, joypad_events);
+        cheats.frame(gameboy);
+        if let Some(console) = console.as_deref_mut() {
+            console.run_until_frame(gameboy);
+        }
+        gameboy.frame(Some(joypad_events));
+        // Clear out all processed input events, leaving the releases of
+        // quick taps for the next frame
+        *joypad_events = later;
+        if let Some(link) = link.as_deref_mut() {
+            link.update(gameboy);
+        }
     }
 }
 
-pub fn read_char() -> Result<char> {
-    let mut buf = [0u8; 1];
-    io::stdin().read(&mut buf)?;
-    Ok(buf[0] as char)
+/// Confirm what a key did, or why it did not, on the overlay and in the log.
+fn notify(overlay: &Mutex<Overlay>, renderer: &mut RenderThread, result: std::result::Result<String, String>) {
+    let message = match result {
+        Ok(message) => {
+            log::info!("{}", message);
+            message
+        }
+        Err(message) => {
+            log::error!("{}", message);
+            message
+        }
+    };
+    overlay.lock().unwrap().notify(message, Instant::now());
+    // Shown right away, even while paused
+    renderer.redraw();
 }
 
-fn spawn_stdin_channel() -> Receiver<char> {
-    let (tx, rx) = mpsc::channel::<char>();
-    thread::spawn(move || loop {
-        let c = read_char().unwrap();
-        tx.send(c).unwrap();
-    });
-    rx
-}
-
-
-fn create_frame(frame_buffer: &FrameBuffer, frame: &mut Vec<u8>) {
-    // Separate pixel into top and bottom color
-    let mut prev_bg_color = Color::Rgb{r:0, g:0, b:0};
-    let mut prev_fg_color = Color::Rgb{r:0, g:0, b:0};
-    queue!(frame, style::SetBackgroundColor(prev_bg_color)).unwrap();
-    queue!(frame, style::SetForegroundColor(prev_fg_color)).unwrap();
-
-    for y in 0..LCD_HEIGHT/2 {
-        for x in 0..LCD_WIDTH {
-            let bg_color_vals = frame_buffer.read(x, y*2);
-            let fg_color_vals = frame_buffer.read(x, y*2+1);
-            let bg_color = Color::Rgb{r:bg_color_vals.red, g:bg_color_vals.green, b:bg_color_vals.blue};
-            let fg_color = Color::Rgb{r:fg_color_vals.red, g:fg_color_vals.green, b:fg_color_vals.blue};
-            if bg_color != prev_bg_color {
-                queue!(frame, style::SetBackgroundColor(bg_color)).unwrap();
-                prev_bg_color = bg_color;
-            }
-            if fg_color != prev_fg_color {
-                queue!(frame, style::SetForegroundColor(fg_color)).unwrap();
-                prev_fg_color = fg_color;
-            }
-            queue!(frame, style::Print("▄")).unwrap();
-        }
-        queue!(frame, cursor::MoveToNextLine(1)).unwrap();
-    }
-}
-
-/// Renders a single Gameboy frame to the console
-fn render_frame(frame_buffer: &FrameBuffer, frame: &mut Vec<u8>, stdout: &mut io::Stdout){
-    // lock stdout
-    let mut stdout = stdout.lock();
-    // Clear the screen with crossterm
-    queue!(
-        stdout,
-        style::ResetColor,
-        //terminal::Clear(ClearType::All),
-        cursor::MoveTo(0, 0)
-    ).unwrap();
-    // Render the frame
-    create_frame(frame_buffer, frame);
-    // Write the frame to stdout
-    stdout.write_all(&frame).unwrap();
-    // Flush the output
-    stdout.flush().unwrap();
-    // empty the frame buffer
-    frame.clear();
-}   
-
-/// Handles a single Gameboy frame.
-fn handle_frame(gameboy: &mut Gameboy, joypad_events: &mut Vec<JoypadEvent>, frame: &mut Vec<u8>, stdout: &mut io::Stdout) {
-    for _ in 0..FRAMES_PER_CYCLE-1{
-        gameboy.frame(Some(joypad_events));
+/// Quicksave, logging where to.
+fn quicksave(gameboy: &Gameboy, path: &Path) -> std::result::Result<(), String> {
+    persist::save_state(gameboy, path).map_err(|e| format!("Failed to save state: {}", e))?;
+    log::info!("Saved state to {}", path.display());
+    Ok(())
+}
+
+/// Save the PPU state as JSON, logging where to.
+fn save_ppu_state(ppu: &Ppu, path: &Path) -> std::result::Result<(), String> {
+    let json = serde_json::to_string_pretty(&vram::to_json(ppu)).unwrap();
+    persist::create_parent(path)
+        .and_then(|()| std::fs::write(path, json))
+        .map_err(|e| format!("Failed to save PPU state: {}", e))?;
+    log::info!("Saved PPU state to {}", path.display());
+    Ok(())
+}
+
+/// Quickload, logging where from. Movies cannot jump around in time.
+fn quickload(gameboy: &mut Gameboy, path: &Path, movie: &Option<Session>, link: Option<&Link>)
+             -> std::result::Result<(), String> {
+    if movie.is_some() {
+        return Err("States cannot be loaded during a movie".to_string());
     }
 
-    let frame_buffer = gameboy.frame(Some(joypad_events));
+    persist::load_state(gameboy, path).map_err(|e| format!("Failed to load state: {}", e))?;
+    log::info!("Loaded state from {}", path.display());
+    // The cable is not part of the state
+    if link.is_some_and(Link::is_connected) {
+        gameboy.set_link_connected(true);
+    }
+    Ok(())
+}
+
+/// Reset the game as if the power was cycled, keeping its battery saves.
+fn reset(gameboy: &mut Gameboy, movie: &Option<Session>, link: Option<&Link>) -> std::result::Result<String, String> {
+    if movie.is_some() {
+        return Err("The game cannot be reset during a movie".to_string());
+    }
 
-    // Clear out all processed input events
-    joypad_events.clear();
+    gameboy.reset();
+    // The cable stays plugged in
+    if link.is_some_and(Link::is_connected) {
+        gameboy.set_link_connected(true);
+    }
+    Ok("Reset".to_string())
+}
 
-    // Render the frame
-    render_frame(frame_buffer, frame, stdout);
+fn movie_frame(movie: &mut Option<Session>, gameboy: &mut Gameboy, joypad_events: &mut Vec<JoypadEvent>) {
+    if let Some(session) = movie {
+        if !session.frame(gameboy, joypad_events) {
+            let ended = movie.take();
+            // Movies run the cartridge clock on emulated time, live play does not
+            gameboy.set_emulated_clock(false);
+            if let Some(Session::Playing(_)) = ended {
+                log::info!("Movie finished, back to live input");
+            }
+        }
+    }
 }
 
-fn cli(rom_file: PathBuf) -> Result<()> {
+fn cli(rom_file: PathBuf, cartridge: Cartridge, options: Options) -> Result<()> {
+    let Options {
+        bindings, gamepad, turbo, turbo_rate, dpad_conflict, quit_key, mut menu_repeat, backend, scale_width, correct_aspect, at, crop, color_mode, grayscale, mut tone, palette, color_correct,
+        keep_last_frame, blend, outline, speed, frameskip, cycle_step, cpu_clock, max_render_fps, sync_output, mute, audio_sync, pacing, spin_threshold,
+        record_skip, record_max, debug, profile, skip_frames, load_state, auto_resume, save_dir, start_paused, bell, cheats, link, input_socket, serial_out, raw_out, record_mp4,
+        record,
+        playback,
+    } = options;
+
+    let game_dir = GameDir::new(&save_dir, &cartridge);
+    game_dir.migrate(&rom_file);
+
+    // Playing back a movie must not overwrite the real saves
+    let mut save_files = if playback.is_some() { None } else { SaveFiles::new(&game_dir, &cartridge) };
+
+    // Color games bring their own palettes
+    if palette.is_some() && cartridge.cgb() {
+        log::info!("Ignoring --palette for a color game");
+    }
+    let screen = Screen::new(&cartridge, palette, color_correct, keep_last_frame);
+
+    // The last frame, after applying the palette. This stays around while
+    // paused, e.g. for screenshots.
+    let mut current = FrameBuffer::new();
+    // And as it is shown, after `--blend`, `--brightness` and the like
+    let mut blend = blend.then(Blend::default);
+    let mut shown = FrameBuffer::new();
+    let mut outline = outline.map(Outline::new);
+
+    // For the window title, to tell instances apart
+    let name = rom::title(Some(&cartridge), &rom_file);
 
-    let mut stdout = io::stdout();
-    execute!(stdout, terminal::EnterAlternateScreen)?;
-    terminal::enable_raw_mode()?;
-    // Hide the cursor
-    execute!(stdout, cursor::Hide)?;
+    // Create the Gameboy, while errors can still be read
+    let mut gameboy = start(cartridge);
+    if let Some(clock) = cpu_clock {
+        gameboy.set_cpu_clock((clock * 100.0).round() as u32);
+        log::warn!("Running the CPU at {}x, which breaks games that count on its timing", clock);
+    }
+
+    // Before taking over the terminal, so that a missing ffmpeg is reported
+    // where it can be read
+    let mut mp4 = record_mp4.map(|path| Mp4Recorder::start(path, Gameboy::FRAME_FREQUENCY / f64::from(frameskip + 1))
+        .unwrap_or_else(|e| fail(e)));
 
-    // Load the ROM
-    let cartridge = get_cartridge(&rom_file, false);
+    // Put the terminal back on the way out, however that happens
+    tty::install_handlers();
+    if at.is_some() {
+        tty::use_normal_screen();
+    }
+    tty::enter()?;
 
-    // Create the Gameboy
-    let mut gameboy = Gameboy::init(cartridge, false).unwrap();
+    // Ask before anything else reads from the terminal
+    let backend = match backend {
+        Backend::Sixel if !sixel::supported() => {
+            log::warn!("The terminal does not report Sixel support, drawing with half blocks instead");
+            Backend::HalfBlock
+        }
+        Backend::Kitty if !kitty::supported() => {
+            log::warn!("The terminal does not support the kitty graphics protocol, drawing with half blocks instead");
+            Backend::HalfBlock
+        }
+        backend => backend,
+    };
+    let sync_output = sync_output.unwrap_or_else(tty::synchronized_output_supported);
+    log::info!("Synchronized output: {}", if sync_output { "on" } else { "off" });
+
+    // Load battery-backed RAM and RTC from a previous run
+    if let Some(save_files) = &mut save_files {
+        save_files.load(&mut gameboy);
+    }
 
-    // Create a channel for receiving input from stdin
-    let rx = spawn_stdin_channel();
+    // Save state slot used by F5 and F9, picked with the number keys
+    let mut slot = 1;
+    let mut state_path = game_dir.save_path_for(FileKind::State(slot));
+
+    let mut cheats = Cheats::new(cheats);
+    if !cheats.is_empty() {
+        cheats.enable(&mut gameboy);
+    }
+
+    // Kept apart from the slots, so that quitting never overwrites a state
+    // saved by hand
+    let auto_state_path = game_dir.save_path_for(FileKind::AutoState);
+
+    let loaded = if load_state {
+        quickload(&mut gameboy, &state_path, &None, None)
+    } else if auto_resume && auto_state_path.exists() {
+        // Boots from scratch if the state cannot be loaded
+        quickload(&mut gameboy, &auto_state_path, &None, None)
+    } else {
+        Ok(())
+    };
+    if let Err(e) = loaded {
+        log::error!("{}", e);
+    }
+
+    // Before anything reads input, so that keys pressed in the meantime are
+    // still there for the game once it shows up
+    if skip_frames > 0 {
+        let start = Instant::now();
+        fast_forward(&mut gameboy, skip_frames, &cheats);
+        log::info!("Skipped {} frames in {:.2?}", skip_frames, start.elapsed());
+    }
+
+    // Movies start from the state the game is in now
+    let mut movie = if let Some(path) = record {
+        match Recorder::start(&path, &mut gameboy) {
+            Ok(recorder) => {
+                log::info!("Recording movie to {}", path.display());
+                Some(Session::Recording(recorder))
+            }
+            Err(e) => {
+                log::error!("Failed to start recording {}: {}", path.display(), e);
+                None
+            }
+        }
+    } else if let Some(playback) = playback {
+        match Player::start(playback, &mut gameboy) {
+            Ok(player) => Some(Session::Playing(player)),
+            Err(e) => {
+                log::error!("Failed to play back movie: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut link = link.and_then(|addr| match Link::open(&addr, &mut gameboy) {
+        Ok(link) => Some(link),
+        Err(e) => {
+            log::error!("Link disabled, failed to listen on {}: {}", addr, e);
+            None
+        }
+    });
+
+    let mut input_socket = input_socket.and_then(|path| match InputSocket::open(&path) {
+        Ok(socket) => {
+            log::info!("Taking joypad input from {}", path.display());
+            Some(socket)
+        }
+        Err(e) => {
+            log::error!("Input socket disabled, failed to listen on {}: {}", path.display(), e);
+            None
+        }
+    });
+
+    // Printed to stdout once the terminal is back to normal
+    let mut serial = serial_out.and_then(|target| match SerialOut::open(&target, false) {
+        Ok(serial) => Some(serial),
+        Err(e) => {
+            log::error!("Serial output disabled, failed to open {:?}: {}", target, e);
+            None
+        }
+    });
+    let mut raw_out = raw_out.map(RawOut::open);
+
+    // Create a channel for receiving terminal events. The debug console
+    // pauses it to read commands from stdin.
+    let input_lock = input::InputLock::default();
+    let rx = input::spawn_event_channel(input_lock.clone());
+    let mut console = if debug { Some(Console::new(input_lock.clone())) } else { None };
+    let mut break_requested = false;
 
     // Create a vector for storing input events
     let mut joypad_events = Vec::new();
-    let mut pressed_keys = Vec::new();
-    let mut previous_pressed_keys = Vec::new();
+    let mut key_tracker = KeyTracker::new(bindings);
+    key_tracker.set_turbo(turbo, turbo_rate);
+    key_tracker.set_dpad_conflict(dpad_conflict);
+    let mut gamepads = match Gamepads::new(gamepad) {
+        Ok(gamepads) => Some(gamepads),
+        Err(e) => {
+            log::warn!("Gamepad disabled: {}", e);
+            None
+        }
+    };
 
-    // More accurate sleep, especially on Windows
-    let sleeper = spin_sleep::SpinSleeper::default();
+    let mut pacer = Pacer::new(pacing, spin_threshold);
 
-    let frame_time_ns = Gameboy::FRAME_DURATION * FRAMES_PER_CYCLE as u64;
-    let frame_duration = Duration::from_nanos(frame_time_ns);
+    // Frames per pass through the loop, only the last of which is drawn
+    let frames_per_cycle = if cycle_step.is_some() { 1 } else { frameskip + 1 };
+    let frame_duration = cycle_duration(frames_per_cycle, 1.0);
+    // When the next frame can be rendered
+    let mut next_render = Instant::now();
+    let min_render_interval = max_render_fps.map_or(Duration::ZERO, |fps| Duration::from_secs_f64(1.0 / fps));
+    // Whether the last frame was not rendered, to stay under the render rate
+    let mut held_back = false;
+    let mut screenshot = false;
+    let mut paused = start_paused;
+    // Latched with Shift-Tab, as if Tab was held
+    let mut fast_forward = false;
+    // Shown instead of the game while open
+    let mut vram_viewer: Option<VramViewer> = None;
+    let mut menu: Option<Menu> = None;
+    // How far the log pane is scrolled back, while it is open
+    let mut log_pane: Option<usize> = None;
+    let mut log_kept = log_pane::kept();
+    // Frames left to advance while paused, starting with the first one
+    let mut steps = u32::from(start_paused);
+    // With `--start-paused`, until any key is pressed
+    let mut waiting = start_paused;
+    if waiting {
+        log::info!("Paused, press any key to start");
+    }
 
-    let mut frame = Vec::with_capacity(LCD_HEIGHT * LCD_WIDTH * 16);
+    let mut audio = match AudioOutput::new(mute) {
+        Ok(audio) => Some(audio),
+        Err(e) => {
+            log::warn!("Audio disabled: {}", e);
+            None
+        }
+    };
+
+    let rewind_states = REWIND_SECONDS as f64 * Gameboy::FRAME_FREQUENCY / (frames_per_cycle * REWIND_INTERVAL) as f64;
+    let mut rewind = Rewind::new(rewind_states as usize, REWIND_INTERVAL);
+
+    let mut recorder: Option<GifRecorder> = None;
+    let mut encoders = Vec::new();
+
+    let output = backend.renderer(RenderOptions { scale_width, correct_aspect, at, color_mode, grayscale, crop });
+    log::info!("Color mode: {:?}", color_mode);
+    let mut overlay = Overlay::new();
+    overlay.origin = at.unwrap_or((0, 0));
+    overlay.bell = bell;
+    let overlay = Arc::new(Mutex::new(overlay));
+    let mut renderer = RenderThread::spawn(io::stdout(), output, overlay.clone(), sync_output, at.is_some(), profile);
+    // Before anything is drawn, so that it goes out ahead of the first frame
+    renderer.set_title(name.clone());
+    // For clicks on the menu
+    let mut size = terminal::size()?;
+    renderer.resize(size.0, size.1);
+    let mut next_title = Instant::now() + TITLE_INTERVAL;
+    // Only collected with `--profile`
+    let mut profile = if profile { Some(Profile::default()) } else { None };
+
+    // Until when the quit key quits, after it was pressed once
+    let mut quit_deadline: Option<Instant> = None;
+    // Taken off the channel while idle, to be handled first
+    let mut woken: Option<Event> = None;
 
     // Start the event loop
     'running: loop {
         let frame_start = Instant::now();
 
-        // See previous state of the joypad_events
-        //let previous_joypad_events = joypad_events.clone();
+        // Killed by a signal
+        if tty::quit_requested() {
+            break;
+        }
+
+        // Nowhere left to show the game, e.g. the output was piped and closed
+        if !renderer.is_running() {
+            break;
+        }
 
         // Handle input
         loop {
-            match rx.try_recv() {
-                // Escape to quit
-                Ok('q') => {
-                    // leave alternate screen
-                    execute!(stdout, terminal::LeaveAlternateScreen)?;
-                    execute!(stdout, cursor::Show)?;
+            let next = match woken.take() {
+                Some(event) => Ok(event),
+                None => rx.try_recv(),
+            };
+            match next {
+                // Ctrl-C quits right away, like the signal it stands in for
+                Ok(Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL, kind: KeyEventKind::Press, ..
+                })) => {
                     break 'running;
                 }
-                Ok(keycode) => {
-                    pressed_keys.push(keycode);
+                // The quit key has to be pressed twice, so that a stray press
+                // does not end the game
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press && quit_key.matches(&key) => {
+                    if quit_deadline.is_some_and(|deadline| frame_start < deadline) {
+                        break 'running;
+                    }
+                    quit_deadline = Some(frame_start + QUIT_CONFIRM_TIMEOUT);
+                    notify(&overlay, &mut renderer, Ok(format!("Press {} again to quit", quit_key)));
+                }
+                Ok(Event::Key(KeyEvent { kind: KeyEventKind::Press, .. })) if waiting => {
+                    waiting = false;
+                    paused = false;
+                    log::info!("Started");
+                }
+                // Everything else goes to the menu while it is open
+                Ok(event @ (Event::Key(KeyEvent { kind: KeyEventKind::Press | KeyEventKind::Repeat, .. })
+                            | Event::Mouse(_))) if menu.is_some() => {
+                    let action = menu.as_mut().and_then(|menu| match event {
+                        Event::Key(key) => menu_repeat.handle(key, frame_start).and_then(|key| menu.handle(key.code)),
+                        Event::Mouse(mouse) => menu.mouse(mouse, size),
+                        _ => None,
+                    });
+                    if action.is_some() {
+                        menu = None;
+                    }
+                    renderer.show_menu(menu);
+
+                    let result = match action {
+                        Some(Action::SaveState) => quicksave(&gameboy, &state_path)
+                            .map(|()| format!("Saved state {}", slot)),
+                        Some(Action::LoadState) => quickload(&mut gameboy, &state_path, &movie, link.as_ref())
+                            .map(|()| format!("Loaded state {}", slot)),
+                        Some(Action::Reset) => reset(&mut gameboy, &movie, link.as_ref()),
+                        // Saves are written out on the way out, as with the quit combo
+                        Some(Action::Quit) => break 'running,
+                        Some(Action::Resume) | None => continue,
+                    };
+                    notify(&overlay, &mut renderer, result);
+                }
+                // Open the menu, unless the key is bound to the joypad
+                Ok(Event::Key(KeyEvent { code: MENU_KEY, kind: KeyEventKind::Press, .. }))
+                    if !key_tracker.is_bound(MENU_KEY) => {
+                    menu = Some(Menu::at(at));
+                    renderer.show_menu(menu);
+                }
+                Ok(Event::Key(KeyEvent { code: RESET_KEY, kind: KeyEventKind::Press, .. })) => {
+                    let result = reset(&mut gameboy, &movie, link.as_ref());
+                    notify(&overlay, &mut renderer, result);
+                }
+                Ok(Event::Key(KeyEvent { code: CHEATS_KEY, kind: KeyEventKind::Press, .. })) if !cheats.is_empty() => {
+                    let enabled = cheats.toggle(&mut gameboy);
+                    notify(&overlay, &mut renderer, Ok(format!("Cheats {}", if enabled { "on" } else { "off" })));
+                }
+                // Pick a save state slot, unless the key is bound to the joypad
+                Ok(Event::Key(KeyEvent { code: KeyCode::Char(digit @ '1'..='9'), kind: KeyEventKind::Press, .. }))
+                    if !key_tracker.is_bound(KeyCode::Char(digit)) => {
+                    slot = digit as u8 - b'0';
+                    state_path = game_dir.save_path_for(FileKind::State(slot));
+                    notify(&overlay, &mut renderer, Ok(format!("Slot {}", slot)));
+                }
+                // Quicksave
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(5), kind: KeyEventKind::Press, .. })) => {
+                    let result = quicksave(&gameboy, &state_path).map(|()| format!("Saved state {}", slot));
+                    notify(&overlay, &mut renderer, result);
+                }
+                // Quickload
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(9), kind: KeyEventKind::Press, .. })) => {
+                    let result = quickload(&mut gameboy, &state_path, &movie, link.as_ref())
+                        .map(|()| format!("Loaded state {}", slot));
+                    notify(&overlay, &mut renderer, result);
+                }
+                // Screenshot, taken once the next frame is ready
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(12), kind: KeyEventKind::Press, .. })) => {
+                    screenshot = true;
+                }
+                // Toggle mute
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(6), kind: KeyEventKind::Press, .. })) => {
+                    if let Some(audio) = &mut audio {
+                        let muted = audio.toggle_mute();
+                        notify(&overlay, &mut renderer, Ok(format!("Audio {}", if muted { "muted" } else { "unmuted" })));
+                    }
+                }
+                // Latch fast forward on or off, unless the key is bound
+                Ok(Event::Key(KeyEvent { code: FAST_FORWARD_TOGGLE_KEY, kind: KeyEventKind::Press, .. }))
+                    if !key_tracker.is_bound(FAST_FORWARD_TOGGLE_KEY) => {
+                    fast_forward = !fast_forward;
+                    overlay.lock().unwrap().fast_forward = fast_forward;
+                    notify(&overlay, &mut renderer, Ok(format!("Fast forward {}", if fast_forward { "on" } else { "off" })));
+                    if !fast_forward {
+                        // Redraw the game cells that "FF" covered
+                        renderer.invalidate();
+                    }
+                    // In the title too, right away
+                    next_title = frame_start;
+                }
+                // Brighten or darken the image, unless the keys are bound
+                Ok(Event::Key(KeyEvent { code: code @ KeyCode::Char(c @ ('-' | '=')), kind: KeyEventKind::Press, .. }))
+                    if !key_tracker.is_bound(code) => {
+                    tone.nudge_brightness(if c == '-' { -tone::BRIGHTNESS_STEP } else { tone::BRIGHTNESS_STEP });
+                    notify(&overlay, &mut renderer, Ok(format!("Brightness {:.1}", tone.brightness())));
+                    // Shown again even while paused
+                    held_back = true;
+                }
+                Ok(Event::Key(KeyEvent { code: VRAM_VIEW_KEY, modifiers: KeyModifiers::SHIFT, kind: KeyEventKind::Press, .. })) => {
+                    let path = game_dir.save_path_for(FileKind::PpuState);
+                    notify(&overlay, &mut renderer, save_ppu_state(gameboy.ppu(), &path).map(|()| "Saved PPU state".to_string()));
+                }
+                // Show what is in VRAM instead of the game, the next of it, or
+                // go back
+                Ok(Event::Key(KeyEvent { code: VRAM_VIEW_KEY, kind: KeyEventKind::Press, .. })) => {
+                    vram_viewer = match vram_viewer {
+                        Some(viewer) => viewer.next_view(),
+                        None => Some(VramViewer::default()),
+                    };
+                    if let Some(viewer) = &vram_viewer {
+                        overlay.lock().unwrap().show_message(viewer.title(gameboy.ppu()), Instant::now());
+                    }
+                    // Shown again even while paused
+                    held_back = true;
+                }
+                Ok(Event::Key(KeyEvent { code: LOG_PANE_KEY, kind: KeyEventKind::Press, .. }))
+                    if !key_tracker.is_bound(LOG_PANE_KEY) => {
+                    log_pane = if log_pane.is_some() { None } else { Some(0) };
+                    renderer.show_log(log_pane);
+                }
+                // Scroll the log, unless flipping through VRAM
+                Ok(Event::Key(KeyEvent { code: code @ (KeyCode::PageUp | KeyCode::PageDown),
+                                         kind: KeyEventKind::Press | KeyEventKind::Repeat, .. }))
+                    if log_pane.is_some() && vram_viewer.is_none() => {
+                    let page = usize::from(log_pane::ROWS - 1);
+                    let scroll = log_pane.unwrap();
+                    let scroll = if code == KeyCode::PageUp { scroll + page } else { scroll.saturating_sub(page) };
+                    log_pane = Some(scroll.min(log_pane::max_scroll()));
+                    renderer.show_log(log_pane);
+                }
+                Ok(Event::Key(KeyEvent { code: code @ (KeyCode::PageUp | KeyCode::PageDown),
+                                         kind: KeyEventKind::Press | KeyEventKind::Repeat, .. })) if vram_viewer.is_some() => {
+                    let viewer = vram_viewer.as_mut().unwrap();
+                    viewer.flip(gameboy.ppu(), if code == KeyCode::PageUp { -1 } else { 1 });
+                    overlay.lock().unwrap().show_message(viewer.title(gameboy.ppu()), Instant::now());
+                    held_back = true;
+                }
+                // The arrow keys scroll the BG map instead of playing
+                Ok(Event::Key(KeyEvent { code: code @ (KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down),
+                                         kind: KeyEventKind::Press | KeyEventKind::Repeat, .. }))
+                    if vram_viewer.as_ref().is_some_and(VramViewer::scrolls) => {
+                    let step = vram::MAP_SCROLL_STEP as isize;
+                    let by = match code {
+                        KeyCode::Left => (-step, 0),
+                        KeyCode::Right => (step, 0),
+                        KeyCode::Up => (0, -step),
+                        _ => (0, step),
+                    };
+                    let viewer = vram_viewer.as_mut().unwrap();
+                    viewer.scroll(by);
+                    overlay.lock().unwrap().show_message(viewer.title(gameboy.ppu()), Instant::now());
+                    held_back = true;
+                }
+                // Pause or resume, unless the key is bound to the joypad
+                Ok(Event::Key(KeyEvent { code: PAUSE_KEY, kind: KeyEventKind::Press, .. }))
+                    if !key_tracker.is_bound(PAUSE_KEY) => {
+                    paused = !paused;
+                    steps = 0;
+                    notify(&overlay, &mut renderer, Ok(if paused { "Paused" } else { "Resumed" }.to_string()));
+                }
+                // Advance a single frame while paused
+                Ok(Event::Key(KeyEvent { code: STEP_KEY, kind: KeyEventKind::Press, .. }))
+                    if paused && !key_tracker.is_bound(STEP_KEY) => {
+                    steps += 1;
+                }
+                // Open the debug console before the next frame
+                Ok(Event::Key(KeyEvent { code: BREAK_KEY, kind: KeyEventKind::Press, .. })) if console.is_some() => {
+                    break_requested = true;
                 }
+                // Log the CPU state, between frames so it is consistent
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(2), kind: KeyEventKind::Press, .. })) => {
+                    log::info!("{}", gameboy.cpu_state());
+                }
+                // Toggle the performance overlay
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(3), kind: KeyEventKind::Press, .. })) => {
+                    renderer.toggle_overlay();
+                }
+                // Start or stop recording
+                Ok(Event::Key(KeyEvent { code: KeyCode::F(8), kind: KeyEventKind::Press, .. })) => {
+                    let message = match recorder.take() {
+                        Some(recorder) => {
+                            encoders.push(recorder.stop());
+                            "Recording stopped"
+                        }
+                        None => {
+                            let path = game_dir.save_path_for(FileKind::Recording);
+                            recorder = Some(GifRecorder::start(path, frame_duration, record_skip, record_max));
+                            "Recording"
+                        }
+                    };
+                    notify(&overlay, &mut renderer, Ok(message.to_string()));
+                }
+                Ok(Event::Key(key)) => {
+                    // Letting go of a key held in the menu
+                    if key.kind == KeyEventKind::Release {
+                        menu_repeat.handle(key, frame_start);
+                    }
+                    key_tracker.handle(key, frame_start, &mut joypad_events);
+                }
+                Ok(Event::Resize(cols, rows)) => {
+                    renderer.resize(cols, rows);
+                    size = (cols, rows);
+                }
+                Ok(_) => (),
                 Err(TryRecvError::Empty) => break,
+                // The input thread gave up, and logged why
                 Err(TryRecvError::Disconnected) => break 'running,
             }
         }
 
-        // Set the 'Up' events
-        // This happens if the key was pressed in the previous frame, but not in this one
-        for keycode in previous_pressed_keys.iter() {
-            if !pressed_keys.contains(keycode) {
-                if let Some(joypad_input) = char_to_joypad_input(Some(*keycode)) {
-                    joypad_events.push(JoypadEvent::Up(joypad_input));
+        // Release keys that have not been seen in a while, if the terminal
+        // does not report releases itself
+        key_tracker.release_stale(frame_start, &mut joypad_events);
+        // Held arrow keys keep moving through the menu
+        if let (Some(open), Some(key)) = (&mut menu, menu_repeat.poll(frame_start)) {
+            open.handle(key.code);
+            renderer.show_menu(menu);
+        }
+
+        if let Some(message) = gamepads.as_mut().and_then(|gamepads| gamepads.poll(&mut joypad_events)) {
+            notify(&overlay, &mut renderer, Ok(message));
+        }
+        if let Some(message) = input_socket.as_mut().and_then(|socket| socket.poll(&mut joypad_events)) {
+            notify(&overlay, &mut renderer, Ok(message));
+        }
+
+        if let Some(save_files) = &mut save_files {
+            save_files.flush_if_due(&mut gameboy, frame_start);
+        }
+
+        // `None` runs uncapped. Bound keys only play
+        let held = |key| key_tracker.is_held(key) && !key_tracker.is_bound(key);
+        let speed = if fast_forward || held(FAST_FORWARD_KEY) {
+            None
+        } else if held(SLOW_MOTION_KEY) {
+            Some(speed * SLOW_MOTION_SPEED)
+        } else {
+            Some(speed)
+        };
+
+        if let (Some(console), true) = (&mut console, break_requested) {
+            console.prompt(&mut gameboy, "Break");
+            break_requested = false;
+        }
+
+        // The menu pauses the game under it
+        let paused = paused || menu.is_some();
+        let step = paused && steps > 0;
+        // Movies cannot go back in time, and a bound key only plays
+        let rewinding = !paused && movie.is_none() && key_tracker.is_held(REWIND_KEY)
+            && !key_tracker.is_bound(REWIND_KEY);
+        if rewinding {
+            if rewind.rewind(&mut gameboy) && link.as_ref().is_some_and(Link::is_connected) {
+                gameboy.set_link_connected(true);
+            }
+            // Input from before the rewind does not apply anymore
+            joypad_events.clear();
+        } else if !paused || step {
+            // Part of a frame at a time, shown as far as it got, until it
+            // is done
+            let frame_done = match (cycle_step, &mut console) {
+                (None, _) => true,
+                (Some(cycles), Some(console)) => console.run_cycles(&mut gameboy, cycles),
+                (Some(cycles), None) => gameboy.step_cycles(cycles),
+            };
+
+            if frame_done {
+                let frames = if paused { 1 } else { frames_per_cycle };
+                handle_frame(&mut gameboy, &mut joypad_events, frames, &mut key_tracker, console.as_mut(),
+                             link.as_mut(), &mut movie, &cheats);
+                overlay.lock().unwrap().record_frames(Instant::now(), frames);
+
+                overlay.lock().unwrap().double_speed = gameboy.double_speed();
+                rewind.tick(&gameboy);
+
+                if let Some(Err(e)) = serial.as_mut().map(|serial| serial.frame(&mut gameboy)) {
+                    log::error!("Serial output disabled, failed to write it: {}", e);
+                    serial = None;
                 }
             }
         }
-        // Set the 'Down' events
-        // This happens if the key was pressed in this frame, but not in the previous one
-        for keycode in pressed_keys.iter() {
-            if !previous_pressed_keys.contains(keycode) {
-                if let Some(joypad_input) = char_to_joypad_input(Some(*keycode)) {
-                    joypad_events.push(JoypadEvent::Down(joypad_input));
+        // Also while paused, as far as a partial frame got or after loading
+        // a state
+        screen.show(&gameboy, &mut current);
+        let frame_buffer = &current;
+        // Only frames the game drew, not the same one again while paused
+        if let (Some(blend), true) = (&mut blend, rewinding || !paused || step) {
+            blend.push(frame_buffer);
+        }
+
+        // The console drew over the game, and the terminal lost its contents
+        if let Some(console @ Console { shown: true, .. }) = &mut console {
+            renderer.invalidate();
+            console.shown = false;
+        }
+
+        if screenshot {
+            capture::save_screenshot(frame_buffer, game_dir.save_path_for(FileKind::Screenshot));
+            notify(&overlay, &mut renderer, Ok("Screenshot".to_string()));
+            screenshot = false;
+        }
+
+        if frame_start >= next_title {
+            let fps = overlay.lock().unwrap().emulated_fps();
+            renderer.set_title(match (paused, fast_forward, cycle_step) {
+                (true, ..) => format!("{} - Paused", name),
+                (false, _, Some(_)) => format!("{} - LY {}", name, gameboy.read_memory(0xFF44)),
+                (false, true, None) => format!("{} - {:.0} FPS - FF", name, fps),
+                (false, false, None) => format!("{} - {:.0} FPS", name, fps),
+            });
+            next_title = frame_start + TITLE_INTERVAL;
+        }
+
+        // The sprite viewer lists the sprites on top of the image
+        {
+            let panel = vram_viewer.as_ref().map_or_else(Vec::new, |viewer| viewer.sprite_list(gameboy.ppu()));
+            let mut overlay = overlay.lock().unwrap();
+            if panel != overlay.panel {
+                if panel.len() < overlay.panel.len() {
+                    // Redraw the cells the lines that went away covered
+                    renderer.invalidate();
                 }
+                overlay.panel = panel;
+                held_back = true;
+            }
+        }
+
+        // New log lines are shown even while paused
+        if log_pane.is_some() && log_pane::kept() != log_kept {
+            log_kept = log_pane::kept();
+            renderer.redraw();
+        }
+
+        if paused && !step {
+            // Show the frame the game was paused on, if it was held back
+            if held_back {
+                renderer.submit(match &mut vram_viewer {
+                    Some(viewer) => viewer.draw(gameboy.ppu()),
+                    None => {
+                        let toned = tone.apply(blend.as_ref().map_or(frame_buffer, Blend::frame), &mut shown);
+                        outline.as_mut().map_or(toned, |outline| outline.apply(toned))
+                    }
+                });
+                held_back = false;
+            }
+
+            // Nothing to do until the next key, so wait for one instead of
+            // going round every frame, but not so long that gamepads, held
+            // menu keys and the like go unchecked
+            match rx.recv_timeout(menu_repeat.timeout(Instant::now(), IDLE_TIMEOUT)) {
+                Ok(event) => woken = Some(event),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            // Frames are then due from when the game goes on, not from when
+            // it stopped
+            pacer.reset();
+            continue;
+        }
+        if step {
+            // The first frame of `--start-paused` was not asked for
+            if !waiting {
+                log::info!("Advanced one frame");
+            }
+            steps -= 1;
+        }
+
+        if let Some(r) = &mut recorder {
+            if !r.push(frame_buffer) {
+                encoders.extend(recorder.take().map(GifRecorder::stop));
             }
         }
+        if let Some(raw_out) = &mut raw_out {
+            raw_out.push(frame_buffer);
+        }
+        // Only what the game played, like the sound
+        if let (Some(mp4), false) = (&mut mp4, rewinding) {
+            mp4.push_frame(frame_buffer);
+        }
 
-        previous_pressed_keys = pressed_keys.clone();
-        pressed_keys.clear();
+        // When running faster than normal, the terminal cannot keep up with
+        // every frame, so only render at the normal rate, or at most at
+        // `--max-render-fps`. Rendering happens on its own thread, so a slow
+        // terminal does not slow down emulation.
+        let faster = !matches!(speed, Some(speed) if speed <= 1.0);
+        let render_interval = if faster { frame_duration.max(min_render_interval) } else { min_render_interval };
+        let now = Instant::now();
+        if now >= next_render {
+            renderer.submit(match &mut vram_viewer {
+                Some(viewer) => viewer.draw(gameboy.ppu()),
+                None => {
+                    let toned = tone.apply(blend.as_ref().map_or(frame_buffer, Blend::frame), &mut shown);
+                    outline.as_mut().map_or(toned, |outline| outline.apply(toned))
+                }
+            });
+            // Keep to the rate on average, without catching up after a stall
+            next_render = (next_render + render_interval).max(now);
+            held_back = false;
+        } else {
+            held_back = true;
+        }
 
-        handle_frame(&mut gameboy, &mut joypad_events, &mut frame, &mut stdout);
+        // Sound does not play backwards
+        let samples: Vec<f32> = gameboy.drain_audio_samples().collect();
+        if !rewinding {
+            if let Some(audio) = &mut audio {
+                audio.push(&samples, speed);
+            }
+            if let Some(mp4) = &mut mp4 {
+                mp4.push_audio(&samples);
+            }
+        }
 
         let elapsed = frame_start.elapsed();
+        let sleep_start = Instant::now();
 
-        //log::debug!("Frame time: {:?}", elapsed);
+        // Wait for the audio to catch up, if it drives timing
+        let synced = match (&audio, speed) {
+            (Some(audio), Some(_)) if audio_sync => audio.wait(),
+            _ => false,
+        };
 
-        // Sleep for the rest of the frame
-        //
-        // TODO: Evaluate if we need VSYNC to avoid tearing on higher Hz displays
-        if elapsed < frame_duration {
-            sleeper.sleep(frame_duration - elapsed);
+        // Otherwise, sleep until the next frame is due. Tearing is taken care
+        // of by synchronized output, where the terminal supports it.
+        match (speed, synced) {
+            (Some(speed), false) => pacer.wait(cycle_duration(frames_per_cycle, speed)),
+            _ => pacer.reset(),
         }
+
+        if let Some(profile) = &mut profile {
+            let slept = sleep_start.elapsed();
+            log::debug!(target: profile::TARGET, "Frame: CPU {:?}, sleep {:?}", elapsed, slept);
+            profile.cpu.push(elapsed);
+            profile.sleep.push(slept);
+        }
+    }
+
+    // Back on the system clock, before the cartridge clock is saved
+    if let Some(movie) = movie {
+        movie.finish();
+        gameboy.set_emulated_clock(false);
     }
+
+    if let Some(save_files) = &mut save_files {
+        if let Err(e) = save_files.flush(&mut gameboy) {
+            log::error!("Failed to write save data: {}", e);
+        }
+    }
+
+    if auto_resume {
+        if let Err(e) = quicksave(&gameboy, &auto_state_path) {
+            log::error!("{}", e);
+        }
+    }
+
+    // Wait for any recordings to be written out
+    encoders.extend(recorder.take().map(GifRecorder::stop));
+    for encoder in encoders.drain(..) {
+        let _ = encoder.join();
+    }
+    if let Some(raw_out) = raw_out {
+        raw_out.finish();
+    }
+    if let Err(e) = mp4.map_or(Ok(()), Mp4Recorder::finish) {
+        log::error!("Failed to save recording: {}", e);
+    }
+
+    // Leave the terminal to whatever comes next, e.g. the ROM picker
+    input_lock.stop();
+
+    // Finish drawing before restoring the terminal
+    let render_times = renderer.stop();
+    tty::restore();
+
+    if let Some(serial) = serial {
+        serial.finish();
+    }
+
+    if let Some(mut profile) = profile {
+        profile.render = render_times;
+        log::info!(target: profile::TARGET, "Frame timings:\n{}", profile);
+    }
+
     Ok(())
 }
 
-fn get_cartridge(path: &PathBuf, boot_rom: bool) -> Cartridge {
-    let data = std::fs::read(path).expect("Failed to open ROM file");
-    let cartridge = Cartridge::from_bytes(data, boot_rom);
-    cartridge
+fn get_cartridge(path: &Path, entry: Option<&str>, patch: Option<&Path>, boot_rom: Option<BootRom>, force: bool,
+                 hardware: Hardware) -> std::result::Result<Cartridge, String> {
+    let mut data = rom::read(path, entry)?;
+    if let Some(patch) = patch {
+        data = patch::read(patch, data)?;
+        log::info!("Patched with {}", patch.display());
+    }
+    if !force {
+        rom::validate(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    let cartridge = hardware.apply(Cartridge::from_bytes(data, false))
+        .map_err(|e| format!("Can't run {} with --mode {}: {}", path.display(), hardware, e))?;
+    // Overdumps and homebrew can have more or less ROM than the header says
+    let cartridge = match cartridge.check() {
+        Err(e @ gbc::Error::CartridgeError(CartridgeError::Size(..))) if force => {
+            log::warn!("{}: {}, running it as that much", path.display(), e);
+            cartridge.with_header_size()
+        }
+        _ => cartridge,
+    };
+    cartridge.check().map_err(|e| format!("Can't load {}: {}", path.display(), e))?;
+
+    Ok(match boot_rom {
+        Some(boot_rom) => {
+            if boot_rom.is_cgb() != cartridge.cgb() {
+                log::warn!("Running a {} boot ROM with a {} game", if boot_rom.is_cgb() { "CGB" } else { "DMG" },
+                           if cartridge.cgb() { "CGB" } else { "DMG" });
+            }
+            cartridge.with_boot_rom(boot_rom)
+        }
+        None => cartridge,
+    })
 }
 
-fn main(){
-    env_logger::init();
+/// Create the `Gameboy`, exiting if the cartridge cannot be loaded.
+fn start(cartridge: Cartridge) -> Gameboy {
+    Gameboy::init(cartridge, false).unwrap_or_else(|e| fail(format!("Failed to start the game: {}", e)))
+}
+
+/// Run a ROM in the terminal, exiting on terminal errors.
+fn run(rom_file: PathBuf, cartridge: Cartridge, options: Options) {
+    match cli(rom_file, cartridge, options) {
+        Ok(()) => (),
+        // The output went away, which is as good as quitting
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+        Err(e) => {
+            tty::restore();
+            fail(format!("Terminal error: {}", e));
+        }
+    }
+}
+
+/// One of the two games of `--dual`
+struct Side {
+    name: String,
+    gameboy: Gameboy,
+    save_files: Option<SaveFiles>,
+    screen: Screen,
+    joypad_events: Vec<JoypadEvent>,
+    /// The last frame, after applying the palette
+    current: FrameBuffer,
+    blend: Option<Blend>,
+    shown: FrameBuffer,
+    outline: Option<Outline>,
+    overlay: Arc<Mutex<Overlay>>,
+    renderer: RenderThread,
+}
+
+/// Run two games side by side, linked to each other, with the keyboard going
+/// to one of them at a time.
+fn dual_cli(first: (PathBuf, Cartridge), second: (PathBuf, Cartridge), options: Options) -> Result<()> {
+    let Options {
+        bindings, gamepad, turbo, turbo_rate, dpad_conflict, quit_key, color_mode, grayscale, tone, palette, color_correct,
+        keep_last_frame, blend, outline, speed, pacing, spin_threshold, sync_output, save_dir, bell, ..
+    } = options;
+
+    let mut game_dirs = Vec::new();
+    let mut games = Vec::new();
+    for (rom_file, cartridge) in [first, second] {
+        let game_dir = GameDir::new(&save_dir, &cartridge);
+        game_dir.migrate(&rom_file);
+        let mut save_files = SaveFiles::new(&game_dir, &cartridge);
+        let screen = Screen::new(&cartridge, palette, color_correct, keep_last_frame);
+        let name = rom::title(Some(&cartridge), &rom_file);
+
+        let mut gameboy = start(cartridge);
+        if let Some(save_files) = &mut save_files {
+            save_files.load(&mut gameboy);
+        }
+        // Two copies of a game would overwrite each other's saves
+        if save_files.is_some() && game_dirs.iter().any(|dir: &GameDir| dir.save_path_for(FileKind::Ram) == game_dir.save_path_for(FileKind::Ram)) {
+            log::warn!("Both sides run {}, so only the saves of the left one are kept", name);
+            save_files = None;
+        }
+
+        game_dirs.push(game_dir);
+        games.push((name, gameboy, save_files, screen));
+    }
+
+    tty::install_handlers();
+    tty::enter()?;
+    let sync_output = sync_output.unwrap_or_else(tty::synchronized_output_supported);
+
+    // Centered, a couple of columns apart
+    let (cols, rows) = terminal::size()?;
+    let (width, height) = (render::HALF_BLOCK_SIZE.0 as u16, render::HALF_BLOCK_SIZE.1 as u16);
+    let left = cols.saturating_sub(2 * width + DUAL_GAP) / 2;
+    let top = rows.saturating_sub(height) / 2;
+    if cols < 2 * width + DUAL_GAP || rows < height {
+        log::warn!("The terminal is too small for both games, make it at least {}x{}", 2 * width + DUAL_GAP, height);
+    }
+
+    let mut sides: Vec<Side> = games.into_iter().enumerate().map(|(i, (name, gameboy, save_files, screen))| {
+        let at = (left + i as u16 * (width + DUAL_GAP), top);
+        let output = Backend::HalfBlock.renderer(RenderOptions { scale_width: 1, correct_aspect: None, at: Some(at),
+                                                                 color_mode, grayscale, crop: Crop::FULL });
+        let mut overlay = Overlay::new();
+        overlay.origin = at;
+        overlay.bell = bell;
+        let overlay = Arc::new(Mutex::new(overlay));
+        let mut renderer = RenderThread::spawn(io::stdout(), output, overlay.clone(), sync_output, false, false);
+        renderer.resize(cols, rows);
+
+        Side {
+            name,
+            gameboy,
+            save_files,
+            screen,
+            joypad_events: Vec::new(),
+            current: FrameBuffer::new(),
+            blend: blend.then(Blend::default),
+            shown: FrameBuffer::new(),
+            outline: outline.map(Outline::new),
+            overlay,
+            renderer,
+        }
+    }).collect();
+    let title = format!("{} + {}", sides[0].name, sides[1].name);
+    sides[0].renderer.set_title(title);
+
+    let mut link = {
+        let (left, right) = sides.split_at_mut(1);
+        LocalLink::connect(&mut left[0].gameboy, &mut right[0].gameboy)
+    };
+
+    let input_lock = input::InputLock::default();
+    let rx = input::spawn_event_channel(input_lock.clone());
+    let mut key_tracker = KeyTracker::new(bindings);
+    key_tracker.set_turbo(turbo, turbo_rate);
+    key_tracker.set_dpad_conflict(dpad_conflict);
+    let mut gamepads = Gamepads::new(gamepad).map_err(|e| log::warn!("Gamepad disabled: {}", e)).ok();
+
+    // The side the keyboard and gamepads go to
+    let mut focus = 0;
+    let side = &mut sides[focus];
+    notify(&side.overlay, &mut side.renderer, Ok("Keys go here, Tab switches".to_string()));
+
+    let mut pacer = Pacer::new(pacing, spin_threshold);
+    let mut quit_deadline: Option<Instant> = None;
+
+    'running: loop {
+        let frame_start = Instant::now();
+        if tty::quit_requested() || sides.iter().any(|side| !side.renderer.is_running()) {
+            break;
+        }
+
+        let mut events = Vec::new();
+        loop {
+            match rx.try_recv() {
+                Ok(Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL, kind: KeyEventKind::Press, ..
+                })) => {
+                    break 'running;
+                }
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press && quit_key.matches(&key) => {
+                    if quit_deadline.is_some_and(|deadline| frame_start < deadline) {
+                        break 'running;
+                    }
+                    quit_deadline = Some(frame_start + QUIT_CONFIRM_TIMEOUT);
+                    let side = &mut sides[focus];
+                    notify(&side.overlay, &mut side.renderer, Ok(format!("Press {} again to quit", quit_key)));
+                }
+                Ok(Event::Key(KeyEvent { code: DUAL_FOCUS_KEY, kind: KeyEventKind::Press, .. })) => {
+                    // Let go of everything on the side losing the keys
+                    key_tracker.release_all(&mut events);
+                    sides[focus].joypad_events.append(&mut events);
+
+                    focus = 1 - focus;
+                    let side = &mut sides[focus];
+                    notify(&side.overlay, &mut side.renderer, Ok("Keys go here".to_string()));
+                }
+                Ok(Event::Key(key)) => key_tracker.handle(key, frame_start, &mut events),
+                Ok(Event::Resize(cols, rows)) => {
+                    for side in &mut sides {
+                        side.renderer.resize(cols, rows);
+                    }
+                }
+                Ok(_) => (),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break 'running,
+            }
+        }
+        key_tracker.release_stale(frame_start, &mut events);
+        key_tracker.turbo_frame(&mut events);
+        if let Some(message) = gamepads.as_mut().and_then(|gamepads| gamepads.poll(&mut events)) {
+            let side = &mut sides[focus];
+            notify(&side.overlay, &mut side.renderer, Ok(message));
+        }
+        sides[focus].joypad_events.append(&mut events);
+
+        for side in &mut sides {
+            let later = input::hold_over_taps(&mut side.joypad_events);
+            side.gameboy.frame(Some(&side.joypad_events));
+            side.joypad_events = later;
+            side.overlay.lock().unwrap().record_frames(Instant::now(), 1);
+
+            side.screen.show(&side.gameboy, &mut side.current);
+            side.overlay.lock().unwrap().double_speed = side.gameboy.double_speed();
+            if let Some(blend) = &mut side.blend {
+                blend.push(&side.current);
+            }
+            let toned = tone.apply(side.blend.as_ref().map_or(&side.current, Blend::frame), &mut side.shown);
+            side.renderer.submit(side.outline.as_mut().map_or(toned, |outline| outline.apply(toned)));
+            // Neither game makes a sound, with two of them at once
+            side.gameboy.drain_audio_samples();
+
+            if let Some(save_files) = &mut side.save_files {
+                save_files.flush_if_due(&mut side.gameboy, frame_start);
+            }
+        }
+        // Once per frame is plenty, as games wait for each byte
+        let (left, right) = sides.split_at_mut(1);
+        link.update(&mut left[0].gameboy, &mut right[0].gameboy);
+
+        pacer.wait(cycle_duration(1, speed));
+    }
+
+    for side in &mut sides {
+        if let Some(save_files) = &mut side.save_files {
+            if let Err(e) = save_files.flush(&mut side.gameboy) {
+                log::error!("Failed to write save data for {}: {}", side.name, e);
+            }
+        }
+    }
+
+    input_lock.stop();
+    for side in sides {
+        side.renderer.stop();
+    }
+    tty::restore();
+
+    Ok(())
+}
+
+/// Run two games side by side, exiting on terminal errors.
+fn run_dual(first: (PathBuf, Cartridge), second: (PathBuf, Cartridge), options: Options) {
+    match dual_cli(first, second, options) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+        Err(e) => {
+            tty::restore();
+            fail(format!("Terminal error: {}", e));
+        }
+    }
+}
+
+/// The ROM to run without a terminal, for `--dump-frames`, `--until-frame` or
+/// `--script`, with its save folder and how its frames look, the same for
+/// all of them.
+fn headless_game(rom: Option<(PathBuf, Cartridge)>, save_dir: &Path, palette: Option<Palette>, color_correct: bool,
+                 keep_last_frame: bool) -> (GameDir, Cartridge, Screen) {
+    // `--rom-dir` is ruled out, so there is a ROM
+    let (rom_file, cartridge) = rom.unwrap();
+    let game_dir = GameDir::new(save_dir, &cartridge);
+    game_dir.migrate(&rom_file);
+    let screen = Screen::new(&cartridge, palette, color_correct, keep_last_frame);
+
+    (game_dir, cartridge, screen)
+}
+
+/// Start a game to run without a terminal, playing back `playback` if given.
+fn headless_gameboy(game_dir: &GameDir, cartridge: Cartridge, playback: Option<Movie>) -> (Gameboy, Option<Player>) {
+    let save_files = SaveFiles::new(game_dir, &cartridge);
+    let mut gameboy = start(cartridge);
+
+    // Movies start from a state of their own. Otherwise, battery saves are
+    // read, but never written back.
+    let player = match playback {
+        Some(movie) => Some(Player::start(movie, &mut gameboy)
+            .unwrap_or_else(|e| fail(format!("Failed to play back movie: {}", e)))),
+        None => {
+            if let Some(mut save_files) = save_files {
+                save_files.load(&mut gameboy);
+            }
+            None
+        }
+    };
+
+    (gameboy, player)
+}
+
+/// Run a ROM with `--dump-frames`, reporting how it went on stderr.
+fn dump(game_dir: &GameDir, cartridge: Cartridge, dir: &Path, frames: u32, screen: Screen, playback: Option<Movie>,
+        serial_out: Option<SerialTarget>) {
+    // Straight to stdout, with nothing else drawn there
+    let mut serial = serial_out.map(|target| SerialOut::open(&target, true)
+        .unwrap_or_else(|e| fail(format!("Failed to open {:?}: {}", target, e))));
+    let (mut gameboy, player) = headless_gameboy(game_dir, cartridge, playback);
+
+    dump::run(&mut gameboy, dir, frames, screen, player, serial.as_mut())
+        .unwrap_or_else(|e| fail(format!("Failed to dump frames: {}", e)));
+
+    if let Some(serial) = serial {
+        serial.finish();
+    }
+
+    eprintln!("Wrote {} frames to {}", frames, dir.display());
+}
+
+/// Run a ROM with `--until-frame`, saving the last frame to `frame_out` and
+/// checking it against the `golden` image, if any, within a tolerance.
+#[allow(clippy::too_many_arguments)]
+fn run_until_frame(game_dir: &GameDir, cartridge: Cartridge, frames: u32, frame_out: &Path,
+                   golden: Option<(PathBuf, u8)>, screen: Screen, playback: Option<Movie>,
+                   serial_out: Option<SerialTarget>) {
+    let mut serial = serial_out.map(|target| SerialOut::open(&target, true)
+        .unwrap_or_else(|e| fail(format!("Failed to open {:?}: {}", target, e))));
+    let (mut gameboy, player) = headless_gameboy(game_dir, cartridge, playback);
+
+    let frame = dump::run_until(&mut gameboy, frames, screen, player, serial.as_mut())
+        .unwrap_or_else(|e| fail(format!("Failed to run to frame {}: {}", frames, e)));
+    if let Some(serial) = serial {
+        serial.finish();
+    }
 
+    capture::save_png(&frame, frame_out)
+        .unwrap_or_else(|e| fail(format!("Failed to save {}: {}", frame_out.display(), e)));
+    eprintln!("Saved frame {} to {}", frames, frame_out.display());
+
+    if let Some((golden, tolerance)) = golden {
+        let difference = dump::compare(&frame, &golden).unwrap_or_else(|e| fail(e));
+        println!("Max pixel delta: {} ({} pixels differ)", difference.max_delta, difference.pixels);
+        if difference.max_delta > tolerance {
+            fail(format!("Frame {} differs from {} by more than {}", frames, golden.display(), tolerance));
+        }
+    }
+}
+
+/// Run a ROM with `--script`, taking commands from stdin.
+fn run_script(game_dir: &GameDir, cartridge: Cartridge, screen: Screen) {
+    // As with `--dump-frames`, battery saves are read, but never written back
+    let save_files = SaveFiles::new(game_dir, &cartridge);
+    let mut gameboy = start(cartridge);
+    if let Some(mut save_files) = save_files {
+        save_files.load(&mut gameboy);
+    }
+
+    script::run(&mut gameboy, io::stdin().lock(), io::stdout().lock(), screen)
+        .unwrap_or_else(|e| fail(format!("Script failed: {}", e)));
+}
+
+/// Report an error from a subcommand and exit.
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn main(){
     let cli2 = Args::from_args();
 
+    // `--profile` is pointless without its summary, so show it unless
+    // RUST_LOG says otherwise
+    let mut logger = env_logger::Builder::new();
+    if let Args::Run { profile: true, .. } = cli2 {
+        logger.filter_module(profile::TARGET, log::LevelFilter::Info);
+    }
+    // Out of the way of the game, so there is room for more of it
+    if let Args::Run { log_file: Some(path), .. } = &cli2 {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .unwrap_or_else(|e| fail(format!("Failed to open {}: {}", path.display(), e)));
+        let file = Mutex::new(file);
+        // This version of env_logger prints to stderr whatever the target, so
+        // each line goes to the file as it is formatted instead
+        logger.filter_module("gbcemu", log::LevelFilter::Info)
+            .filter_module("gbc", log::LevelFilter::Info)
+            .format(move |buf, record| {
+                writeln!(file.lock().unwrap(), "[{} {:<5} {}] {}", buf.timestamp(), record.level(), record.target(), record.args())
+            });
+    }
+    log_pane::init(logger.parse_env(env_logger::Env::default()).build());
+
     match cli2 {
-        Args::Run { rom_file,} => {
-            cli(rom_file).unwrap();
+        Args::Run {
+            rom_file, entry, patch, rom_dir, boot_rom, force, mode, bindings: overrides, turbo, turbo_rate, dpad_conflict, quit_key, menu_repeat_delay, menu_repeat_rate, renderer, fullres, ascii, scale_width, correct_aspect, cell_aspect, at, crop, color_mode, grayscale, brightness, contrast, gamma,
+            palette, palette_rgb,
+            palette_file, color_correct, keep_last_frame, blend, accessibility_outline, outline_color, speed, frameskip, cycle_step, cpu_clock, max_render_fps, sync_output, no_sync_output, mute, audio_sync, pacing, spin_threshold, record_skip, record_max, debug, profile, log_file: _, skip_frames, load_state, auto_resume,
+            save_dir, start_paused, bell, cheats, link, input_socket, dual, serial_out, raw_out, raw_info, record_mp4, record, playback,
+            dump_frames, dump_count, script, until_frame, frame_out, compare, tolerance,
+        } => {
+            if raw_info {
+                // As fast as the game runs, less the frames skipped
+                let config = Config::load();
+                let frames_per_second = Gameboy::FRAME_FREQUENCY * speed.or(config.speed).unwrap_or(1.0)
+                    / f64::from(frameskip.or(config.frameskip).unwrap_or(0) + 1);
+                println!("{}", raw::info(frames_per_second));
+                return;
+            }
+
+            // Load the ROM before taking over the terminal, so errors are readable
+            let rom = rom_file.map(|path| {
+                let cartridge = get_cartridge(&path, entry.as_deref(), patch.as_deref(), boot_rom.clone(), force, mode).unwrap_or_else(|e| fail(e));
+                (path, cartridge)
+            });
+            let dual = dual.map(|path| {
+                let cartridge = get_cartridge(&path, None, None, boot_rom.clone(), force, mode).unwrap_or_else(|e| fail(e));
+                (path, cartridge)
+            });
+            let picker = rom_dir.map(|dir| {
+                let picker = Picker::open(&dir).unwrap_or_else(|e| fail(format!("Failed to read {}: {}", dir.display(), e)));
+                if picker.roms().is_empty() {
+                    fail(format!("No .gb or .gbc files in {}", dir.display()));
+                }
+                picker
+            });
+            let playback = playback.map(|path| Movie::load(&path).unwrap_or_else(|e| fail(e)));
+            let config = Config::load();
+            // In the working directory, if there is nowhere better
+            let save_dir = save_dir.or(config.save_dir).or_else(persist::default_dir)
+                .unwrap_or_else(|| PathBuf::from("saves"));
+            let palette = palette.or(palette_rgb).or(palette_file).or(config.palette);
+            let color_correct = color_correct || config.color_correct.unwrap_or(false);
+            let keep_last_frame = keep_last_frame || config.keep_last_frame.unwrap_or(false);
+
+            if let Some(dir) = dump_frames {
+                let (game_dir, cartridge, screen) = headless_game(rom, &save_dir, palette, color_correct, keep_last_frame);
+                let frames = dump_count.or(playback.as_ref().map(|movie| movie.length)).unwrap_or(dump::DEFAULT_FRAMES);
+                dump(&game_dir, cartridge, &dir, frames, screen, playback, serial_out);
+                return;
+            }
+
+            if let Some(frames) = until_frame {
+                let (game_dir, cartridge, screen) = headless_game(rom, &save_dir, palette, color_correct, keep_last_frame);
+                let frame_out = frame_out.unwrap_or_else(|| PathBuf::from(format!("frame-{}.png", frames)));
+                let golden = compare.map(|path| (path, tolerance.unwrap_or(0)));
+                run_until_frame(&game_dir, cartridge, frames, &frame_out, golden, screen, playback, serial_out);
+                return;
+            }
+
+            if script {
+                let (game_dir, cartridge, screen) = headless_game(rom, &save_dir, palette, color_correct, keep_last_frame);
+                run_script(&game_dir, cartridge, screen);
+                return;
+            }
+
+            // Escape sequences would end up in whatever stdout goes to
+            if !io::stdout().is_terminal() {
+                fail("Output is not a terminal. Use --dump-frames <dir> to save the frames as PNGs instead.".to_string());
+            }
+
+            let mut bindings = KeyBindings::default();
+            for (keycode, input) in config.bindings.into_iter().chain(overrides) {
+                bindings.bind(keycode, input);
+            }
+            let mut gamepad = GamepadBindings::default();
+            for (button, input) in config.gamepad {
+                gamepad.bind(button, input);
+            }
+
+            let options = Options {
+                bindings,
+                gamepad,
+                turbo: if turbo.is_empty() { config.turbo } else { turbo },
+                turbo_rate: turbo_rate.or(config.turbo_rate).unwrap_or(input::DEFAULT_TURBO_RATE),
+                dpad_conflict: dpad_conflict.or(config.dpad_conflict).unwrap_or_default(),
+                quit_key: quit_key.or(config.quit_key).unwrap_or(input::DEFAULT_QUIT_KEY),
+                menu_repeat: KeyRepeat::new(menu_repeat_delay.or(config.menu_repeat_delay).unwrap_or(input::DEFAULT_REPEAT_DELAY),
+                                            menu_repeat_rate.or(config.menu_repeat_rate).unwrap_or(input::DEFAULT_REPEAT_RATE)),
+                // Flags win over the config file, whichever renderer they pick
+                backend: pick_backend(renderer, ascii, fullres)
+                    .or(pick_backend(config.renderer, config.ascii == Some(true), config.fullres == Some(true)))
+                    .unwrap_or(Backend::HalfBlock),
+                scale_width: scale_width.or(config.scale_width).unwrap_or(1),
+                // `--scale-width` wins over `correct_aspect` in the config file
+                correct_aspect: (correct_aspect || scale_width.is_none() && config.correct_aspect.unwrap_or(false))
+                    .then_some(cell_aspect.or(config.cell_aspect).unwrap_or(DEFAULT_CELL_ASPECT)),
+                at,
+                crop: crop.unwrap_or(Crop::FULL),
+                color_mode: color_mode.or(config.color_mode).unwrap_or(ColorMode::Auto).resolve(),
+                grayscale: grayscale || config.grayscale.unwrap_or(false),
+                tone: Tone::new(brightness.or(config.brightness).unwrap_or(1.0), contrast.or(config.contrast).unwrap_or(1.0),
+                                gamma.or(config.gamma).unwrap_or(1.0)),
+                palette,
+                color_correct,
+                keep_last_frame,
+                blend: blend || config.blend.unwrap_or(false),
+                outline: (accessibility_outline || config.accessibility_outline.unwrap_or(false))
+                    .then_some(outline_color.or(config.outline_color).unwrap_or(outline::DEFAULT_COLOR)),
+                speed: speed.or(config.speed).unwrap_or(1.0),
+                frameskip: frameskip.or(config.frameskip).unwrap_or(0),
+                cycle_step,
+                cpu_clock,
+                max_render_fps: max_render_fps.or(config.max_render_fps),
+                sync_output: match (sync_output, no_sync_output) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => config.sync_output,
+                },
+                mute: mute || config.audio == Some(false),
+                audio_sync,
+                pacing: pacing.or(config.pacing).unwrap_or_default(),
+                spin_threshold: spin_threshold.or(config.spin_threshold).unwrap_or(pace::DEFAULT_SPIN_THRESHOLD),
+                record_skip,
+                record_max: Duration::from_secs(record_max),
+                debug,
+                profile,
+                skip_frames: skip_frames.unwrap_or(0),
+                load_state,
+                auto_resume,
+                save_dir,
+                start_paused,
+                bell: bell || config.bell.unwrap_or(false),
+                cheats,
+                link,
+                input_socket,
+                serial_out,
+                raw_out,
+                record_mp4,
+                record,
+                playback,
+            };
+
+            match (rom, picker) {
+                (Some(first), _) if dual.is_some() => run_dual(first, dual.unwrap(), options),
+                (Some((rom_file, cartridge)), _) => run(rom_file, cartridge, options),
+                (None, Some(mut picker)) => {
+                    picker.repeat = options.menu_repeat.clone();
+                    // Until the picker is quit
+                    loop {
+                        let rom_file = match picker.pick() {
+                            Ok(Some(rom)) => rom.path.clone(),
+                            Ok(None) => break,
+                            Err(e) => fail(format!("Terminal error: {}", e)),
+                        };
+
+                        match get_cartridge(&rom_file, None, None, boot_rom.clone(), force, mode) {
+                            Ok(cartridge) => {
+                                picker.status = None;
+                                run(rom_file, cartridge, options.clone());
+                            }
+                            Err(e) => picker.status = Some(e),
+                        }
+
+                        if tty::quit_requested() {
+                            break;
+                        }
+                    }
+                }
+                (None, None) => unreachable!("structopt requires a ROM file or a directory"),
+            }
+        }
+        Args::Bench { rom_file, entry, frames, seed, force } => {
+            let cartridge = get_cartridge(&rom_file, entry.as_deref(), None, None, force, Hardware::Auto).unwrap_or_else(|e| fail(e));
+            let mut gameboy = start(cartridge);
+            println!("{}", bench::run(&mut gameboy, frames, seed));
+        }
+        Args::Disasm { rom_file, entry, addr, count, bank } => {
+            let lines = rom::read(&rom_file, entry.as_deref())
+                .and_then(|rom| disasm::disassemble_rom(&rom, addr, bank, count))
+                .unwrap_or_else(|e| fail(e));
+
+            for line in &lines {
+                println!("{}", disasm::format_line(line));
+            }
         }
+        Args::Info { rom_file, entry, json, list_mbc } => {
+            if list_mbc {
+                println!("{}", info::mbc_list());
+                return;
+            }
+
+            // Required unless listing
+            let rom_file = rom_file.unwrap();
+            let info = rom::read(&rom_file, entry.as_deref())
+                .and_then(info::CartridgeInfo::from_rom)
+                .unwrap_or_else(|e| fail(e));
+
+            if json {
+                println!("{}", info.to_json());
+            } else {
+                println!("{}", info);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cycles_keep_games_at_their_normal_speed() {
+        // Frames emulated per real second
+        let rate = |frames, speed| frames as f64 / cycle_duration(frames, speed).as_secs_f64();
+
+        for frames in 1..=config::MAX_FRAMESKIP + 1 {
+            assert!((rate(frames, 1.0) - Gameboy::FRAME_FREQUENCY).abs() < 0.01);
+            assert!((rate(frames, 2.0) - 2.0 * Gameboy::FRAME_FREQUENCY).abs() < 0.01);
+            assert!((rate(frames, 0.25) - 0.25 * Gameboy::FRAME_FREQUENCY).abs() < 0.01);
+        }
+
+        // 1000 frames of 70224 cycles at 4 MiHz, without rounding adding up
+        let expected = 1000.0 * 70224.0 / 4194304.0;
+        assert!((cycle_duration(1000, 1.0).as_secs_f64() - expected).abs() < 1e-6);
     }
 }
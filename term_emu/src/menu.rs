@@ -0,0 +1,168 @@
+//! In-game menu, opened with Escape
+//!
+//! Pauses the game and offers the actions that otherwise each need a hotkey,
+//! in a box drawn over the middle of the screen. Items can be picked with the
+//! keyboard or the mouse.
+use std::io;
+
+use crossterm::event::{KeyCode, MouseButton, MouseEvent, MouseEventKind};
+use crossterm::{cursor, queue, style, style::Color};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Resume,
+    SaveState,
+    LoadState,
+    Reset,
+    Quit,
+}
+
+const ITEMS: [(Action, &str); 5] = [
+    (Action::Resume, "Resume"),
+    (Action::SaveState, "Save state"),
+    (Action::LoadState, "Load state"),
+    (Action::Reset, "Reset"),
+    (Action::Quit, "Quit"),
+];
+
+/// Columns inside the box
+const WIDTH: usize = 18;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Menu {
+    selected: usize,
+
+    /// Top left corner of the box, instead of the middle of the terminal
+    corner: Option<(u16, u16)>,
+}
+
+impl Menu {
+    /// A menu drawn with its top left corner at `corner`, if given.
+    pub fn at(corner: Option<(u16, u16)>) -> Self {
+        Self { corner, ..Self::default() }
+    }
+
+    /// Move the selection, or return the action picked. Escape resumes.
+    pub fn handle(&mut self, key: KeyCode) -> Option<Action> {
+        match key {
+            KeyCode::Up => self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len(),
+            KeyCode::Down => self.selected = (self.selected + 1) % ITEMS.len(),
+            KeyCode::Enter => return Some(ITEMS[self.selected].0),
+            KeyCode::Esc => return Some(Action::Resume),
+            _ => (),
+        }
+
+        None
+    }
+
+    /// Select the item under the mouse, or return the one clicked. `size` is
+    /// the terminal size the menu was drawn at.
+    pub fn mouse(&mut self, event: MouseEvent, size: (u16, u16)) -> Option<Action> {
+        match event.kind {
+            MouseEventKind::ScrollUp => return self.handle(KeyCode::Up),
+            MouseEventKind::ScrollDown => return self.handle(KeyCode::Down),
+            MouseEventKind::Moved | MouseEventKind::Down(MouseButton::Left) => (),
+            _ => return None,
+        }
+
+        // Items are on the rows between the top and bottom of the box
+        let (left, top) = self.origin(size);
+        let item = (event.row as usize).checked_sub(top as usize + 1).filter(|&item| item < ITEMS.len());
+        let inside = (left..left + WIDTH as u16 + 2).contains(&event.column);
+        match item {
+            Some(item) if inside => {
+                self.selected = item;
+                matches!(event.kind, MouseEventKind::Down(_)).then(|| ITEMS[item].0)
+            }
+            _ => None,
+        }
+    }
+
+    /// The box, a row at a time, with the selected item marked.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("┌{:─^width$}┐", " Paused ", width = WIDTH)];
+        for (i, (_, label)) in ITEMS.iter().enumerate() {
+            let marker = if i == self.selected { '>' } else { ' ' };
+            lines.push(format!("│ {} {:<width$}│", marker, label, width = WIDTH - 3));
+        }
+        lines.push(format!("└{}┘", "─".repeat(WIDTH)));
+
+        lines
+    }
+
+    /// Draw the box in the middle of a terminal of `cols` by `rows` cells, or
+    /// at its corner.
+    pub fn draw(&self, out: &mut impl io::Write, (cols, rows): (u16, u16)) -> io::Result<()> {
+        let lines = self.lines();
+        let (left, top) = self.origin((cols, rows));
+
+        queue!(out, style::SetBackgroundColor(Color::Black), style::SetForegroundColor(Color::White))?;
+        for (row, line) in lines.iter().enumerate() {
+            queue!(out, cursor::MoveTo(left, top + row as u16), style::Print(line))?;
+        }
+        queue!(out, style::ResetColor)
+    }
+
+    /// Top left corner of the box, centered in a terminal of `cols` by `rows`
+    /// cells unless it has a corner of its own
+    fn origin(&self, (cols, rows): (u16, u16)) -> (u16, u16) {
+        self.corner.unwrap_or_else(|| {
+            let left = cols.saturating_sub(WIDTH as u16 + 2) / 2;
+            let top = rows.saturating_sub(ITEMS.len() as u16 + 2) / 2;
+            (left, top)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crossterm::event::KeyModifiers;
+
+    use super::*;
+
+    #[test]
+    fn selection_wraps_around() {
+        let mut menu = Menu::default();
+        assert_eq!(menu.handle(KeyCode::Up), None);
+        assert_eq!(menu.handle(KeyCode::Enter), Some(Action::Quit));
+        assert_eq!(menu.handle(KeyCode::Down), None);
+        assert_eq!(menu.handle(KeyCode::Down), None);
+        assert_eq!(menu.handle(KeyCode::Enter), Some(Action::SaveState));
+        assert_eq!(menu.handle(KeyCode::Esc), Some(Action::Resume));
+    }
+
+    #[test]
+    fn items_can_be_clicked() {
+        let mouse = |kind, column, row| MouseEvent { kind, column, row, modifiers: KeyModifiers::NONE };
+        let mut menu = Menu::default();
+        // The box is drawn at (10, 5) in a 40x17 terminal, with Resume on row 6
+        let size = (40, 17);
+
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Moved, 12, 8), size), None);
+        assert_eq!(menu.lines()[3], "│ > Load state     │");
+
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Down(MouseButton::Left), 25, 10), size), Some(Action::Quit));
+        // Outside the box and on its borders
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Down(MouseButton::Left), 9, 6), size), None);
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Down(MouseButton::Left), 12, 5), size), None);
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Down(MouseButton::Left), 12, 11), size), None);
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Down(MouseButton::Right), 12, 6), size), None);
+
+        // Or at a corner of its own
+        let mut menu = Menu::at(Some((2, 3)));
+        assert_eq!(menu.mouse(mouse(MouseEventKind::Down(MouseButton::Left), 3, 4), size), Some(Action::Resume));
+    }
+
+    #[test]
+    fn boxes_are_square() {
+        let mut menu = Menu::default();
+        menu.handle(KeyCode::Down);
+
+        let lines = menu.lines();
+        assert_eq!(lines.len(), ITEMS.len() + 2);
+        assert!(lines.iter().all(|line| line.chars().count() == WIDTH + 2));
+        assert_eq!(lines[0], "┌───── Paused ─────┐");
+        assert_eq!(lines[2], "│ > Save state     │");
+        assert_eq!(lines[3], "│   Load state     │");
+    }
+}
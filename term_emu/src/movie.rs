@@ -0,0 +1,390 @@
+//! Input movies
+//!
+//! A movie is a save state, taken when recording started, followed by the
+//! joypad events fed to every frame after it. Emulation is deterministic (the
+//! cartridge clock runs on emulated time during movies), so loading the state
+//! and feeding the same events back in reproduces the run exactly.
+//!
+//! To catch runs that play out differently anyway, e.g. because of an
+//! emulation bug, a hash of the full emulator state is recorded every
+//! `HASH_INTERVAL` frames. Playback reports the first frame where the hashes
+//! do not match.
+//!
+//! The file starts with `GBM1` and the length of the state as a little endian
+//! `u32`, followed by the state itself. After that come records, each made of
+//! a frame number (`u32`, counting from 0 at the state) and a count (`u8`):
+//!
+//! * For frames that had any events, the number of events, followed by one
+//!   byte per event: the input in the low bits and `0x80` set for presses.
+//! * For hashes, `0xFF` followed by the hash of the state at the start of the
+//!   frame, as a `u64`.
+//! * The last record has a count of 0, with the length of the movie as the
+//!   frame number. Movies cut short, e.g. by a crash, end after the last
+//!   events instead.
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use gbc::Gameboy;
+use gbc::joypad::{JoypadEvent, JoypadInput};
+
+const MAGIC: &[u8] = b"GBM1";
+
+/// Inputs by their number in the file
+const INPUTS: [JoypadInput; 8] = [
+    JoypadInput::Up,
+    JoypadInput::Down,
+    JoypadInput::Left,
+    JoypadInput::Right,
+    JoypadInput::A,
+    JoypadInput::B,
+    JoypadInput::Start,
+    JoypadInput::Select,
+];
+
+const PRESS: u8 = 0x80;
+
+/// Count that marks a hash record
+const HASH: u8 = 0xFF;
+
+/// Frames between state hashes, about once a second
+const HASH_INTERVAL: u32 = 60;
+
+fn encode_event(event: JoypadEvent) -> u8 {
+    let (input, press) = match event {
+        JoypadEvent::Down(input) => (input, PRESS),
+        JoypadEvent::Up(input) => (input, 0),
+    };
+    INPUTS.iter().position(|i| *i == input).unwrap() as u8 | press
+}
+
+fn decode_event(byte: u8) -> Option<JoypadEvent> {
+    let input = *INPUTS.get((byte & !PRESS) as usize)?;
+    Some(if byte & PRESS != 0 { JoypadEvent::Down(input) } else { JoypadEvent::Up(input) })
+}
+
+/// The events in a movie, by frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub state: Vec<u8>,
+    pub frames: Vec<(u32, Vec<JoypadEvent>)>,
+    pub hashes: Vec<(u32, u64)>,
+    pub length: u32,
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        Self::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let invalid = || "Not a movie file, or it is truncated".to_string();
+
+        let rest = data.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        let (len, rest) = split_u32(rest).ok_or_else(invalid)?;
+        if rest.len() < len as usize {
+            return Err(invalid());
+        }
+        let (state, mut rest) = rest.split_at(len as usize);
+
+        let mut frames = Vec::new();
+        let mut hashes = Vec::new();
+        loop {
+            if rest.is_empty() {
+                let length = frames.last().map_or(0, |(frame, _)| frame + 1);
+                return Ok(Self { state: state.to_vec(), frames, hashes, length });
+            }
+
+            let (frame, tail) = split_u32(rest).ok_or_else(invalid)?;
+            let (&count, tail) = tail.split_first().ok_or_else(invalid)?;
+            if count == 0 {
+                return Ok(Self { state: state.to_vec(), frames, hashes, length: frame });
+            }
+            if count == HASH {
+                if tail.len() < 8 {
+                    return Err(invalid());
+                }
+                let (hash, tail) = tail.split_at(8);
+                hashes.push((frame, u64::from_le_bytes(hash.try_into().unwrap())));
+                rest = tail;
+                continue;
+            }
+            if tail.len() < count as usize {
+                return Err(invalid());
+            }
+            let (events, tail) = tail.split_at(count as usize);
+            let events = events.iter()
+                .map(|&byte| decode_event(byte).ok_or_else(|| format!("Invalid event at frame {}", frame)))
+                .collect::<Result<_, _>>()?;
+            frames.push((frame, events));
+            rest = tail;
+        }
+    }
+}
+
+fn split_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (value, rest) = data.split_at(4);
+    Some((u32::from_le_bytes(value.try_into().unwrap()), rest))
+}
+
+/// Writes the events of every frame to a movie file.
+pub struct Recorder {
+    out: BufWriter<File>,
+    frame: u32,
+    hash_interval: u32,
+}
+
+impl Recorder {
+    /// Start recording from the current state of the `Gameboy`.
+    pub fn start(path: &Path, gameboy: &mut Gameboy) -> gbc::Result<Self> {
+        gameboy.set_emulated_clock(true);
+        let state = gameboy.save_state()?;
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(state.len() as u32).to_le_bytes())?;
+        out.write_all(&state)?;
+        Ok(Self { out, frame: 0, hash_interval: HASH_INTERVAL })
+    }
+
+    /// Record the events for the next frame, which is about to run.
+    pub fn frame(&mut self, gameboy: &Gameboy, events: &[JoypadEvent]) -> io::Result<()> {
+        if self.frame.is_multiple_of(self.hash_interval) {
+            self.out.write_all(&self.frame.to_le_bytes())?;
+            self.out.write_all(&[HASH])?;
+            self.out.write_all(&gameboy.state_hash().to_le_bytes())?;
+        }
+
+        if !events.is_empty() {
+            // Events for a frame come from a single cycle, so there are only
+            // ever a handful of them
+            let events = &events[..events.len().min(HASH as usize - 1)];
+            self.out.write_all(&self.frame.to_le_bytes())?;
+            self.out.write_all(&[events.len() as u8])?;
+            for &event in events {
+                self.out.write_all(&[encode_event(event)])?;
+            }
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Write out the end of the movie.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.write_all(&self.frame.to_le_bytes())?;
+        self.out.write_all(&[0])?;
+        self.out.flush()
+    }
+}
+
+/// A movie being recorded or played back
+pub enum Session {
+    Recording(Recorder),
+    Playing(Player),
+}
+
+impl Session {
+    /// Record the events for the next frame, or replace them with the
+    /// recorded ones.
+    ///
+    /// Returns `false` once the session is over, either because the movie
+    /// ended or because it could not be written.
+    pub fn frame(&mut self, gameboy: &Gameboy, events: &mut Vec<JoypadEvent>) -> bool {
+        match self {
+            Self::Recording(recorder) => match recorder.frame(gameboy, events) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!("Failed to write movie, recording stopped: {}", e);
+                    false
+                }
+            },
+            Self::Playing(player) => player.frame(gameboy, events),
+        }
+    }
+
+    /// Finish writing out the movie, if recording.
+    pub fn finish(self) {
+        if let Self::Recording(recorder) = self {
+            if let Err(e) = recorder.finish() {
+                log::error!("Failed to write movie: {}", e);
+            }
+        }
+    }
+}
+
+/// Feeds the events from a movie back in, frame by frame.
+pub struct Player {
+    frames: std::vec::IntoIter<(u32, Vec<JoypadEvent>)>,
+    next: Option<(u32, Vec<JoypadEvent>)>,
+    hashes: std::vec::IntoIter<(u32, u64)>,
+    next_hash: Option<(u32, u64)>,
+    frame: u32,
+    length: u32,
+
+    /// Number of hashes that matched so far, and the frame of the last one
+    matched: usize,
+    last_match: u32,
+
+    /// First frame where the state did not match the recording
+    diverged: Option<u32>,
+}
+
+impl Player {
+    /// Start playing a movie, restoring the `Gameboy` to the state it starts
+    /// from.
+    pub fn start(movie: Movie, gameboy: &mut Gameboy) -> gbc::Result<Self> {
+        gameboy.load_state(&movie.state)?;
+        gameboy.set_emulated_clock(true);
+
+        let mut frames = movie.frames.into_iter();
+        let mut hashes = movie.hashes.into_iter();
+        Ok(Self {
+            next: frames.next(),
+            frames,
+            next_hash: hashes.next(),
+            hashes,
+            frame: 0,
+            length: movie.length,
+            matched: 0,
+            last_match: 0,
+            diverged: None,
+        })
+    }
+
+    /// Replace `events` with the recorded ones for the next frame, which is
+    /// about to run, after checking the state against the recording.
+    ///
+    /// Returns `false` once the movie is over, leaving `events` alone.
+    pub fn frame(&mut self, gameboy: &Gameboy, events: &mut Vec<JoypadEvent>) -> bool {
+        if self.frame >= self.length {
+            if self.diverged.is_none() {
+                log::info!("Movie matched the recording at all {} checks", self.matched);
+            }
+            return false;
+        }
+
+        if let Some((frame, hash)) = self.next_hash {
+            if frame == self.frame {
+                if self.diverged.is_none() {
+                    if gameboy.state_hash() == hash {
+                        self.matched += 1;
+                        self.last_match = frame;
+                    } else {
+                        // The state could have diverged at any frame since the last check
+                        log::error!("Movie diverged from the recording at frame {} (frame {} still matched)",
+                                    frame, self.last_match);
+                        self.diverged = Some(frame);
+                    }
+                }
+                self.next_hash = self.hashes.next();
+            }
+        }
+
+        events.clear();
+        if let Some((frame, recorded)) = &self.next {
+            if *frame == self.frame {
+                events.extend_from_slice(recorded);
+                self.next = self.frames.next();
+            }
+        }
+        self.frame += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    #[test]
+    fn record_and_play_back() {
+        let path = std::env::temp_dir().join(format!("gbcemu-movie-{}.gbm", std::process::id()));
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap();
+        gameboy.frame(None);
+
+        let recorded = [
+            vec![],
+            vec![JoypadEvent::Down(JoypadInput::A), JoypadEvent::Down(JoypadInput::Left)],
+            vec![],
+            vec![JoypadEvent::Up(JoypadInput::Select)],
+            vec![],
+        ];
+        let mut recorder = Recorder::start(&path, &mut gameboy).unwrap();
+        for events in &recorded {
+            recorder.frame(&gameboy, events).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let movie = Movie::parse(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(movie.state, gameboy.save_state().unwrap());
+        assert_eq!(movie.frames, [(1, recorded[1].clone()), (3, recorded[3].clone())]);
+        assert_eq!(movie.length, 5);
+        assert_eq!(movie.hashes, [(0, gameboy.state_hash())]);
+
+        // Live input is replaced until the movie ends
+        gameboy.frame(None);
+        let mut player = Player::start(Movie::load(&path).unwrap(), &mut gameboy).unwrap();
+        assert_eq!(gameboy.save_state().unwrap(), movie.state);
+        let mut events = vec![JoypadEvent::Down(JoypadInput::B)];
+        for expected in &recorded {
+            assert!(player.frame(&gameboy, &mut events));
+            assert_eq!(&events, expected);
+        }
+        assert!(!player.frame(&gameboy, &mut events));
+        assert_eq!(player.diverged, None);
+
+        // Without an end, the movie stops after the last events
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(Movie::parse(&data[..data.len() - 5]).unwrap().length, 4);
+        assert!(Movie::parse(&data[..data.len() - 6]).is_err());
+        assert!(Movie::parse(b"GBM1\x10\x00\x00\x00").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn divergence_is_reported() {
+        let path = std::env::temp_dir().join(format!("gbcemu-diverge-{}.gbm", std::process::id()));
+        // JR -2 at the entry point, so the game idles forever
+        let mut rom = vec![0u8; 32 * 1024];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(rom, false), false).unwrap();
+
+        let mut recorder = Recorder::start(&path, &mut gameboy).unwrap();
+        recorder.hash_interval = 4;
+        // Up to the third hash
+        let length = 4 * 2 + 1;
+        for _ in 0..length {
+            recorder.frame(&gameboy, &[]).unwrap();
+            gameboy.frame(None);
+        }
+        recorder.finish().unwrap();
+
+        // The same run matches
+        let mut player = Player::start(Movie::load(&path).unwrap(), &mut gameboy).unwrap();
+        let mut events = Vec::new();
+        while player.frame(&gameboy, &mut events) {
+            gameboy.frame(None);
+        }
+        assert_eq!(player.diverged, None);
+
+        // Change memory behind the movie's back halfway through
+        let mut player = Player::start(Movie::load(&path).unwrap(), &mut gameboy).unwrap();
+        for frame in 0..length {
+            if frame == 6 {
+                gameboy.write_memory(0xC000, 0x42);
+            }
+            assert!(player.frame(&gameboy, &mut events));
+            gameboy.frame(None);
+        }
+        assert_eq!(player.diverged, Some(8));
+        assert_eq!(player.last_match, 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! MP4 recordings, for `run --record-mp4`
+//!
+//! Frames are piped to an ffmpeg child process as they are drawn, at the
+//! game's own resolution, and encoded to H.264. ffmpeg takes one stream from
+//! its stdin, so the sound goes to a temporary file meanwhile, and is muxed in
+//! with a second, quick run of ffmpeg once the recording stops.
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use gbc::apu::SAMPLE_RATE;
+use gbc::ppu::{FrameBuffer, LCD_HEIGHT, LCD_WIDTH};
+
+use crate::capture::frame_pixels;
+use crate::persist;
+
+const FFMPEG: &str = "ffmpeg";
+
+pub struct Mp4Recorder {
+    path: PathBuf,
+    ffmpeg: Child,
+    tx: Sender<Vec<u8>>,
+    /// Feeds frames to ffmpeg, so that a slow encoder does not stall the game
+    writer: JoinHandle<io::Result<()>>,
+
+    /// The video on its own, until the sound is muxed in
+    video_path: PathBuf,
+    /// Interleaved stereo samples, as 32-bit floats
+    audio: BufWriter<File>,
+    audio_path: PathBuf,
+}
+
+impl Mp4Recorder {
+    /// Start ffmpeg, to record to `path` at `fps` frames per second.
+    pub fn start(path: PathBuf, fps: f64) -> Result<Self, String> {
+        Self::start_with(FFMPEG, path, fps)
+    }
+
+    fn start_with(program: &str, path: PathBuf, fps: f64) -> Result<Self, String> {
+        persist::create_parent(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+        // Hidden next to the recording until it is done
+        let temp = |suffix: &str| {
+            let mut name = OsString::from(".");
+            name.push(path.file_name().unwrap_or_default());
+            name.push(suffix);
+            path.with_file_name(name)
+        };
+        let video_path = temp(".video.mp4");
+        let audio_path = temp(".audio.f32");
+
+        let mut ffmpeg = Command::new(program)
+            .args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pixel_format", "rgb24"])
+            .arg("-video_size").arg(format!("{}x{}", LCD_WIDTH, LCD_HEIGHT))
+            .arg("-framerate").arg(format!("{:.6}", fps))
+            .args(["-i", "pipe:0"])
+            .args(["-c:v", "libx264", "-preset", "veryfast", "-crf", "16", "-pix_fmt", "yuv420p"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            // Kept off the terminal, and reported if ffmpeg fails
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => "--record-mp4 needs ffmpeg, which was not found. Install it, or make \
+                                            sure it is on the PATH.".to_string(),
+                _ => format!("Failed to start ffmpeg: {}", e),
+            })?;
+
+        let audio = match File::create(&audio_path) {
+            Ok(file) => BufWriter::new(file),
+            Err(e) => {
+                let _ = ffmpeg.kill();
+                return Err(format!("Failed to create {}: {}", audio_path.display(), e));
+            }
+        };
+
+        let mut stdin = ffmpeg.stdin.take().unwrap();
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let writer = thread::spawn(move || {
+            for pixels in rx {
+                stdin.write_all(&pixels)?;
+            }
+            // Closing stdin ends the video
+            Ok(())
+        });
+
+        log::info!("Recording to {}", path.display());
+
+        Ok(Self {
+            path,
+            ffmpeg,
+            tx,
+            writer,
+            video_path,
+            audio,
+            audio_path,
+        })
+    }
+
+    /// Record a frame.
+    pub fn push_frame(&mut self, frame_buffer: &FrameBuffer) {
+        // The writer only goes away if ffmpeg did, which `finish` reports
+        let _ = self.tx.send(frame_pixels(frame_buffer));
+    }
+
+    /// Record the sound that goes with the frames, as drained from the
+    /// `Gameboy`.
+    pub fn push_audio(&mut self, samples: &[f32]) {
+        let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        if let Err(e) = self.audio.write_all(&bytes) {
+            log::warn!("Failed to save the sound for {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Stop recording, and wait for the file to be written.
+    pub fn finish(self) -> Result<(), String> {
+        let Self { path, ffmpeg, tx, writer, video_path, audio, audio_path } = self;
+
+        drop(tx);
+        let written = writer.join().unwrap();
+        let encoded = wait(ffmpeg);
+        let audio = audio.into_inner().map_err(|e| e.into_error().to_string());
+
+        let result = match (written, encoded, audio) {
+            (_, Err(e), _) => Err(e),
+            (Err(e), ..) => Err(format!("Failed to pipe frames to ffmpeg: {}", e)),
+            (.., Err(e)) => Err(format!("Failed to save the sound: {}", e)),
+            (Ok(()), Ok(()), Ok(_)) => mux(&video_path, &audio_path, &path).or_else(|e| {
+                // Better than nothing
+                log::warn!("Saving the recording without sound, as {}", e);
+                fs::rename(&video_path, &path).map_err(|e| format!("Failed to move the video in place: {}", e))
+            }),
+        };
+
+        let _ = fs::remove_file(&video_path);
+        let _ = fs::remove_file(&audio_path);
+
+        result.map(|()| log::info!("Saved recording to {}", path.display()))
+    }
+}
+
+/// Wait for ffmpeg to exit, with what it printed on failure.
+fn wait(ffmpeg: Child) -> Result<(), String> {
+    let output = ffmpeg.wait_with_output().map_err(|e| format!("Failed to wait for ffmpeg: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("ffmpeg failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Put the video and sound together in `path`, copying the video as it is.
+fn mux(video_path: &Path, audio_path: &Path, path: &Path) -> Result<(), String> {
+    let ffmpeg = Command::new(FFMPEG)
+        .args(["-loglevel", "error", "-nostdin", "-y"])
+        .arg("-i").arg(video_path)
+        .args(["-f", "f32le", "-ac", "2"])
+        .arg("-ar").arg(SAMPLE_RATE.to_string())
+        .arg("-i").arg(audio_path)
+        .args(["-c:v", "copy", "-c:a", "aac", "-b:a", "192k", "-shortest"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+
+    wait(ffmpeg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_ffmpeg_is_reported() {
+        let path = std::env::temp_dir().join(format!("gbcemu-mp4-{}.mp4", std::process::id()));
+
+        let error = Mp4Recorder::start_with("gbcemu-no-such-ffmpeg", path.clone(), 59.7).err().unwrap();
+        assert!(error.contains("needs ffmpeg"), "{}", error);
+        assert!(!path.with_file_name(format!(".gbcemu-mp4-{}.mp4.audio.f32", std::process::id())).exists());
+    }
+}
@@ -0,0 +1,113 @@
+//! Outlines around high-contrast edges, for `run --accessibility-outline`
+//!
+//! Small sprites on a busy screen are hard to pick out with low vision, all
+//! the more so at the size a terminal draws them. With outlines, every pixel
+//! that is a good deal brighter than one next to it is drawn in a bright
+//! color instead. Games mostly draw dark objects on light backgrounds, so the
+//! outline lands around objects, on the background, rather than on top of
+//! their details.
+//!
+//! Like the tone, this only changes what the terminal shows, and it is done
+//! last, so the outlines stay bright whatever the tone.
+use gbc::ppu::{FrameBuffer, GameboyRgb, LCD_HEIGHT, LCD_WIDTH};
+
+/// The color outlines are drawn in when none is given
+pub const DEFAULT_COLOR: GameboyRgb = GameboyRgb { red: 0xFF, green: 0xFF, blue: 0x00 };
+
+/// How much brighter than a neighbour a pixel has to be to be outlined, out
+/// of 255. Two shades apart on the DMG, but not one.
+const THRESHOLD: u8 = 100;
+
+pub struct Outline {
+    color: GameboyRgb,
+
+    /// Luminance of each pixel of the frame being outlined
+    luminance: Vec<u8>,
+
+    /// The last frame, outlined
+    outlined: FrameBuffer,
+}
+
+fn luminance(pixel: GameboyRgb) -> u8 {
+    ((299 * u32::from(pixel.red) + 587 * u32::from(pixel.green) + 114 * u32::from(pixel.blue)) / 1000) as u8
+}
+
+impl Outline {
+    pub fn new(color: GameboyRgb) -> Self {
+        Self {
+            color,
+            luminance: vec![0; LCD_WIDTH * LCD_HEIGHT],
+            outlined: FrameBuffer::new(),
+        }
+    }
+
+    /// Outline the edges in `frame_buffer`.
+    pub fn apply(&mut self, frame_buffer: &FrameBuffer) -> &FrameBuffer {
+        for (out, &pixel) in self.luminance.iter_mut().zip(frame_buffer.data.iter()) {
+            *out = luminance(pixel);
+        }
+
+        let luminance = &self.luminance;
+        let at = |x: usize, y: usize| luminance[y * LCD_WIDTH + x];
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                let here = at(x, y);
+                let neighbours = [
+                    (x > 0).then(|| at(x - 1, y)),
+                    (x + 1 < LCD_WIDTH).then(|| at(x + 1, y)),
+                    (y > 0).then(|| at(x, y - 1)),
+                    (y + 1 < LCD_HEIGHT).then(|| at(x, y + 1)),
+                ];
+                let edge = neighbours.iter().flatten().any(|&next| here.saturating_sub(next) >= THRESHOLD);
+                self.outlined.write(x, y, if edge { self.color } else { frame_buffer.read(x, y) });
+            }
+        }
+
+        &self.outlined
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WHITE: GameboyRgb = GameboyRgb { red: 0xFF, green: 0xFF, blue: 0xFF };
+    const LIGHT: GameboyRgb = GameboyRgb { red: 0xAA, green: 0xAA, blue: 0xAA };
+    const BLACK: GameboyRgb = GameboyRgb { red: 0, green: 0, blue: 0 };
+    const PINK: GameboyRgb = GameboyRgb { red: 0xFF, green: 0x80, blue: 0xFF };
+
+    #[test]
+    fn dark_objects_are_outlined_around() {
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.data.fill(WHITE);
+        for y in 20..28 {
+            for x in 40..48 {
+                frame_buffer.write(x, y, BLACK);
+            }
+        }
+
+        let mut outline = Outline::new(PINK);
+        let outlined = outline.apply(&frame_buffer);
+        // Around the square, but not on it or away from it
+        for (x, y) in [(39, 20), (48, 27), (44, 19), (44, 28)] {
+            assert_eq!(outlined.read(x, y), PINK, "({}, {})", x, y);
+        }
+        for (x, y) in [(40, 20), (44, 24), (39, 19), (38, 24), (0, 0)] {
+            assert_eq!(outlined.read(x, y), frame_buffer.read(x, y), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn small_steps_are_not_edges() {
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.data.fill(WHITE);
+        for x in 0..LCD_WIDTH / 2 {
+            for y in 0..LCD_HEIGHT {
+                frame_buffer.write(x, y, LIGHT);
+            }
+        }
+
+        let mut outline = Outline::new(PINK);
+        assert!(outline.apply(&frame_buffer).data == frame_buffer.data);
+    }
+}
@@ -0,0 +1,286 @@
+//! Performance overlay
+//!
+//! Shows emulated FPS, terminal (rendered) FPS, render times and whether a CGB
+//! game runs the CPU at double speed on the top row of the terminal, or of the
+//! image with `--at`. Rates are averaged over a rolling window so that they
+//! stay readable.
+//!
+//! Short messages, e.g. the save state slot just picked, are shown there for a
+//! couple of seconds, whether or not the stats are, and can ring the terminal
+//! bell with `--bell`. So is `FF` while fast forward is latched on. Longer text, like the sprite viewer's list, goes
+//! in a panel of lines under it.
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crossterm::{cursor, queue, style, style::Color};
+
+/// Number of samples to average over
+const WINDOW: usize = 60;
+
+/// Overlay text is padded to this many columns, so that shorter text fully
+/// covers longer text drawn before it
+const WIDTH: usize = 52;
+
+/// Rings the terminal bell
+const BELL: &[u8] = b"\x07";
+
+/// How long messages stay up
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+pub struct Overlay {
+    pub visible: bool,
+
+    /// Whether fast forward is latched on, to show that it is
+    pub fast_forward: bool,
+
+    /// Whether the game is in CGB double-speed mode
+    pub double_speed: bool,
+
+    /// Whether to ring the terminal bell along with notifications
+    pub bell: bool,
+
+    /// Where the text starts, in cells
+    pub origin: (u16, u16),
+
+    /// Lines to show under the top row, left to whoever set them to clear
+    pub panel: Vec<String>,
+
+    /// When each batch of frames was emulated, and how many frames it held
+    emulated: VecDeque<(Instant, u32)>,
+
+    /// When each frame was rendered, and how long it took
+    rendered: VecDeque<(Instant, Duration)>,
+
+    /// The message to show, and until when
+    message: Option<(String, Instant)>,
+
+    /// Whether a message was replaced by another one, which may not cover it
+    replaced: bool,
+
+    /// Whether the bell is to be rung with the next draw
+    ring: bool,
+}
+
+/// Push to a rolling window, dropping the oldest sample once it is full.
+fn push<T>(window: &mut VecDeque<T>, sample: T) {
+    if window.len() == WINDOW {
+        window.pop_front();
+    }
+    window.push_back(sample);
+}
+
+/// Events per second over a window of timestamps, given the number of events
+/// at each of them.
+fn rate<T>(window: &VecDeque<(Instant, T)>, count: impl Fn(&T) -> u32) -> f64 {
+    let (first, last) = match (window.front(), window.back()) {
+        (Some(first), Some(last)) if window.len() > 1 => (first.0, last.0),
+        _ => return 0.0,
+    };
+
+    // Events at the first timestamp happened before the window started
+    let events: u32 = window.iter().skip(1).map(|(_, n)| count(n)).sum();
+    let elapsed = last.duration_since(first).as_secs_f64();
+
+    if elapsed > 0.0 { events as f64 / elapsed } else { 0.0 }
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Record that `frames` frames have been emulated.
+    pub fn record_frames(&mut self, now: Instant, frames: u32) {
+        push(&mut self.emulated, (now, frames));
+    }
+
+    /// Record that a frame was rendered, taking `took` to do so.
+    pub fn record_render(&mut self, now: Instant, took: Duration) {
+        push(&mut self.rendered, (now, took));
+    }
+
+    /// Show `message` for a couple of seconds from `now`.
+    pub fn show_message(&mut self, message: String, now: Instant) {
+        self.replaced |= self.message.is_some();
+        self.message = Some((message, now + MESSAGE_DURATION));
+    }
+
+    /// Show `message` to confirm what a key did, ringing the bell if enabled.
+    pub fn notify(&mut self, message: String, now: Instant) {
+        self.show_message(message, now);
+        self.ring |= self.bell;
+    }
+
+    /// Forget the message if it is past its time, returning whether the cells
+    /// it covered need to be redrawn: when it went away, or when a shorter
+    /// one may have been drawn over it.
+    pub fn expire_message(&mut self, now: Instant) -> bool {
+        let expired = self.message.as_ref().is_some_and(|(_, until)| now >= *until);
+        if expired {
+            self.message = None;
+        }
+        expired | std::mem::take(&mut self.replaced)
+    }
+
+    pub fn emulated_fps(&self) -> f64 {
+        rate(&self.emulated, |frames| *frames)
+    }
+
+    pub fn rendered_fps(&self) -> f64 {
+        rate(&self.rendered, |_| 1)
+    }
+
+    /// Average time taken to render a frame
+    pub fn render_time(&self) -> Duration {
+        let total: Duration = self.rendered.iter().map(|(_, took)| *took).sum();
+        total / self.rendered.len().max(1) as u32
+    }
+
+    pub fn text(&self) -> String {
+        let text = format!(
+            " EMU {:5.1} fps | TERM {:5.1} fps | {:5.2} ms | CPU {}x",
+            self.emulated_fps(),
+            self.rendered_fps(),
+            self.render_time().as_secs_f64() * 1000.0,
+            if self.double_speed { 2 } else { 1 }
+        );
+        format!("{:<width$}", text, width = WIDTH)
+    }
+
+    /// Draw the overlay on top of whatever is on the top row.
+    pub fn draw(&mut self, out: &mut impl io::Write) -> io::Result<()> {
+        if std::mem::take(&mut self.ring) {
+            out.write_all(BELL)?;
+        }
+
+        let mut text = if self.visible { self.text() } else { String::new() };
+        if self.fast_forward {
+            text.push_str(" FF ");
+        }
+        if let Some((message, _)) = &self.message {
+            text.push_str(&format!(" {} ", message));
+        }
+        if text.is_empty() && self.panel.is_empty() {
+            return Ok(());
+        }
+
+        queue!(out, style::SetBackgroundColor(Color::Black), style::SetForegroundColor(Color::White))?;
+        if !text.is_empty() {
+            queue!(out, cursor::MoveTo(self.origin.0, self.origin.1), style::Print(text))?;
+        }
+        // Padded, so that every line covers the longest
+        let width = self.panel.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        for (row, line) in self.panel.iter().enumerate() {
+            let at = (self.origin.0, self.origin.1 + 1 + row as u16);
+            queue!(out, cursor::MoveTo(at.0, at.1), style::Print(format!(" {:<width$} ", line, width = width)))?;
+        }
+        queue!(out, style::ResetColor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rates_are_averaged_over_a_rolling_window() {
+        let mut overlay = Overlay::new();
+        let start = Instant::now();
+
+        // 2 frames every 100ms, then a long stall that falls out of the window
+        overlay.record_frames(start, 2);
+        overlay.record_frames(start + Duration::from_secs(10), 2);
+        for i in 1..=WINDOW as u32 {
+            overlay.record_frames(start + Duration::from_secs(10) + Duration::from_millis(100) * i, 2);
+        }
+        assert!((overlay.emulated_fps() - 20.0).abs() < 0.01);
+
+        overlay.record_render(start, Duration::from_millis(1));
+        overlay.record_render(start + Duration::from_millis(500), Duration::from_millis(3));
+        assert!((overlay.rendered_fps() - 2.0).abs() < 0.01);
+        assert_eq!(overlay.render_time(), Duration::from_millis(2));
+
+        assert_eq!(overlay.text().chars().count(), WIDTH);
+        assert!(overlay.text().contains("| CPU 1x"));
+        overlay.double_speed = true;
+        assert!(overlay.text().contains("| CPU 2x"));
+    }
+
+    #[test]
+    fn messages_are_shown_for_a_while() {
+        let mut overlay = Overlay::new();
+        let start = Instant::now();
+        let mut out = Vec::new();
+        overlay.draw(&mut out).unwrap();
+        assert!(out.is_empty());
+
+        overlay.show_message("Slot 3".to_string(), start);
+        assert!(!overlay.expire_message(start + Duration::from_secs(1)));
+        overlay.draw(&mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains(" Slot 3 "));
+
+        assert!(overlay.expire_message(start + MESSAGE_DURATION));
+        assert!(!overlay.expire_message(start + MESSAGE_DURATION));
+        out.clear();
+        overlay.draw(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn notifications_ring_the_bell_once() {
+        let mut overlay = Overlay::new();
+        let start = Instant::now();
+        let mut out = Vec::new();
+
+        overlay.notify("Saved state".to_string(), start);
+        overlay.draw(&mut out).unwrap();
+        assert!(!out.contains(&0x07));
+
+        overlay.bell = true;
+        overlay.notify("Loaded state".to_string(), start);
+        // What "Saved state" covered is redrawn
+        assert!(overlay.expire_message(start));
+        assert!(!overlay.expire_message(start));
+        out.clear();
+        overlay.draw(&mut out).unwrap();
+        assert!(out.starts_with(BELL) && String::from_utf8_lossy(&out).contains(" Loaded state "));
+
+        out.clear();
+        overlay.draw(&mut out).unwrap();
+        assert!(!out.contains(&0x07));
+    }
+
+    #[test]
+    fn fast_forward_is_shown_until_turned_off() {
+        let mut overlay = Overlay::new();
+        let mut out = Vec::new();
+
+        overlay.fast_forward = true;
+        overlay.draw(&mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains(" FF "));
+
+        overlay.fast_forward = false;
+        out.clear();
+        overlay.draw(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn panel_lines_are_padded_to_the_longest() {
+        let mut overlay = Overlay::new();
+        overlay.origin = (4, 2);
+        overlay.panel = vec!["0 tile 1A".to_string(), "12 tile 3".to_string(), "7".to_string()];
+        let mut out = Vec::new();
+        overlay.draw(&mut out).unwrap();
+
+        let text = String::from_utf8_lossy(&out);
+        assert!(!text.contains("\x1b[3;5H"));
+        assert!(text.contains("\x1b[4;5H 0 tile 1A \x1b[5;5H 12 tile 3 \x1b[6;5H 7         "));
+    }
+}
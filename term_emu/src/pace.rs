@@ -0,0 +1,166 @@
+//! Frame pacing
+//!
+//! Sleeping for whatever is left of each frame adds up the error of every
+//! sleep, so a game slowly drifts off its speed, and the sound with it. A
+//! `Pacer` keeps a timeline of when each frame is due instead, and sleeps
+//! until then, so a frame that ends late is made up for by the next one.
+//!
+//! Frames are whatever `Gameboy::frame` runs until the PPU has drawn one. The
+//! CPU counts its cycles at single speed, halving them in CGB double-speed
+//! mode, so the PPU and the sound keep their pace either way, and a frame is
+//! always a frame's time: nothing here needs to know the speed.
+//!
+//! How the wait is done is up to `--pacing`. The OS wakes a sleeping thread
+//! a little late, by up to a millisecond or so on Linux and more on other
+//! systems, so sleeping alone makes frames come out unevenly. Spinning until
+//! the frame is due is exact, but keeps a core busy the whole time. Hybrid
+//! pacing sleeps for most of the wait and spins through the end of it.
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use spin_sleep::SpinSleeper;
+
+/// Further behind than this, e.g. after a stall, the timeline starts over
+/// from now instead of racing through frames to catch up.
+pub const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// How much of the wait hybrid pacing spins through when not told
+pub const DEFAULT_SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// How to wait until a frame is due
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pacing {
+    /// Spin the whole time, for the least jitter
+    Spin,
+    /// Sleep the whole time, for the least CPU
+    Sleep,
+    /// Sleep, then spin through the last of the wait
+    #[default]
+    Hybrid,
+}
+
+impl FromStr for Pacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "spin" => Ok(Self::Spin),
+            "sleep" => Ok(Self::Sleep),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => Err(format!("Unknown pacing: {} (expected spin, sleep or hybrid)", s)),
+        }
+    }
+}
+
+/// Parse how much of the wait hybrid pacing spins through, in microseconds.
+pub fn parse_spin_threshold(s: &str) -> Result<Duration, String> {
+    let micros: u64 = s.parse().map_err(|_| format!("Invalid spin threshold: {}", s))?;
+    if micros > 10_000 {
+        return Err(format!("Spin threshold must be at most 10000µs, got {}", micros));
+    }
+    Ok(Duration::from_micros(micros))
+}
+
+pub struct Pacer {
+    /// When the next frame is due
+    next: Instant,
+    pacing: Pacing,
+    /// Sleeps, then spins through the threshold, handling the timer
+    /// resolution on Windows
+    sleeper: SpinSleeper,
+}
+
+impl Pacer {
+    /// A pacer waiting with `pacing`, spinning through the last
+    /// `spin_threshold` of each wait if hybrid.
+    pub fn new(pacing: Pacing, spin_threshold: Duration) -> Self {
+        let spin_ns = match pacing {
+            Pacing::Hybrid => spin_threshold.as_nanos() as u32,
+            Pacing::Spin | Pacing::Sleep => 0,
+        };
+        Self {
+            next: Instant::now(),
+            pacing,
+            sleeper: SpinSleeper::new(spin_ns),
+        }
+    }
+
+    /// Sleep until `duration` after the last frame was due.
+    pub fn wait(&mut self, duration: Duration) {
+        self.next += duration;
+
+        let now = Instant::now();
+        if self.next > now {
+            match self.pacing {
+                Pacing::Spin => while Instant::now() < self.next {
+                    std::hint::spin_loop();
+                },
+                Pacing::Sleep | Pacing::Hybrid => self.sleeper.sleep(self.next - now),
+            }
+        } else if now - self.next > MAX_LAG {
+            self.next = now;
+        }
+    }
+
+    /// Start the timeline over from now, after frames that were not paced,
+    /// such as fast-forwarded ones.
+    pub fn reset(&mut self) {
+        self.next = Instant::now();
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new(Pacing::default(), DEFAULT_SPIN_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_does_not_drift_over_many_frames() {
+        let frame = Duration::from_micros(500);
+        for pacing in [Pacing::Spin, Pacing::Sleep, Pacing::Hybrid] {
+            let start = Instant::now();
+            let mut pacer = Pacer::new(pacing, DEFAULT_SPIN_THRESHOLD);
+
+            for i in 0..1000u64 {
+                // Frames take a varying while, but never longer than they have
+                std::thread::sleep(Duration::from_micros(i % 5 * 50));
+                pacer.wait(frame);
+            }
+            let elapsed = start.elapsed();
+
+            // Sleeping for 500µs at a time would overshoot by a good deal more
+            let expected = frame * 1000;
+            assert!(elapsed >= expected, "{:?}: {:?}", pacing, elapsed);
+            assert!(elapsed < expected + Duration::from_millis(20), "{:?}: {:?}", pacing, elapsed);
+        }
+    }
+
+    #[test]
+    fn stalls_are_not_caught_up_on() {
+        let mut pacer = Pacer::default();
+        std::thread::sleep(MAX_LAG * 2);
+
+        // Right away, as the frame was long overdue
+        let start = Instant::now();
+        pacer.wait(Duration::from_millis(10));
+        // Then at the normal pace from there, rather than all at once
+        pacer.wait(Duration::from_millis(10));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn parse_pacing() {
+        assert_eq!("Spin".parse(), Ok(Pacing::Spin));
+        assert_eq!("hybrid".parse(), Ok(Pacing::Hybrid));
+        assert!("busy".parse::<Pacing>().is_err());
+        assert_eq!(parse_spin_threshold("500"), Ok(Duration::from_micros(500)));
+        assert_eq!(parse_spin_threshold("0"), Ok(Duration::ZERO));
+        assert!(parse_spin_threshold("20000").is_err());
+        assert!(parse_spin_threshold("1.5").is_err());
+    }
+}
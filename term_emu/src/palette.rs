@@ -0,0 +1,246 @@
+//! Palettes for monochrome games
+//!
+//! The emulator renders DMG titles using the four shades of `DMG_PALETTE`.
+//! Palettes swap those shades out for other colors once a frame is done, so
+//! the core is not involved.
+//!
+//! Besides the built-in palettes, custom ones can be given as four hex colors,
+//! either inline or as a JSON array in a file.
+//!
+//! Color games can have their colors corrected instead, to look more like
+//! they did on the CGB's LCD than the raw colors do on a modern display.
+use std::path::Path;
+use std::str::FromStr;
+
+use gbc::Gameboy;
+use gbc::cartridge::Cartridge;
+use gbc::ppu::{DMG_PALETTE, FrameBuffer, GameboyRgb};
+
+const fn rgb(hex: u32) -> GameboyRgb {
+    GameboyRgb {
+        red: (hex >> 16) as u8,
+        green: (hex >> 8) as u8,
+        blue: hex as u8,
+    }
+}
+
+/// Four shades, from lightest to darkest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub shades: [GameboyRgb; 4],
+}
+
+impl Palette {
+    /// Built-in palettes, by name
+    pub const NAMED: &'static [(&'static str, Palette)] = &[
+        // The original DMG's green LCD
+        ("green", Palette { shades: [rgb(0x9BBC0F), rgb(0x8BAC0F), rgb(0x306230), rgb(0x0F380F)] }),
+        // The Game Boy Pocket's slightly tinted grey LCD
+        ("pocket", Palette { shades: [rgb(0xC4CFA1), rgb(0x8B956D), rgb(0x4D533C), rgb(0x1F1F1F)] }),
+        // Plain greyscale
+        ("grey", Palette { shades: [rgb(0xFFFFFF), rgb(0xAAAAAA), rgb(0x555555), rgb(0x000000)] }),
+    ];
+
+    /// Build a palette from exactly four hex colors (e.g. `#9bbc0f`), from
+    /// lightest to darkest.
+    pub fn from_hex<S: AsRef<str>>(colors: &[S]) -> Result<Self, String> {
+        if colors.len() != 4 {
+            return Err(format!("Expected 4 colors, got {}", colors.len()));
+        }
+
+        let mut shades = [GameboyRgb::white(); 4];
+        for (shade, color) in shades.iter_mut().zip(colors) {
+            *shade = parse_hex(color.as_ref())?;
+        }
+
+        Ok(Self { shades })
+    }
+
+    /// Load a palette from a JSON file holding an array of four hex colors.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let colors: Vec<String> = serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid palette file {}: {}", path.display(), e))?;
+
+        Self::from_hex(&colors)
+    }
+
+    /// Copy `src` into `dst`, replacing each DMG shade with its counterpart
+    /// from this palette.
+    pub fn apply(&self, src: &FrameBuffer, dst: &mut FrameBuffer) {
+        for (out, pixel) in dst.data.iter_mut().zip(src.data.iter()) {
+            *out = match DMG_PALETTE.iter().position(|shade| shade == pixel) {
+                Some(i) => self.shades[i],
+                None => *pixel,
+            };
+        }
+    }
+}
+
+/// Back from 8 bits per channel to the CGB's 5
+fn to_5_bits(value: u8) -> u32 {
+    (u32::from(value) * 31 + 127) / 255
+}
+
+/// Mix a color the way the CGB's LCD does, less saturated and a little
+/// blue-green. This is the well-known matrix from higan, scaled so that white
+/// stays white.
+pub fn correct_color(pixel: GameboyRgb) -> GameboyRgb {
+    let (r, g, b) = (to_5_bits(pixel.red), to_5_bits(pixel.green), to_5_bits(pixel.blue));
+    let mix = |value: u32| (value.min(960) * 255 / 960) as u8;
+
+    GameboyRgb {
+        red: mix(r * 26 + g * 4 + b * 2),
+        green: mix(g * 24 + b * 8),
+        blue: mix(r * 6 + g * 4 + b * 22),
+    }
+}
+
+/// Copy `src` into `dst`, through `palette` for a monochrome game, or with
+/// the colors corrected for a color one.
+pub fn recolor(src: &FrameBuffer, dst: &mut FrameBuffer, palette: Option<&Palette>, color_correct: bool) {
+    match palette {
+        Some(palette) => palette.apply(src, dst),
+        None if color_correct => {
+            for (out, pixel) in dst.data.iter_mut().zip(src.data.iter()) {
+                *out = correct_color(*pixel);
+            }
+        }
+        None => dst.data.copy_from_slice(&src.data),
+    }
+}
+
+/// Fill `dst` with what the screen shows while the LCD is off: nothing, so
+/// the lightest shade, as the real screen is then.
+pub fn blank(dst: &mut FrameBuffer, palette: Option<&Palette>) {
+    dst.data.fill(palette.map_or(GameboyRgb::white(), |palette| palette.shades[0]));
+}
+
+/// How a game's frames are shown: through the palette or with the colors
+/// corrected, and blank while the LCD is off
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Screen {
+    pub palette: Option<Palette>,
+    pub color_correct: bool,
+    /// Keep the last frame while the LCD is off, instead of a blank screen
+    pub keep_last_frame: bool,
+}
+
+impl Screen {
+    /// A screen for the game in `cartridge`. Color games bring their own
+    /// palettes, and only they have colors to correct.
+    pub fn new(cartridge: &Cartridge, palette: Option<Palette>, color_correct: bool, keep_last_frame: bool) -> Self {
+        Self {
+            palette: palette.filter(|_| !cartridge.cgb()),
+            color_correct: color_correct && cartridge.cgb(),
+            keep_last_frame,
+        }
+    }
+
+    /// Copy what the screen shows of `gameboy`'s last frame into `dst`.
+    pub fn show(&self, gameboy: &Gameboy, dst: &mut FrameBuffer) {
+        recolor(gameboy.frame_buffer(), dst, self.palette.as_ref(), self.color_correct);
+        // The game turned the screen off, e.g. between scenes
+        if !self.keep_last_frame && !gameboy.ppu().lcd_enabled() {
+            blank(dst, self.palette.as_ref());
+        }
+    }
+}
+
+/// Parse a `#rrggbb` color, with or without the `#`.
+pub fn parse_hex(s: &str) -> Result<GameboyRgb, String> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid color: {} (expected #rrggbb)", s.trim()));
+    }
+
+    Ok(rgb(u32::from_str_radix(hex, 16).unwrap()))
+}
+
+/// Parse a comma-separated list of four hex colors.
+pub fn parse_palette_rgb(s: &str) -> Result<Palette, String> {
+    Palette::from_hex(&s.split(',').collect::<Vec<_>>())
+}
+
+/// Parse the path to a palette file, loading it right away.
+pub fn parse_palette_file(s: &str) -> Result<Palette, String> {
+    Palette::load(Path::new(s))
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMED
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, palette)| *palette)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::NAMED.iter().map(|(name, _)| *name).collect();
+                format!("Unknown palette: {} (expected one of: {})", s, names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shades_are_replaced_and_other_colors_kept() {
+        let palette: Palette = "Green".parse().unwrap();
+        let other = rgb(0x123456);
+
+        let mut src = FrameBuffer::new();
+        src.write(0, 0, DMG_PALETTE[0]);
+        src.write(1, 0, DMG_PALETTE[3]);
+        src.write(2, 0, other);
+
+        let mut dst = FrameBuffer::new();
+        palette.apply(&src, &mut dst);
+        assert_eq!(dst.read(0, 0), palette.shades[0]);
+        assert_eq!(dst.read(1, 0), palette.shades[3]);
+        assert_eq!(dst.read(2, 0), other);
+
+        assert!("purple".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn cgb_colors_are_corrected() {
+        let corrected = |hex| {
+            let pixel = correct_color(rgb(hex));
+            (pixel.red, pixel.green, pixel.blue)
+        };
+
+        assert_eq!(corrected(0xFFFFFF), (255, 255, 255));
+        assert_eq!(corrected(0x000000), (0, 0, 0));
+        assert_eq!(corrected(0xFF0000), (214, 0, 49));
+        assert_eq!(corrected(0x00FF00), (32, 197, 32));
+        assert_eq!(corrected(0x0000FF), (16, 65, 181));
+    }
+
+    #[test]
+    fn parse_custom_palettes() {
+        let palette = parse_palette_rgb("#9bbc0f, 8bac0f,#306230,#0F380F").unwrap();
+        assert_eq!(palette, "green".parse().unwrap());
+
+        assert_eq!(parse_palette_rgb("#000000,#ffffff").unwrap_err(), "Expected 4 colors, got 2");
+        assert_eq!(parse_palette_rgb("#000000,#ffffff,#fff,#000000").unwrap_err(),
+                   "Invalid color: #fff (expected #rrggbb)");
+        assert!(parse_palette_rgb("#000000,#ffffff,#gggggg,#000000").is_err());
+    }
+
+    #[test]
+    fn load_palette_file() {
+        let path = std::env::temp_dir().join(format!("gbcemu-palette-{}.json", std::process::id()));
+
+        std::fs::write(&path, r##"["#ffffff", "#aaaaaa", "#555555", "#000000"]"##).unwrap();
+        assert_eq!(Palette::load(&path).unwrap(), "grey".parse().unwrap());
+
+        std::fs::write(&path, r##"{"colors": []}"##).unwrap();
+        assert!(Palette::load(&path).unwrap_err().starts_with("Invalid palette file"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
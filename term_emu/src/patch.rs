@@ -0,0 +1,284 @@
+//! ROM patches, for `--patch`
+//!
+//! Fan translations and hacks are usually distributed as IPS or BPS patches.
+//! Both are applied in memory, to the ROM as read from disk. BPS patches carry
+//! checksums of the ROM they expect and the one they produce, which are
+//! checked.
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+
+use flate2::Crc;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// Source, target and patch CRC32s at the end of a BPS patch
+const BPS_FOOTER: usize = 12;
+
+/// Read a patch file, and apply it to `rom`.
+pub fn read(path: &Path, rom: Vec<u8>) -> Result<Vec<u8>, String> {
+    let patch = std::fs::read(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    apply(rom, &patch).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Apply an IPS or BPS patch to `rom`, telling them apart by their magic bytes.
+pub fn apply(rom: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, String> {
+    if let Some(records) = patch.strip_prefix(IPS_MAGIC) {
+        ips(rom, records)
+    } else if patch.starts_with(BPS_MAGIC) {
+        bps(&rom, patch)
+    } else {
+        Err("Not an IPS or BPS patch".to_string())
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Reads through a patch, failing on truncation.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or("Patch is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self.take(len)?.iter().fold(0, |n, &b| (n << 8) | usize::from(b)))
+    }
+
+    /// A BPS number: 7 bits at a time, least significant first, with the top
+    /// bit set on the last byte
+    fn number(&mut self) -> Result<usize, String> {
+        let mut n = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let b = self.byte()?;
+            n = usize::from(b & 0x7F).checked_mul(shift).and_then(|b| n.checked_add(b)).ok_or("Patch is corrupt")?;
+            if b & 0x80 != 0 {
+                return Ok(n);
+            }
+            shift = shift.checked_mul(0x80).ok_or("Patch is corrupt")?;
+            n = n.checked_add(shift).ok_or("Patch is corrupt")?;
+        }
+    }
+
+    /// A BPS offset: a number with the sign in the lowest bit
+    fn offset(&mut self) -> Result<isize, String> {
+        let n = self.number()?;
+        let magnitude = (n >> 1) as isize;
+        Ok(if n & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
+
+fn ips(mut rom: Vec<u8>, records: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader { data: records, pos: 0 };
+
+    loop {
+        let offset = reader.take(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = offset.iter().fold(0, |n, &b| (n << 8) | usize::from(b));
+
+        // Runs of the same byte have a length of 0, followed by the real one
+        let (len, data) = match reader.big_endian(2)? {
+            0 => {
+                let len = reader.big_endian(2)?;
+                (len, None)
+            }
+            len => (len, Some(reader.take(len)?)),
+        };
+
+        if rom.len() < offset + len {
+            rom.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => rom[offset..offset + len].copy_from_slice(data),
+            None => {
+                let value = reader.byte()?;
+                rom[offset..offset + len].fill(value);
+            }
+        }
+    }
+
+    // Some patches end with the size to truncate the ROM to
+    if let Ok(size) = reader.big_endian(3) {
+        rom.truncate(size);
+    }
+
+    Ok(rom)
+}
+
+fn bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER {
+        return Err("Patch is truncated".to_string());
+    }
+
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER);
+    let checksum = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+    let (source_crc, target_crc, patch_crc) = (checksum(0), checksum(1), checksum(2));
+
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err("Patch is corrupt, its checksum does not match".to_string());
+    }
+
+    let mut reader = Reader { data: body, pos: BPS_MAGIC.len() };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.take(metadata_size)?;
+
+    if source.len() != source_size {
+        return Err(format!("Patch is for a ROM of {} bytes, not {}", source_size, source.len()));
+    }
+    if crc32(source) != source_crc {
+        return Err(format!("Patch is for a different ROM (CRC32 {:08X}, not {:08X})", source_crc, crc32(source)));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+    let corrupt = || "Patch is corrupt".to_string();
+
+    while reader.pos < body.len() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        if target.len() + len > target_size {
+            return Err(corrupt());
+        }
+
+        match action & 3 {
+            // Bytes from the source, where they are in the target
+            0 => {
+                let bytes = source.get(target.len()..target.len() + len).ok_or_else(corrupt)?;
+                target.extend_from_slice(bytes);
+            }
+            // Bytes from the patch
+            1 => target.extend_from_slice(reader.take(len)?),
+            // Bytes from elsewhere in the source
+            2 => {
+                source_offset += reader.offset()?;
+                let start = usize::try_from(source_offset).map_err(|_| corrupt())?;
+                let bytes = source.get(start..start + len).ok_or_else(corrupt)?;
+                target.extend_from_slice(bytes);
+                source_offset += len as isize;
+            }
+            // Bytes already written, a byte at a time since they can overlap
+            _ => {
+                target_offset += reader.offset()?;
+                let start = usize::try_from(target_offset).map_err(|_| corrupt())?;
+                if start >= target.len() {
+                    return Err(corrupt());
+                }
+                for i in start..start + len {
+                    target.push(target[i]);
+                }
+                target_offset += len as isize;
+            }
+        }
+    }
+
+    if target.len() != target_size || crc32(&target) != target_crc {
+        return Err("Patched ROM does not match the checksum in the patch".to_string());
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn number(mut n: usize, out: &mut Vec<u8>) {
+        loop {
+            let b = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(b | 0x80);
+                return;
+            }
+            out.push(b);
+            n -= 1;
+        }
+    }
+
+    /// A BPS patch from `source` to `target`, made of the given actions
+    fn bps_patch(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        number(source.len(), &mut patch);
+        number(target.len(), &mut patch);
+        number(0, &mut patch);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_records() {
+        let rom = vec![0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 1
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        // A run of 3 bytes at 6, past the end
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 3, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(apply(rom.clone(), &patch).unwrap(), [0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC]);
+
+        // Truncated to 4 bytes
+        patch.extend_from_slice(&[0, 0, 4]);
+        assert_eq!(apply(rom.clone(), &patch).unwrap(), [0, 0xAA, 0xBB, 0]);
+
+        assert!(apply(rom.clone(), &patch[..10]).is_err());
+        assert!(apply(rom, b"NOT A PATCH").is_err());
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcXYXYXgh".to_vec();
+
+        let mut actions = Vec::new();
+        // Source read "abc"
+        number(2 << 2, &mut actions);
+        // Target read "XY"
+        number((1 << 2) | 1, &mut actions);
+        actions.extend_from_slice(b"XY");
+        // Target copy "XYX", from 3
+        number((2 << 2) | 3, &mut actions);
+        number(3 << 1, &mut actions);
+        // Source copy "gh", from 6
+        number((1 << 2) | 2, &mut actions);
+        number(6 << 1, &mut actions);
+
+        let patch = bps_patch(&source, &target, &actions);
+        assert_eq!(apply(source.clone(), &patch).unwrap(), target);
+
+        // The wrong ROM is caught before patching
+        let error = apply(b"abcdefgX".to_vec(), &patch).unwrap_err();
+        assert!(error.contains("different ROM"), "{}", error);
+        assert!(apply(b"abc".to_vec(), &patch).unwrap_err().contains("3"));
+
+        // And so is a damaged patch
+        let mut damaged = patch.clone();
+        damaged[10] ^= 1;
+        assert!(apply(source, &damaged).unwrap_err().contains("corrupt"));
+    }
+}
@@ -0,0 +1,370 @@
+//! Battery-backed cartridge persistence
+//!
+//! Every game gets a folder of its own under the save directory
+//! (`$XDG_DATA_HOME/gbc-terminal` by default, or `%APPDATA%\gbc-terminal\saves`
+//! on Windows), named after its title and checksum so that different games
+//! with the same title are kept apart.
+//!
+//! Cartridge RAM is stored there in a `.sav` file, and the RTC (if any) in a
+//! `.rtc` file. Both are loaded on startup and written back out periodically,
+//! as well as on exit. Save files from before there was a save directory,
+//! next to the ROM, are copied over the first time the game is run.
+//!
+//! Save states (full snapshots of the emulator) are stored in `.state` files,
+//! one for each slot, and are only written or loaded on request. Screenshots
+//! and recordings go in the same folder.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use gbc::Gameboy;
+use gbc::cartridge::Cartridge;
+
+/// How often dirty save data is flushed to disk while running.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Folder name for games without a title in their header
+const UNTITLED: &str = "UNTITLED";
+
+/// The files kept for each game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Ram,
+    Rtc,
+    /// A save state, in one of the slots from 1 to 9
+    State(u8),
+    /// The save state for `--auto-resume`, saved on quit
+    AutoState,
+    Screenshot,
+    Recording,
+    /// The palettes, LCD registers and BG maps, as JSON
+    PpuState,
+}
+
+impl FileKind {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Ram => "sav",
+            Self::Rtc => "rtc",
+            Self::State(_) | Self::AutoState => "state",
+            Self::Screenshot => "png",
+            Self::Recording => "gif",
+            Self::PpuState => "json",
+        }
+    }
+}
+
+/// The save directory to use when none is configured, if there is a place
+/// for it.
+pub fn default_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("gbc-terminal").join("saves"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+            .map(|dir| dir.join("gbc-terminal"))
+    }
+}
+
+/// Where the files for one game are kept
+#[derive(Clone, Debug)]
+pub struct GameDir {
+    dir: PathBuf,
+
+    /// File names start with this
+    name: String,
+}
+
+impl GameDir {
+    /// The folder for `cartridge` under the save directory `base`.
+    pub fn new(base: &Path, cartridge: &Cartridge) -> Self {
+        let name = sanitize(cartridge.title().unwrap_or_default());
+        let dir = base.join(format!("{}-{:04X}", name, cartridge.global_checksum()));
+
+        Self { dir, name }
+    }
+
+    /// Path to a file of the given kind. Screenshots, recordings and PPU
+    /// states are timestamped, so that they never replace each other.
+    pub fn save_path_for(&self, kind: FileKind) -> PathBuf {
+        let file = match kind {
+            FileKind::Screenshot | FileKind::Recording | FileKind::PpuState => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or_default();
+                format!("{}-{}.{}", self.name, millis, kind.extension())
+            }
+            // The first slot is where states were saved before there were others
+            FileKind::State(slot) if slot != 1 => format!("{}-{}.{}", self.name, slot, kind.extension()),
+            FileKind::AutoState => format!("{}-auto.{}", self.name, kind.extension()),
+            _ => format!("{}.{}", self.name, kind.extension()),
+        };
+
+        self.dir.join(file)
+    }
+
+    /// Copy the save files and save state that used to be kept next to the
+    /// ROM, if there are none here yet. The originals are left in place.
+    pub fn migrate(&self, rom_file: &Path) {
+        for kind in [FileKind::Ram, FileKind::Rtc, FileKind::State(1)] {
+            let old = rom_file.with_extension(kind.extension());
+            let new = self.save_path_for(kind);
+            if new.exists() || !old.is_file() {
+                continue;
+            }
+
+            let copied = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::copy(&old, &new));
+            match copied {
+                Ok(_) => log::info!("Copied {} to {}", old.display(), new.display()),
+                Err(e) => log::warn!("Failed to copy {} to {}: {}", old.display(), new.display(), e),
+            }
+        }
+    }
+}
+
+/// A cartridge title, made safe to use as a file name on any system.
+fn sanitize(title: &str) -> String {
+    let name: String = title.trim_end_matches('\0').trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_') { c } else { '_' })
+        .collect();
+
+    if name.is_empty() {
+        UNTITLED.to_string()
+    } else {
+        name
+    }
+}
+
+/// Save files for a single ROM.
+pub struct SaveFiles {
+    /// `None` if this cartridge has no battery-backed RAM, or if the existing
+    /// save file could not be loaded and must not be overwritten
+    ram_path: Option<PathBuf>,
+
+    /// `None` if this cartridge has no RTC
+    rtc_path: Option<PathBuf>,
+
+    /// Cartridge RAM size, in bytes
+    ram_size: usize,
+
+    last_flush: Instant,
+}
+
+impl SaveFiles {
+    /// Determine which save files a cartridge needs.
+    ///
+    /// Returns `None` if the cartridge has nothing worth persisting.
+    pub fn new(game_dir: &GameDir, cartridge: &Cartridge) -> Option<Self> {
+        let cartridge_type = cartridge.cartridge_type().ok()?;
+        if !cartridge_type.is_battery_backed() && !cartridge_type.is_rtc() {
+            return None;
+        }
+
+        let ram_path = if cartridge_type.is_battery_backed() {
+            Some(game_dir.save_path_for(FileKind::Ram))
+        } else {
+            None
+        };
+
+        let rtc_path = if cartridge_type.is_rtc() {
+            Some(game_dir.save_path_for(FileKind::Rtc))
+        } else {
+            None
+        };
+
+        Some(Self {
+            ram_path,
+            rtc_path,
+            ram_size: cartridge.ram_size().map(usize::from).unwrap_or(0),
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Load any existing save files into the `Gameboy`.
+    ///
+    /// Save files that do not match the cartridge RAM size are ignored with a
+    /// warning, and left untouched on disk.
+    pub fn load(&mut self, gameboy: &mut Gameboy) {
+        let ram_size = self.ram_size;
+        let ram = match &self.ram_path {
+            Some(path) => match std::fs::read(path) {
+                Ok(data) if data.len() == ram_size => Some(data),
+                Ok(data) => {
+                    log::warn!("Ignoring {}: expected {} bytes, found {}; it will not be overwritten",
+                               path.display(), ram_size, data.len());
+                    self.ram_path = None;
+                    None
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    log::warn!("Failed to read {}: {}; it will not be overwritten", path.display(), e);
+                    self.ram_path = None;
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let rtc = self.rtc_path.as_ref().and_then(|path| std::fs::read(path).ok());
+
+        if let Err(e) = gameboy.unpersist(ram.as_ref(), rtc.as_ref()) {
+            log::warn!("Failed to load save data: {}", e);
+        }
+    }
+
+    /// Write out any save data that changed since the last flush.
+    pub fn flush(&mut self, gameboy: &mut Gameboy) -> io::Result<()> {
+        self.last_flush = Instant::now();
+
+        let state = match gameboy.persist() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        if let (Some(path), Some(ram)) = (&self.ram_path, state.ram) {
+            write_atomic(path, ram)?;
+        }
+
+        if let (Some(path), Some(rtc)) = (&self.rtc_path, state.rtc) {
+            write_atomic(path, &rtc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush if enough time has passed since the last flush.
+    ///
+    /// This ensures that a crash only loses the last few seconds of progress.
+    pub fn flush_if_due(&mut self, gameboy: &mut Gameboy, now: Instant) {
+        if now.duration_since(self.last_flush) < FLUSH_INTERVAL {
+            return;
+        }
+
+        if let Err(e) = self.flush(gameboy) {
+            log::warn!("Failed to write save data: {}", e);
+        }
+    }
+}
+
+/// Save a snapshot of the `Gameboy` to `path`.
+pub fn save_state(gameboy: &Gameboy, path: &Path) -> gbc::Result<()> {
+    let state = gameboy.save_state()?;
+    write_atomic(path, &state)?;
+    Ok(())
+}
+
+/// Restore a snapshot of the `Gameboy` from `path`.
+///
+/// Fails without touching the `Gameboy` if the snapshot is for another ROM.
+pub fn load_state(gameboy: &mut Gameboy, path: &Path) -> gbc::Result<()> {
+    let state = std::fs::read(path)?;
+    gameboy.load_state(&state)
+}
+
+/// Write to a temporary file first so that a crash mid-write does not corrupt
+/// an existing save. The game's folder is created if needed.
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    create_parent(path)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Create the folder `path` goes in, if it does not exist yet.
+pub fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A blank 32K MBC1 ROM with 8K of battery-backed RAM
+    fn mbc1_battery_cartridge() -> Cartridge {
+        let mut data = vec![0u8; 32 * 1024];
+        data[0x147] = 0x03;
+        data[0x148] = 0x00;
+        data[0x149] = 0x02;
+        Cartridge::from_bytes(data, false)
+    }
+
+    #[test]
+    fn mismatched_save_file_is_ignored_and_kept() {
+        let dir = std::env::temp_dir().join(format!("gbcemu-persist-{}", std::process::id()));
+        let cartridge = mbc1_battery_cartridge();
+        let game_dir = GameDir::new(&dir, &cartridge);
+        let sav_file = game_dir.save_path_for(FileKind::Ram);
+        std::fs::create_dir_all(sav_file.parent().unwrap()).unwrap();
+        std::fs::write(&sav_file, [0xAA; 100]).unwrap();
+
+        let mut save_files = SaveFiles::new(&game_dir, &cartridge).unwrap();
+        let mut gameboy = Gameboy::init(cartridge, false).unwrap();
+
+        save_files.load(&mut gameboy);
+        assert!(save_files.ram_path.is_none());
+
+        save_files.flush(&mut gameboy).unwrap();
+        assert_eq!(std::fs::read(&sav_file).unwrap(), vec![0xAA; 100]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cartridges_without_a_battery_are_not_persisted() {
+        let mut data = vec![0u8; 32 * 1024];
+        data[0x147] = 0x02; // MBC1 + RAM, no battery
+        data[0x149] = 0x02;
+        let cartridge = Cartridge::from_bytes(data, false);
+
+        assert!(SaveFiles::new(&GameDir::new(Path::new("saves"), &cartridge), &cartridge).is_none());
+    }
+
+    #[test]
+    fn games_get_a_folder_each() {
+        let mut data = vec![0u8; 32 * 1024];
+        data[0x134..0x140].copy_from_slice(b"DR.MARIO/2\0\0");
+        data[0x14E] = 0xBE;
+        data[0x14F] = 0xEF;
+        let game_dir = GameDir::new(Path::new("saves"), &Cartridge::from_bytes(data, false));
+
+        assert_eq!(game_dir.save_path_for(FileKind::State(1)), Path::new("saves/DR_MARIO_2-BEEF/DR_MARIO_2.state"));
+        assert_eq!(game_dir.save_path_for(FileKind::State(9)), Path::new("saves/DR_MARIO_2-BEEF/DR_MARIO_2-9.state"));
+        assert_eq!(game_dir.save_path_for(FileKind::AutoState), Path::new("saves/DR_MARIO_2-BEEF/DR_MARIO_2-auto.state"));
+        let screenshot = game_dir.save_path_for(FileKind::Screenshot);
+        assert!(screenshot.starts_with("saves/DR_MARIO_2-BEEF"));
+        assert!(screenshot.to_str().unwrap().ends_with(".png"));
+
+        let untitled = GameDir::new(Path::new("saves"), &mbc1_battery_cartridge());
+        assert_eq!(untitled.save_path_for(FileKind::Ram), Path::new("saves/UNTITLED-0000/UNTITLED.sav"));
+    }
+
+    #[test]
+    fn old_saves_next_to_the_rom_are_copied() {
+        let dir = std::env::temp_dir().join(format!("gbcemu-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_file = dir.join("game.gb");
+        std::fs::write(dir.join("game.sav"), [0x42; 8]).unwrap();
+
+        let game_dir = GameDir::new(&dir.join("saves"), &mbc1_battery_cartridge());
+        game_dir.migrate(&rom_file);
+        assert_eq!(std::fs::read(game_dir.save_path_for(FileKind::Ram)).unwrap(), [0x42; 8]);
+        assert!(dir.join("game.sav").exists());
+        assert!(!game_dir.save_path_for(FileKind::State(1)).exists());
+
+        // Saves made since are not overwritten
+        std::fs::write(game_dir.save_path_for(FileKind::Ram), [0x43; 8]).unwrap();
+        game_dir.migrate(&rom_file);
+        assert_eq!(std::fs::read(game_dir.save_path_for(FileKind::Ram)).unwrap(), [0x43; 8]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! ROM picker, for `run --rom-dir`
+//!
+//! Lists the ROMs in a directory by their cartridge titles, and runs the one
+//! picked, with the keyboard or by clicking it. Quitting a game comes back to
+//! the list, with the same ROM selected. Held arrow keys repeat as set with
+//! `--menu-repeat-delay` and `--menu-repeat-rate`.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyModifiers, MouseButton, MouseEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+
+use gbc::cartridge::Cartridge;
+
+use crate::input::KeyRepeat;
+use crate::{rom, tty};
+
+/// Extensions of the files that are listed
+const EXTENSIONS: &[&str] = &["gb", "gbc"];
+
+/// Cartridge headers end at this offset
+const HEADER_END: usize = 0x150;
+
+/// How long to wait for a key before checking for a termination signal
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Rows taken by the heading, and by the status line at the bottom
+const HEADER_ROWS: u16 = 2;
+const FOOTER_ROWS: u16 = 1;
+
+pub struct Rom {
+    pub path: PathBuf,
+
+    /// The cartridge title, or the file name if the header has none
+    pub title: String,
+}
+
+pub struct Picker {
+    roms: Vec<Rom>,
+    selected: usize,
+
+    /// First ROM shown, when they do not all fit
+    top: usize,
+
+    /// Shown at the bottom of the list, e.g. when a ROM failed to load
+    pub status: Option<String>,
+
+    /// How held keys repeat
+    pub repeat: KeyRepeat,
+}
+
+impl Picker {
+    /// List the ROMs in `dir`, sorted by title.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let mut roms = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let listed = path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| EXTENSIONS.iter().any(|rom| rom.eq_ignore_ascii_case(ext)));
+            if !listed || !path.is_file() {
+                continue;
+            }
+
+            match fs::read(&path) {
+                Ok(data) => roms.push(Rom { title: title(data, &path), path }),
+                Err(e) => log::warn!("Skipping {}: {}", path.display(), e),
+            }
+        }
+
+        roms.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()).then_with(|| a.path.cmp(&b.path)));
+
+        Ok(Self {
+            roms,
+            selected: 0,
+            top: 0,
+            status: None,
+            repeat: KeyRepeat::default(),
+        })
+    }
+
+    pub fn roms(&self) -> &[Rom] {
+        &self.roms
+    }
+
+    /// Show the list until a ROM is picked, or `None` if the picker was quit.
+    pub fn pick(&mut self) -> io::Result<Option<&Rom>> {
+        tty::enter()?;
+        // Turned off again by `restore`
+        let picked = execute!(io::stdout(), event::EnableMouseCapture).and_then(|_| self.run());
+        tty::restore();
+
+        let roms = &self.roms;
+        Ok(picked?.map(|i| &roms[i]))
+    }
+
+    fn run(&mut self) -> io::Result<Option<usize>> {
+        let mut stdout = io::stdout();
+        // What was last drawn, so that nothing is sent while nothing changes
+        let mut drawn = None;
+
+        loop {
+            let (cols, rows) = terminal::size()?;
+            if drawn != Some((cols, rows, self.selected)) {
+                self.draw(&mut stdout, cols, rows)?;
+                stdout.flush()?;
+                drawn = Some((cols, rows, self.selected));
+            }
+
+            if tty::quit_requested() {
+                return Ok(None);
+            }
+
+            let page = rows.saturating_sub(HEADER_ROWS + FOOTER_ROWS).max(1) as usize;
+            // Woken up early for a held key
+            if !event::poll(self.repeat.timeout(Instant::now(), POLL_INTERVAL))? {
+                if let Some(key) = self.repeat.poll(Instant::now()) {
+                    self.navigate(key.code, page);
+                }
+                continue;
+            }
+
+            let last = self.roms.len().saturating_sub(1);
+
+            let key = match event::read()? {
+                Event::Key(key) => match self.repeat.handle(key, Instant::now()) {
+                    Some(key) => key,
+                    None => continue,
+                },
+                // Pointing at a ROM selects it, and clicking it plays it
+                Event::Mouse(mouse) => {
+                    let row = (mouse.row as usize).checked_sub(HEADER_ROWS as usize).filter(|&row| row < page);
+                    match (mouse.kind, row.map(|row| self.top + row).filter(|&i| i <= last)) {
+                        (MouseEventKind::Down(MouseButton::Left), Some(i)) if !self.roms.is_empty() => return Ok(Some(i)),
+                        (MouseEventKind::Moved, Some(i)) => self.selected = i,
+                        (MouseEventKind::ScrollUp, _) => self.selected = self.selected.saturating_sub(1),
+                        (MouseEventKind::ScrollDown, _) => self.selected = (self.selected + 1).min(last),
+                        _ => (),
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match key.code {
+                KeyCode::Enter if !self.roms.is_empty() => return Ok(Some(self.selected)),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+                code => self.navigate(code, page),
+            }
+        }
+    }
+
+    /// Move the selection with `code`, `page` ROMs at a time for Page Up and
+    /// Page Down.
+    fn navigate(&mut self, code: KeyCode, page: usize) {
+        let last = self.roms.len().saturating_sub(1);
+        match code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(page),
+            KeyCode::PageDown => self.selected = (self.selected + page).min(last),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            _ => (),
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write, cols: u16, rows: u16) -> io::Result<()> {
+        let height = rows.saturating_sub(HEADER_ROWS + FOOTER_ROWS).max(1) as usize;
+        self.top = scroll(self.top, self.selected, height);
+        let width = cols as usize;
+
+        queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0),
+               Print(fit("Pick a game: Up/Down to move, Enter or click to play, Q to quit", width)))?;
+
+        for (row, (i, rom)) in self.roms.iter().enumerate().skip(self.top).take(height).enumerate() {
+            let name = rom.path.file_name().unwrap_or_default().to_string_lossy();
+            let line = format!("{} {:<16}  {}", if i == self.selected { '>' } else { ' ' }, rom.title, name);
+
+            queue!(out, cursor::MoveTo(0, HEADER_ROWS + row as u16))?;
+            if i == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(fit(&line, width)),
+                       SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(out, Print(fit(&line, width)))?;
+            }
+        }
+
+        if let Some(status) = &self.status {
+            queue!(out, cursor::MoveTo(0, rows.saturating_sub(1)), Print(fit(status, width)))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The title from the cartridge header, if the file is big enough to have one.
+fn title(data: Vec<u8>, path: &Path) -> String {
+    let cartridge = (data.len() >= HEADER_END).then(|| Cartridge::from_bytes(data, false));
+    rom::title(cartridge.as_ref(), path)
+}
+
+/// The first row to show, moving as little as possible from `top` to keep
+/// `selected` in view.
+fn scroll(top: usize, selected: usize, height: usize) -> usize {
+    top.min(selected).max((selected + 1).saturating_sub(height))
+}
+
+/// Cut `s` down to `width` characters, so that lines never wrap.
+fn fit(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom(title: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; 32 * 1024];
+        data[0x134..0x134 + title.len()].copy_from_slice(title);
+        data
+    }
+
+    #[test]
+    fn roms_are_listed_by_title() {
+        let dir = std::env::temp_dir().join(format!("gbcemu-picker-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.gb"), rom(b"ZELDA")).unwrap();
+        fs::write(dir.join("b.GBC"), rom(b"apple")).unwrap();
+        fs::write(dir.join("untitled.gb"), rom(b"")).unwrap();
+        fs::write(dir.join("notes.txt"), b"not a ROM").unwrap();
+
+        let picker = Picker::open(&dir).unwrap();
+        let titles: Vec<&str> = picker.roms().iter().map(|rom| rom.title.as_str()).collect();
+        assert_eq!(titles, ["apple", "untitled", "ZELDA"]);
+        assert_eq!(picker.roms()[0].path, dir.join("b.GBC"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scrolling_keeps_the_selection_in_view() {
+        // Everything fits
+        assert_eq!(scroll(0, 3, 10), 0);
+        // Moving down past the bottom scrolls by as little as needed
+        assert_eq!(scroll(0, 10, 10), 1);
+        assert_eq!(scroll(1, 15, 10), 6);
+        // Moving back up within the view stays put, until the top
+        assert_eq!(scroll(6, 8, 10), 6);
+        assert_eq!(scroll(6, 4, 10), 4);
+    }
+}
@@ -0,0 +1,102 @@
+//! Frame timing, for `run --profile`
+//!
+//! Each pass through the main loop is split into the time spent working, i.e.
+//! emulating and handing the frame over, and the time spent sleeping, and the render thread times every frame it draws.
+//! The result tells whether stutter comes from the emulator or the terminal.
+//! Nothing is collected without `--profile`.
+use std::fmt;
+use std::time::Duration;
+
+/// Log target for profiling output, which `--profile` shows by default
+pub const TARGET: &str = "gbcemu::profile";
+
+/// Upper bounds of the histogram buckets, in ms. Anything slower goes into a
+/// last bucket.
+const BUCKETS: [u64; 6] = [1, 2, 4, 8, 16, 33];
+
+#[derive(Default)]
+pub struct Profile {
+    /// Working, per pass through the main loop
+    pub cpu: Vec<Duration>,
+
+    /// Drawing frames on the render thread
+    pub render: Vec<Duration>,
+
+    /// Waiting for the next pass, per pass through the main loop
+    pub sleep: Vec<Duration>,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for (name, samples) in [("CPU", &self.cpu), ("Render", &self.render), ("Sleep", &self.sleep)] {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            summarize(f, name, samples)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn summarize(f: &mut fmt::Formatter, name: &str, samples: &[Duration]) -> fmt::Result {
+    if samples.is_empty() {
+        return write!(f, "{}: no samples", name);
+    }
+
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+    let mean = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+
+    writeln!(f, "{}: {} samples, mean {:.2?}, median {:.2?}, 95% {:.2?}, 99% {:.2?}, max {:.2?}", name,
+             sorted.len(), mean, percentile(50), percentile(95), percentile(99), sorted[sorted.len() - 1])?;
+
+    let mut counts = [0; BUCKETS.len() + 1];
+    for sample in &sorted {
+        let bucket = BUCKETS.iter().position(|&ms| *sample < Duration::from_millis(ms)).unwrap_or(BUCKETS.len());
+        counts[bucket] += 1;
+    }
+
+    let width = |count: usize| (count * 40).div_ceil(sorted.len());
+    for (i, &count) in counts.iter().enumerate() {
+        let label = match i {
+            0 => format!("< {} ms", BUCKETS[0]),
+            i if i == BUCKETS.len() => format!(">= {} ms", BUCKETS[i - 1]),
+            i => format!("{}-{} ms", BUCKETS[i - 1], BUCKETS[i]),
+        };
+        write!(f, "  {:>9} {:>7} {}", label, count, "#".repeat(width(count)))?;
+        if i < BUCKETS.len() {
+            writeln!(f)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary_has_percentiles_and_histogram() {
+        let profile = Profile {
+            cpu: (1..=100).map(|us| Duration::from_micros(us * 100)).collect(),
+            render: vec![Duration::from_millis(40)],
+            sleep: Vec::new(),
+        };
+
+        let summary = profile.to_string();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "CPU: 100 samples, mean 5.05ms, median 5.00ms, 95% 9.50ms, 99% 9.90ms, max 10.00ms");
+        assert_eq!(lines[1].split_whitespace().collect::<Vec<_>>(), ["<", "1", "ms", "9", "####"]);
+        assert_eq!(lines[4].split_whitespace().collect::<Vec<_>>()[..3], ["4-8", "ms", "40"]);
+        assert_eq!(lines[5].split_whitespace().collect::<Vec<_>>()[..3], ["8-16", "ms", "21"]);
+        assert!(lines[7].trim_start().starts_with(">= 33 ms       0"));
+        assert!(lines[8].starts_with("Render: 1 samples"));
+        assert!(lines[15].trim_start().starts_with(">= 33 ms       1 ####"));
+        assert_eq!(lines[16], "Sleep: no samples");
+    }
+}
@@ -0,0 +1,140 @@
+//! Raw frames, for `run --raw-out`
+//!
+//! Every frame is written as plain RGB bytes to a file or a named pipe, for
+//! ffmpeg or other tools to pick up as the game runs. There is no header, so
+//! the stream can be read as `rawvideo` as is; `--raw-info` prints the format.
+//!
+//! Writing is done on a separate thread. A reader that cannot keep up, or has
+//! not opened the pipe yet, misses frames rather than slowing the game down.
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use gbc::ppu::{FrameBuffer, LCD_HEIGHT, LCD_WIDTH};
+
+use crate::capture::frame_pixels;
+
+/// Bytes in each frame of the stream
+pub const FRAME_BYTES: usize = LCD_WIDTH * LCD_HEIGHT * 3;
+
+/// Frames waiting for the writer, beyond which new ones are dropped
+const QUEUE: usize = 8;
+
+/// How long [`RawOut::finish`] waits for the reader to take what is queued
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The format of the stream, at `fps` frames per second, and how to read it
+/// with ffmpeg.
+pub fn info(fps: f64) -> String {
+    format!("{w}x{h} pixels, 8-bit RGB (rgb24), rows top to bottom, {} bytes per frame, no header\n\
+             {:.4} frames per second\n\
+             ffmpeg: -f rawvideo -pixel_format rgb24 -video_size {w}x{h} -framerate {:.4} -i <path>",
+            FRAME_BYTES, fps, fps, w = LCD_WIDTH, h = LCD_HEIGHT)
+}
+
+pub struct RawOut {
+    tx: SyncSender<Vec<u8>>,
+    writer: JoinHandle<()>,
+
+    /// Frames the writer could not keep up with
+    dropped: u64,
+}
+
+impl RawOut {
+    /// Start writing frames to `path`, creating or truncating it.
+    pub fn open(path: PathBuf) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(QUEUE);
+        let writer = thread::spawn(move || {
+            let result = (|| -> io::Result<u64> {
+                // Opening a named pipe waits for a reader, so it happens here
+                let mut file = File::create(&path)?;
+
+                let mut frames = 0;
+                for pixels in rx {
+                    file.write_all(&pixels)?;
+                    frames += 1;
+                }
+
+                Ok(frames)
+            })();
+
+            match result {
+                Ok(frames) => log::info!("Wrote {} raw frames to {}", frames, path.display()),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => log::info!("Raw output closed by the reader"),
+                Err(e) => log::error!("Raw output to {} stopped: {}", path.display(), e),
+            }
+        });
+
+        Self {
+            tx,
+            writer,
+            dropped: 0,
+        }
+    }
+
+    /// Queue a frame to be written, or drop it if the writer is behind.
+    pub fn push(&mut self, frame_buffer: &FrameBuffer) {
+        match self.tx.try_send(frame_pixels(frame_buffer)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            // The writer only goes away if it failed, which it reports itself
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+
+    /// Stop writing, giving the reader a moment to take the frames still
+    /// queued.
+    pub fn finish(self) {
+        if self.dropped > 0 {
+            log::warn!("Dropped {} raw frames the reader did not keep up with", self.dropped);
+        }
+
+        drop(self.tx);
+        let deadline = Instant::now() + FINISH_TIMEOUT;
+        while !self.writer.is_finished() {
+            if Instant::now() >= deadline {
+                log::warn!("Gave up on raw frames still queued for the reader");
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.writer.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::ppu::GameboyRgb;
+
+    use super::*;
+
+    #[test]
+    fn frames_are_written_back_to_back() {
+        let path = std::env::temp_dir().join(format!("gbcemu-raw-{}.rgb", std::process::id()));
+
+        let mut frame = FrameBuffer::new();
+        frame.write(1, 0, GameboyRgb { red: 1, green: 2, blue: 3 });
+
+        let mut raw = RawOut::open(path.clone());
+        raw.push(&frame);
+        raw.push(&FrameBuffer::new());
+        raw.finish();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 2 * FRAME_BYTES);
+        assert_eq!(bytes[..6], [0xFF, 0xFF, 0xFF, 1, 2, 3]);
+        assert!(bytes[FRAME_BYTES..].iter().all(|&byte| byte == 0xFF));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn info_matches_the_stream() {
+        let info = info(59.7275);
+        assert!(info.starts_with("160x144 pixels, 8-bit RGB (rgb24), rows top to bottom, 69120 bytes per frame"));
+        assert!(info.contains("-video_size 160x144 -framerate 59.7275 "));
+    }
+}
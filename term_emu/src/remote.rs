@@ -0,0 +1,249 @@
+//! Joypad input from a Unix socket, for `run --input-socket`
+//!
+//! Other programs (bots, scripts, accessibility devices) can press buttons
+//! by connecting to the socket and writing a line per input, `down <input>`
+//! or `up <input>`, with inputs named as in key bindings (e.g. `down a`).
+//! Blank lines and lines starting with `#` are skipped, and lines that do not
+//! parse are answered with `error: ...`. The input goes in alongside the
+//! keyboard and gamepads.
+//!
+//! Any number of clients can be connected at a time, and they can come and go
+//! while a game runs. Whatever a client holds is let go of when it goes.
+//!
+//! Sockets are only supported on Unix. Elsewhere, opening one fails.
+use gbc::joypad::JoypadEvent;
+
+use crate::input::parse_joypad_input;
+
+#[cfg(unix)]
+pub use self::unix_socket::InputSocket;
+
+#[cfg(not(unix))]
+pub use self::null_socket::InputSocket;
+
+/// Parse a line sent over the socket, if it is not blank or a comment.
+pub fn parse_command(line: &str) -> Result<Option<JoypadEvent>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["down", input] => Ok(Some(JoypadEvent::Down(parse_joypad_input(input)?))),
+        ["up", input] => Ok(Some(JoypadEvent::Up(parse_joypad_input(input)?))),
+        _ => Err(format!("Expected `down <input>` or `up <input>`, got: {}", line)),
+    }
+}
+
+#[cfg(unix)]
+mod unix_socket {
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+
+    use gbc::joypad::{JoypadEvent, JoypadInput};
+
+    use super::parse_command;
+
+    struct Client {
+        stream: UnixStream,
+
+        /// Bytes read that do not make up a whole line yet
+        incoming: Vec<u8>,
+
+        /// Inputs the client holds, to let go of when it goes
+        held: Vec<JoypadInput>,
+    }
+
+    impl Client {
+        /// Push the events in the lines read since the last poll to `events`,
+        /// returning whether the client is still connected.
+        fn poll(&mut self, events: &mut Vec<JoypadEvent>) -> bool {
+            let mut buf = [0; 256];
+            let connected = loop {
+                match self.stream.read(&mut buf) {
+                    Ok(0) => break false,
+                    Ok(n) => self.incoming.extend_from_slice(&buf[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break true,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                    Err(e) => {
+                        log::warn!("Input socket client lost: {}", e);
+                        break false;
+                    }
+                }
+            };
+
+            while let Some(end) = self.incoming.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.incoming.drain(..=end).collect();
+                match parse_command(&String::from_utf8_lossy(&line)) {
+                    Ok(Some(event)) => self.press(event, events),
+                    Ok(None) => (),
+                    Err(e) => {
+                        log::warn!("Input socket: {}", e);
+                        // The client may not be listening, which is fine
+                        let _ = writeln!(self.stream, "error: {}", e);
+                    }
+                }
+            }
+
+            connected
+        }
+
+        fn press(&mut self, event: JoypadEvent, events: &mut Vec<JoypadEvent>) {
+            match event {
+                JoypadEvent::Down(input) if !self.held.contains(&input) => self.held.push(input),
+                JoypadEvent::Up(input) if self.held.contains(&input) => self.held.retain(|&held| held != input),
+                // Already down, or not down at all
+                _ => return,
+            }
+            events.push(event);
+        }
+    }
+
+    pub struct InputSocket {
+        path: PathBuf,
+        listener: UnixListener,
+        clients: Vec<Client>,
+    }
+
+    impl InputSocket {
+        /// Listen on a socket at `path`. A socket left there by an instance
+        /// that did not get to remove it is replaced, but one that is still
+        /// listening, or any other file, is not.
+        pub fn open(path: &Path) -> io::Result<Self> {
+            let stale = std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket())
+                && UnixStream::connect(path).is_err();
+            if stale {
+                std::fs::remove_file(path)?;
+            }
+
+            let listener = UnixListener::bind(path)?;
+            listener.set_nonblocking(true)?;
+
+            Ok(Self {
+                path: path.to_path_buf(),
+                listener,
+                clients: Vec::new(),
+            })
+        }
+
+        /// Push the joypad events sent since the last poll to `events`,
+        /// returning a message if a client connected or disconnected.
+        pub fn poll(&mut self, events: &mut Vec<JoypadEvent>) -> Option<String> {
+            let mut message = None;
+
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = stream.set_nonblocking(true) {
+                            log::warn!("Input socket client refused: {}", e);
+                            continue;
+                        }
+                        self.clients.push(Client { stream, incoming: Vec::new(), held: Vec::new() });
+                        message = Some("Input socket client connected".to_string());
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                    Err(e) => {
+                        log::warn!("Input socket: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            let mut i = 0;
+            while i < self.clients.len() {
+                if self.clients[i].poll(events) {
+                    i += 1;
+                } else {
+                    let client = self.clients.remove(i);
+                    events.extend(client.held.into_iter().map(JoypadEvent::Up));
+                    message = Some("Input socket client disconnected".to_string());
+                }
+            }
+
+            message
+        }
+    }
+
+    impl Drop for InputSocket {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod null_socket {
+    use std::io;
+    use std::path::Path;
+
+    use gbc::joypad::JoypadEvent;
+
+    /// Stand-in for systems without Unix sockets.
+    pub struct InputSocket;
+
+    impl InputSocket {
+        pub fn open(_path: &Path) -> io::Result<Self> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "input sockets are only supported on Unix"))
+        }
+
+        pub fn poll(&mut self, _events: &mut Vec<JoypadEvent>) -> Option<String> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::joypad::JoypadInput;
+
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_command("down a\n"), Ok(Some(JoypadEvent::Down(JoypadInput::A))));
+        assert_eq!(parse_command("  up   start "), Ok(Some(JoypadEvent::Up(JoypadInput::Start))));
+        assert_eq!(parse_command(""), Ok(None));
+        assert_eq!(parse_command("# a comment"), Ok(None));
+        assert!(parse_command("down").is_err());
+        assert!(parse_command("press a").is_err());
+        assert!(parse_command("down x").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clients_press_and_let_go() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("gbcemu-input-{}.sock", std::process::id()));
+        let mut socket = InputSocket::open(&path).unwrap();
+        let mut events = Vec::new();
+        assert_eq!(socket.poll(&mut events), None);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        // Sent in pieces, with a line that is not a command
+        client.write_all(b"down a\ndown a\nup a\ndown ri").unwrap();
+        client.write_all(b"ght\njump\n").unwrap();
+        client.flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(socket.poll(&mut events), Some("Input socket client connected".to_string()));
+        assert_eq!(events, [JoypadEvent::Down(JoypadInput::A), JoypadEvent::Up(JoypadInput::A),
+                            JoypadEvent::Down(JoypadInput::Right)]);
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert!(reply.starts_with("error: "));
+
+        // Right is still held when it goes
+        events.clear();
+        drop(client);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(socket.poll(&mut events), Some("Input socket client disconnected".to_string()));
+        assert_eq!(events, [JoypadEvent::Up(JoypadInput::Right)]);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
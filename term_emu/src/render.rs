@@ -0,0 +1,920 @@
+//! Converts Gameboy frames into terminal output
+//!
+//! Output is produced by a `Renderer`, picked once at startup with a `Backend`.
+//!
+//! The renderers that draw with text first convert frames into a grid of
+//! character cells using one of the cell builders. A `Screen` then compares the
+//! grid against what it drew last time and only emits escape sequences for the
+//! cells that changed.
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+
+use crossterm::{cursor, queue, style, style::Color, terminal};
+
+use gbc::ppu::{FrameBuffer, GameboyRgb, LCD_HEIGHT, LCD_WIDTH};
+
+use crate::color::{self, ColorMode};
+use crate::kitty::KittyRenderer;
+use crate::sixel::SixelRenderer;
+
+/// Sextant cells are 2 pixels wide and 3 pixels tall.
+const SEXTANT_WIDTH: usize = 2;
+const SEXTANT_HEIGHT: usize = 3;
+
+/// ASCII cells are 2 pixels wide and 4 pixels tall, about the shape of a
+/// terminal cell.
+const ASCII_WIDTH: usize = 2;
+const ASCII_HEIGHT: usize = 4;
+
+/// Characters for ASCII cells, from lightest to darkest
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Cell colors to keep escape sequences for, before starting over. All
+/// 32768 Gameboy colors fit, but averaged or blended ones can be anything.
+const COLOR_CODES_LIMIT: usize = 1 << 16;
+
+// The sextant and ASCII builders pack whole blocks of pixels into each cell
+const _: () = assert!(LCD_WIDTH.is_multiple_of(SEXTANT_WIDTH) && LCD_HEIGHT.is_multiple_of(SEXTANT_HEIGHT));
+const _: () = assert!(LCD_WIDTH.is_multiple_of(ASCII_WIDTH) && LCD_HEIGHT.is_multiple_of(ASCII_HEIGHT));
+// An odd height would still render, with black under the last row
+const _: () = assert!(LCD_HEIGHT.is_multiple_of(2), "half-block cells need an even LCD height");
+
+/// A single character cell on the terminal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+fn to_color(rgb: GameboyRgb) -> Color {
+    Color::Rgb { r: rgb.red, g: rgb.green, b: rgb.blue }
+}
+
+/// The part of the frame to show, in pixels, e.g. to leave out a game's
+/// border or status bar. The image is centered as if it were the whole frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// The whole frame
+    pub const FULL: Self = Self { left: 0, top: 0, width: LCD_WIDTH, height: LCD_HEIGHT };
+
+    /// Read pixel `(x, y)` of the crop, treating anything past its edges as
+    /// black.
+    pub fn read(&self, frame_buffer: &FrameBuffer, x: usize, y: usize) -> GameboyRgb {
+        if x < self.width && y < self.height {
+            frame_buffer.read(self.left + x, self.top + y)
+        } else {
+            GameboyRgb { red: 0, green: 0, blue: 0 }
+        }
+    }
+
+    /// The pixels of the crop, in row-major order.
+    pub fn pixels<'a>(&'a self, frame_buffer: &'a FrameBuffer) -> impl Iterator<Item = GameboyRgb> + 'a {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.read(frame_buffer, x, y)))
+    }
+}
+
+impl Default for Crop {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    /// Parse `left,top,width,height`, which has to fit in the frame.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numbers: Vec<usize> = s.split(',').map(|n| n.trim().parse()).collect::<Result<_, _>>()
+            .map_err(|_| format!("Expected a crop as left,top,width,height, got: {}", s))?;
+        let [left, top, width, height] = numbers[..] else {
+            return Err(format!("Expected a crop as left,top,width,height, got: {}", s));
+        };
+
+        if width == 0 || height == 0 {
+            return Err(format!("A crop needs a width and height of at least 1, got: {}", s));
+        }
+        if left + width > LCD_WIDTH || top + height > LCD_HEIGHT {
+            return Err(format!("A crop has to fit in the {}x{} frame, got: {}", LCD_WIDTH, LCD_HEIGHT, s));
+        }
+
+        Ok(Self { left, top, width, height })
+    }
+}
+
+/// Grid dimensions, in cells, produced by the half-block builder.
+pub const HALF_BLOCK_SIZE: (usize, usize) = (LCD_WIDTH, LCD_HEIGHT.div_ceil(2));
+
+/// Grid dimensions, in cells, produced by the sextant builder.
+pub const SEXTANT_SIZE: (usize, usize) = (LCD_WIDTH / SEXTANT_WIDTH, LCD_HEIGHT / SEXTANT_HEIGHT);
+
+/// Grid dimensions, in cells, produced by the ASCII builder.
+pub const ASCII_SIZE: (usize, usize) = (LCD_WIDTH / ASCII_WIDTH, LCD_HEIGHT / ASCII_HEIGHT);
+
+/// Turns frames into terminal output.
+pub trait Renderer: Send {
+    /// Append the output that brings the terminal up to date with a frame to
+    /// `out`.
+    fn render(&mut self, frame_buffer: &FrameBuffer, out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Update the terminal size, in cells.
+    fn resize(&mut self, cols: u16, rows: u16);
+
+    /// Redraw everything on the next frame, after something else drew over
+    /// the terminal.
+    fn invalidate(&mut self);
+}
+
+/// Settings shared by the renderers, where they apply
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    /// Terminal columns per cell
+    pub scale_width: usize,
+    /// The height of a terminal cell over its width, to stretch the image
+    /// with instead of `scale_width` so that pixels come out square
+    pub correct_aspect: Option<f64>,
+    /// Top-left corner to draw at, in cells, instead of centering the image
+    /// on a cleared terminal
+    pub at: Option<(u16, u16)>,
+    pub color_mode: ColorMode,
+    pub grayscale: bool,
+    /// The part of the frame to draw
+    pub crop: Crop,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            scale_width: 1,
+            correct_aspect: None,
+            at: None,
+            color_mode: ColorMode::TrueColor,
+            grayscale: false,
+            crop: Crop::FULL,
+        }
+    }
+}
+
+/// Cells are about twice as tall as they are wide in most fonts.
+pub const DEFAULT_CELL_ASPECT: f64 = 2.0;
+
+/// Terminal columns per cell that make pixels square, for cells of `pixels`
+/// (width, height) on a terminal whose cells are `cell_aspect` times as tall
+/// as they are wide. Columns are never dropped, so this is at least 1.
+fn aspect_scale(pixels: (usize, usize), cell_aspect: f64) -> f64 {
+    let scale = pixels.0 as f64 * cell_aspect / pixels.1 as f64;
+    if scale < 1.0 {
+        log::warn!("Cells are too wide to correct the aspect ratio, pixels will be {:.0}% too wide", 100.0 / scale - 100.0);
+    }
+    scale.max(1.0)
+}
+
+/// The available renderers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    HalfBlock,
+    Sextant,
+    Ascii,
+    Sixel,
+    Kitty,
+}
+
+impl Backend {
+    pub fn renderer(self, options: RenderOptions) -> Box<dyn Renderer> {
+        let (pixels, build): (_, CellBuilder) = match self {
+            Self::HalfBlock => ((1, 2), half_block_cells),
+            Self::Sextant => ((SEXTANT_WIDTH, SEXTANT_HEIGHT), sextant_cells),
+            Self::Ascii => ((ASCII_WIDTH, ASCII_HEIGHT), ascii_cells),
+            Self::Sixel => return Box::new(SixelRenderer::new(options.at, options.crop)),
+            Self::Kitty => return Box::new(KittyRenderer::new(options.at, options.crop)),
+        };
+
+        Box::new(CellRenderer::new(pixels, build, options))
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "half-block" => Ok(Self::HalfBlock),
+            "sextant" => Ok(Self::Sextant),
+            "ascii" => Ok(Self::Ascii),
+            "sixel" => Ok(Self::Sixel),
+            "kitty" => Ok(Self::Kitty),
+            _ => Err(format!("Unknown renderer: {} (expected half-block, sextant, ascii, sixel or kitty)", s)),
+        }
+    }
+}
+
+/// Converts the cropped part of a frame into a grid of cells, in row-major
+/// order
+type CellBuilder = fn(&FrameBuffer, Crop, &mut Vec<Cell>);
+
+/// Draws frames as text, with one of the cell builders.
+pub struct CellRenderer {
+    screen: Screen,
+    build: CellBuilder,
+    crop: Crop,
+}
+
+impl CellRenderer {
+    /// Draw with `build`, which packs blocks of `pixels` (width, height) into
+    /// each cell.
+    pub fn new(pixels: (usize, usize), build: CellBuilder, options: RenderOptions) -> Self {
+        let crop = options.crop;
+        let mut screen = Screen::new((crop.width.div_ceil(pixels.0), crop.height.div_ceil(pixels.1)));
+        screen.set_scale_width(match options.correct_aspect {
+            Some(cell_aspect) => aspect_scale(pixels, cell_aspect),
+            None => options.scale_width as f64,
+        });
+        screen.set_colors(options.color_mode, options.grayscale);
+        if let Some(at) = options.at {
+            screen.fix_origin(at);
+        }
+
+        Self { screen, build, crop }
+    }
+}
+
+impl Renderer for CellRenderer {
+    fn render(&mut self, frame_buffer: &FrameBuffer, out: &mut Vec<u8>) -> io::Result<()> {
+        (self.build)(frame_buffer, self.crop, &mut self.screen.cells);
+
+        // Only draw the cells that changed since the last frame
+        let start = out.len();
+        self.screen.draw(out)?;
+        log::debug!("Cells emitted: {} ({} bytes)", self.screen.cells_emitted, out.len() - start);
+        Ok(())
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.screen.resize(cols, rows);
+    }
+
+    fn invalidate(&mut self) {
+        self.screen.invalidate();
+    }
+}
+
+/// Builds cells using the `▄` half-block, packing two vertical pixels per cell.
+///
+/// The top pixel is drawn with the background color and the bottom pixel with
+/// the foreground color.
+pub fn half_block_cells(frame_buffer: &FrameBuffer, crop: Crop, cells: &mut Vec<Cell>) {
+    cells.clear();
+
+    for y in 0..crop.height.div_ceil(2) {
+        for x in 0..crop.width {
+            cells.push(Cell {
+                glyph: '▄',
+                fg: to_color(crop.read(frame_buffer, x, y*2+1)),
+                bg: to_color(crop.read(frame_buffer, x, y*2)),
+            });
+        }
+    }
+}
+
+/// Builds cells using Unicode sextant glyphs, packing a 2x3 block of pixels
+/// into each cell.
+///
+/// This shows more detail per cell at the cost of requiring a font with the
+/// "Symbols for Legacy Computing" block.
+pub fn sextant_cells(frame_buffer: &FrameBuffer, crop: Crop, cells: &mut Vec<Cell>) {
+    cells.clear();
+
+    for row in 0..crop.height.div_ceil(SEXTANT_HEIGHT) {
+        for col in 0..crop.width.div_ceil(SEXTANT_WIDTH) {
+            let mut pixels = [GameboyRgb::white(); SEXTANT_WIDTH * SEXTANT_HEIGHT];
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let x = col * SEXTANT_WIDTH + i % SEXTANT_WIDTH;
+                let y = row * SEXTANT_HEIGHT + i / SEXTANT_WIDTH;
+                *pixel = crop.read(frame_buffer, x, y);
+            }
+
+            let (glyph, fg, bg) = sextant_cell(&pixels);
+            cells.push(Cell { glyph, fg: to_color(fg), bg: to_color(bg) });
+        }
+    }
+}
+
+/// Builds cells out of plain ASCII characters, without any color, for
+/// terminals that cannot show anything else.
+///
+/// Each cell covers a 2x4 block of pixels, and the darker the block is on
+/// average, the denser its character, so that the image looks like ink on
+/// paper: white is a space and black is `@`.
+pub fn ascii_cells(frame_buffer: &FrameBuffer, crop: Crop, cells: &mut Vec<Cell>) {
+    cells.clear();
+
+    for row in 0..crop.height.div_ceil(ASCII_HEIGHT) {
+        for col in 0..crop.width.div_ceil(ASCII_WIDTH) {
+            let mut total = 0;
+            for y in row * ASCII_HEIGHT..(row + 1) * ASCII_HEIGHT {
+                for x in col * ASCII_WIDTH..(col + 1) * ASCII_WIDTH {
+                    let rgb = crop.read(frame_buffer, x, y);
+                    total += color::luma(rgb.red, rgb.green, rgb.blue) as usize;
+                }
+            }
+
+            let darkness = 255 - total / (ASCII_WIDTH * ASCII_HEIGHT);
+            let glyph = ASCII_RAMP[darkness * ASCII_RAMP.len() / 256] as char;
+            cells.push(Cell { glyph, fg: Color::Reset, bg: Color::Reset });
+        }
+    }
+}
+
+/// The terminal's view of the frame.
+///
+/// Keeps a copy of the cells that were last drawn so that only changed cells
+/// are redrawn on the next frame. The image is centered on the terminal, or
+/// replaced with a message if the terminal is too small to hold it.
+///
+/// Each cell can be repeated across several terminal columns, making the
+/// image wider on terminals with many small cells. With a fractional scale,
+/// some cells get one more column than others.
+pub struct Screen {
+    width: usize,
+    height: usize,
+
+    /// Terminal columns per cell, on average
+    scale_width: f64,
+
+    /// Terminal size, in cells
+    terminal: (usize, usize),
+
+    /// Top-left corner of the image on the terminal
+    origin: (usize, usize),
+
+    /// Where to keep the image instead of centering it, leaving the rest of
+    /// the terminal alone
+    fixed: Option<(usize, usize)>,
+
+    /// Cells for the frame being built
+    pub cells: Vec<Cell>,
+
+    /// Cells currently on the terminal, or `None` if a full redraw is needed
+    drawn: Option<Vec<Cell>>,
+
+    /// Number of cells written on the last draw
+    pub cells_emitted: usize,
+
+    /// How colors are sent to the terminal
+    color_mode: ColorMode,
+
+    /// Draw in shades of gray only
+    grayscale: bool,
+
+    /// Escape sequences for each cell color seen so far
+    color_codes: HashMap<Color, ColorCodes>,
+}
+
+/// Escape sequences that set a color
+struct ColorCodes {
+    fg: Vec<u8>,
+    bg: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new((width, height): (usize, usize)) -> Self {
+        Self {
+            width,
+            height,
+            scale_width: 1.0,
+            terminal: (width, height),
+            origin: (0, 0),
+            fixed: None,
+            cells: Vec::with_capacity(width * height),
+            drawn: None,
+            cells_emitted: 0,
+            color_mode: ColorMode::TrueColor,
+            grayscale: false,
+            color_codes: HashMap::new(),
+        }
+    }
+
+    /// Set how colors are sent to the terminal.
+    pub fn set_colors(&mut self, color_mode: ColorMode, grayscale: bool) {
+        self.color_mode = color_mode;
+        self.grayscale = grayscale;
+        self.color_codes.clear();
+        self.invalidate();
+    }
+
+    /// Forget what is on the terminal, forcing a full redraw on the next frame.
+    ///
+    /// This needs to be called whenever the terminal contents change behind our
+    /// back, e.g. after a resize or clear.
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    /// Update the terminal size, recentering the image.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        let (cols, rows) = (cols as usize, rows as usize);
+        self.terminal = (cols, rows);
+        self.origin = self.fixed
+            .unwrap_or((cols.saturating_sub(self.columns()) / 2, rows.saturating_sub(self.height) / 2));
+        self.invalidate();
+    }
+
+    /// Draw at `(col, row)` from now on, without ever clearing the terminal.
+    pub fn fix_origin(&mut self, (col, row): (u16, u16)) {
+        self.fixed = Some((col as usize, row as usize));
+        let (cols, rows) = self.terminal;
+        self.resize(cols as u16, rows as u16);
+    }
+
+    /// Repeat every cell across `scale` terminal columns.
+    pub fn set_scale_width(&mut self, scale: f64) {
+        self.scale_width = scale;
+        let (cols, rows) = self.terminal;
+        self.resize(cols as u16, rows as u16);
+    }
+
+    /// Column where cell `x` starts, from the left of the image.
+    fn column(&self, x: usize) -> usize {
+        (x as f64 * self.scale_width).round() as usize
+    }
+
+    /// Width of the image on the terminal, in columns.
+    fn columns(&self) -> usize {
+        self.column(self.width)
+    }
+
+    /// Whether the whole image fits on the terminal.
+    pub fn fits(&self) -> bool {
+        self.terminal.0 >= self.origin.0 + self.columns() && self.terminal.1 >= self.origin.1 + self.height
+    }
+
+    /// Append the escape sequences needed to bring the terminal up to date
+    /// with `self.cells` to `out`.
+    pub fn draw(&mut self, out: &mut impl io::Write) -> io::Result<()> {
+        assert_eq!(self.cells.len(), self.width * self.height);
+
+        self.cells_emitted = 0;
+
+        if self.drawn.is_none() {
+            // Get rid of anything left over from a previous size. A fixed
+            // image always covers the same cells, and the rest is not ours
+            queue!(out, style::ResetColor)?;
+            if self.fixed.is_none() {
+                queue!(out, terminal::Clear(terminal::ClearType::All))?;
+            }
+
+            if !self.fits() {
+                // A fixed image needs the space up to its corner as well
+                let (left, top) = self.fixed.unwrap_or((0, 0));
+                let message = format!("Terminal too small (need {}x{})", left + self.columns(), top + self.height);
+                let at = if left < self.terminal.0 && top < self.terminal.1 { (left, top) } else { (0, 0) };
+                queue!(out, cursor::MoveTo(at.0 as u16, at.1 as u16), style::Print(message))?;
+            }
+        }
+
+        if !self.fits() {
+            // Nothing to draw until the next resize
+            self.drawn = Some(self.cells.clone());
+            return Ok(());
+        }
+
+        let mut fg = None;
+        let mut bg = None;
+
+        // Position the cursor would be at after the last glyph we printed
+        let mut cursor = None;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let cell = self.cells[i];
+
+                if let Some(drawn) = &self.drawn {
+                    if drawn[i] == cell {
+                        continue;
+                    }
+                }
+
+                if cursor != Some((x, y)) {
+                    let (col, row) = (self.origin.0 + self.column(x), self.origin.1 + y);
+                    queue!(out, cursor::MoveTo(col as u16, row as u16))?;
+                }
+                if bg != Some(cell.bg) {
+                    out.write_all(&self.color_codes(cell.bg).bg)?;
+                    bg = Some(cell.bg);
+                }
+                if fg != Some(cell.fg) {
+                    out.write_all(&self.color_codes(cell.fg).fg)?;
+                    fg = Some(cell.fg);
+                }
+                for _ in self.column(x)..self.column(x + 1) {
+                    queue!(out, style::Print(cell.glyph))?;
+                }
+
+                cursor = Some((x + 1, y));
+                self.cells_emitted += 1;
+            }
+        }
+
+        match &mut self.drawn {
+            Some(drawn) => drawn.copy_from_slice(&self.cells),
+            None => self.drawn = Some(self.cells.clone()),
+        }
+        Ok(())
+    }
+
+    /// The escape sequences for a cell color.
+    ///
+    /// Converting and formatting colors adds up with thousands of cells per
+    /// frame, so this is only done the first time a color is seen. Sextants
+    /// and `--blend` mix pixels into any 24-bit color, so the cache is
+    /// cleared once it holds `COLOR_CODES_LIMIT` of them.
+    fn color_codes(&mut self, color: Color) -> &ColorCodes {
+        let (color_mode, grayscale) = (self.color_mode, self.grayscale);
+        if self.color_codes.len() >= COLOR_CODES_LIMIT && !self.color_codes.contains_key(&color) {
+            self.color_codes.clear();
+        }
+
+        self.color_codes.entry(color).or_insert_with(|| {
+            let color = color_mode.convert(if grayscale { color::grayscale(color) } else { color });
+            let (mut fg, mut bg) = (Vec::new(), Vec::new());
+            // Writing to a `Vec` cannot fail
+            let _ = queue!(fg, style::SetForegroundColor(color));
+            let _ = queue!(bg, style::SetBackgroundColor(color));
+            ColorCodes { fg, bg }
+        })
+    }
+}
+
+fn distance(a: GameboyRgb, b: GameboyRgb) -> u32 {
+    let dr = a.red as i32 - b.red as i32;
+    let dg = a.green as i32 - b.green as i32;
+    let db = a.blue as i32 - b.blue as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+fn average(pixels: impl Iterator<Item = GameboyRgb>) -> GameboyRgb {
+    let (mut r, mut g, mut b, mut n) = (0u32, 0u32, 0u32, 0u32);
+    for p in pixels {
+        r += p.red as u32;
+        g += p.green as u32;
+        b += p.blue as u32;
+        n += 1;
+    }
+
+    GameboyRgb {
+        red: (r / n) as u8,
+        green: (g / n) as u8,
+        blue: (b / n) as u8,
+    }
+}
+
+/// Picks the sextant glyph and (foreground, background) colors that best
+/// represent a 2x3 block of pixels, given in row-major order.
+///
+/// The two colors furthest apart seed a foreground and a background group. Each
+/// pixel joins the group whose seed is closest, and each group is then drawn
+/// with the average color of its pixels. Ties go to the background, so the
+/// top-left pixel always ends up in the background.
+pub fn sextant_cell(pixels: &[GameboyRgb; 6]) -> (char, GameboyRgb, GameboyRgb) {
+    // Find the two most distant colors in the block
+    let (mut bg_seed, mut fg_seed, mut max) = (pixels[0], pixels[0], 0);
+    for (i, &a) in pixels.iter().enumerate() {
+        for &b in &pixels[i + 1..] {
+            let d = distance(a, b);
+            if d > max {
+                max = d;
+                bg_seed = a;
+                fg_seed = b;
+            }
+        }
+    }
+
+    if max == 0 {
+        // Solid block
+        return (' ', pixels[0], pixels[0]);
+    }
+
+    // Partition the pixels, setting a bit for each pixel in the foreground group
+    let mut pattern = 0u8;
+    for (i, &p) in pixels.iter().enumerate() {
+        if distance(p, fg_seed) < distance(p, bg_seed) {
+            pattern |= 1 << i;
+        }
+    }
+
+    let fg = average(pixels.iter().enumerate().filter(|(i, _)| pattern & 1 << i != 0).map(|(_, p)| *p));
+    let bg = average(pixels.iter().enumerate().filter(|(i, _)| pattern & 1 << i == 0).map(|(_, p)| *p));
+
+    (sextant_glyph(pattern), fg, bg)
+}
+
+/// Returns the glyph that has the foreground drawn in the given subpixels.
+///
+/// Bit 0 is the top-left subpixel, bit 1 top-right, and so on in row-major order.
+/// The Unicode sextant block skips the four patterns that already exist as
+/// block elements (empty, full, left half and right half).
+pub fn sextant_glyph(pattern: u8) -> char {
+    const LEFT_HALF: u8 = 0b010101;
+    const RIGHT_HALF: u8 = 0b101010;
+
+    let offset = match pattern & 0x3F {
+        0 => return ' ',
+        0x3F => return '█',
+        LEFT_HALF => return '▌',
+        RIGHT_HALF => return '▐',
+        p if p < LEFT_HALF => p - 1,
+        p if p < RIGHT_HALF => p - 2,
+        p => p - 3,
+    };
+
+    std::char::from_u32(0x1FB00 + offset as u32).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BLACK: GameboyRgb = GameboyRgb { red: 0, green: 0, blue: 0 };
+    const WHITE: GameboyRgb = GameboyRgb { red: 0xFF, green: 0xFF, blue: 0xFF };
+    const GRAY: GameboyRgb = GameboyRgb { red: 0xF0, green: 0xF0, blue: 0xF0 };
+
+    fn checkerboard() -> FrameBuffer {
+        let mut frame_buffer = FrameBuffer::new();
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                let color = if (x + y) % 2 == 0 { BLACK } else { WHITE };
+                frame_buffer.write(x, y, color);
+            }
+        }
+        frame_buffer
+    }
+
+    #[test]
+    fn sextant_glyphs() {
+        assert_eq!(sextant_glyph(0b000001), '\u{1FB00}');
+        assert_eq!(sextant_glyph(0b010100), '\u{1FB13}');
+        assert_eq!(sextant_glyph(0b010110), '\u{1FB14}');
+        assert_eq!(sextant_glyph(0b101011), '\u{1FB28}');
+        assert_eq!(sextant_glyph(0b111110), '\u{1FB3B}');
+        assert_eq!(sextant_glyph(0b010101), '▌');
+        assert_eq!(sextant_glyph(0b101010), '▐');
+        assert_eq!(sextant_glyph(0b111111), '█');
+        assert_eq!(sextant_glyph(0), ' ');
+    }
+
+    #[test]
+    fn sextant_cells() {
+        // Solid color
+        let (glyph, _, bg) = sextant_cell(&[WHITE; 6]);
+        assert_eq!(glyph, ' ');
+        assert_eq!(bg, WHITE);
+
+        // Black right column on a white background
+        let pixels = [WHITE, BLACK, WHITE, BLACK, WHITE, BLACK];
+        assert_eq!(sextant_cell(&pixels), ('▐', BLACK, WHITE));
+
+        // Bottom row in black, with a near-white pixel that should join the white group
+        let pixels = [WHITE, GRAY, WHITE, WHITE, BLACK, BLACK];
+        let (glyph, fg, bg) = sextant_cell(&pixels);
+        assert_eq!(glyph, sextant_glyph(0b110000));
+        assert_eq!(fg, BLACK);
+        assert_eq!(bg, average([WHITE, GRAY, WHITE, WHITE].iter().cloned()));
+    }
+
+    #[test]
+    fn ascii_cells_follow_brightness() {
+        let mut frame_buffer = FrameBuffer::new();
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                frame_buffer.write(x, y, WHITE);
+            }
+        }
+        // Solid black in the first cell, and half of the second
+        for y in 0..4 {
+            frame_buffer.write(0, y, BLACK);
+            frame_buffer.write(1, y, BLACK);
+            frame_buffer.write(2, y, BLACK);
+        }
+
+        let mut cells = Vec::new();
+        ascii_cells(&frame_buffer, Crop::FULL, &mut cells);
+        assert_eq!(cells.len(), ASCII_SIZE.0 * ASCII_SIZE.1);
+        assert_eq!(cells[0].glyph, '@');
+        assert_eq!(cells[1].glyph, '+');
+        assert_eq!(cells[2].glyph, ' ');
+        assert!(cells.iter().all(|cell| cell.fg == Color::Reset && cell.bg == Color::Reset));
+    }
+
+    #[test]
+    fn frames_are_cropped() {
+        assert_eq!("0, 16, 160, 128".parse(), Ok(Crop { left: 0, top: 16, width: 160, height: 128 }));
+        assert!("0,16,160".parse::<Crop>().is_err());
+        assert!("0,0,0,144".parse::<Crop>().is_err());
+        assert!("1,0,160,144".parse::<Crop>().unwrap_err().contains("has to fit in the 160x144 frame"));
+
+        // Odd heights end in black, like the whole frame would
+        let crop = Crop { left: 3, top: 2, width: 4, height: 5 };
+        let mut cells = Vec::new();
+        half_block_cells(&checkerboard(), crop, &mut cells);
+        assert_eq!(cells.len(), 4 * 3);
+        assert_eq!((cells[0].bg, cells[0].fg), (to_color(WHITE), to_color(BLACK)));
+        assert_eq!(cells[8].fg, to_color(BLACK));
+
+        let mut renderer = Backend::Sixel.renderer(RenderOptions { crop, ..RenderOptions::default() });
+        renderer.resize(200, 100);
+        let mut out = Vec::new();
+        renderer.render(&checkerboard(), &mut out).unwrap();
+        // Scaled up to fill the terminal, keeping the crop's shape
+        let output = String::from_utf8(out).unwrap();
+        let size = output.split("\"1;1;").nth(1).unwrap().split('#').next().unwrap();
+        let (width, height) = size.split_once(';').unwrap();
+        assert_eq!(width.parse::<usize>().unwrap() * 5, height.parse::<usize>().unwrap() * 4);
+    }
+
+    #[test]
+    fn only_changed_cells_are_redrawn() {
+        let mut frame_buffer = checkerboard();
+        let mut screen = Screen::new(HALF_BLOCK_SIZE);
+        let mut out = Vec::new();
+
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        assert_eq!(screen.cells_emitted, HALF_BLOCK_SIZE.0 * HALF_BLOCK_SIZE.1);
+
+        // Nothing changed
+        out.clear();
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        assert_eq!(screen.cells_emitted, 0);
+        assert!(out.is_empty());
+
+        // Two pixels in the same cell, and one in another
+        frame_buffer.write(10, 20, GRAY);
+        frame_buffer.write(10, 21, GRAY);
+        frame_buffer.write(0, 0, GRAY);
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        assert_eq!(screen.cells_emitted, 2);
+
+        // Everything is redrawn after invalidation
+        screen.invalidate();
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        assert_eq!(screen.cells_emitted, HALF_BLOCK_SIZE.0 * HALF_BLOCK_SIZE.1);
+    }
+
+    #[test]
+    fn image_is_centered_or_replaced_when_too_small() {
+        let frame_buffer = checkerboard();
+        let mut screen = Screen::new(HALF_BLOCK_SIZE);
+        let mut out = Vec::new();
+
+        screen.resize(200, 100);
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out.clone()).unwrap();
+        assert!(output.contains("\x1b[15;21H"));
+
+        out.clear();
+        screen.resize(100, 100);
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(screen.cells_emitted, 0);
+        assert!(output.contains("Terminal too small (need 160x72)"));
+        assert!(!output.contains('▄'));
+
+        // Stays quiet until the next resize
+        out.clear();
+        screen.draw(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn cells_are_repeated_when_scaled() {
+        let frame_buffer = checkerboard();
+        let mut screen = Screen::new(HALF_BLOCK_SIZE);
+        let mut out = Vec::new();
+
+        // Too narrow for double width
+        screen.resize(200, 100);
+        screen.set_scale_width(2.0);
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        assert!(String::from_utf8(out.clone()).unwrap().contains("Terminal too small (need 320x72)"));
+
+        out.clear();
+        screen.resize(330, 100);
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out.clone()).unwrap();
+        assert!(output.contains("\x1b[15;6H"));
+        assert_eq!(output.matches('▄').count(), 2 * HALF_BLOCK_SIZE.0 * HALF_BLOCK_SIZE.1);
+
+        // Changed cells are redrawn at their scaled position
+        out.clear();
+        screen.cells[1].glyph = 'x';
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.starts_with("\x1b[15;8H") && output.ends_with("mxx"));
+    }
+
+    #[test]
+    fn aspect_is_corrected_for_cell_shape() {
+        // Half blocks are square on cells twice as tall as they are wide
+        assert_eq!(aspect_scale((1, 2), DEFAULT_CELL_ASPECT), 1.0);
+        assert_eq!(aspect_scale((1, 2), 2.5), 1.25);
+        // Sextant pixels are a third taller than wide, and too wide cells
+        // are left alone
+        assert!((aspect_scale((2, 3), DEFAULT_CELL_ASPECT) - 4.0 / 3.0).abs() < 1e-9);
+        assert_eq!(aspect_scale((1, 2), 1.5), 1.0);
+
+        let mut screen = Screen::new(SEXTANT_SIZE);
+        screen.set_scale_width(4.0 / 3.0);
+        screen.resize(200, 100);
+        super::sextant_cells(&checkerboard(), Crop::FULL, &mut screen.cells);
+        let mut out = Vec::new();
+        screen.draw(&mut out).unwrap();
+
+        // 3 cells across 4 columns
+        assert_eq!(screen.columns(), 107);
+        assert_eq!((screen.column(1), screen.column(2), screen.column(3)), (1, 3, 4));
+        let output = String::from_utf8(out).unwrap();
+        assert!(!output.contains("Terminal too small"));
+        assert_eq!(output.chars().filter(|c| !c.is_ascii()).count(), 107 * SEXTANT_SIZE.1);
+    }
+
+    #[test]
+    fn fixed_images_leave_the_rest_alone() {
+        let mut screen = Screen::new(HALF_BLOCK_SIZE);
+        screen.fix_origin((10, 5));
+        screen.resize(200, 100);
+        half_block_cells(&checkerboard(), Crop::FULL, &mut screen.cells);
+        let mut out = Vec::new();
+        screen.draw(&mut out).unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        assert!(!output.contains("\x1b[2J"));
+        assert!(output.contains("\x1b[6;11H") && !output.contains("\x1b[1;1H"));
+
+        // Counting the space before the corner
+        let mut out = Vec::new();
+        screen.resize(165, 100);
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("\x1b[6;11HTerminal too small (need 170x77)"));
+    }
+
+    #[test]
+    fn every_backend_draws_a_frame() {
+        let frame_buffer = checkerboard();
+
+        for backend in [Backend::HalfBlock, Backend::Sextant, Backend::Ascii, Backend::Sixel, Backend::Kitty] {
+            let mut renderer = backend.renderer(RenderOptions::default());
+            renderer.resize(200, 100);
+
+            let mut out = Vec::new();
+            renderer.render(&frame_buffer, &mut out).unwrap();
+            assert!(!out.is_empty(), "{:?} drew nothing", backend);
+        }
+    }
+
+    #[test]
+    fn colors_are_quantized_in_256_color_mode() {
+        let frame_buffer = checkerboard();
+        let mut screen = Screen::new(HALF_BLOCK_SIZE);
+        screen.set_colors(ColorMode::Ansi256, false);
+        let mut out = Vec::new();
+
+        half_block_cells(&frame_buffer, Crop::FULL, &mut screen.cells);
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out.clone()).unwrap();
+        assert!(output.contains("\x1b[48;5;16m") && output.contains("\x1b[38;5;231m"));
+        assert!(!output.contains("\x1b[48;2;"));
+
+        // Switching modes redraws with the new colors
+        out.clear();
+        screen.set_colors(ColorMode::TrueColor, true);
+        screen.draw(&mut out).unwrap();
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("\x1b[48;2;0;0;0m") && output.contains("\x1b[38;2;255;255;255m"));
+        assert!(!output.contains("\x1b[48;5;"));
+    }
+
+    #[test]
+    fn color_codes_are_not_kept_forever() {
+        let mut screen = Screen::new(HALF_BLOCK_SIZE);
+        for i in 0..COLOR_CODES_LIMIT as u32 + 10 {
+            let [_, r, g, b] = i.to_be_bytes();
+            assert!(!screen.color_codes(Color::Rgb { r, g, b }).fg.is_empty());
+        }
+        assert_eq!(screen.color_codes.len(), 10);
+    }
+}
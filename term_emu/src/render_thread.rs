@@ -0,0 +1,467 @@
+//! Rendering on a dedicated thread
+//!
+//! Writing a frame to the terminal can take longer than emulating it,
+//! especially over slow connections. The emulator hands finished frames to a
+//! render thread through a single slot instead, so it never waits on the
+//! terminal: if the render thread falls behind, frames it did not get to are
+//! replaced by newer ones and never drawn.
+//!
+//! The Escape menu is drawn by the render thread too, on top of the paused
+//! game, so that it is never torn by a frame drawn at the same time. So is the
+//! window title, which would otherwise land in the middle of a frame, and
+//! mouse capture, which is only on while the menu is open. The log pane is
+//! drawn under the game, which shrinks to make room for it.
+//!
+//! With synchronized output, each frame is bracketed so that the terminal shows
+//! it all at once, without tearing. When the image is drawn in place with
+//! `--at`, each frame hides the cursor and puts it back where it was after.
+//!
+//! With `--profile`, the thread times every frame it draws, and hands the
+//! timings back when it stops.
+//!
+//! If the terminal goes away, e.g. when the output was piped into a program
+//! that exited, the thread stops and the main loop quits.
+use std::io::{self, ErrorKind, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossterm::{cursor, event, queue, terminal};
+use gbc::ppu::{FrameBuffer, LCD_HEIGHT, LCD_WIDTH};
+
+use crate::log_pane;
+use crate::menu::Menu;
+use crate::overlay::Overlay;
+use crate::profile;
+use crate::render::Renderer;
+use crate::tty;
+
+/// Work waiting for the render thread
+#[derive(Default)]
+struct Pending {
+    frame: Option<FrameBuffer>,
+    resize: Option<(u16, u16)>,
+    toggle_overlay: bool,
+    /// `Some(None)` to hide the menu
+    menu: Option<Option<Menu>>,
+    /// `Some(None)` to hide the log pane, or how far back to scroll it
+    log_pane: Option<Option<usize>>,
+    title: Option<String>,
+    redraw: bool,
+    invalidate: bool,
+    quit: bool,
+}
+
+impl Pending {
+    fn is_empty(&self) -> bool {
+        self.frame.is_none() && self.resize.is_none() && !self.toggle_overlay && self.menu.is_none() && self.log_pane.is_none()
+            && self.title.is_none() && !self.redraw && !self.invalidate && !self.quit
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+pub struct RenderThread {
+    shared: Arc<Shared>,
+    thread: JoinHandle<Vec<Duration>>,
+
+    /// Spare buffer, swapped with the one in the slot to avoid allocations
+    spare: Option<FrameBuffer>,
+}
+
+impl RenderThread {
+    /// Start rendering to `out`, using synchronized output if `sync` is set,
+    /// and timing each frame if `profile` is set.
+    ///
+    /// The overlay is shared with the caller, which records emulated frames
+    /// on it.
+    pub fn spawn<W: Write + Send + 'static>(out: W, renderer: Box<dyn Renderer>, overlay: Arc<Mutex<Overlay>>,
+                                            sync: bool, keep_cursor: bool, profile: bool) -> Self {
+        let shared = Arc::new(Shared::default());
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || run(out, renderer, overlay, sync, keep_cursor, profile, &shared))
+        };
+
+        Self {
+            shared,
+            thread,
+            spare: None,
+        }
+    }
+
+    /// Queue a frame for rendering, replacing any frame that has not been
+    /// rendered yet. Never waits for the terminal.
+    pub fn submit(&mut self, frame_buffer: &FrameBuffer) {
+        let mut frame = self.spare.take().unwrap_or_else(FrameBuffer::new);
+        frame.data.copy_from_slice(&frame_buffer.data);
+
+        self.spare = self.update(|pending| pending.frame.replace(frame));
+    }
+
+    /// Handle a terminal resize.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.update(|pending| pending.resize = Some((cols, rows)));
+    }
+
+    pub fn toggle_overlay(&mut self) {
+        self.update(|pending| pending.toggle_overlay = !pending.toggle_overlay);
+    }
+
+    /// Show the menu on top of the game, or hide it with `None`.
+    pub fn show_menu(&mut self, menu: Option<Menu>) {
+        self.update(|pending| pending.menu = Some(menu));
+    }
+
+    /// Show the log pane under the game, scrolled back some lines, or hide it
+    /// with `None`.
+    pub fn show_log(&mut self, scroll: Option<usize>) {
+        self.update(|pending| pending.log_pane = Some(scroll));
+    }
+
+    /// Set the terminal's window title.
+    pub fn set_title(&mut self, title: String) {
+        self.update(|pending| pending.title = Some(title));
+    }
+
+    /// Draw the current frame again, e.g. to show a message on the overlay.
+    pub fn redraw(&mut self) {
+        self.update(|pending| pending.redraw = true);
+    }
+
+    /// Redraw every cell on the next frame, after something else drew over
+    /// the screen.
+    pub fn invalidate(&mut self) {
+        self.update(|pending| pending.invalidate = true);
+    }
+
+    /// Whether frames are still being drawn. This turns `false` if writing to
+    /// the terminal failed.
+    pub fn is_running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    /// Stop after drawing anything still pending, and wait for the thread to
+    /// exit so that nothing is written to the terminal afterwards.
+    ///
+    /// Returns how long each frame took to draw, if profiling.
+    pub fn stop(self) -> Vec<Duration> {
+        self.update(|pending| pending.quit = true);
+        self.thread.join().unwrap_or_default()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut Pending) -> T) -> T {
+        let result = f(&mut self.shared.pending.lock().unwrap());
+        self.shared.ready.notify_one();
+        result
+    }
+}
+
+fn run<W: Write>(mut out: W, mut renderer: Box<dyn Renderer>, overlay: Arc<Mutex<Overlay>>, sync: bool,
+                 keep_cursor: bool, profile: bool, shared: &Shared) -> Vec<Duration> {
+    let mut render_times = Vec::new();
+    let mut frame_buffer = FrameBuffer::new();
+    let mut size = (0, 0);
+    let mut menu = None;
+    // The whole terminal, of which the game gets `size` with the log pane open
+    let mut terminal: (u16, u16) = (0, 0);
+    let mut log_pane: Option<usize> = None;
+    // Lines kept when the pane was last drawn
+    let mut log_drawn = None;
+    let mut frame = Vec::with_capacity(LCD_HEIGHT * LCD_WIDTH * 16);
+
+    loop {
+        let pending = {
+            let pending = shared.pending.lock().unwrap();
+            let mut pending = shared.ready.wait_while(pending, |pending| pending.is_empty()).unwrap();
+            std::mem::take(&mut *pending)
+        };
+
+        // Written out along with the frame
+        let mut written = Ok(());
+        if let Some(scroll) = pending.log_pane {
+            if scroll.is_none() && log_pane.is_some() {
+                // Take the pane off, even where the game is not cleared
+                let top = terminal.1.saturating_sub(log_pane::ROWS);
+                written = queue!(out, cursor::MoveTo(0, top), terminal::Clear(terminal::ClearType::FromCursorDown));
+            }
+            log_pane = scroll;
+            log_drawn = None;
+        }
+        // The game is resized too when the pane opens or closes
+        if let Some(new) = pending.resize.or(pending.log_pane.map(|_| terminal)) {
+            terminal = new;
+            let (cols, rows) = terminal;
+            size = (cols, if log_pane.is_some() { rows.saturating_sub(log_pane::ROWS) } else { rows });
+            renderer.resize(size.0, size.1);
+            log_drawn = None;
+        }
+
+        if pending.toggle_overlay {
+            let mut overlay = overlay.lock().unwrap();
+            overlay.toggle();
+            if !overlay.visible {
+                // Redraw the game cells that were covered
+                renderer.invalidate();
+                log_drawn = None;
+            }
+        }
+
+        if let Some(new) = pending.menu {
+            if new.is_none() && menu.is_some() {
+                // Redraw the game cells that were covered
+                renderer.invalidate();
+                log_drawn = None;
+                written = written.and_then(|_| queue!(out, event::DisableMouseCapture));
+            } else if new.is_some() && menu.is_none() {
+                written = written.and_then(|_| queue!(out, event::EnableMouseCapture));
+            }
+            menu = new;
+        }
+
+        if pending.invalidate || overlay.lock().unwrap().expire_message(Instant::now()) {
+            renderer.invalidate();
+            log_drawn = None;
+        }
+
+        if let Some(new) = pending.frame {
+            frame_buffer = new;
+        }
+
+        if let Some(title) = pending.title {
+            written = written.and_then(|_| queue!(out, terminal::SetTitle(title)));
+        }
+
+        // Anything pending changes what is on screen, so always redraw
+        let render_start = Instant::now();
+        let menu_shown = menu.as_ref().map(|menu| (menu, size));
+        // Only drawn again when there is something new in it, or the game
+        // was drawn over it
+        let kept = log_pane::kept();
+        let log_shown = log_pane.filter(|_| log_drawn != Some(kept)).map(|scroll| (terminal, scroll));
+        log_drawn = log_pane.map(|_| kept);
+        if let Err(e) = written.and_then(|_| render_frame(&frame_buffer, renderer.as_mut(), &overlay, menu_shown, log_shown,
+                                                          &mut frame, &mut out, sync, keep_cursor)) {
+            if e.kind() == ErrorKind::BrokenPipe {
+                log::info!("Output closed, stopping");
+            } else {
+                log::error!("Failed to draw to the terminal: {}", e);
+            }
+            return render_times;
+        }
+        let now = Instant::now();
+        overlay.lock().unwrap().record_render(now, now - render_start);
+        if profile {
+            log::debug!(target: profile::TARGET, "Render: {:?}", now - render_start);
+            render_times.push(now - render_start);
+        }
+
+        if pending.quit {
+            return render_times;
+        }
+    }
+}
+
+/// Renders a single Gameboy frame to the console
+#[allow(clippy::too_many_arguments)]
+fn render_frame<W: Write>(frame_buffer: &FrameBuffer, renderer: &mut dyn Renderer, overlay: &Mutex<Overlay>,
+                          menu: Option<(&Menu, (u16, u16))>, log_pane: Option<((u16, u16), usize)>, frame: &mut Vec<u8>,
+                          out: &mut W, sync: bool, keep_cursor: bool) -> io::Result<()> {
+    if sync {
+        frame.extend_from_slice(tty::BEGIN_SYNCHRONIZED_OUTPUT);
+    }
+    if keep_cursor {
+        queue!(frame, cursor::SavePosition, cursor::Hide)?;
+    }
+    let start = frame.len();
+
+    renderer.render(frame_buffer, frame)?;
+    // Draw stats on top of the game, if enabled
+    overlay.lock().unwrap().draw(frame)?;
+    // The log under it
+    if let Some((terminal, scroll)) = log_pane {
+        log_pane::draw(frame, terminal, scroll)?;
+    }
+    // And the menu on top of everything
+    if let Some((menu, size)) = menu {
+        menu.draw(frame, size)?;
+    }
+
+    if frame.len() == start {
+        // Nothing changed
+        frame.clear();
+    } else {
+        if keep_cursor {
+            queue!(frame, cursor::RestorePosition, cursor::Show)?;
+        }
+        if sync {
+            frame.extend_from_slice(tty::END_SYNCHRONIZED_OUTPUT);
+        }
+    }
+    // Write the frame out, without holding up the emulator
+    let written = out.write_all(frame).and_then(|_| out.flush());
+    // empty the frame buffer
+    frame.clear();
+    written
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use gbc::ppu::GameboyRgb;
+
+    use crate::render::{Backend, RenderOptions};
+
+    use super::*;
+
+    /// A terminal that takes a long time to write each frame
+    struct SlowWriter {
+        writes: Arc<Mutex<usize>>,
+    }
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(50));
+            *self.writes.lock().unwrap() += 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn slow_output_never_blocks_submitting_frames() {
+        let writes = Arc::new(Mutex::new(0));
+        let out = SlowWriter { writes: writes.clone() };
+
+        let mut half_block = Backend::HalfBlock.renderer(RenderOptions::default());
+        half_block.resize(160, 72);
+        let mut renderer = RenderThread::spawn(out, half_block, Arc::new(Mutex::new(Overlay::new())), false, false, true);
+
+        let mut frame_buffer = FrameBuffer::new();
+        let start = Instant::now();
+        for i in 0..100 {
+            frame_buffer.write(0, 0, GameboyRgb { red: i, green: 0, blue: 0 });
+            renderer.submit(&frame_buffer);
+        }
+        // Waiting on each write would take 5 seconds
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Stale frames were dropped, but the last frame is always drawn
+        let render_times = renderer.stop();
+        let writes = *writes.lock().unwrap();
+        assert!((1..100).contains(&writes));
+
+        // Every frame written out was timed, slow as it was
+        let slow = render_times.iter().filter(|&&time| time >= Duration::from_millis(50)).count();
+        assert_eq!(slow, writes);
+    }
+
+    /// A terminal that has gone away
+    struct ClosedWriter;
+
+    impl Write for ClosedWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn closed_output_stops_rendering() {
+        let mut half_block = Backend::HalfBlock.renderer(RenderOptions::default());
+        half_block.resize(160, 72);
+        let mut renderer = RenderThread::spawn(ClosedWriter, half_block, Arc::new(Mutex::new(Overlay::new())), false, false, false);
+        assert!(renderer.is_running());
+
+        renderer.submit(&FrameBuffer::new());
+        let start = Instant::now();
+        while renderer.is_running() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!renderer.is_running());
+
+        // Still safe to submit to and stop
+        renderer.submit(&FrameBuffer::new());
+        renderer.stop();
+    }
+
+    /// A terminal that keeps everything written to it
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn synchronized_frames_are_bracketed() {
+        let out = SharedWriter::default();
+        let mut half_block = Backend::HalfBlock.renderer(RenderOptions::default());
+        half_block.resize(160, 72);
+        let mut renderer = RenderThread::spawn(out.clone(), half_block, Arc::new(Mutex::new(Overlay::new())), true, false, false);
+
+        renderer.submit(&FrameBuffer::new());
+        renderer.stop();
+
+        let written = out.0.lock().unwrap();
+        assert!(written.starts_with(tty::BEGIN_SYNCHRONIZED_OUTPUT));
+        assert!(written.ends_with(tty::END_SYNCHRONIZED_OUTPUT));
+    }
+
+    #[test]
+    fn menu_is_drawn_over_the_game() {
+        let out = SharedWriter::default();
+        let half_block = Backend::HalfBlock.renderer(RenderOptions::default());
+        let mut renderer = RenderThread::spawn(out.clone(), half_block, Arc::new(Mutex::new(Overlay::new())), false, false, false);
+        renderer.resize(160, 72);
+
+        renderer.submit(&FrameBuffer::new());
+        renderer.show_menu(Some(Menu::default()));
+        renderer.stop();
+
+        let written = String::from_utf8_lossy(&out.0.lock().unwrap()).into_owned();
+        assert!(written.contains("Paused"));
+        assert!(written.contains("> Resume"));
+        // The mouse picks menu items
+        assert!(written.contains("\x1b[?1000h"));
+    }
+
+    #[test]
+    fn title_is_set_before_the_frame() {
+        let out = SharedWriter::default();
+        let half_block = Backend::HalfBlock.renderer(RenderOptions::default());
+        let mut renderer = RenderThread::spawn(out.clone(), half_block, Arc::new(Mutex::new(Overlay::new())), true, false, false);
+        // As at startup, whenever the thread gets to each of them
+        renderer.set_title("TETRIS - 60 FPS".to_string());
+        renderer.resize(160, 72);
+        renderer.submit(&FrameBuffer::new());
+        renderer.stop();
+
+        let written = out.0.lock().unwrap();
+        let find = |needle: &[u8]| written.windows(needle.len()).position(|window| window == needle);
+        let title = b"\x1b]0;TETRIS - 60 FPS\x07";
+        let at = find(title).unwrap();
+        assert!(at < find(tty::BEGIN_SYNCHRONIZED_OUTPUT).unwrap());
+        // Once only, not with every frame
+        assert_eq!(written.windows(title.len()).filter(|window| window == title).count(), 1);
+    }
+}
@@ -0,0 +1,216 @@
+//! Rewinding gameplay
+//!
+//! Save states are taken every few frames and kept in a ring buffer, dropping
+//! the oldest ones once it is full. Rewinding restores them newest first.
+//!
+//! Consecutive states differ in only a small part of memory, so only the
+//! newest one is kept in full. Each older one is stored as its XOR with the
+//! next newer state, which is mostly zeros, with the runs of zeros left out.
+use std::collections::VecDeque;
+
+use gbc::Gameboy;
+
+enum Snapshot {
+    /// XOR with the next newer state, run-length encoded
+    Delta(Vec<u8>),
+
+    /// Could not be delta encoded, as the state size changed
+    Full(Vec<u8>),
+}
+
+pub struct Rewind {
+    /// The newest state
+    latest: Option<Vec<u8>>,
+
+    /// Older states, oldest first
+    older: VecDeque<Snapshot>,
+
+    /// Maximum number of states to keep
+    capacity: usize,
+
+    /// A state is taken every `interval` calls to `tick`
+    interval: u32,
+    ticks: u32,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        Self {
+            latest: None,
+            older: VecDeque::with_capacity(capacity),
+            capacity,
+            interval,
+            ticks: 0,
+        }
+    }
+
+    /// Call after running the Gameboy for a while, to take a state when one is
+    /// due.
+    pub fn tick(&mut self, gameboy: &Gameboy) {
+        self.ticks += 1;
+        if self.ticks < self.interval {
+            return;
+        }
+        self.ticks = 0;
+
+        match gameboy.save_state() {
+            Ok(state) => self.push(state),
+            Err(e) => log::warn!("Failed to take a rewind state: {}", e),
+        }
+    }
+
+    fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.replace(state) {
+            let latest = self.latest.as_ref().unwrap();
+            let snapshot = if previous.len() == latest.len() {
+                Snapshot::Delta(encode(&previous, latest))
+            } else {
+                Snapshot::Full(previous)
+            };
+
+            if self.older.len() + 1 >= self.capacity {
+                self.older.pop_front();
+            }
+            self.older.push_back(snapshot);
+        }
+    }
+
+    /// Go back to the newest state, and drop it so that the next call goes
+    /// further back. Once only the oldest state is left, it is restored every
+    /// time.
+    ///
+    /// Returns `false` if there was nothing to go back to.
+    pub fn rewind(&mut self, gameboy: &mut Gameboy) -> bool {
+        let latest = match &self.latest {
+            Some(latest) => latest,
+            None => return false,
+        };
+
+        if let Err(e) = gameboy.load_state(latest) {
+            log::warn!("Failed to rewind: {}", e);
+            return false;
+        }
+
+        if let Some(snapshot) = self.older.pop_back() {
+            let previous = match snapshot {
+                Snapshot::Delta(delta) => decode(&delta, latest),
+                Snapshot::Full(state) => state,
+            };
+            self.latest = Some(previous);
+        }
+
+        // Whatever the game did after the restored state never happened
+        self.ticks = 0;
+        true
+    }
+}
+
+/// Encode `state` as the XOR with `base`, which must be the same size.
+///
+/// The result is a sequence of runs, each made of the number of zero bytes,
+/// the number of literal bytes, both as LEB128 varints, and the literals.
+fn encode(state: &[u8], base: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut xor = state.iter().zip(base).map(|(a, b)| a ^ b).peekable();
+
+    while xor.peek().is_some() {
+        let mut zeros = 0;
+        while xor.next_if_eq(&0).is_some() {
+            zeros += 1;
+        }
+
+        let literals: Vec<u8> = std::iter::from_fn(|| xor.next_if(|&b| b != 0)).collect();
+        // Trailing zeros change nothing
+        if literals.is_empty() {
+            break;
+        }
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals.len());
+        out.extend_from_slice(&literals);
+    }
+
+    out
+}
+
+/// Undo `encode`, given the same `base`.
+fn decode(delta: &[u8], base: &[u8]) -> Vec<u8> {
+    let mut state = base.to_vec();
+    let mut pos = 0;
+    let mut input = delta;
+
+    while !input.is_empty() {
+        pos += read_varint(&mut input);
+        let literals = read_varint(&mut input);
+        for (b, x) in state[pos..pos + literals].iter_mut().zip(&input[..literals]) {
+            *b ^= x;
+        }
+        pos += literals;
+        input = &input[literals..];
+    }
+
+    state
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[0];
+        *input = &input[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    #[test]
+    fn delta_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut state = base.clone();
+        state[0] ^= 1;
+        state[500..700].fill(0xAA);
+        state[999] = 0;
+
+        let delta = encode(&state, &base);
+        assert!(delta.len() < 250);
+        assert_eq!(decode(&delta, &base), state);
+        assert!(encode(&base, &base).is_empty());
+    }
+
+    #[test]
+    fn rewind_restores_older_states() {
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(vec![0u8; 32 * 1024], false), false).unwrap();
+        let mut rewind = Rewind::new(3, 2);
+        assert!(!rewind.rewind(&mut gameboy));
+
+        // A state every other frame, of which the last 3 are kept
+        let mut pcs = Vec::new();
+        for _ in 0..10 {
+            gameboy.frame(None);
+            rewind.tick(&gameboy);
+            pcs.push(gameboy.registers().PC);
+        }
+
+        for frame in [9, 7, 5, 5] {
+            assert!(rewind.rewind(&mut gameboy));
+            assert_eq!(gameboy.registers().PC, pcs[frame]);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Reading ROM files, including compressed ones
+//!
+//! ROMs are often distributed as `.zip` or `.gz` files. Archives are detected
+//! by their magic bytes rather than the extension, and unpacked in memory.
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::read::GzDecoder;
+use gbc::cartridge::Cartridge;
+use zip::ZipArchive;
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// The smallest ROM there is: two banks of 16K
+const MIN_SIZE: usize = 0x8000;
+
+/// Extensions of ROM files inside zip archives
+const ROM_EXTENSIONS: &[&str] = &["gb", "gbc", "cgb"];
+
+/// Read a ROM file, decompressing it if needed.
+///
+/// For zip archives, `entry` names the file to load. It can be left out if the
+/// archive only has one ROM in it.
+pub fn read(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let data = std::fs::read(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!("ROM not found: {}", path.display()),
+        _ => format!("Failed to open {}: {}", path.display(), e),
+    })?;
+    unpack(data, entry).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Decompress ROM data, or return it as is if it is not compressed.
+pub fn unpack(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    if data.starts_with(ZIP_MAGIC) {
+        return unzip(data, entry);
+    }
+
+    if entry.is_some() {
+        return Err("--entry is only supported for zip archives".to_string());
+    }
+
+    if data.starts_with(GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut rom).map_err(|e| format!("Invalid gzip file: {}", e))?;
+        return Ok(rom);
+    }
+
+    Ok(data)
+}
+
+/// The hardware to run a game on, for `run --mode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Hardware {
+    /// What the cartridge header asks for
+    #[default]
+    Auto,
+    /// The original Game Boy, e.g. for color-enhanced games that also run on it
+    Dmg,
+    /// The Game Boy Color
+    Cgb,
+}
+
+impl Hardware {
+    /// Set up `cartridge` to run on this hardware, if the game can.
+    pub fn apply(self, cartridge: Cartridge) -> Result<Cartridge, String> {
+        match self {
+            Self::Auto => Ok(cartridge),
+            Self::Dmg if cartridge.cgb_only() => Err("the game only runs on a Game Boy Color".to_string()),
+            Self::Dmg => Ok(cartridge.with_cgb(false)),
+            // The Color runs these in a compatibility mode of its own, which
+            // is not emulated. They would come out blank.
+            Self::Cgb if !cartridge.cgb() => Err("the game has no Game Boy Color support. Use --palette to color it \
+                                                  instead.".to_string()),
+            Self::Cgb => Ok(cartridge.with_cgb(true)),
+        }
+    }
+}
+
+impl fmt::Display for Hardware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Dmg => "dmg",
+            Self::Cgb => "cgb",
+        })
+    }
+}
+
+impl FromStr for Hardware {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "dmg" => Ok(Self::Dmg),
+            "cgb" => Ok(Self::Cgb),
+            _ => Err(format!("Unknown mode: {} (expected auto, dmg or cgb)", s)),
+        }
+    }
+}
+
+/// Check that `data` looks like a Game Boy ROM: that it is big enough for a
+/// header, and that the header has the Nintendo logo and a matching checksum.
+///
+/// Homebrew and test ROMs sometimes get these wrong, so this is only a guard
+/// against being handed the wrong file.
+pub fn validate(data: &[u8]) -> Result<(), String> {
+    let reason = if data.len() < MIN_SIZE {
+        format!("it is only {} bytes", data.len())
+    } else {
+        let cartridge = Cartridge::from_bytes(data[..MIN_SIZE].to_vec(), false);
+        if !cartridge.verify_logo() {
+            "its header has no Nintendo logo".to_string()
+        } else if !cartridge.verify_header_checksum() {
+            "its header checksum does not match".to_string()
+        } else {
+            return Ok(());
+        }
+    };
+
+    Err(format!("This doesn't look like a Game Boy ROM, as {}. Use --force to run it anyway.", reason))
+}
+
+/// The title from the cartridge header, falling back to the file name.
+pub fn title(cartridge: Option<&Cartridge>, path: &Path) -> String {
+    let title = cartridge
+        .map(|cartridge| cartridge.title().unwrap_or_default().trim_end_matches('\0').trim().to_string())
+        .unwrap_or_default();
+
+    if title.is_empty() {
+        path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+    } else {
+        title
+    }
+}
+
+fn is_rom(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ROM_EXTENSIONS.iter().any(|rom| rom.eq_ignore_ascii_case(ext)))
+}
+
+fn unzip(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|e| format!("Invalid zip file: {}", e))?;
+    // In archive order, so that listings are stable
+    let names = (0..archive.len())
+        .map(|i| archive.by_index_raw(i).map(|file| file.name().to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid zip file: {}", e))?;
+
+    let name = match entry {
+        Some(entry) => names.iter()
+            .find(|name| *name == entry || Path::new(name).file_name().is_some_and(|file| file == entry))
+            .ok_or_else(|| format!("No {} in archive (has: {})", entry, names.join(", ")))?,
+        None => {
+            let roms: Vec<&String> = names.iter().filter(|name| is_rom(name)).collect();
+            match roms[..] {
+                [rom] => rom,
+                [] => return Err(format!("No .gb or .gbc file in archive (has: {})", names.join(", "))),
+                _ => {
+                    let roms: Vec<&str> = roms.iter().map(|rom| rom.as_str()).collect();
+                    return Err(format!("Archive has several ROMs, pick one with --entry: {}", roms.join(", ")));
+                }
+            }
+        }
+    };
+
+    let mut file = archive.by_name(name).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    let mut rom = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut rom).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use zip::write::{FileOptions, ZipWriter};
+
+    use super::*;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn unpack_archives() {
+        let rom = vec![0xC3; 1024];
+
+        // Plain ROMs are passed through
+        assert_eq!(unpack(rom.clone(), None), Ok(rom.clone()));
+
+        let mut gz = GzEncoder::new(Vec::new(), Default::default());
+        gz.write_all(&rom).unwrap();
+        assert_eq!(unpack(gz.finish().unwrap(), None), Ok(rom.clone()));
+
+        // The only ROM in the archive is picked
+        let single = zip(&[("readme.txt", b"hi"), ("Game/Game.GBC", &rom)]);
+        assert_eq!(unpack(single.clone(), None), Ok(rom.clone()));
+        assert_eq!(unpack(single.clone(), Some("readme.txt")), Ok(b"hi".to_vec()));
+        assert!(unpack(single, Some("other.gb")).is_err());
+
+        // Several ROMs need an entry
+        let several = zip(&[("a.gb", &rom), ("b.gb", &[0x00])]);
+        assert!(unpack(several.clone(), None).unwrap_err().contains("a.gb, b.gb"));
+        assert_eq!(unpack(several.clone(), Some("b.gb")), Ok(vec![0x00]));
+
+        assert!(unpack(zip(&[("readme.txt", b"hi")]), None).is_err());
+        assert!(unpack(rom, Some("a.gb")).is_err());
+    }
+
+    #[test]
+    fn hardware_modes() {
+        let cartridge = |cgb_flag: u8| {
+            let mut data = vec![0; 0x8000];
+            data[0x143] = cgb_flag;
+            Cartridge::from_bytes(data, false)
+        };
+
+        assert!(!Hardware::Dmg.apply(cartridge(0x80)).unwrap().cgb());
+        assert!(Hardware::Cgb.apply(cartridge(0x80)).unwrap().cgb());
+        assert!(Hardware::Auto.apply(cartridge(0x00)).is_ok_and(|cartridge| !cartridge.cgb()));
+
+        assert!(Hardware::Dmg.apply(cartridge(0xC0)).is_err());
+        assert!(Hardware::Cgb.apply(cartridge(0x00)).is_err());
+        assert_eq!("CGB".parse(), Ok(Hardware::Cgb));
+    }
+
+    #[test]
+    fn missing_roms_are_reported() {
+        assert_eq!(read(Path::new("/nonexistent/game.gb"), None), Err("ROM not found: /nonexistent/game.gb".to_string()));
+    }
+
+    #[test]
+    fn non_roms_are_caught() {
+        let tetris = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/tetris_world.gb")).unwrap();
+        assert_eq!(validate(&tetris), Ok(()));
+
+        let error = |data: &[u8]| validate(data).unwrap_err();
+        assert!(error(b"%PDF-1.4").contains("as it is only 8 bytes"));
+        assert!(error(&[0; MIN_SIZE]).contains("as its header has no Nintendo logo"));
+
+        let mut corrupt = tetris;
+        corrupt[0x134] ^= 1;
+        assert!(error(&corrupt).contains("as its header checksum does not match"));
+    }
+}
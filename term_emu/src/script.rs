@@ -0,0 +1,199 @@
+//! Scripted runs, for `run --script`
+//!
+//! Without a terminal, the game is driven by commands read from stdin, one per
+//! line, e.g. from a test or a bot. Frames only run when a command asks for
+//! them, and every command is answered with a status line on stdout: `ok` and
+//! the number of frames run so far, or `error` and what went wrong.
+//!
+//! ```text
+//! press start 5       # hold Start for 5 frames, then let go
+//! press a+right 10    # several inputs at once
+//! hold b              # until released
+//! release b
+//! wait 30             # run 30 frames
+//! screenshot foo.png
+//! savestate s1.state
+//! loadstate s1.state
+//! quit                # or the end of the input
+//! ```
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use gbc::Gameboy;
+use gbc::joypad::{JoypadEvent, JoypadInput};
+use gbc::ppu::FrameBuffer;
+
+use crate::capture;
+use crate::input::parse_joypad_input;
+use crate::palette::Screen;
+use crate::persist;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Hold inputs for a number of frames, then release them
+    Press(Vec<JoypadInput>, u32),
+    Hold(Vec<JoypadInput>),
+    Release(Vec<JoypadInput>),
+    Wait(u32),
+    Screenshot(PathBuf),
+    SaveState(PathBuf),
+    LoadState(PathBuf),
+    Quit,
+}
+
+impl Command {
+    /// Parse a line of a script. Blank lines and `#` comments are `None`.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let inputs = |arg: &str| arg.split('+').map(parse_joypad_input).collect::<Result<Vec<_>, _>>();
+        let frames = |arg: &str| arg.parse::<u32>().map_err(|_| format!("Invalid number of frames: {}", arg));
+
+        let command = match words.as_slice() {
+            [] => return Ok(None),
+            ["press", input] => Self::Press(inputs(input)?, 1),
+            ["press", input, count] => Self::Press(inputs(input)?, frames(count)?),
+            ["hold", input] => Self::Hold(inputs(input)?),
+            ["release", input] => Self::Release(inputs(input)?),
+            ["wait", count] => Self::Wait(frames(count)?),
+            ["screenshot", path] => Self::Screenshot(PathBuf::from(path)),
+            ["savestate", path] => Self::SaveState(PathBuf::from(path)),
+            ["loadstate", path] => Self::LoadState(PathBuf::from(path)),
+            ["quit"] => Self::Quit,
+            [command @ ("press" | "hold" | "release" | "wait" | "screenshot" | "savestate" | "loadstate" | "quit"), ..] =>
+                return Err(format!("Wrong arguments for {}", command)),
+            [command, ..] => return Err(format!("Unknown command: {}", command)),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+/// What a script drives: the game, and how its frames look
+struct Runner<'a> {
+    gameboy: &'a mut Gameboy,
+    /// Frames run so far
+    frames: u64,
+    /// The last frame, after applying the palette, for screenshots
+    current: FrameBuffer,
+    screen: Screen,
+}
+
+impl Runner<'_> {
+    fn run_frames(&mut self, count: u32) {
+        for _ in 0..count {
+            self.gameboy.frame(None);
+            self.screen.show(self.gameboy, &mut self.current);
+            self.frames += 1;
+        }
+    }
+
+    fn joypad(&mut self, inputs: &[JoypadInput], down: bool) {
+        let events: Vec<_> = inputs.iter()
+            .map(|&input| if down { JoypadEvent::Down(input) } else { JoypadEvent::Up(input) })
+            .collect();
+        self.gameboy.update_joypad(Some(&events));
+    }
+
+    /// Returns `false` once the script is done.
+    fn execute(&mut self, command: Command) -> Result<bool, String> {
+        match command {
+            Command::Press(inputs, count) => {
+                self.joypad(&inputs, true);
+                self.run_frames(count);
+                self.joypad(&inputs, false);
+            }
+            Command::Hold(inputs) => self.joypad(&inputs, true),
+            Command::Release(inputs) => self.joypad(&inputs, false),
+            Command::Wait(count) => self.run_frames(count),
+            Command::Screenshot(path) => capture::save_png(&self.current, &path)
+                .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?,
+            Command::SaveState(path) => persist::save_state(self.gameboy, &path)
+                .map_err(|e| format!("Failed to save state to {}: {}", path.display(), e))?,
+            Command::LoadState(path) => {
+                persist::load_state(self.gameboy, &path)
+                    .map_err(|e| format!("Failed to load state from {}: {}", path.display(), e))?;
+                self.screen.show(self.gameboy, &mut self.current);
+            }
+            Command::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Run the commands in `input` against `gameboy` until `quit` or the end of
+/// the input, answering each one on `out`. Screenshots get the palette or
+/// color correction, like frames shown in the terminal.
+pub fn run(gameboy: &mut Gameboy, input: impl BufRead, mut out: impl Write, screen: Screen) -> io::Result<()> {
+    let mut runner = Runner {
+        gameboy,
+        frames: 0,
+        current: FrameBuffer::new(),
+        screen,
+    };
+
+    for line in input.lines() {
+        let result = Command::parse(&line?).and_then(|command| match command {
+            Some(command) => runner.execute(command),
+            None => Ok(true),
+        });
+
+        match result {
+            Ok(running) => {
+                writeln!(out, "ok {}", runner.frames)?;
+                if !running {
+                    break;
+                }
+            }
+            Err(e) => writeln!(out, "error {}", e)?,
+        }
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use gbc::cartridge::Cartridge;
+
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("press a 5"), Ok(Some(Command::Press(vec![JoypadInput::A], 5))));
+        assert_eq!(Command::parse("  press Up+B  # jump"),
+                   Ok(Some(Command::Press(vec![JoypadInput::Up, JoypadInput::B], 1))));
+        assert_eq!(Command::parse("wait 30"), Ok(Some(Command::Wait(30))));
+        assert_eq!(Command::parse("savestate s1"), Ok(Some(Command::SaveState(PathBuf::from("s1")))));
+        assert_eq!(Command::parse("# nothing"), Ok(None));
+
+        assert_eq!(Command::parse("wait"), Err("Wrong arguments for wait".to_string()));
+        assert_eq!(Command::parse("wait -1"), Err("Invalid number of frames: -1".to_string()));
+        assert_eq!(Command::parse("press x"), Err("Unknown joypad input: x".to_string()));
+        assert_eq!(Command::parse("jump"), Err("Unknown command: jump".to_string()));
+    }
+
+    #[test]
+    fn scripts_are_answered_line_by_line() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/tetris_world.gb");
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(std::fs::read(path).unwrap(), false), false).unwrap();
+        let dir = std::env::temp_dir().join(format!("gbcemu-script-{}", std::process::id()));
+
+        let script = format!("wait 10\npress start 2\n\nscreenshot {0}/shot.png\nsavestate {0}/s1\nloadstate {0}/s1\n\
+                              jump\nquit\nwait 5\n", dir.display());
+        let mut out = Vec::new();
+        run(&mut gameboy, script.as_bytes(), &mut out, Screen::default()).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().collect::<Vec<_>>(),
+                   ["ok 10", "ok 12", "ok 12", "ok 12", "ok 12", "ok 12", "error Unknown command: jump", "ok 12"]);
+        assert!(dir.join("shot.png").exists() && dir.join("s1").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
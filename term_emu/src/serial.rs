@@ -0,0 +1,133 @@
+//! Serial output, for `run --serial-out`
+//!
+//! Test ROMs like Blargg's report their results over the serial port, and
+//! some games print debug output there. The bytes are appended to a file as
+//! they come, or printed to stdout: as they come with `--dump-frames`, and
+//! once the game is quit while the terminal is busy showing it.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use gbc::Gameboy;
+
+/// Where `--serial-out` sends serial output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SerialTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl FromStr for SerialTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("Expected stdout or a file".to_string()),
+            "stdout" | "-" => Ok(Self::Stdout),
+            path => Ok(Self::File(PathBuf::from(path))),
+        }
+    }
+}
+
+enum Sink {
+    Stdout,
+    File(File),
+    /// Printed to stdout by `finish`
+    Held(Vec<u8>),
+}
+
+pub struct SerialOut {
+    sink: Sink,
+}
+
+impl SerialOut {
+    /// Open `target`, appending to it if it is a file. Unless `live`, stdout
+    /// is only written to by [`finish`](Self::finish).
+    pub fn open(target: &SerialTarget, live: bool) -> io::Result<Self> {
+        let sink = match target {
+            SerialTarget::Stdout if live => Sink::Stdout,
+            SerialTarget::Stdout => Sink::Held(Vec::new()),
+            SerialTarget::File(path) => Sink::File(OpenOptions::new().create(true).append(true).open(path)?),
+        };
+
+        Ok(Self { sink })
+    }
+
+    /// Pass on the bytes the game sent since the last call.
+    pub fn frame(&mut self, gameboy: &mut Gameboy) -> io::Result<()> {
+        let bytes: Vec<u8> = gameboy.drain_serial_output().collect();
+        self.write(&bytes)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        match &mut self.sink {
+            Sink::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(bytes)?;
+                stdout.flush()
+            }
+            // Unbuffered, so the file can be followed with `tail -f`
+            Sink::File(file) => file.write_all(bytes),
+            Sink::Held(held) => {
+                held.extend_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    /// Print the output held back from stdout, once the terminal is back to
+    /// normal.
+    pub fn finish(self) {
+        if let Sink::Held(mut held) = self.sink {
+            if !held.is_empty() {
+                // Leave the shell prompt on a line of its own
+                if !held.ends_with(b"\n") {
+                    held.push(b'\n');
+                }
+                let mut stdout = io::stdout().lock();
+                let _ = stdout.write_all(&held).and_then(|()| stdout.flush());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        assert_eq!("stdout".parse(), Ok(SerialTarget::Stdout));
+        assert_eq!("-".parse(), Ok(SerialTarget::Stdout));
+        assert_eq!("out/serial.txt".parse(), Ok(SerialTarget::File(PathBuf::from("out/serial.txt"))));
+        assert!("".parse::<SerialTarget>().is_err());
+    }
+
+    #[test]
+    fn files_are_appended_to() {
+        let path = std::env::temp_dir().join(format!("gbcemu-serial-{}.txt", std::process::id()));
+        let target = SerialTarget::File(path.clone());
+
+        for bytes in [&b"cpu_instrs\n"[..], b"Passed\n"] {
+            let mut serial = SerialOut::open(&target, false).unwrap();
+            serial.write(bytes).unwrap();
+            serial.finish();
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"cpu_instrs\nPassed\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stdout_is_held_back_for_the_terminal() {
+        let mut serial = SerialOut::open(&SerialTarget::Stdout, false).unwrap();
+        serial.write(b"Pass").unwrap();
+        serial.write(b"ed").unwrap();
+        assert!(matches!(&serial.sink, Sink::Held(held) if held == b"Passed"));
+    }
+}
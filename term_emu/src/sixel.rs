@@ -0,0 +1,285 @@
+//! Sixel graphics output
+//!
+//! Terminals with Sixel support (xterm, mlterm, foot, WezTerm) can show the
+//! frame as an actual image, at full resolution, scaled up to fill the
+//! terminal window.
+//!
+//! Sixel images are much larger than the text renderers' output, so a frame
+//! is only sent if it differs from the last one. When the terminal cannot keep
+//! up, e.g. over SSH, the render thread drops the frames it did not get to.
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crossterm::{cursor, queue, style, terminal};
+
+use gbc::ppu::{FrameBuffer, GameboyRgb};
+
+use crate::render::{Crop, Renderer};
+use crate::tty;
+
+/// Most terminals have this many color registers
+const MAX_COLORS: usize = 256;
+
+/// Runs shorter than this are cheaper to write out than to repeat
+const MIN_RUN: usize = 4;
+
+/// Whether the terminal reports Sixel support, as attribute 4 in its answer
+/// to the primary device attributes query.
+#[cfg(unix)]
+pub fn supported() -> bool {
+    match tty::query(b"") {
+        Some(response) => parse_device_attributes(&response).contains(&4),
+        None => false,
+    }
+}
+
+/// There is no way to ask, so take the user's word for it.
+#[cfg(not(unix))]
+pub fn supported() -> bool {
+    true
+}
+
+/// Parse an answer of the form `CSI ? 62 ; 4 ; 22 c`.
+fn parse_device_attributes(response: &[u8]) -> Vec<u32> {
+    let response = String::from_utf8_lossy(response);
+    let attributes = response.rsplit("\x1b[?").next().unwrap_or_default();
+
+    attributes.trim_end_matches('c').split(';').filter_map(|a| a.parse().ok()).collect()
+}
+
+pub struct SixelRenderer {
+    /// Pixels per Gameboy pixel, in each direction
+    scale: usize,
+
+    /// Top-left corner of the image on the terminal, in cells
+    origin: (u16, u16),
+
+    /// Where to keep the image instead of centering it, in cells
+    fixed: Option<(u16, u16)>,
+
+    /// The part of the frame to draw
+    crop: Crop,
+
+    /// The last frame sent, or `None` if the terminal needs to be cleared and
+    /// the image redrawn
+    drawn: Option<Box<[GameboyRgb]>>,
+}
+
+impl Default for SixelRenderer {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            origin: (0, 0),
+            fixed: None,
+            crop: Crop::FULL,
+            drawn: None,
+        }
+    }
+}
+
+impl SixelRenderer {
+    /// Draw `crop` of each frame, at `at` without ever clearing the terminal
+    /// if given.
+    pub fn new(at: Option<(u16, u16)>, crop: Crop) -> Self {
+        Self { fixed: at, crop, ..Self::default() }
+    }
+}
+
+impl Renderer for SixelRenderer {
+    fn render(&mut self, frame_buffer: &FrameBuffer, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.drawn.as_deref() == Some(&*frame_buffer.data) {
+            return Ok(());
+        }
+
+        if self.drawn.is_none() && self.fixed.is_none() {
+            queue!(out, style::ResetColor, terminal::Clear(terminal::ClearType::All))?;
+        }
+
+        queue!(out, cursor::MoveTo(self.origin.0, self.origin.1))?;
+        let start = out.len();
+        encode(frame_buffer, self.crop, self.scale, out)?;
+        log::debug!("Sixel bytes: {}", out.len() - start);
+
+        self.drawn = Some(frame_buffer.data.clone());
+        Ok(())
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        let (cell_width, cell_height) = tty::cell_pixels(cols, rows);
+        // A fixed image gets the space to the right of and below its corner
+        let (left, top) = self.fixed.unwrap_or((0, 0));
+        let (cols, rows) = (cols.saturating_sub(left).max(1) as usize, rows.saturating_sub(top).max(1) as usize);
+
+        // Keep the last row free, so that the image never scrolls the terminal
+        let (width, height) = (cols * cell_width, (rows - 1).max(1) * cell_height);
+        self.scale = (width / self.crop.width).min(height / self.crop.height).max(1);
+
+        let image_cols = (self.crop.width * self.scale).div_ceil(cell_width);
+        let image_rows = (self.crop.height * self.scale).div_ceil(cell_height);
+        self.origin = self.fixed
+            .unwrap_or((cols.saturating_sub(image_cols) as u16 / 2, rows.saturating_sub(image_rows) as u16 / 2));
+
+        self.invalidate();
+    }
+
+    fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+}
+
+/// Append `crop` of a frame as a Sixel image, with each pixel drawn as a
+/// block of `scale` by `scale` pixels.
+///
+/// Each band of 6 rows is drawn one color at a time, with a character per
+/// column whose bits say which of its 6 pixels have that color.
+pub fn encode(frame_buffer: &FrameBuffer, crop: Crop, scale: usize, out: &mut Vec<u8>) -> io::Result<()> {
+    let (width, height) = (crop.width * scale, crop.height * scale);
+
+    // Color registers, in the order the colors first show up
+    let mut registers: HashMap<GameboyRgb, usize> = HashMap::new();
+    let mut colors = Vec::new();
+    let pixels: Vec<usize> = crop.pixels(frame_buffer).map(|rgb| {
+        if let Some(&register) = registers.get(&rgb) {
+            return register;
+        }
+        if colors.len() == MAX_COLORS {
+            // Out of registers, which only happens with mid-frame palette tricks
+            return nearest(&colors, rgb);
+        }
+        registers.insert(rgb, colors.len());
+        colors.push(rgb);
+        colors.len() - 1
+    }).collect();
+
+    // Square pixels, and the size of the image
+    write!(out, "\x1bPq\"1;1;{};{}", width, height)?;
+    for (i, rgb) in colors.iter().enumerate() {
+        let percent = |c: u8| (c as u32 * 100 + 127) / 255;
+        write!(out, "#{};2;{};{};{}", i, percent(rgb.red), percent(rgb.green), percent(rgb.blue))?;
+    }
+
+    let mut band = vec![0u8; width];
+    for top in (0..height).step_by(6) {
+        let rows = top..(top + 6).min(height);
+
+        // Only the colors used in this band need a pass
+        let mut used = vec![false; colors.len()];
+        for y in rows.clone().step_by(scale) {
+            for &register in &pixels[y / scale * crop.width..][..crop.width] {
+                used[register] = true;
+            }
+        }
+
+        let mut first = true;
+        for register in (0..colors.len()).filter(|&r| used[r]) {
+            for (x, bits) in band.iter_mut().enumerate() {
+                *bits = rows.clone().enumerate()
+                    .filter(|&(_, y)| pixels[y / scale * crop.width + x / scale] == register)
+                    .fold(0, |bits, (i, _)| bits | 1 << i);
+            }
+
+            if !first {
+                // Back to the start of the band
+                out.push(b'$');
+            }
+            first = false;
+            write!(out, "#{}", register)?;
+            write_band(&band, out)?;
+        }
+
+        out.push(b'-');
+    }
+
+    out.extend_from_slice(b"\x1b\\");
+    Ok(())
+}
+
+/// Write a row of sixels, run-length encoded. Trailing empty sixels are left
+/// out, as they change nothing.
+fn write_band(band: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+    let end = band.iter().rposition(|&bits| bits != 0).map_or(0, |i| i + 1);
+
+    let mut x = 0;
+    while x < end {
+        let bits = band[x];
+        let run = band[x..end].iter().take_while(|&&b| b == bits).count();
+        let sixel = b'?' + bits;
+
+        if run >= MIN_RUN {
+            write!(out, "!{}", run)?;
+            out.push(sixel);
+        } else {
+            out.extend(std::iter::repeat_n(sixel, run));
+        }
+        x += run;
+    }
+
+    Ok(())
+}
+
+fn nearest(colors: &[GameboyRgb], rgb: GameboyRgb) -> usize {
+    let distance = |c: &GameboyRgb| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(c.red, rgb.red) + d(c.green, rgb.green) + d(c.blue, rgb.blue)
+    };
+
+    (0..colors.len()).min_by_key(|&i| distance(&colors[i])).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::ppu::{LCD_HEIGHT, LCD_WIDTH};
+
+    use super::*;
+
+    const BLACK: GameboyRgb = GameboyRgb { red: 0, green: 0, blue: 0 };
+    const WHITE: GameboyRgb = GameboyRgb { red: 0xFF, green: 0xFF, blue: 0xFF };
+
+    #[test]
+    fn device_attributes() {
+        assert_eq!(parse_device_attributes(b"\x1b[?62;4;22c"), [62, 4, 22]);
+        assert_eq!(parse_device_attributes(b"\x1b[?1;2c"), [1, 2]);
+        assert!(parse_device_attributes(b"junk").is_empty());
+    }
+
+    #[test]
+    fn frames_are_encoded_by_band_and_color() {
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.data.fill(WHITE);
+        // A black pixel in the top-left corner, and the second row of the second band
+        frame_buffer.write(0, 0, BLACK);
+        for x in 0..LCD_WIDTH {
+            frame_buffer.write(x, 7, BLACK);
+        }
+
+        let mut out = Vec::new();
+        encode(&frame_buffer, Crop::FULL, 1, &mut out).unwrap();
+        let sixel = String::from_utf8(out).unwrap();
+
+        assert!(sixel.starts_with("\x1bPq\"1;1;160;144#0;2;0;0;0#1;2;100;100;100"));
+        assert!(sixel.ends_with("-\x1b\\"));
+        assert_eq!(sixel.matches('-').count(), LCD_HEIGHT / 6);
+
+        let bands: Vec<&str> = sixel.split('-').collect();
+        // Black, then white everywhere else
+        assert_eq!(bands[0].rsplit('q').next().unwrap().split_once("#0@").unwrap().1, "$#1}!159~");
+        assert_eq!(bands[1], "#0!160A$#1!160|");
+        assert_eq!(bands[2], "#1!160~");
+    }
+
+    #[test]
+    fn scaling_repeats_pixels() {
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.data.fill(WHITE);
+        frame_buffer.write(0, 0, BLACK);
+
+        let mut out = Vec::new();
+        encode(&frame_buffer, Crop::FULL, 3, &mut out).unwrap();
+        let sixel = String::from_utf8(out).unwrap();
+
+        assert!(sixel.contains("\"1;1;480;432"));
+        // 3x3 black pixels in the corner of the first band
+        assert!(sixel.contains("#0FFF$#1www!477~-"));
+        assert_eq!(sixel.matches('-').count(), LCD_HEIGHT * 3 / 6);
+    }
+}
@@ -0,0 +1,118 @@
+//! Brightness, contrast and gamma, for terminals whose colors wash out the
+//! image
+//!
+//! Frames are adjusted on their way to the terminal only, so screenshots and
+//! recordings keep the colors the game drew. Each channel goes through the
+//! same lookup table, built from the three settings.
+use gbc::ppu::FrameBuffer;
+
+/// How far `--brightness`, `--contrast` and `--gamma` go either way
+const MIN: f64 = 0.1;
+const MAX: f64 = 4.0;
+
+/// How much a key press nudges the brightness
+pub const BRIGHTNESS_STEP: f64 = 0.1;
+
+/// Parse a setting, 1.0 meaning unchanged.
+pub fn parse_factor(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(factor) if (MIN..=MAX).contains(&factor) => Ok(factor),
+        _ => Err(format!("Expected a number from {} to {}, got: {}", MIN, MAX, s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    brightness: f64,
+    contrast: f64,
+    gamma: f64,
+    table: [u8; 256],
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self::new(1.0, 1.0, 1.0)
+    }
+}
+
+impl Tone {
+    pub fn new(brightness: f64, contrast: f64, gamma: f64) -> Self {
+        let mut table = [0; 256];
+        for (i, out) in table.iter_mut().enumerate() {
+            let value = i as f64 / 255.0;
+            // Gamma above 1 lifts the midtones, contrast spreads them out
+            // from the middle, and brightness scales the result
+            let value = value.powf(1.0 / gamma);
+            let value = (value - 0.5) * contrast + 0.5;
+            let value = value * brightness;
+            *out = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+
+        Self { brightness, contrast, gamma, table }
+    }
+
+    pub fn brightness(&self) -> f64 {
+        self.brightness
+    }
+
+    /// Change the brightness by `step`, within the allowed range.
+    pub fn nudge_brightness(&mut self, step: f64) {
+        // Rounded, so that repeated steps land back on 1.0
+        let brightness = ((self.brightness + step) * 100.0).round() / 100.0;
+        *self = Self::new(brightness.clamp(MIN, MAX), self.contrast, self.gamma);
+    }
+
+    /// Adjust `src`, into `dst` if there is anything to change.
+    pub fn apply<'a>(&self, src: &'a FrameBuffer, dst: &'a mut FrameBuffer) -> &'a FrameBuffer {
+        if self.table.iter().enumerate().all(|(i, &out)| i == usize::from(out)) {
+            return src;
+        }
+
+        for (out, pixel) in dst.data.iter_mut().zip(src.data.iter()) {
+            out.red = self.table[usize::from(pixel.red)];
+            out.green = self.table[usize::from(pixel.green)];
+            out.blue = self.table[usize::from(pixel.blue)];
+        }
+        dst
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gbc::ppu::GameboyRgb;
+
+    use super::*;
+
+    #[test]
+    fn channels_are_adjusted_and_clamped() {
+        assert_eq!(Tone::default().table[100], 100);
+
+        let brighter = Tone::new(2.0, 1.0, 1.0);
+        assert_eq!((brighter.table[50], brighter.table[200]), (100, 255));
+
+        let contrast = Tone::new(1.0, 2.0, 1.0);
+        assert_eq!((contrast.table[32], contrast.table[96], contrast.table[224]), (0, 65, 255));
+
+        let gamma = Tone::new(1.0, 1.0, 2.0);
+        assert!(gamma.table[64] > 64);
+        assert_eq!((gamma.table[0], gamma.table[255]), (0, 255));
+    }
+
+    #[test]
+    fn frames_are_left_alone_unless_adjusted() {
+        let mut src = FrameBuffer::new();
+        src.data[0] = GameboyRgb { red: 10, green: 100, blue: 200 };
+        let mut dst = FrameBuffer::new();
+
+        let mut tone = Tone::default();
+        assert!(std::ptr::eq(tone.apply(&src, &mut dst), &src));
+
+        tone.nudge_brightness(BRIGHTNESS_STEP);
+        let pixel = tone.apply(&src, &mut dst).data[0];
+        assert_eq!((pixel.red, pixel.green, pixel.blue), (11, 110, 220));
+
+        tone.nudge_brightness(-BRIGHTNESS_STEP);
+        assert_eq!(tone.brightness(), 1.0);
+        assert!(parse_factor("0").is_err() && parse_factor("1.5").is_ok());
+    }
+}
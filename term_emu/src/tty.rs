@@ -0,0 +1,249 @@
+//! Terminal setup and teardown
+//!
+//! The emulator runs with the terminal in raw mode on the alternate screen,
+//! or on the normal screen with `--at`, drawing in place next to whatever is
+//! already there.
+//! Whatever way it stops, by quitting, panicking or being signalled, the
+//! terminal has to be put back the way it was for the shell to be usable.
+use std::io::{self, Write};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crossterm::{cursor, execute, style, terminal};
+
+/// Whether the terminal is currently set up for the emulator
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether `enter` switches to the alternate screen
+static ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(true);
+
+/// Set when a termination signal was received
+static QUIT: AtomicBool = AtomicBool::new(false);
+
+/// Save the window title, and bring it back (xterm window operations, which
+/// terminals without them ignore)
+const PUSH_TITLE: &[u8] = b"\x1b[22;0t";
+const POP_TITLE: &[u8] = b"\x1b[23;0t";
+
+/// Stay on the normal screen, leaving the cursor alone, from now on.
+pub fn use_normal_screen() {
+    ALTERNATE_SCREEN.store(false, Ordering::SeqCst);
+}
+
+/// Switch to the alternate screen and raw mode, hiding the cursor. On the
+/// normal screen, only raw mode is turned on.
+pub fn enter() -> crossterm::Result<()> {
+    let mut stdout = io::stdout();
+    ACTIVE.store(true, Ordering::SeqCst);
+
+    stdout.write_all(PUSH_TITLE)?;
+    if ALTERNATE_SCREEN.load(Ordering::SeqCst) {
+        execute!(stdout, terminal::EnterAlternateScreen)?;
+    }
+    terminal::enable_raw_mode()?;
+    if ALTERNATE_SCREEN.load(Ordering::SeqCst) {
+        execute!(stdout, cursor::Hide)?;
+    }
+
+    // Ask the terminal to report key releases
+    #[cfg(unix)]
+    execute!(stdout, crate::input::keyboard_enhancement())?;
+
+    Ok(())
+}
+
+/// Undo `enter`. Does nothing if the terminal was already restored, so this
+/// is safe to call from every exit path.
+pub fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    // Errors are ignored, as this runs while things are already going wrong
+    let mut stdout = io::stdout();
+    #[cfg(unix)]
+    let _ = execute!(stdout, crossterm::event::PopKeyboardEnhancementFlags);
+    // In case a frame was cut off half-way, which would leave the terminal
+    // waiting for the rest of it
+    let _ = stdout.write_all(END_SYNCHRONIZED_OUTPUT);
+    let _ = execute!(stdout, crossterm::event::DisableMouseCapture, style::ResetColor, cursor::Show);
+    if ALTERNATE_SCREEN.load(Ordering::SeqCst) {
+        let _ = execute!(stdout, terminal::LeaveAlternateScreen);
+    }
+    // Clear the title for terminals that cannot bring back the old one
+    let _ = execute!(stdout, terminal::SetTitle(""));
+    let _ = stdout.write_all(POP_TITLE).and_then(|_| stdout.flush());
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Restore the terminal before panic messages are printed, and ask the
+/// emulator to quit on SIGINT, SIGTERM or SIGHUP. Only the first call does
+/// anything.
+///
+/// In raw mode, Ctrl-C arrives as a key press rather than a signal, so that
+/// needs to be handled separately.
+pub fn install_handlers() {
+    static INSTALLED: Once = Once::new();
+
+    INSTALLED.call_once(|| {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+
+        if let Err(e) = ctrlc::set_handler(|| QUIT.store(true, Ordering::SeqCst)) {
+            log::warn!("Failed to install a signal handler: {}", e);
+        }
+    });
+}
+
+/// Whether a termination signal was received.
+pub fn quit_requested() -> bool {
+    QUIT.load(Ordering::SeqCst)
+}
+
+/// Bracket output that the terminal should show all at once (mode 2026), to
+/// avoid tearing when it repaints half-way through a frame
+pub const BEGIN_SYNCHRONIZED_OUTPUT: &[u8] = b"\x1b[?2026h";
+pub const END_SYNCHRONIZED_OUTPUT: &[u8] = b"\x1b[?2026l";
+
+/// How long to wait for the terminal to answer a query
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Send a query to the terminal, followed by a primary device attributes
+/// query, and read the answers up to the end of the last one.
+///
+/// Every terminal answers the device attributes query, so this does not have
+/// to wait for a timeout on terminals that ignore the first one. It reads
+/// straight from stdin, so it only works in raw mode before input events are
+/// being read. Returns `None` if nothing came back in time.
+#[cfg(unix)]
+pub fn query(request: &[u8]) -> Option<Vec<u8>> {
+    use std::io::IsTerminal;
+
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+
+    let mut stdout = io::stdout();
+    stdout.write_all(request).and_then(|_| stdout.write_all(b"\x1b[c")).and_then(|_| stdout.flush()).ok()?;
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut response = Vec::new();
+    while !ends_with_device_attributes(&response) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        // Safe, as the pointers are to locals that outlive the calls
+        if unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+            log::debug!("No answer to terminal query, got {:?}", String::from_utf8_lossy(&response));
+            return None;
+        }
+
+        // One byte at a time, so that nothing after the answer is consumed
+        let mut byte = 0u8;
+        if unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) } != 1 {
+            return None;
+        }
+        response.push(byte);
+    }
+
+    Some(response)
+}
+
+/// Whether the terminal supports synchronized output, according to its answer
+/// to a mode query.
+#[cfg(unix)]
+pub fn synchronized_output_supported() -> bool {
+    match query(b"\x1b[?2026$p") {
+        // Set or reset, rather than unknown or permanently off
+        Some(response) => matches!(mode_report(&response, 2026), Some(1) | Some(2)),
+        None => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn synchronized_output_supported() -> bool {
+    false
+}
+
+/// Find the report for `mode` in an answer of the form `CSI ? 2026 ; 2 $ y`.
+#[cfg(unix)]
+fn mode_report(response: &[u8], mode: u32) -> Option<u32> {
+    let response = String::from_utf8_lossy(response);
+    let prefix = format!("\x1b[?{};", mode);
+    let report = &response[response.find(&prefix)? + prefix.len()..];
+
+    report[..report.find("$y")?].parse().ok()
+}
+
+/// Whether `response` ends with an answer of the form `CSI ? 62 ; 4 c`.
+#[cfg(unix)]
+fn ends_with_device_attributes(response: &[u8]) -> bool {
+    let start = match response.windows(3).rposition(|w| w == b"\x1b[?") {
+        Some(start) => start + 3,
+        None => return false,
+    };
+
+    match response[start..].split_last() {
+        Some((b'c', params)) => params.iter().all(|&b| b.is_ascii_digit() || b == b';'),
+        _ => false,
+    }
+}
+
+/// Size of a cell in pixels, for a terminal of `cols` by `rows` cells. This is
+/// a guess if the terminal does not report its size in pixels.
+pub fn cell_pixels(cols: u16, rows: u16) -> (usize, usize) {
+    const DEFAULT: (usize, usize) = (10, 20);
+
+    match pixel_size() {
+        Some((width, height)) => ((width / cols.max(1)).max(1) as usize, (height / rows.max(1)).max(1) as usize),
+        None => DEFAULT,
+    }
+}
+
+/// Size of the terminal window in pixels, if the terminal reports it.
+#[cfg(unix)]
+pub fn pixel_size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // Safe, as the pointer is to a local that outlives the call
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+
+    if size.ws_xpixel == 0 || size.ws_ypixel == 0 {
+        None
+    } else {
+        Some((size.ws_xpixel, size.ws_ypixel))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn pixel_size() -> Option<(u16, u16)> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn device_attributes_end_a_response() {
+        assert!(ends_with_device_attributes(b"\x1b[?62;4;22c"));
+        assert!(ends_with_device_attributes(b"\x1b_Gi=31;OK\x1b\\\x1b[?1;2c"));
+        assert!(!ends_with_device_attributes(b"\x1b[?62;4"));
+        assert!(!ends_with_device_attributes(b"\x1b_Gi=31;EINVAL:bad c"));
+        assert!(!ends_with_device_attributes(b""));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn mode_reports() {
+        assert_eq!(mode_report(b"\x1b[?2026;2$y\x1b[?62;4c", 2026), Some(2));
+        assert_eq!(mode_report(b"\x1b[?2026;0$y", 2026), Some(0));
+        assert_eq!(mode_report(b"\x1b[?2027;1$y", 2026), None);
+        assert_eq!(mode_report(b"\x1b[?62;4c", 2026), None);
+    }
+}
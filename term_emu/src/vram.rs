@@ -0,0 +1,405 @@
+//! Tile, BG map and sprite viewers, for debugging graphics
+//!
+//! Replaces the game on screen with what is in VRAM and OAM, drawn by the same
+//! renderer as the game. The game keeps running underneath, so the views are
+//! live. There are three, one after the other:
+//!
+//! * The tiles, with the colors of the BG and OBJ palettes next to them. Each
+//!   page holds the 256 tiles of one VRAM bank that the PPU addresses from
+//!   0x8000 or from 0x8800, depending on LCDC bit 4.
+//! * A BG map, as much of it as fits, with the part on the screen outlined.
+//!   It can be scrolled around, and either map shown.
+//! * The sprites in OAM, drawn to the right of a list of those on the screen.
+use gbc::ppu::{FrameBuffer, GameboyRgb, OamEntry, Ppu, BG_MAP_SIZE, LCD_HEIGHT, LCD_WIDTH, OAM_SPRITES, TILES_PER_BANK};
+
+/// Tiles on each row of a page, and on each page
+const TILES_ACROSS: usize = 16;
+const PAGE_TILES: usize = 256;
+
+/// First tile of each page in a bank, at 0x8000 and 0x8800
+const PAGE_STARTS: [usize; 2] = [0, 128];
+
+/// Tiles are shown in plain grays, lightest first, whatever palette they are
+/// drawn with in the game
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// Behind the tiles and palettes
+const BACKDROP: GameboyRgb = GameboyRgb { red: 0x40, green: 0x40, blue: 0x60 };
+
+/// Left edge of the BG and OBJ palettes, which are drawn to the right of the
+/// tiles one above the other, with colors `SWATCH_WIDTH` pixels wide
+const PALETTE_LEFT: [usize; 2] = [132, 146];
+const SWATCH_WIDTH: usize = 3;
+const PALETTE_HEIGHT: usize = 16;
+
+/// Around the part of the BG map that is on the screen
+const OUTLINE: GameboyRgb = GameboyRgb { red: 0xFF, green: 0x00, blue: 0x00 };
+
+/// How far the BG map scrolls at a time, and how far it can go
+pub const MAP_SCROLL_STEP: usize = 8;
+const MAX_MAP_SCROLL: (usize, usize) = (BG_MAP_SIZE - LCD_WIDTH, BG_MAP_SIZE - LCD_HEIGHT);
+
+/// Sprites are drawn in a grid on the right, leaving room for the list on the
+/// left, each in a cell big enough for two tiles
+const SPRITES_LEFT: usize = 100;
+const SPRITES_ACROSS: usize = 5;
+const SPRITE_CELL: (usize, usize) = (12, 18);
+
+const _: () = assert!(TILES_ACROSS * 8 < PALETTE_LEFT[0] && PALETTE_LEFT[1] + 4 * SWATCH_WIDTH <= LCD_WIDTH);
+const _: () = assert!(PAGE_TILES / TILES_ACROSS * 8 <= LCD_HEIGHT && 8 * PALETTE_HEIGHT <= LCD_HEIGHT);
+const _: () = assert!(PAGE_STARTS[1] + PAGE_TILES <= TILES_PER_BANK);
+const _: () = assert!(SPRITES_LEFT + SPRITES_ACROSS * SPRITE_CELL.0 <= LCD_WIDTH);
+const _: () = assert!(OAM_SPRITES.div_ceil(SPRITES_ACROSS) * SPRITE_CELL.1 <= LCD_HEIGHT);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum View {
+    Tiles,
+    Map,
+    Sprites,
+}
+
+pub struct VramViewer {
+    view: View,
+    /// Page of tiles shown
+    page: usize,
+    /// BG map shown, or `None` for the one the BG is drawn from
+    map: Option<u8>,
+    /// Top left corner of the part of the BG map shown
+    scroll: (usize, usize),
+    frame: FrameBuffer,
+}
+
+impl Default for VramViewer {
+    fn default() -> Self {
+        Self { view: View::Tiles, page: 0, map: None, scroll: (0, 0), frame: FrameBuffer::new() }
+    }
+}
+
+impl VramViewer {
+    /// Go on to the next view, or `None` after the last one.
+    pub fn next_view(mut self) -> Option<Self> {
+        self.view = match self.view {
+            View::Tiles => View::Map,
+            View::Map => View::Sprites,
+            View::Sprites => return None,
+        };
+        Some(self)
+    }
+
+    /// Flip forward `by` pages of tiles, or back, wrapping around. On the BG
+    /// map, show the other one.
+    pub fn flip(&mut self, ppu: &Ppu, by: isize) {
+        match self.view {
+            View::Tiles => {
+                let pages = (ppu.vram_banks() as usize * PAGE_STARTS.len()) as isize;
+                self.page = (self.page as isize + by).rem_euclid(pages) as usize;
+            }
+            View::Map => self.map = Some(1 - self.map(ppu)),
+            View::Sprites => (),
+        }
+    }
+
+    /// Whether the view can be scrolled, which only the BG map can.
+    pub fn scrolls(&self) -> bool {
+        self.view == View::Map
+    }
+
+    /// Move the part of the BG map shown, by pixels, within the map.
+    pub fn scroll(&mut self, (dx, dy): (isize, isize)) {
+        let clamp = |at: usize, by: isize, max: usize| (at as isize + by).clamp(0, max as isize) as usize;
+        self.scroll = (clamp(self.scroll.0, dx, MAX_MAP_SCROLL.0), clamp(self.scroll.1, dy, MAX_MAP_SCROLL.1));
+    }
+
+    /// What is shown, e.g. "VRAM bank 0, 8800-97FF".
+    pub fn title(&self, ppu: &Ppu) -> String {
+        match self.view {
+            View::Tiles => {
+                let (bank, start) = self.page();
+                let addr = 0x8000 + start * 16;
+                format!("VRAM bank {}, {:04X}-{:04X}", bank, addr, addr + PAGE_TILES * 16 - 1)
+            }
+            View::Map => {
+                let map = self.map(ppu);
+                let used = if map == ppu.bg_map() { ", on screen" } else { "" };
+                format!("BG map {:04X}{} at {},{}", 0x9800 + map as usize * 0x400, used, self.scroll.0, self.scroll.1)
+            }
+            View::Sprites => format!("Sprites, 8x{}", ppu.sprite_height()),
+        }
+    }
+
+    /// A line for each sprite on the screen, with the sprite viewer.
+    pub fn sprite_list(&self, ppu: &Ppu) -> Vec<String> {
+        if self.view != View::Sprites {
+            return Vec::new();
+        }
+
+        ppu.oam_entries().iter().enumerate()
+            .filter(|(_, sprite)| on_screen(sprite, ppu.sprite_height()))
+            .map(|(i, sprite)| {
+                let flags: String = [(sprite.flip_x, 'X'), (sprite.flip_y, 'Y'), (sprite.behind_bg, 'B')].iter()
+                    .map(|&(set, flag)| if set { flag } else { '-' })
+                    .collect();
+                format!("{:2} {:3},{:<3} tile {:02X} pal {} bank {} {}",
+                        i, sprite.x, sprite.y, sprite.tile, sprite.palette, sprite.bank, flags)
+            })
+            .collect()
+    }
+
+    fn page(&self) -> (u8, usize) {
+        ((self.page / PAGE_STARTS.len()) as u8, PAGE_STARTS[self.page % PAGE_STARTS.len()])
+    }
+
+    fn map(&self, ppu: &Ppu) -> u8 {
+        self.map.unwrap_or_else(|| ppu.bg_map())
+    }
+
+    /// Draw what is in VRAM now.
+    pub fn draw(&mut self, ppu: &Ppu) -> &FrameBuffer {
+        self.frame.data.fill(BACKDROP);
+
+        // A bank can go away if a CGB game was swapped for a DMG one
+        if self.page >= ppu.vram_banks() as usize * PAGE_STARTS.len() {
+            self.page = 0;
+        }
+        match self.view {
+            View::Tiles => self.draw_tiles(ppu),
+            View::Map => self.draw_map(ppu),
+            View::Sprites => self.draw_sprites(ppu),
+        }
+
+        &self.frame
+    }
+
+    fn draw_tiles(&mut self, ppu: &Ppu) {
+        let (bank, start) = self.page();
+        for i in 0..PAGE_TILES {
+            let (left, top) = (i % TILES_ACROSS * 8, i / TILES_ACROSS * 8);
+            for (y, row) in ppu.tile(bank, start + i).iter().enumerate() {
+                for (x, &color) in row.iter().enumerate() {
+                    let shade = SHADES[color as usize];
+                    self.frame.write(left + x, top + y, GameboyRgb { red: shade, green: shade, blue: shade });
+                }
+            }
+        }
+
+        for (sprite, left) in [(false, PALETTE_LEFT[0]), (true, PALETTE_LEFT[1])] {
+            for num in 0..ppu.palettes(sprite) {
+                let top = num as usize * PALETTE_HEIGHT;
+                for (i, &color) in ppu.palette(sprite, num).iter().enumerate() {
+                    // With a gap below, to tell the palettes apart
+                    for y in top + 1..top + PALETTE_HEIGHT - 1 {
+                        for x in left + i * SWATCH_WIDTH..left + (i + 1) * SWATCH_WIDTH {
+                            self.frame.write(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_map(&mut self, ppu: &Ppu) {
+        let map = self.map(ppu);
+        let (scx, scy) = ppu.scroll();
+        // The screen wraps around the edges of the map
+        let outlined = |x: u8, y: u8| {
+            let (dx, dy) = (x.wrapping_sub(scx) as usize, y.wrapping_sub(scy) as usize);
+            (dx == 0 || dx == LCD_WIDTH - 1) && dy < LCD_HEIGHT || (dy == 0 || dy == LCD_HEIGHT - 1) && dx < LCD_WIDTH
+        };
+
+        for y in 0..LCD_HEIGHT {
+            for x in 0..LCD_WIDTH {
+                let (map_x, map_y) = ((self.scroll.0 + x) as u8, (self.scroll.1 + y) as u8);
+                let pixel = if map == ppu.bg_map() && outlined(map_x, map_y) {
+                    OUTLINE
+                } else {
+                    ppu.map_pixel(map, map_x, map_y)
+                };
+                self.frame.write(x, y, pixel);
+            }
+        }
+    }
+
+    fn draw_sprites(&mut self, ppu: &Ppu) {
+        let height = ppu.sprite_height() as usize;
+        for (i, sprite) in ppu.oam_entries().iter().enumerate() {
+            let left = SPRITES_LEFT + i % SPRITES_ACROSS * SPRITE_CELL.0 + (SPRITE_CELL.0 - 8) / 2;
+            let top = i / SPRITES_ACROSS * SPRITE_CELL.1 + (SPRITE_CELL.1 - 16) / 2;
+            let colors = ppu.palette(true, sprite.palette);
+
+            // Both tiles of a tall sprite, flipped as they are on the screen
+            for y in 0..height {
+                let tile_y = if sprite.flip_y { height - 1 - y } else { y };
+                let tile = if height == 8 { sprite.tile } else { sprite.tile & 0xFE | (tile_y / 8) as u8 };
+                let row = ppu.tile(sprite.bank, tile as usize)[tile_y % 8];
+                for x in 0..8 {
+                    let color = row[if sprite.flip_x { 7 - x } else { x }];
+                    // Color 0 is transparent
+                    if color != 0 {
+                        self.frame.write(left + x, top + y, colors[color as usize]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether any of a sprite is on the screen.
+fn on_screen(sprite: &OamEntry, height: u8) -> bool {
+    (1..LCD_WIDTH as u8 + 8).contains(&sprite.x) && (17 - height..LCD_HEIGHT as u8 + 16).contains(&sprite.y)
+}
+
+/// The palettes, LCD registers and BG maps as JSON, for tools to read what
+/// the viewers show. Colors are as the PPU would draw them, and each map is
+/// 32 rows of 32 tile numbers, with the attributes alongside on the CGB.
+pub fn to_json(ppu: &Ppu) -> serde_json::Value {
+    let palettes = |sprite: bool| -> Vec<Vec<String>> {
+        (0..ppu.palettes(sprite))
+            .map(|num| ppu.palette(sprite, num).iter()
+                .map(|color| format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue))
+                .collect())
+            .collect()
+    };
+    let rows = |map: &[u8]| -> Vec<Vec<u8>> { map.chunks(32).map(<[u8]>::to_vec).collect() };
+    let maps: Vec<_> = (0..2)
+        .map(|map| serde_json::json!({
+            "address": 0x9800 + map as u16 * 0x400,
+            "tiles": rows(ppu.map_tiles(map)),
+            "attributes": ppu.map_attributes(map).map(rows),
+        }))
+        .collect();
+
+    let registers = ppu.registers();
+    serde_json::json!({
+        "cgb": ppu.vram_banks() == 2,
+        "registers": {
+            "lcdc": registers.lcdc,
+            "stat": registers.stat,
+            "scy": registers.scy,
+            "scx": registers.scx,
+            "ly": registers.ly,
+            "lyc": registers.lyc,
+            "wy": registers.wy,
+            "wx": registers.wx,
+            "bgp": registers.bgp,
+            "obp0": registers.obp0,
+            "obp1": registers.obp1,
+        },
+        "bg_map": ppu.bg_map(),
+        "bg_palettes": palettes(false),
+        "obj_palettes": palettes(true),
+        "maps": maps,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use gbc::Gameboy;
+    use gbc::cartridge::Cartridge;
+    use gbc::joypad::{JoypadEvent, JoypadInput};
+    use gbc::ppu::DMG_PALETTE;
+
+    use super::*;
+
+    fn tetris(frames: usize) -> Gameboy {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/tetris_world.gb");
+        let mut gameboy = Gameboy::init(Cartridge::from_bytes(std::fs::read(path).unwrap(), false), false).unwrap();
+        for _ in 0..frames {
+            gameboy.frame(Some(&[JoypadEvent::Down(JoypadInput::Start), JoypadEvent::Up(JoypadInput::Start)]));
+        }
+        gameboy
+    }
+
+    #[test]
+    fn tiles_and_palettes_are_drawn() {
+        let gameboy = tetris(120);
+        let mut viewer = VramViewer::default();
+        let frame = viewer.draw(gameboy.ppu());
+        assert!(frame.data.iter().any(|&pixel| pixel.red == 0));
+        assert_eq!(frame.read(0, 130), BACKDROP);
+        // BGP, and OBP1 but not a third OBJ palette
+        assert_eq!(frame.read(PALETTE_LEFT[0], 1), DMG_PALETTE[0]);
+        assert_ne!(frame.read(PALETTE_LEFT[1], PALETTE_HEIGHT + 1), BACKDROP);
+        assert_eq!(frame.read(PALETTE_LEFT[1], 2 * PALETTE_HEIGHT + 1), BACKDROP);
+
+        // A DMG has a single bank
+        let ppu = gameboy.ppu();
+        assert_eq!(viewer.title(ppu), "VRAM bank 0, 8000-8FFF");
+        viewer.flip(ppu, 1);
+        assert_eq!(viewer.title(ppu), "VRAM bank 0, 8800-97FF");
+        viewer.flip(ppu, 1);
+        assert_eq!(viewer.title(ppu), "VRAM bank 0, 8000-8FFF");
+        viewer.flip(ppu, -1);
+        assert_eq!(viewer.title(ppu), "VRAM bank 0, 8800-97FF");
+    }
+
+    #[test]
+    fn ppu_state_as_json() {
+        let gameboy = tetris(120);
+        let json = to_json(gameboy.ppu());
+        assert_eq!(json["cgb"], false);
+        assert_eq!(json["registers"]["bgp"], 0xE4);
+        assert_eq!(json["registers"]["scx"], 0);
+        assert_eq!(json["bg_map"], 0);
+        assert_eq!(json["bg_palettes"].as_array().unwrap().len(), 1);
+        assert_eq!(json["bg_palettes"][0][0], "#e0f8d0");
+        assert_eq!(json["obj_palettes"].as_array().unwrap().len(), 2);
+
+        let map = &json["maps"][0];
+        assert_eq!(map["address"], 0x9800);
+        assert!(map["attributes"].is_null());
+        let tiles = map["tiles"].as_array().unwrap();
+        assert_eq!(tiles.len(), 32);
+        assert_eq!(tiles[3].as_array().unwrap().len(), 32);
+        assert_eq!(tiles[3][5], gameboy.ppu().map_tiles(0)[3 * 32 + 5]);
+    }
+
+    #[test]
+    fn bg_map_is_outlined_and_scrolled() {
+        let gameboy = tetris(120);
+        let ppu = gameboy.ppu();
+        let mut viewer = VramViewer::default().next_view().unwrap();
+        assert!(viewer.scrolls());
+        assert_eq!(viewer.title(ppu), "BG map 9800, on screen at 0,0");
+
+        // The screen is not scrolled, so its outline starts in the corner
+        let frame = viewer.draw(ppu);
+        assert_eq!((frame.read(0, 0), frame.read(80, 143), frame.read(159, 80)), (OUTLINE, OUTLINE, OUTLINE));
+        assert_eq!(frame.read(80, 72), gameboy.frame_buffer().read(80, 72));
+
+        viewer.scroll((-8, 1000));
+        assert_eq!(viewer.title(ppu), "BG map 9800, on screen at 0,112");
+        viewer.scroll((200, 0));
+        let frame = viewer.draw(ppu);
+        assert_eq!(frame.read(63, 31), OUTLINE);
+        assert_ne!(frame.read(64, 31), OUTLINE);
+
+        viewer.flip(ppu, 1);
+        assert_eq!(viewer.title(ppu), "BG map 9C00 at 96,112");
+        assert!(!viewer.draw(ppu).data.contains(&OUTLINE));
+    }
+
+    #[test]
+    fn sprites_are_listed() {
+        // Into the game, where the first piece is falling
+        let gameboy = tetris(600);
+        let ppu = gameboy.ppu();
+        let mut viewer = VramViewer::default();
+        assert!(viewer.sprite_list(ppu).is_empty());
+        viewer = viewer.next_view().and_then(VramViewer::next_view).unwrap();
+        assert!(!viewer.scrolls());
+
+        let list = viewer.sprite_list(ppu);
+        let entries = ppu.oam_entries();
+        assert!(!list.is_empty() && list.len() < OAM_SPRITES);
+        let first = entries.iter().position(|sprite| on_screen(sprite, 8)).unwrap();
+        assert_eq!(list[0], format!("{:2} {:3},{:<3} tile {:02X} pal {} bank 0 ---",
+                                    first, entries[first].x, entries[first].y, entries[first].tile, entries[first].palette));
+
+        let frame = viewer.draw(ppu);
+        assert!(frame.data[..SPRITES_LEFT].iter().all(|&pixel| pixel == BACKDROP));
+        assert!(frame.data.iter().any(|&pixel| pixel != BACKDROP));
+        assert!(viewer.next_view().is_none());
+    }
+}
@@ -0,0 +1,149 @@
+//! Run test ROMs through `gbcemu::Emulator`, as the frontend would
+//!
+//! The ROMs are not bundled. Tests whose ROMs are missing from `tests/roms`
+//! are skipped, so drop in whichever of these are at hand:
+//!
+//! * `cpu_instrs.gb` and `instr_timing.gb`, from Blargg's test suite
+//! * `dmg-acid2.gb` and `cgb-acid2.gbc`, each with its reference image next to
+//!   it (`dmg-acid2.png`, `cgb-acid2.png`)
+//! * Any of Mooneye's tests, in `tests/roms/mooneye`
+use std::path::{Path, PathBuf};
+
+use gbc::cartridge::Cartridge;
+use gbc::ppu::{LCD_HEIGHT, LCD_WIDTH};
+use gbcemu::{Emulator, EmulatorOptions, Palette};
+
+/// Emulated time a test ROM gets to finish in
+const MAX_FRAMES: u32 = 60 * 120;
+
+/// Mooneye's tests are all over within a few seconds.
+const MOONEYE_FRAMES: u32 = 60 * 10;
+
+/// The acid tests draw their face within a few frames, then stay still.
+const ACID_FRAMES: u32 = 60;
+
+/// How far apart channels can be to count as the same color, since colors
+/// on a CGB can be scaled up from 5 bits slightly differently
+const TOLERANCE: u8 = 4;
+
+/// Where Mooneye's tests leave the Fibonacci numbers when they pass, and
+/// 0x42 in every register when they fail
+const MOONEYE_PASS: (u16, u16, u16) = (0x0305, 0x080D, 0x1522);
+const MOONEYE_FAIL: (u16, u16, u16) = (0x4242, 0x4242, 0x4242);
+
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms")
+}
+
+/// A ROM from `tests/roms`, or `None` if it is not there and the test should
+/// be skipped.
+fn test_rom(name: &str) -> Option<PathBuf> {
+    let path = roms_dir().join(name);
+    if path.exists() {
+        Some(path)
+    } else {
+        eprintln!("Skipping, {} not found", path.display());
+        None
+    }
+}
+
+fn emulator(path: &Path, palette: Option<Palette>) -> Emulator {
+    let data = std::fs::read(path).unwrap();
+    let options = EmulatorOptions { palette, ..EmulatorOptions::default() };
+    Emulator::new(Cartridge::from_bytes(data, false), options).unwrap()
+}
+
+/// Run a Blargg test, which reports on the serial port.
+fn run_blargg(name: &str) {
+    let Some(path) = test_rom(name) else { return };
+    let mut emulator = emulator(&path, None);
+
+    for _ in 0..MAX_FRAMES {
+        emulator.step_frame(&[]);
+        let serial = emulator.gameboy().serial_output();
+        if serial.contains("Passed") {
+            return;
+        }
+        assert!(!serial.contains("Failed"), "{} failed:\n{}", name, serial);
+    }
+
+    panic!("{} did not finish:\n{}", name, emulator.gameboy().serial_output());
+}
+
+/// Run an acid test, comparing the face it draws against its reference
+/// image.
+fn run_acid(name: &str, reference: &str, palette: Option<Palette>) {
+    let (Some(path), Some(reference)) = (test_rom(name), test_rom(reference)) else { return };
+    let reference = image::open(reference).unwrap().to_rgb8();
+    assert_eq!(reference.dimensions(), (LCD_WIDTH as u32, LCD_HEIGHT as u32));
+
+    let mut emulator = emulator(&path, palette);
+    for _ in 0..ACID_FRAMES {
+        emulator.step_frame(&[]);
+    }
+
+    let frame = emulator.frame_buffer();
+    let wrong = reference.enumerate_pixels()
+        .filter(|&(x, y, expected)| {
+            let pixel = frame.read(x as usize, y as usize);
+            [pixel.red, pixel.green, pixel.blue].iter().zip(expected.0.iter())
+                .any(|(&actual, &expected)| actual.abs_diff(expected) > TOLERANCE)
+        })
+        .count();
+
+    assert_eq!(wrong, 0, "{} pixels of {} differ from the reference", wrong, name);
+}
+
+#[test]
+fn blargg_cpu_instrs() {
+    run_blargg("cpu_instrs.gb");
+}
+
+#[test]
+fn blargg_instr_timing() {
+    run_blargg("instr_timing.gb");
+}
+
+#[test]
+fn dmg_acid2() {
+    // The reference image is in plain grey
+    let grey = Palette::NAMED.iter().find(|(name, _)| *name == "grey").unwrap().1;
+    run_acid("dmg-acid2.gb", "dmg-acid2.png", Some(grey));
+}
+
+#[test]
+fn cgb_acid2() {
+    run_acid("cgb-acid2.gbc", "cgb-acid2.png", None);
+}
+
+#[test]
+fn mooneye() {
+    let Ok(entries) = std::fs::read_dir(roms_dir().join("mooneye")) else {
+        eprintln!("Skipping, no Mooneye tests in {}", roms_dir().display());
+        return;
+    };
+
+    let mut paths: Vec<_> = entries.map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gb" || ext == "gbc"))
+        .collect();
+    paths.sort();
+
+    let failed: Vec<_> = paths.iter()
+        .filter(|path| {
+            let mut emulator = emulator(path, None);
+            for _ in 0..MOONEYE_FRAMES {
+                emulator.step_frame(&[]);
+                let state = emulator.gameboy().cpu_state();
+                match (state.bc, state.de, state.hl) {
+                    MOONEYE_PASS => return false,
+                    MOONEYE_FAIL => break,
+                    _ => (),
+                }
+            }
+            eprintln!("{} failed", path.display());
+            true
+        })
+        .collect();
+
+    assert!(failed.is_empty(), "{} of {} Mooneye tests failed", failed.len(), paths.len());
+}